@@ -0,0 +1,123 @@
+use std::fmt;
+
+/// Text buffered for an incomplete document is dropped beyond this many bytes by default
+pub const DEFAULT_MAX_DOCUMENT: usize = 16 * 1024 * 1024;
+
+/// How complete documents are told apart in a stream of text messages
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Framing {
+    /// Every document ends with this text, e.g. `\n` for newline-delimited JSON
+    Delimiter(String),
+    /// Every document starts with its length in bytes as decimal digits and a `:`, e.g. `7:{"a":1}`
+    LengthPrefix,
+}
+
+/// Why buffered text was dropped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AssemblyError {
+    /// The incomplete document grew over the limit
+    TooLarge(usize),
+    /// A length prefix which isn't one, or whose length ends inside a character
+    InvalidPrefix,
+}
+
+impl fmt::Display for AssemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssemblyError::TooLarge(max) => {
+                write!(f, "incomplete document over {} bytes was dropped", max)
+            }
+            AssemblyError::InvalidPrefix => {
+                write!(f, "invalid length prefix, the buffered text was dropped")
+            }
+        }
+    }
+}
+
+/// Joins text messages which carry parts of documents, e.g. a server streaming JSON in arbitrary chunks.
+/// A message may end a document, hold several of them or only a part of one.
+#[derive(Debug)]
+pub struct TextAssembler {
+    framing: Framing,
+    max_document: usize,
+    buffer: String,
+}
+
+impl TextAssembler {
+    pub fn new(framing: Framing, max_document: Option<usize>) -> Self {
+        TextAssembler {
+            framing,
+            max_document: max_document.unwrap_or(DEFAULT_MAX_DOCUMENT),
+            buffer: String::new(),
+        }
+    }
+
+    /// Drop the incomplete document, e.g. when a new connection opened
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    /// Take `text` and return the documents it completed, in their order. Empty documents are skipped.
+    /// On an error the buffered text is dropped, the documents completed before it are still returned.
+    pub fn push(&mut self, text: &str) -> (Vec<String>, Option<AssemblyError>) {
+        self.buffer.push_str(text);
+        let mut documents = Vec::new();
+        let error = loop {
+            match self.next_document() {
+                Ok(Some(document)) => {
+                    if !document.is_empty() {
+                        documents.push(document);
+                    }
+                }
+                Ok(None) if self.buffer.len() > self.max_document => {
+                    break Some(AssemblyError::TooLarge(self.max_document));
+                }
+                Ok(None) => break None,
+                Err(e) => break Some(e),
+            }
+        };
+        if error.is_some() {
+            self.buffer.clear();
+        }
+        (documents, error)
+    }
+
+    fn next_document(&mut self) -> Result<Option<String>, AssemblyError> {
+        match &self.framing {
+            Framing::Delimiter(delimiter) => Ok(self.buffer.find(delimiter.as_str()).map(|end| {
+                let document = self.buffer[..end].to_string();
+                self.buffer.drain(..end + delimiter.len());
+                document
+            })),
+            Framing::LengthPrefix => {
+                // Whitespace between documents, e.g. a newline after each one, is skipped
+                let whitespace = self.buffer.len() - self.buffer.trim_start().len();
+                self.buffer.drain(..whitespace);
+                let Some(colon) = self.buffer.find(':') else {
+                    return if self.buffer.bytes().all(|byte| byte.is_ascii_digit()) {
+                        Ok(None)
+                    } else {
+                        Err(AssemblyError::InvalidPrefix)
+                    };
+                };
+                let len = self.buffer[..colon]
+                    .parse::<usize>()
+                    .map_err(|_| AssemblyError::InvalidPrefix)?;
+                if len > self.max_document {
+                    return Err(AssemblyError::TooLarge(self.max_document));
+                }
+                let start = colon + 1;
+                if self.buffer.len() < start + len {
+                    return Ok(None);
+                }
+                let document = self
+                    .buffer
+                    .get(start..start + len)
+                    .ok_or(AssemblyError::InvalidPrefix)?
+                    .to_string();
+                self.buffer.drain(..start + len);
+                Ok(Some(document))
+            }
+        }
+    }
+}
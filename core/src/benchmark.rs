@@ -0,0 +1,184 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::{
+    sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender},
+    time::{timeout, Instant},
+};
+use tokio_tungstenite::tungstenite::{protocol::Message, Bytes};
+
+use crate::{ConnectOptions, Connection, Error, Event, EventHandler, Result, WebSocketError};
+
+/// Room for the sequence number at the start of every message
+const SEQUENCE_LEN: usize = 8;
+
+/// How to drive an echo server
+#[derive(Debug, Clone)]
+pub struct BenchmarkOptions {
+    /// Payload bytes of every message, at least 8 for the sequence number
+    pub size: usize,
+    /// Messages of each phase
+    pub count: u32,
+    /// How long to wait for an echo before giving up
+    pub timeout: Duration,
+    pub connect: ConnectOptions,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        BenchmarkOptions {
+            size: 1024,
+            count: 1000,
+            timeout: Duration::from_secs(10),
+            connect: ConnectOptions::default(),
+        }
+    }
+}
+
+/// What the client measured against the echo server
+#[derive(Debug, Clone, Default)]
+pub struct BenchmarkReport {
+    pub size: usize,
+    pub count: u32,
+    /// From `connect()` until the connection is open
+    pub connect_ms: f64,
+    /// Round trips of one message at a time
+    pub latency_min_ms: f64,
+    pub latency_mean_ms: f64,
+    pub latency_p50_ms: f64,
+    pub latency_p90_ms: f64,
+    pub latency_p99_ms: f64,
+    pub latency_max_ms: f64,
+    /// All messages sent at once, until the last echo arrived
+    pub throughput_ms: f64,
+    pub messages_per_second: f64,
+    /// Payload bytes sent and received per second
+    pub bytes_per_second: f64,
+}
+
+/// Hands the sequence number of every echo to the benchmark, `None` once the connection ended
+struct Echoes {
+    sender: UnboundedSender<Option<u64>>,
+}
+
+impl EventHandler for Echoes {
+    fn handle(&self, event: Event) {
+        let data = match event {
+            Event::Binary(data) => data,
+            Event::Close(_) => {
+                let _ = self.sender.send(None);
+                return;
+            }
+            _ => return,
+        };
+        if let Some(sequence) = data.get(..SEQUENCE_LEN) {
+            let mut bytes = [0; SEQUENCE_LEN];
+            bytes.copy_from_slice(sequence);
+            let _ = self.sender.send(Some(u64::from_be_bytes(bytes)));
+        }
+    }
+}
+
+fn payload(sequence: u64, size: usize) -> Message {
+    let mut data = vec![0; size];
+    data[..SEQUENCE_LEN].copy_from_slice(&sequence.to_be_bytes());
+    Message::Binary(Bytes::from(data))
+}
+
+/// Wait for the echo of `sequence`, older ones are skipped
+async fn echo(
+    receiver: &mut UnboundedReceiver<Option<u64>>,
+    sequence: u64,
+    wait: Duration,
+) -> Result<()> {
+    loop {
+        match timeout(wait, receiver.recv()).await {
+            Ok(Some(Some(echoed))) if echoed >= sequence => return Ok(()),
+            Ok(Some(Some(_))) => {}
+            Ok(Some(None) | None) => {
+                return Err(Error::new(
+                    WebSocketError::ReceiveError,
+                    "the echo server closed the connection",
+                ))
+            }
+            Err(_) => {
+                return Err(Error::new(
+                    WebSocketError::ReceiveError,
+                    format!("no echo of message {} within {:?}", sequence, wait),
+                ))
+            }
+        }
+    }
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// The round trip below which `percent` of `sorted` are
+fn percentile(sorted: &[f64], percent: usize) -> f64 {
+    let index = (sorted.len() * percent).div_ceil(100).max(1) - 1;
+    sorted[index.min(sorted.len() - 1)]
+}
+
+/// Measure against an echo server at `echo_url`, e.g. `ws://192.168.1.2:9001`.
+/// Latency is measured first with one message in flight at a time,
+/// throughput after that with all messages sent at once.
+pub async fn run_benchmark(echo_url: &str, options: BenchmarkOptions) -> Result<BenchmarkReport> {
+    if options.size < SEQUENCE_LEN || options.count == 0 {
+        return Err(Error::new(
+            WebSocketError::SendError,
+            format!(
+                "benchmark needs a size of at least {} bytes and at least one message",
+                SEQUENCE_LEN
+            ),
+        ));
+    }
+    let (sender, mut receiver) = unbounded_channel();
+    let connection = Arc::new(Connection::new(echo_url.to_string(), options.connect));
+    let started = Instant::now();
+    connection.connect(Arc::new(Echoes { sender })).await?;
+    let connect_ms = millis(started.elapsed());
+    let link = connection.link();
+
+    let measured = async {
+        let mut sequence = 0;
+        let mut latencies = Vec::with_capacity(options.count as usize);
+        for _ in 0..options.count {
+            sequence += 1;
+            let sent = Instant::now();
+            link.send_message(payload(sequence, options.size)).await?;
+            echo(&mut receiver, sequence, options.timeout).await?;
+            latencies.push(millis(sent.elapsed()));
+        }
+
+        let started = Instant::now();
+        for _ in 0..options.count {
+            sequence += 1;
+            link.send_message(payload(sequence, options.size)).await?;
+        }
+        echo(&mut receiver, sequence, options.timeout).await?;
+        Ok::<_, Error>((latencies, started.elapsed()))
+    }
+    .await;
+    let _ = link.close().await;
+    connection.closed().await;
+    let (mut latencies, throughput) = measured?;
+
+    latencies.sort_by(f64::total_cmp);
+    let seconds = throughput.as_secs_f64().max(f64::EPSILON);
+    let count = f64::from(options.count);
+    Ok(BenchmarkReport {
+        size: options.size,
+        count: options.count,
+        connect_ms,
+        latency_min_ms: latencies[0],
+        latency_mean_ms: latencies.iter().sum::<f64>() / count,
+        latency_p50_ms: percentile(&latencies, 50),
+        latency_p90_ms: percentile(&latencies, 90),
+        latency_p99_ms: percentile(&latencies, 99),
+        latency_max_ms: latencies[latencies.len() - 1],
+        throughput_ms: millis(throughput),
+        messages_per_second: count / seconds,
+        bytes_per_second: count * options.size as f64 * 2.0 / seconds,
+    })
+}
@@ -0,0 +1,133 @@
+use std::sync::{Arc, OnceLock, PoisonError, RwLock};
+
+use crate::{BoxFuture, ClientKey, Error, Result, SignScheme, TlsOptions, WebSocketError};
+
+/// A client certificate kept in a [`CertificateStore`], its private key never leaves the store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredCredential {
+    /// URI of the credential in the store
+    pub uri: String,
+    pub scheme: SignScheme,
+}
+
+/// Certificates the system keeps and hands out by URI, e.g. the HarmonyOS Certificate Manager.
+/// They're looked up on every connect, so certificates provisioned or renewed in between are used.
+pub trait CertificateStore: Send + Sync {
+    /// The trusted CA certificate at `uri`, PEM or DER encoded
+    fn ca_cert(&self, uri: &str) -> BoxFuture<Result<Vec<u8>>>;
+
+    /// The certificate chain of `credential`, with a signer which signs with its key inside the store.
+    /// The signer should be the same one for the same credential, so connections can share their TLS setup.
+    fn client_key(&self, credential: &StoredCredential) -> BoxFuture<Result<ClientKey>>;
+}
+
+fn store() -> &'static RwLock<Option<Arc<dyn CertificateStore>>> {
+    static STORE: OnceLock<RwLock<Option<Arc<dyn CertificateStore>>>> = OnceLock::new();
+    STORE.get_or_init(RwLock::default)
+}
+
+/// Look up the certificate URIs of [`TlsOptions`] in `store` from now on, replacing the store set before.
+/// Open connections keep the certificates they were made with.
+pub fn set_certificate_store(certificate_store: Option<Arc<dyn CertificateStore>>) {
+    *store().write().unwrap_or_else(PoisonError::into_inner) = certificate_store;
+}
+
+/// Fetch the certificates `tls` references by URI: CA certificates are added to `ca_certs`
+/// and the credential becomes the client key
+pub(crate) async fn resolve_certificates(tls: &mut TlsOptions) -> Result<()> {
+    if tls.ca_cert_uris.is_empty() && tls.client_credential.is_none() {
+        return Ok(());
+    }
+    let certificate_store = store()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+        .ok_or_else(|| {
+            Error::new(
+                WebSocketError::TlsError,
+                "certificates are referenced by URI, but no certificate store is registered",
+            )
+        })?;
+    for uri in &tls.ca_cert_uris {
+        let cert = certificate_store.ca_cert(uri).await?;
+        tls.ca_certs.push(cert);
+    }
+    if let Some(credential) = &tls.client_credential {
+        tls.client_key = Some(certificate_store.client_key(credential).await?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ExternalSigner;
+
+    struct Signer;
+
+    impl ExternalSigner for Signer {
+        fn sign(
+            &self,
+            _scheme: SignScheme,
+            _message: &[u8],
+        ) -> std::result::Result<Vec<u8>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    struct Store(Arc<Signer>);
+
+    impl CertificateStore for Store {
+        fn ca_cert(&self, uri: &str) -> BoxFuture<Result<Vec<u8>>> {
+            let cert = format!("ca of {}", uri).into_bytes();
+            Box::pin(async move { Ok(cert) })
+        }
+
+        fn client_key(&self, credential: &StoredCredential) -> BoxFuture<Result<ClientKey>> {
+            let client_key = ClientKey {
+                cert_chain: vec![credential.uri.clone().into_bytes()],
+                scheme: credential.scheme,
+                signer: self.0.clone(),
+            };
+            Box::pin(async move { Ok(client_key) })
+        }
+    }
+
+    // One test, the store is shared by the whole process
+    #[tokio::test]
+    async fn certificates_are_looked_up_in_the_registered_store() {
+        let options = || TlsOptions {
+            ca_certs: vec![b"given".to_vec()],
+            ca_cert_uris: vec!["ca-1".to_string(), "ca-2".to_string()],
+            client_credential: Some(StoredCredential {
+                uri: "client".to_string(),
+                scheme: SignScheme::EcdsaP256Sha256,
+            }),
+            ..TlsOptions::default()
+        };
+
+        set_certificate_store(None);
+        let error = resolve_certificates(&mut options()).await.unwrap_err();
+        assert_eq!(error.kind, WebSocketError::TlsError);
+
+        set_certificate_store(Some(Arc::new(Store(Arc::new(Signer)))));
+        let mut tls = options();
+        resolve_certificates(&mut tls).await.unwrap();
+        assert_eq!(
+            tls.ca_certs,
+            [
+                b"given".to_vec(),
+                b"ca of ca-1".to_vec(),
+                b"ca of ca-2".to_vec()
+            ]
+        );
+        let client_key = tls.client_key.unwrap();
+        assert_eq!(client_key.cert_chain, [b"client".to_vec()]);
+        assert_eq!(client_key.scheme, SignScheme::EcdsaP256Sha256);
+
+        let mut untouched = TlsOptions::default();
+        resolve_certificates(&mut untouched).await.unwrap();
+        assert!(untouched.ca_certs.is_empty() && untouched.client_key.is_none());
+        set_certificate_store(None);
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::{ConnectOptions, Connection, Error, Event, EventHandler, Link, Result, WebSocketError};
+
+/// How to drive an Autobahn fuzzing server
+#[derive(Debug, Clone)]
+pub struct ConformanceOptions {
+    /// Name of the client in the reports of the server
+    pub agent: String,
+    /// Case numbers to run, starting at 1. All cases of the server if empty.
+    pub cases: Option<Vec<u32>>,
+    /// Options of every connection, e.g. [`ConnectOptions::strict_rfc6455`]
+    pub connect: ConnectOptions,
+}
+
+impl Default for ConformanceOptions {
+    fn default() -> Self {
+        ConformanceOptions {
+            agent: "harmony-websocket".to_string(),
+            cases: None,
+            connect: ConnectOptions {
+                strict_rfc6455: true,
+                ..ConnectOptions::default()
+            },
+        }
+    }
+}
+
+/// The verdict of the server on one case
+#[derive(Debug, Clone)]
+pub struct CaseResult {
+    pub case: u32,
+    /// e.g. `1.1.1`
+    pub id: Option<String>,
+    pub description: Option<String>,
+    /// `OK`, `NON-STRICT`, `INFORMATIONAL`, `UNIMPLEMENTED` or `FAILED`
+    pub behavior: String,
+    /// The first error of the client while running the case
+    pub error: Option<String>,
+}
+
+/// Echoes every message back, as the Autobahn cases expect
+struct Echo {
+    link: Arc<Link>,
+    error: Mutex<Option<String>>,
+}
+
+impl EventHandler for Echo {
+    fn handle(&self, event: Event) {
+        match event {
+            Event::Text(text) => {
+                self.link.try_send_message(Message::Text(text));
+            }
+            Event::Binary(data) => {
+                self.link.try_send_message(Message::Binary(data));
+            }
+            Event::Error(e) => {
+                self.error
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .get_or_insert(e.message);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Keeps the first text message, the answer of the server's control endpoints
+#[derive(Default)]
+struct Answer {
+    text: Mutex<Option<String>>,
+}
+
+impl EventHandler for Answer {
+    fn handle(&self, event: Event) {
+        if let Event::Text(text) = event {
+            self.text
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get_or_insert_with(|| text.to_string());
+        }
+    }
+}
+
+/// Percent-encode everything but unreserved characters
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// The string value of `field` in a flat JSON object, enough for the answers of the server
+fn json_string(json: &str, field: &str) -> Option<String> {
+    let key = format!("\"{}\"", field);
+    let rest = &json[json.find(&key)? + key.len()..];
+    let rest = rest.trim_start().strip_prefix(':')?.trim_start();
+    let rest = rest.strip_prefix('"')?;
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+    None
+}
+
+/// Connect to `url` and return the first text message
+async fn ask(url: String, options: &ConnectOptions) -> Result<String> {
+    let connection = Arc::new(Connection::new(url.clone(), options.clone()));
+    let answer = Arc::new(Answer::default());
+    connection.connect(answer.clone()).await?;
+    connection.closed().await;
+    let text = answer
+        .text
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .take();
+    text.ok_or_else(|| {
+        Error::new(
+            WebSocketError::ReceiveError,
+            format!("no answer from {}", url),
+        )
+    })
+}
+
+/// Run the cases of an Autobahn fuzzing server at `server_url`, e.g. `ws://192.168.1.2:9001`,
+/// and let it update its reports. A case which fails on the client is still reported by the server.
+pub async fn run_conformance_suite(
+    server_url: &str,
+    options: ConformanceOptions,
+) -> Result<Vec<CaseResult>> {
+    let base = server_url.trim_end_matches('/');
+    let agent = encode(&options.agent);
+    // Control endpoints only answer, strictness is for the cases
+    let control = ConnectOptions {
+        strict_rfc6455: false,
+        ..options.connect.clone()
+    };
+
+    let cases = match options.cases {
+        Some(cases) => cases,
+        None => {
+            let count = ask(format!("{}/getCaseCount", base), &control).await?;
+            let count: u32 = count.trim().parse().map_err(|_| {
+                Error::new(
+                    WebSocketError::ReceiveError,
+                    format!("invalid case count: {}", count),
+                )
+            })?;
+            (1..=count).collect()
+        }
+    };
+
+    let mut results = Vec::with_capacity(cases.len());
+    for case in cases {
+        let connection = Arc::new(Connection::new(
+            format!("{}/runCase?case={}&agent={}", base, case, agent),
+            options.connect.clone(),
+        ));
+        let echo = Arc::new(Echo {
+            link: connection.link().clone(),
+            error: Mutex::new(None),
+        });
+        let connected = connection.connect(echo.clone()).await;
+        if connected.is_ok() {
+            connection.closed().await;
+        }
+        let mut error = echo
+            .error
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Err(e) = connected {
+            error.get_or_insert(e.message);
+        }
+
+        let info = ask(format!("{}/getCaseInfo?case={}", base, case), &control)
+            .await
+            .ok();
+        let status = ask(
+            format!("{}/getCaseStatus?case={}&agent={}", base, case, agent),
+            &control,
+        )
+        .await?;
+        results.push(CaseResult {
+            case,
+            id: info.as_deref().and_then(|info| json_string(info, "id")),
+            description: info
+                .as_deref()
+                .and_then(|info| json_string(info, "description")),
+            behavior: json_string(&status, "behavior").unwrap_or(status),
+            error,
+        });
+    }
+
+    // The reports are optional, the results are complete without them
+    let _ = ask(format!("{}/updateReports?agent={}", base, agent), &control).await;
+    Ok(results)
+}
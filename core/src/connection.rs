@@ -0,0 +1,1724 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, VecDeque},
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+use futures_util::StreamExt;
+use tokio::{
+    sync::{watch, Notify},
+    time::{sleep, sleep_until, timeout, Instant},
+};
+use tokio_tungstenite::tungstenite::{
+    self,
+    error::ProtocolError,
+    handshake::client::{Request, Response},
+    http::{
+        header::{LOCATION, SEC_WEBSOCKET_EXTENSIONS, SEC_WEBSOCKET_PROTOCOL},
+        StatusCode, Uri,
+    },
+    protocol::{
+        frame::{coding::CloseCode, CloseFrame},
+        Message,
+    },
+};
+
+#[cfg(feature = "diagnostics")]
+use crate::report::Diagnostics;
+use crate::{
+    certstore::resolve_certificates,
+    control::{ControlFramePolicy, ControlFrames},
+    dial::{
+        dial, peer_addr, transport, DialError, DialTimeouts, DialTrace, Gates, LocalBind,
+        TcpOptions, WsStream,
+    },
+    dns::DnsOptions,
+    envelope::{last_envelope_id, skip_envelope_ids},
+    grace::CloseGrace,
+    history::{now_ms, ConnectAttempt, ConnectHistory, ErrorLog, ErrorRecorder, ReportedError},
+    keepalive_ping, limits,
+    pong::PongReplies,
+    profile,
+    proxy::{Proxy, ResolveProxy},
+    request::{build_request, is_secret_header, HandshakeResponse},
+    sample::Sampler,
+    sequence::{SequenceCheck, SequenceSource},
+    snapshot::SessionState,
+    writer::{run_writer, WriterOptions},
+    BoxFuture, ClientKey, ConnState, ConnectTimings, Error, Event, EventHandler, HandshakeRequest,
+    KeepAlive, Link, Pong, PongPolicy, ReadyState, Result, SampleOptions, SendBeforeConnect,
+    TlsConnector, TlsInfo, TlsOptions, WebSocketError,
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ConnectOptions {
+    /// The registered profile of the host applies when left default, see [`crate::TlsProfile`]
+    pub tls: TlsOptions,
+
+    /// Custom headers in the order they're sent, a name may be given more than once
+    pub headers: Vec<(String, String)>,
+
+    /// Offer `permessage-deflate; client_max_window_bits` in `Sec-WebSocket-Extensions`,
+    /// until the server accepted it or refused the handshake because of it
+    pub enable_extension: bool,
+
+    /// Host overrides, the lookup timeout, the dns server and retries of transient failures
+    pub dns: DnsOptions,
+
+    /// Limits of the tcp connect and the handshake
+    pub timeouts: DialTimeouts,
+
+    /// Local address and interface of the socket
+    pub local: LocalBind,
+
+    /// Nodelay, keepalive and buffer sizes of the tcp socket
+    pub tcp: TcpOptions,
+
+    /// Tunnel through this http proxy, for `ws://` and `wss://` alike
+    pub proxy: Option<Proxy>,
+
+    /// Asked for the proxy of every dial unless `proxy` is set
+    pub proxy_resolver: Option<Arc<dyn ResolveProxy>>,
+
+    /// What data messages sent without a connection do
+    pub send_before_connect: SendBeforeConnect,
+
+    /// Save data on metered networks: small messages are written in batches
+    /// and keepalive pings are skipped while frames arrive
+    pub data_saver: bool,
+
+    /// Report violations of RFC 6455 as `ProtocolError` naming the violation,
+    /// and fail the connection on close codes which may not be sent, e.g. for running the Autobahn test suite
+    pub strict_rfc6455: bool,
+
+    /// Deliver only some of the received data messages
+    pub sample: SampleOptions,
+
+    /// Redirects of the handshake which are followed, none by default
+    pub max_redirects: u32,
+
+    /// Keep reading after protocol errors until there are too many of them.
+    /// Without a policy the first one fails the connection.
+    pub protocol_errors: Option<ProtocolErrorPolicy>,
+
+    /// Messages read in a row before the reader yields to the writer and other tasks,
+    /// [`DEFAULT_READ_BUDGET`] if `None`
+    pub read_budget: Option<u32>,
+
+    /// Make the connection again when it ended abnormally, not at all if `None`
+    pub reconnect: Option<ReconnectPolicy>,
+
+    /// Hold back messages which aren't urgent for this long, so they go out in one burst
+    pub coalesce: Option<Duration>,
+
+    /// Who answers the pings of the server
+    pub pong_policy: PongPolicy,
+
+    /// Give the connection up when no frame arrived for this long, it's made again with a reconnect policy
+    pub idle_timeout: Option<Duration>,
+
+    /// Ping the server and give the connection up when a pong doesn't arrive in time
+    pub keep_alive: Option<KeepAlive>,
+
+    /// Wait for the close handshake before dropping the stream, [`DEFAULT_CLOSE_TIMEOUT`] if `None`
+    pub close_timeout: Option<Duration>,
+    /// Close the connection once it's this old and make it again right away
+    pub max_age: Option<MaxAge>,
+    /// Hold back the close frame of the server for this long, so the app can still send,
+    /// see [`Event::ServerClosing`]
+    pub close_grace: Option<Duration>,
+    /// What happens with control frames of the server over 125 bytes
+    pub oversized_control: ControlFramePolicy,
+    /// Verify the counter the server puts into every message, anomalies are reported as events
+    pub sequence: Option<SequenceSource>,
+}
+
+/// Messages read in a row by default, low enough that sends stay responsive while the server floods
+pub const DEFAULT_READ_BUDGET: u32 = 32;
+
+/// When protocol errors of the server close the connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProtocolErrorPolicy {
+    /// Errors within `window` which close the connection with 1002
+    pub max_errors: u32,
+    pub window: Duration,
+}
+
+/// How a connection which ended abnormally is made again.
+/// It's not made again after the app closed it, also when that close wasn't clean.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Wait before the first attempt
+    pub initial_delay: Duration,
+    /// Longest wait between two attempts
+    pub max_delay: Duration,
+    /// Factor of the wait after every failed attempt
+    pub multiplier: f64,
+    /// Attempts after every drop, unlimited if `None`
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// The wait before `attempt`, starting at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = self
+            .multiplier
+            .max(1.0)
+            .powi(attempt.saturating_sub(1).min(64) as i32);
+        let delay = self.initial_delay.as_secs_f64() * factor;
+        Duration::from_secs_f64(delay.min(self.max_delay.as_secs_f64()))
+    }
+}
+
+/// How long a connection lives before it's rotated, e.g. for load balancers which only balance new connections
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaxAge {
+    pub age: Duration,
+    /// Up to this much is added at random, so connections made together don't rotate together
+    pub jitter: Duration,
+}
+
+impl MaxAge {
+    /// The age of one connection, `age` plus a random share of `jitter`
+    pub fn pick(&self) -> Duration {
+        let random = RandomState::new().build_hasher().finish();
+        self.age + self.jitter.mul_f64(random as f64 / u64::MAX as f64)
+    }
+}
+
+/// One attempt to make a connection again, before its delay
+#[derive(Debug, Clone)]
+pub struct ReconnectAttempt {
+    /// Starting at 1 after every drop
+    pub attempt: u32,
+    pub delay: Duration,
+}
+
+/// Hand a received data message to `handler`
+fn deliver(handler: &Arc<dyn EventHandler>, message: Message) {
+    match message {
+        Message::Text(text) => handler.handle(Event::Text(text)),
+        Message::Binary(data) => handler.handle(Event::Binary(data)),
+        _ => {}
+    }
+}
+
+/// How long [`Connection::finish_close`] waits for the close handshake by default
+pub const DEFAULT_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long the close frame of a connection which is given up may take to be written, the peer is likely gone
+const GIVE_UP_CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Status code of a close because of protocol errors
+const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+/// Status code of a close frame without one
+const CLOSE_NO_STATUS: u16 = 1005;
+/// Status code reported when the connection ended without a close frame
+const CLOSE_ABNORMAL: u16 = 1006;
+
+/// Who ended a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseInitiator {
+    /// The app closed it, see [`Link::close`]
+    Client,
+    /// The server sent a close frame first
+    Server,
+    /// The stream broke or ended without a close frame
+    TransportError,
+    /// The idle timeout or a keepalive ping ran out and the connection was given up
+    KeepaliveTimeout,
+    /// The connection reached its [`MaxAge`] and was closed to be made again
+    Rotation,
+    /// The device switched networks and the connection was dropped to be made again, see [`Connection::migrate`]
+    NetworkChange,
+}
+
+/// How a connection ended
+#[derive(Debug, Clone)]
+pub struct CloseInfo {
+    /// The close handshake completed and the stream ended afterwards
+    pub was_clean: bool,
+    /// Status code of the peer's close frame, 1005 if it had none and 1006 if there was no close frame
+    pub code: u16,
+    pub reason: String,
+    pub initiator: CloseInitiator,
+    /// The latest error reported while it was open, e.g. why the stream broke
+    pub error: Option<Error>,
+    /// Milliseconds since the unix epoch
+    pub closed_at_ms: f64,
+}
+
+/// Summary of an established connection
+#[derive(Debug, Clone)]
+pub struct OpenInfo {
+    /// `Sec-WebSocket-Protocol` chosen by the server
+    pub protocol: Option<String>,
+    /// Extensions accepted by the server
+    pub extensions: Vec<String>,
+    pub remote_address: Option<SocketAddr>,
+    /// An earlier connect of this connection was open
+    pub reconnect: bool,
+    /// Handshakes it took, 2 after an extension fallback
+    pub attempts: u32,
+    /// The url of the accepted handshake, another one than the configured url after redirects
+    pub url: String,
+    /// Redirects followed on the way
+    pub redirects: u32,
+    /// The proxy the accepted handshake was tunneled through, `None` for a direct connection
+    pub proxy: Option<Proxy>,
+}
+
+/// A redirect of the handshake which was followed
+#[derive(Debug, Clone)]
+pub struct RedirectInfo {
+    pub from: String,
+    pub to: String,
+    pub status: u16,
+    /// Redirects followed so far, including this one
+    pub hops: u32,
+}
+
+/// Where a dial ended up
+struct Dialed {
+    ws_stream: WsStream,
+    response: Response,
+    url: String,
+    redirects: u32,
+    /// The options of the host of `url`
+    tls: TlsOptions,
+    proxy: Option<Proxy>,
+}
+
+/// The url a handshake redirect to `location` leads to, `None` if it can't be followed.
+/// `http` urls are taken as `ws` ones, a redirect never gives up TLS.
+fn redirect_target(from: &Uri, location: &str) -> Option<String> {
+    let target = if let Some((scheme, rest)) = location.split_once("://") {
+        let scheme = match scheme.to_ascii_lowercase().as_str() {
+            "ws" | "http" => "ws",
+            "wss" | "https" => "wss",
+            _ => return None,
+        };
+        format!("{}://{}", scheme, rest)
+    } else if location.starts_with("//") {
+        format!("{}:{}", from.scheme_str()?, location)
+    } else if location.starts_with('/') {
+        format!("{}://{}{}", from.scheme_str()?, from.authority()?, location)
+    } else {
+        return None;
+    };
+    if from.scheme_str() == Some("wss") && !target.starts_with("wss://") {
+        return None;
+    }
+    Some(target)
+}
+
+/// The headers sent to the `target` of a redirect from `from`.
+/// Credentials, e.g. `Authorization`, `Cookie` or a token header, are only sent to the same host.
+fn redirect_headers(
+    headers: Vec<(String, String)>,
+    from: &Uri,
+    target: &str,
+) -> Vec<(String, String)> {
+    let same_host = target.parse::<Uri>().ok().is_some_and(|target| {
+        target
+            .host()
+            .is_some_and(|host| host.eq_ignore_ascii_case(from.host().unwrap_or_default()))
+    });
+    if same_host {
+        return headers;
+    }
+    headers
+        .into_iter()
+        .filter(|(name, _)| !is_secret_header(name))
+        .collect()
+}
+
+/// Whether the server refused the handshake because of the `Sec-WebSocket-Extensions` offer:
+/// a client error which names the header, or an extension, in its headers or body
+fn rejects_extension(e: &tungstenite::Error) -> bool {
+    let tungstenite::Error::Http(response) = e else {
+        return false;
+    };
+    if !response.status().is_client_error() {
+        return false;
+    }
+    response.headers().contains_key(SEC_WEBSOCKET_EXTENSIONS)
+        || response.body().as_deref().is_some_and(|body| {
+            String::from_utf8_lossy(body)
+                .to_ascii_lowercase()
+                .contains("extension")
+        })
+}
+
+/// A websocket client connection to a fixed url
+pub struct Connection {
+    url: String,
+    options: ConnectOptions,
+    link: Arc<Link>,
+    client_key: Mutex<Option<ClientKey>>,
+    /// Sent after the headers of the options, see [`Connection::append_header`]
+    appended_headers: Mutex<Vec<(String, String)>>,
+    last_handshake_request: Mutex<Option<HandshakeRequest>>,
+    /// The answer to the most recent handshake, `None` when that one failed before it
+    last_handshake_response: Mutex<Option<HandshakeResponse>>,
+    last_connect_timings: Mutex<Option<ConnectTimings>>,
+    /// When the running, or latest, connect started
+    connect_started: Mutex<Option<Instant>>,
+    extensions: Mutex<Vec<String>>,
+    last_close: Mutex<Option<CloseInfo>>,
+    tls_info: Mutex<Option<TlsInfo>>,
+    opened: AtomicBool,
+    /// Whether the connection task is running
+    running: watch::Sender<bool>,
+    /// Number of pending connects
+    connecting: watch::Sender<usize>,
+    /// Result of the latest connect, `None` while it's pending, see [`Connection::connect`]
+    connect_outcome: watch::Sender<Option<Result<()>>>,
+    history: ConnectHistory,
+    errors: Arc<ErrorLog>,
+    /// The reconnect policy of the options, or the one of an imported state
+    reconnect: Mutex<Option<ReconnectPolicy>>,
+    aborted: Notify,
+    /// Drops the stream of the running connection, see [`Connection::restart`]
+    stop: Notify,
+    /// Drops the running connection to make it again, see [`Connection::migrate`]
+    migration: Notify,
+    /// Set once the server couldn't negotiate the extension offer, later connects don't offer it
+    extension_refused: AtomicBool,
+    disposed: AtomicBool,
+}
+
+impl Connection {
+    pub fn new(url: String, options: ConnectOptions) -> Self {
+        let reconnect = options.reconnect;
+        let link = Arc::new(Link::default());
+        link.set_send_before_connect(options.send_before_connect);
+        Connection {
+            url,
+            options,
+            link,
+            client_key: Mutex::new(None),
+            appended_headers: Mutex::new(Vec::new()),
+            last_handshake_request: Mutex::new(None),
+            last_handshake_response: Mutex::new(None),
+            last_connect_timings: Mutex::new(None),
+            connect_started: Mutex::new(None),
+            extensions: Mutex::new(Vec::new()),
+            last_close: Mutex::new(None),
+            tls_info: Mutex::new(None),
+            opened: AtomicBool::new(false),
+            running: watch::channel(false).0,
+            connecting: watch::channel(0).0,
+            connect_outcome: watch::channel(None).0,
+            history: ConnectHistory::default(),
+            errors: Arc::default(),
+            reconnect: Mutex::new(reconnect),
+            aborted: Notify::new(),
+            stop: Notify::new(),
+            migration: Notify::new(),
+            extension_refused: AtomicBool::new(false),
+            disposed: AtomicBool::new(false),
+        }
+    }
+
+    pub fn link(&self) -> &Arc<Link> {
+        &self.link
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn keep_alive(&self) -> Option<KeepAlive> {
+        self.options.keep_alive
+    }
+
+    /// Use a client certificate with an external signer on the next connect, `None` removes it
+    pub fn set_client_key(&self, client_key: Option<ClientKey>) {
+        *self
+            .client_key
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = client_key;
+    }
+
+    pub fn last_handshake_request(&self) -> Option<HandshakeRequest> {
+        self.last_handshake_request
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn last_connect_timings(&self) -> Option<ConnectTimings> {
+        self.last_connect_timings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Send another header from the next connect on, after the ones already there
+    pub fn append_header(&self, name: String, value: String) {
+        self.appended_headers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((name, value));
+    }
+
+    fn headers(&self) -> Vec<(String, String)> {
+        let appended = self
+            .appended_headers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.options
+            .headers
+            .iter()
+            .chain(appended.iter())
+            .cloned()
+            .collect()
+    }
+
+    fn request(&self, enable_extension: bool) -> Result<Request> {
+        build_request(
+            &self.url,
+            &self.headers(),
+            enable_extension,
+            self.options.data_saver,
+        )
+    }
+
+    /// The tls options for `host`: the configured ones, or the registered profile for it when none were configured.
+    /// Certificates referenced by URI are fetched from the certificate store, a key of `set_client_key` wins.
+    async fn tls_options(&self, host: &str) -> Result<TlsOptions> {
+        let mut tls = if self.options.tls.is_default() {
+            profile::tls_profile_for(host).unwrap_or_default()
+        } else {
+            self.options.tls.clone()
+        };
+        resolve_certificates(&mut tls).await?;
+        if let Some(client_key) = self
+            .client_key
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+        {
+            tls.client_key = Some(client_key);
+        }
+        Ok(tls)
+    }
+
+    /// Dial, following up to `max_redirects` redirects of the handshake.
+    /// A redirect to another host switches to the tls options of that host.
+    async fn dial(
+        &self,
+        mut request: Request,
+        mut tls: TlsOptions,
+        mut connector: TlsConnector,
+        handler: &Arc<dyn EventHandler>,
+    ) -> std::result::Result<Dialed, DialError> {
+        let mut redirects = 0;
+        loop {
+            let from = request.uri().clone();
+            let offered = request.headers().contains_key(SEC_WEBSOCKET_EXTENSIONS);
+            let response = match self.dial_once(request, connector.clone()).await {
+                Ok((ws_stream, response, proxy)) => {
+                    return Ok(Dialed {
+                        ws_stream,
+                        response,
+                        url: from.to_string(),
+                        redirects,
+                        tls,
+                        proxy,
+                    })
+                }
+                Err(DialError::Handshake(tungstenite::Error::Http(response)))
+                    if redirects < self.options.max_redirects =>
+                {
+                    response
+                }
+                Err(e) => return Err(e),
+            };
+            let status = response.status();
+            let target = match status {
+                StatusCode::MOVED_PERMANENTLY
+                | StatusCode::FOUND
+                | StatusCode::SEE_OTHER
+                | StatusCode::TEMPORARY_REDIRECT
+                | StatusCode::PERMANENT_REDIRECT => response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|location| location.to_str().ok())
+                    .and_then(|location| redirect_target(&from, location)),
+                _ => None,
+            };
+            let Some(target) = target else {
+                return Err(DialError::Handshake(tungstenite::Error::Http(response)));
+            };
+            let headers = redirect_headers(self.headers(), &from, &target);
+            request = build_request(&target, &headers, offered, self.options.data_saver)
+                .map_err(|e| DialError::InvalidUrl(e.message))?;
+            let host = request.uri().host().unwrap_or_default();
+            if !host.eq_ignore_ascii_case(from.host().unwrap_or_default()) {
+                tls = self
+                    .tls_options(host)
+                    .await
+                    .map_err(|e| DialError::Tls(e.message))?;
+                connector = TlsConnector::cached(&tls).map_err(|e| DialError::Tls(e.message))?;
+            }
+            redirects += 1;
+            handler.handle(Event::Redirect(RedirectInfo {
+                from: from.to_string(),
+                to: target,
+                status: status.as_u16(),
+                hops: redirects,
+            }));
+        }
+    }
+
+    /// Dial once and keep the request and timings for the getters
+    async fn dial_once(
+        &self,
+        request: Request,
+        connector: TlsConnector,
+    ) -> std::result::Result<(WsStream, Response, Option<Proxy>), DialError> {
+        let url = request.uri().to_string();
+        self.last_handshake_request
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(HandshakeRequest::from(&request));
+
+        let started_at_ms = now_ms();
+        let start = Instant::now();
+        let mut trace = DialTrace::default();
+        let dial_result = dial(
+            request,
+            connector,
+            &self.options,
+            Gates {
+                replies: Arc::new(PongReplies::new(self.options.pong_policy)),
+                grace: Arc::new(CloseGrace::new(self.options.close_grace)),
+                control: Arc::new(ControlFrames::new(
+                    self.options.oversized_control,
+                    self.options.strict_rfc6455,
+                )),
+            },
+            &self.link,
+            &mut trace,
+        )
+        .await;
+        self.history.push(ConnectAttempt {
+            started_at_ms,
+            url,
+            addresses: trace.addresses,
+            proxy: trace.proxy.clone(),
+            remote_address: dial_result
+                .as_ref()
+                .ok()
+                .and_then(|(ws_stream, _)| peer_addr(transport(ws_stream))),
+            failed_stage: dial_result.as_ref().err().map(DialError::stage),
+            error: dial_result
+                .as_ref()
+                .err()
+                .map(|e| Error::new(e.kind(), e.to_string())),
+            duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+        });
+        self.last_connect_timings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(trace.timings);
+        *self
+            .last_handshake_response
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = match &dial_result {
+            Ok((_, response)) => Some(HandshakeResponse::from(response)),
+            Err(DialError::Handshake(tungstenite::Error::Http(response))) => {
+                Some(HandshakeResponse::from(response))
+            }
+            Err(_) => None,
+        };
+        dial_result.map(|(ws_stream, response)| (ws_stream, response, trace.proxy))
+    }
+
+    /// The latest connect attempts, oldest first, e.g. for a support request
+    pub fn connect_history(&self) -> Vec<ConnectAttempt> {
+        self.history.attempts()
+    }
+
+    /// TLS session of the most recent connection, `None` for `ws://` or before connecting
+    pub fn tls_info(&self) -> Option<TlsInfo> {
+        self.tls_info
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// How the most recent connection ended
+    pub fn last_close(&self) -> Option<CloseInfo> {
+        self.last_close
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// The latest error of this connection, also when it happened before a connection was open
+    pub fn last_error(&self) -> Option<ReportedError> {
+        self.errors.last()
+    }
+
+    /// What's needed to carry on after the process was killed: the reconnect policy, the tag,
+    /// the remembered messages, the data messages which weren't written yet and the id counters.
+    /// The open connection itself can't be kept, [`Connection::import_state`] and a connect make it again.
+    pub fn export_state(&self) -> SessionState {
+        SessionState {
+            url: self.url.clone(),
+            tag: self.link.tag(),
+            reconnect: *self
+                .reconnect
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+            remembered: self.link.remembered(),
+            queued: self.link.queued(),
+            last_message_id: self.link.queue().last_id(),
+            last_remembered_id: self.link.last_remembered_id(),
+            last_envelope_id: last_envelope_id(),
+            exported_at_ms: now_ms(),
+        }
+    }
+
+    /// Carry on with the state an earlier process exported, before connecting.
+    /// Remembered messages are remembered again and the queued ones queued behind those already there,
+    /// with the time since the export taken off their ttl. Ids continue after the exported ones.
+    /// Returns the new ids of the remembered messages, in their order.
+    pub fn import_state(&self, state: SessionState) -> Result<Vec<u64>> {
+        if state.url != self.url {
+            return Err(Error::new(
+                WebSocketError::InvalidState,
+                format!("the state was exported for {}, not {}", state.url, self.url),
+            ));
+        }
+        if !state.tag.is_empty() {
+            self.link.set_tag(state.tag);
+        }
+        if state.reconnect.is_some() {
+            *self
+                .reconnect
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = state.reconnect;
+        }
+        self.link.queue().skip_ids(state.last_message_id);
+        self.link.skip_remembered_ids(state.last_remembered_id);
+        skip_envelope_ids(state.last_envelope_id);
+        let ids = state
+            .remembered
+            .into_iter()
+            .map(|message| self.link.remember(message))
+            .collect();
+        let elapsed = Duration::from_secs_f64((now_ms() - state.exported_at_ms).max(0.0) / 1000.0);
+        let queued = state
+            .queued
+            .into_iter()
+            .filter_map(|(message, mut options)| {
+                if let Some(ttl) = options.ttl {
+                    options.ttl = Some(ttl.checked_sub(elapsed)?);
+                }
+                Some((message, options))
+            })
+            .collect();
+        self.link.queue().restore(queued);
+        Ok(ids)
+    }
+
+    /// A JSON report of what this connection went through, to attach to a bug report: the options,
+    /// the latest connect attempts and errors, the stats and the most recent handshake.
+    /// Credentials are redacted, i.e. secret headers, the values of the query and certificates and keys.
+    #[cfg(feature = "diagnostics")]
+    pub fn diagnostics(&self) -> String {
+        Diagnostics {
+            url: &self.url,
+            options: &self.options,
+            ready_state: format!("{:?}", self.link.state()),
+            conn_state: format!("{:?}", self.link.conn_state()),
+            connects: self.history.attempts(),
+            timings: self.last_connect_timings(),
+            errors: self.errors.recent(),
+            close: self.last_close(),
+            tls: self.tls_info(),
+            extensions: self.extensions(),
+            pending_count: self.link.pending_count(),
+            pending_bytes: self.link.pending_bytes(),
+            write_stats: self.link.write_stats(),
+            request: self.last_handshake_request(),
+            response: self
+                .last_handshake_response
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+            generated_at_ms: now_ms(),
+        }
+        .pack()
+    }
+
+    /// Extensions accepted by the server on the most recent connect, empty when uncompressed
+    pub fn extensions(&self) -> Vec<String> {
+        self.extensions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Whether a connection is open or closing
+    pub fn is_running(&self) -> bool {
+        *self.running.borrow()
+    }
+
+    /// Wait until the running connection is over and return how it ended.
+    /// A pending connect is waited for first, a failed one doesn't change the result.
+    /// Returns right away when none is running, with how the most recent one ended.
+    pub async fn closed(&self) -> Option<CloseInfo> {
+        let mut connecting = self.connecting.subscribe();
+        let _ = connecting.wait_for(|connecting| *connecting == 0).await;
+        let mut running = self.running.subscribe();
+        let _ = running.wait_for(|running| !running).await;
+        self.last_close()
+    }
+
+    /// Cancel the pending connects, whichever stage they are in, and stop reconnecting.
+    /// A running connection is not affected.
+    pub fn abort(&self) {
+        self.aborted.notify_waiters();
+    }
+
+    /// Back in the foreground after [`Link::suspend`]: the held messages are written
+    /// and a keepalive ping checks right away whether the connection survived.
+    /// Returns whether it was open when suspended and was lost meanwhile, so it should be made again.
+    pub fn resume(&self) -> bool {
+        let was_open = self.link.resume();
+        was_open
+            && !self.is_running()
+            && !self.link.close_requested()
+            && *self.connecting.borrow() == 0
+    }
+
+    /// Drop the open connection right away and make it again, e.g. after the device switched from Wi-Fi to cellular,
+    /// instead of waiting until the route of the old network times out. Returns whether one was open.
+    /// The close of the dropped one reports [`CloseInitiator::NetworkChange`], queued messages go out on the new one.
+    pub fn migrate(&self) -> bool {
+        if self.link.state() != ReadyState::Open {
+            return false;
+        }
+        self.migration.notify_waiters();
+        true
+    }
+
+    /// Release the connection for good: pending connects are aborted, reconnecting stops,
+    /// the stream of a running connection is dropped without a close handshake
+    /// and every queued, scheduled and remembered message is discarded.
+    /// The handler is dropped once the connection task is over, later connects fail.
+    pub fn dispose(&self) {
+        self.disposed.store(true, Ordering::Release);
+        self.link.release();
+        self.abort();
+        // Stores a permit when the task isn't waiting for it yet
+        self.stop.notify_one();
+    }
+
+    /// Connect, returning once the connection is open. It keeps running on a task of its own
+    /// until it is closed, see [`Connection::closed`], delivering everything to `handler`.
+    /// Fails with `Aborted` when [`Connection::abort`] is called before. A connect while another one
+    /// is pending joins it and returns its result, one while the previous connection isn't over
+    /// fails with `InvalidState`, so there is never more than one using the link.
+    pub async fn connect(self: &Arc<Self>, handler: Arc<dyn EventHandler>) -> Result<()> {
+        let aborted = self.aborted.notified();
+        let mut pending = false;
+        let idle = self.connecting.send_if_modified(|connecting| {
+            pending = *connecting > 0;
+            let idle = !pending && !self.is_running();
+            if idle {
+                *connecting += 1;
+                self.connect_outcome.send_replace(None);
+            }
+            idle
+        });
+        if pending {
+            let mut outcome = self.connect_outcome.subscribe();
+            let joined = outcome
+                .wait_for(Option::is_some)
+                .await
+                .ok()
+                .and_then(|outcome| outcome.clone());
+            return joined.unwrap_or(Ok(()));
+        }
+        if !idle {
+            let state = match self.link.state() {
+                ReadyState::Open => "open",
+                ReadyState::Closing => "closing",
+                ReadyState::Connecting | ReadyState::Closed => "connecting",
+            };
+            return Err(Error::new(
+                WebSocketError::InvalidState,
+                format!("ws-rs connection is already {}", state),
+            ));
+        }
+        self.link.reset_close_requested();
+        self.link.observe(&handler);
+        let handler: Arc<dyn EventHandler> =
+            Arc::new(ErrorRecorder::new(self.errors.clone(), handler));
+        let result = tokio::select! {
+            result = self.open(handler) => result,
+            _ = aborted => {
+                self.link.set_state(ReadyState::Closed);
+                Err(Error::new(WebSocketError::Aborted, "ws-rs connect aborted"))
+            }
+        };
+        self.connect_outcome.send_replace(Some(result.clone()));
+        self.connecting.send_modify(|connecting| *connecting -= 1);
+        if let Err(e) = &result {
+            self.errors.record(e);
+        }
+        result
+    }
+
+    /// Wait for the close handshake started with [`Link::close`] and the end of the stream,
+    /// so every message queued before the close has been written. When the peer doesn't answer
+    /// within the close timeout the stream is dropped and a `CloseError` returned.
+    pub async fn finish_close(&self) -> Result<Option<CloseInfo>> {
+        let wait = self.options.close_timeout.unwrap_or(DEFAULT_CLOSE_TIMEOUT);
+        match timeout(wait, self.closed()).await {
+            Ok(info) => Ok(info),
+            Err(_) => {
+                self.stop.notify_waiters();
+                self.closed().await;
+                Err(Error::new(
+                    WebSocketError::CloseError,
+                    format!(
+                        "no close handshake within {} ms, the connection was dropped",
+                        wait.as_millis()
+                    ),
+                ))
+            }
+        }
+    }
+
+    /// Close the running connection, if any, and connect again with the same url and options.
+    /// The stream is dropped when the close handshake doesn't finish within the close timeout.
+    pub async fn restart(self: &Arc<Self>, handler: Arc<dyn EventHandler>) -> Result<()> {
+        if self.is_running() {
+            let _ = self.link.close().await;
+            let _ = self.finish_close().await;
+        }
+        self.connect(handler).await
+    }
+
+    /// Make the connection and start its task
+    async fn open(self: &Arc<Self>, handler: Arc<dyn EventHandler>) -> Result<()> {
+        if self.disposed.load(Ordering::Acquire) {
+            return Err(Error::new(
+                WebSocketError::ConnectError,
+                "ws-rs connection is disposed",
+            ));
+        }
+        *self
+            .connect_started
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+        let offer =
+            self.options.enable_extension && !self.extension_refused.load(Ordering::Acquire);
+        let request = self.request(offer)?;
+        let tls = self
+            .tls_options(request.uri().host().unwrap_or_default())
+            .await?;
+        let connector = TlsConnector::cached(&tls)?;
+
+        // Held by the connection task until the connection is over.
+        // Waiting for it is limited by the connect timeout, so `connect` doesn't hang on a full limit.
+        let host = request.uri().host().unwrap_or_default();
+        let slot = match self.options.timeouts.connect {
+            Some(limit) => timeout(limit, limits::acquire(host)).await.map_err(|_| {
+                Error::new(
+                    WebSocketError::ConnectTimeout,
+                    format!("no connection slot free within {} ms", limit.as_millis()),
+                )
+            })??,
+            None => limits::acquire(host).await?,
+        };
+
+        self.link.set_state(ReadyState::Connecting);
+        let offered = request.headers().contains_key(SEC_WEBSOCKET_EXTENSIONS);
+        let mut dial_result = self
+            .dial(request, tls.clone(), connector.clone(), &handler)
+            .await;
+        let mut attempts = 1;
+
+        // Compression is not implemented, so an accepted offer would garble the frames.
+        // It's retried once without the offer, and not offered again, when the server accepted it
+        // or refused the handshake because of it. Other handshake errors are reported as they are.
+        if offered {
+            let fallback = match &mut dial_result {
+                Ok(dialed) => match dialed.response.headers().get(SEC_WEBSOCKET_EXTENSIONS) {
+                    Some(accepted) => {
+                        let reason = format!(
+                            "server accepted unsupported extensions: {}",
+                            accepted.to_str().unwrap_or_default()
+                        );
+                        let _ = dialed.ws_stream.close(None).await;
+                        Some(reason)
+                    }
+                    None => None,
+                },
+                Err(DialError::Handshake(e)) if rejects_extension(e) => {
+                    Some(format!("server refused the extension offer: {}", e))
+                }
+                Err(_) => None,
+            };
+            if let Some(reason) = fallback {
+                self.extension_refused.store(true, Ordering::Release);
+                handler.handle(Event::ExtensionFallback(reason));
+                let request = self.request(false).inspect_err(|_| {
+                    self.link.set_state(ReadyState::Closed);
+                })?;
+                dial_result = self.dial(request, tls, connector, &handler).await;
+                attempts += 1;
+            }
+        }
+
+        let Dialed {
+            ws_stream,
+            response,
+            url,
+            redirects,
+            tls,
+            proxy,
+        } = match dial_result {
+            Ok(dialed) => dialed,
+            Err(e) => {
+                self.link.set_state(ReadyState::Closed);
+                return Err(Error::new(
+                    e.kind(),
+                    format!("ws-rs connection failed: {}", e),
+                ));
+            }
+        };
+
+        *self.tls_info.lock().unwrap_or_else(PoisonError::into_inner) =
+            TlsInfo::from_stream(transport(&ws_stream), &tls);
+
+        let extensions: Vec<String> = response
+            .headers()
+            .get_all(SEC_WEBSOCKET_EXTENSIONS)
+            .iter()
+            .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+            .map(|extension| extension.trim().to_string())
+            .filter(|extension| !extension.is_empty())
+            .collect();
+        *self
+            .extensions
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = extensions.clone();
+
+        let info = OpenInfo {
+            protocol: response
+                .headers()
+                .get(SEC_WEBSOCKET_PROTOCOL)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string),
+            extensions,
+            remote_address: peer_addr(transport(&ws_stream)),
+            reconnect: self.opened.swap(true, Ordering::AcqRel),
+            attempts,
+            url,
+            redirects,
+            proxy,
+        };
+        self.link.set_state(ReadyState::Open);
+        handler.handle(Event::Open(info));
+
+        let mut headers_map = HashMap::new();
+        for (key, value) in response.headers().iter() {
+            headers_map.insert(
+                key.to_string(),
+                value.to_str().unwrap_or_default().to_string(),
+            );
+        }
+        handler.handle(Event::HeaderReceived(headers_map));
+
+        // Before returning, so messages can be sent right away
+        self.link.attach();
+        self.running.send_replace(true);
+        let connection = self.clone();
+        tokio::spawn(async move {
+            let info = connection.run(ws_stream, handler.clone()).await;
+            drop(slot);
+            let rotated = matches!(
+                info.initiator,
+                CloseInitiator::Rotation | CloseInitiator::NetworkChange
+            );
+            if rotated && connection.clone().rotate(handler.clone()).await {
+                return;
+            }
+            let policy = *connection
+                .reconnect
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if let Some(policy) = policy {
+                if (!info.was_clean || rotated)
+                    && !connection.link.close_requested()
+                    && connection.clone().reconnect(policy, handler).await
+                {
+                    // The new connection is running now
+                    return;
+                }
+            }
+            connection.running.send_replace(false);
+        });
+        Ok(())
+    }
+
+    /// Make the connection again with the backoff of `policy`, until it's open, the attempts are used up,
+    /// or it's closed or aborted. Returns whether it's open again. Boxed, as it's called from the task of the connection.
+    fn reconnect(
+        self: Arc<Self>,
+        policy: ReconnectPolicy,
+        handler: Arc<dyn EventHandler>,
+    ) -> BoxFuture<bool> {
+        Box::pin(async move {
+            let mut attempt = 0;
+            while policy
+                .max_attempts
+                .is_none_or(|max_attempts| attempt < max_attempts)
+            {
+                attempt += 1;
+                self.link.set_conn_state(ConnState::Reconnecting);
+                let aborted = self.aborted.notified();
+                let mut close_requested = self.link.subscribe_close_requested();
+                let delay = policy.delay(attempt);
+                handler.handle(Event::Reconnecting(ReconnectAttempt { attempt, delay }));
+                let result = tokio::select! {
+                    result = async {
+                        sleep(delay).await;
+                        self.open(handler.clone()).await
+                    } => result,
+                    _ = aborted => {
+                        self.link.set_state(ReadyState::Closed);
+                        return false;
+                    }
+                    _ = close_requested.wait_for(|requested| *requested) => {
+                        self.link.set_state(ReadyState::Closed);
+                        return false;
+                    }
+                };
+                match result {
+                    Ok(()) => {
+                        handler.handle(Event::Reconnected(attempt));
+                        return true;
+                    }
+                    Err(e) => handler.handle(Event::Error(e)),
+                }
+            }
+            self.link.set_state(ReadyState::Closed);
+            handler.handle(Event::ReconnectFailed(attempt));
+            false
+        })
+    }
+
+    /// Make the connection again right away after it was rotated, until it's closed or aborted.
+    /// Returns whether it's open again. Boxed, as it's called from the task of the connection.
+    fn rotate(self: Arc<Self>, handler: Arc<dyn EventHandler>) -> BoxFuture<bool> {
+        Box::pin(async move {
+            self.link.set_state(ReadyState::Connecting);
+            let aborted = self.aborted.notified();
+            let mut close_requested = self.link.subscribe_close_requested();
+            let opened = tokio::select! {
+                result = self.open(handler.clone()) => match result {
+                    Ok(()) => true,
+                    Err(e) => {
+                        handler.handle(Event::Error(e));
+                        false
+                    }
+                },
+                _ = aborted => false,
+                _ = close_requested.wait_for(|requested| *requested) => false,
+            };
+            if !opened {
+                self.link.set_state(ReadyState::Closed);
+            }
+            opened
+        })
+    }
+
+    /// Report why the connection is given up and send a close frame to the peer, which is likely gone.
+    /// The frame only gets a moment to be written before the stream is dropped.
+    async fn give_up(&self, handler: &Arc<dyn EventHandler>, error: Error, reason: &str) {
+        handler.handle(Event::Error(error));
+        let _ = self
+            .link
+            .send_message(Message::Close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: reason.to_string().into(),
+            })))
+            .await;
+        let _ = self.link.flush(Some(GIVE_UP_CLOSE_TIMEOUT)).await;
+    }
+
+    /// Put the time since the connect started into the timings of the running connection
+    fn time_first(&self, set: impl FnOnce(&mut ConnectTimings, f64)) {
+        let Some(started) = *self
+            .connect_started
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+        else {
+            return;
+        };
+        if let Some(timings) = self
+            .last_connect_timings
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_mut()
+        {
+            set(timings, started.elapsed().as_secs_f64() * 1000.0);
+        }
+    }
+
+    /// Read and write until the connection breaks or is closed, returns how it ended
+    async fn run(&self, ws_stream: WsStream, handler: Arc<dyn EventHandler>) -> CloseInfo {
+        let replies = ws_stream.get_ref().replies().clone();
+        let grace = ws_stream.get_ref().get_ref().grace().clone();
+        let control = ws_stream.get_ref().get_ref().get_ref().frames().clone();
+        let (write, read) = ws_stream.split();
+        // Errors of the handshake don't belong to this connection
+        self.errors.take_current();
+
+        let write_loop = run_writer(
+            write,
+            self.link.clone(),
+            handler.clone(),
+            WriterOptions {
+                data_saver: self.options.data_saver,
+                coalesce: self.options.coalesce,
+            },
+        );
+
+        // The close frame of the peer, if one arrived, and whether the stream ended afterwards
+        let mut close_frame: Option<Option<CloseFrame>> = None;
+        let mut ended = false;
+        // Whether the idle timeout or keepalive gave the connection up
+        let mut gave_up = false;
+        // Whether the max age closed the connection
+        let mut rotated = false;
+        // Whether it was dropped for another network
+        let mut migrated = false;
+        let started = Instant::now();
+        let read_loop = async {
+            let mut read = read;
+            let mut sampler = Sampler::new(self.options.sample);
+            // Protocol errors within the window of the policy, and whether they closed the connection
+            let mut violations: VecDeque<Instant> = VecDeque::new();
+            let mut closing = false;
+            let budget = self
+                .options
+                .read_budget
+                .unwrap_or(DEFAULT_READ_BUDGET)
+                .max(1);
+            let mut read_in_row = 0;
+            let mut last_frame = Instant::now();
+            let keep_alive = self.options.keep_alive;
+            let mut next_ping = keep_alive.map(|keep_alive| last_frame + keep_alive.interval);
+            let mut keepalive_id = 0;
+            // The keepalive ping waiting for its pong, and until when
+            let mut awaiting: Option<(u64, Instant)> = None;
+            // When the connection is rotated, and once it is, until when the close is waited for
+            let mut rotate_by = self.options.max_age.map(|max_age| started + max_age.pick());
+            let mut reading_paused = self.link.subscribe_reading_paused();
+            let mut background = self.link.subscribe_background();
+            let mut power_save = self.link.subscribe_power_save();
+            let mut keepalive_slot = self.link.subscribe_keepalive_slot();
+            // The ping after coming back to the foreground, which data saver doesn't skip
+            let mut resync = false;
+            let mut sequence = self.options.sequence.clone().map(SequenceCheck::new);
+            let (mut received_frame, mut received_message) = (false, false);
+            loop {
+                // Nothing arrives while reading is paused, that mustn't look like a dead connection
+                let paused = *reading_paused.borrow_and_update();
+                // In the background the keepalive is slowed down, a factor of 0 stops it
+                let factor = background
+                    .borrow_and_update()
+                    .map_or(1, |background| background.keepalive_factor);
+                let watched = !paused && factor > 0;
+                // Power save slows it down further, it never stops it
+                let factor = match *power_save.borrow_and_update() {
+                    power_save if power_save.enabled => {
+                        factor.saturating_mul(power_save.keepalive_factor.max(1))
+                    }
+                    _ => factor,
+                };
+                let slot = *keepalive_slot.borrow_and_update();
+                let due = sampler.due();
+                let idle = self
+                    .options
+                    .idle_timeout
+                    .filter(|_| watched)
+                    .map(|idle| last_frame + idle);
+                let ping_at = next_ping
+                    .zip(keep_alive)
+                    .filter(|_| awaiting.is_none() && watched)
+                    .map(|(at, keep_alive)| {
+                        let at = at + keep_alive.interval * (factor - 1);
+                        match slot {
+                            // On a shared timer, except the ping after the foreground which goes out right away
+                            Some(slot) if !resync => {
+                                let tick = slot.align(at, keep_alive.interval);
+                                if tick > Instant::now() {
+                                    tick
+                                } else {
+                                    slot.next(Instant::now(), keep_alive.interval)
+                                }
+                            }
+                            _ => at,
+                        }
+                    });
+                let pong_by = awaiting.filter(|_| watched).map(|(_, by)| by);
+                // A flooding server always has the next message ready, which would starve the writer
+                read_in_row += 1;
+                if read_in_row > budget {
+                    read_in_row = 1;
+                    tokio::task::yield_now().await;
+                }
+                let message_result = tokio::select! {
+                    message_result = read.next(), if !paused => match message_result {
+                        Some(message_result) => message_result,
+                        None => break,
+                    },
+                    _ = reading_paused.changed() => {
+                        // The deadlines start over once it's resumed
+                        last_frame = Instant::now();
+                        if let (Some((id, _)), Some(keep_alive)) = (awaiting, keep_alive) {
+                            awaiting = Some((id, last_frame + keep_alive.timeout));
+                        }
+                        continue;
+                    }
+                    _ = background.changed() => {
+                        // Timers run late while the app is frozen, so the deadlines start over
+                        last_frame = Instant::now();
+                        if let (Some((id, _)), Some(keep_alive)) = (awaiting, keep_alive) {
+                            awaiting = Some((id, last_frame + keep_alive.timeout));
+                        }
+                        // Back in the foreground a ping right away tells whether the connection survived
+                        if background.borrow().is_none() && awaiting.is_none() {
+                            next_ping = keep_alive.map(|_| last_frame);
+                            resync = true;
+                        }
+                        continue;
+                    }
+                    // A new interval applies to the next ping
+                    _ = power_save.changed() => continue,
+                    _ = keepalive_slot.changed() => continue,
+                    _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                        if let Some(message) = sampler.take(Instant::now()) {
+                            deliver(&handler, message);
+                        }
+                        continue;
+                    }
+                    _ = sleep_until(idle.unwrap_or_else(Instant::now)), if idle.is_some() => {
+                        let idle = self.options.idle_timeout.unwrap_or_default();
+                        let error = Error::new(
+                            WebSocketError::IdleTimeout,
+                            format!("no frame received within {} ms", idle.as_millis()),
+                        );
+                        self.give_up(&handler, error, "idle timeout").await;
+                        gave_up = true;
+                        return;
+                    }
+                    _ = sleep_until(ping_at.unwrap_or_else(Instant::now)), if ping_at.is_some() => {
+                        let Some(keep_alive) = keep_alive else {
+                            continue;
+                        };
+                        // Data saver skips the ping while frames keep arriving
+                        if self.options.data_saver
+                            && !resync
+                            && last_frame + keep_alive.interval > Instant::now()
+                        {
+                            next_ping = Some(last_frame + keep_alive.interval);
+                            continue;
+                        }
+                        resync = false;
+                        keepalive_id += 1;
+                        if let Err(e) = self
+                            .link
+                            .send_message(Message::Ping(keepalive_ping(keepalive_id)))
+                            .await
+                        {
+                            handler.handle(Event::Error(e));
+                        }
+                        awaiting = Some((keepalive_id, Instant::now() + keep_alive.timeout));
+                        continue;
+                    }
+                    _ = sleep_until(pong_by.unwrap_or_else(Instant::now)), if pong_by.is_some() => {
+                        let wait = keep_alive.map(|keep_alive| keep_alive.timeout).unwrap_or_default();
+                        let error = Error::new(
+                            WebSocketError::KeepaliveTimeout,
+                            format!(
+                                "keepalive ping {} not answered within {} ms",
+                                keepalive_id,
+                                wait.as_millis()
+                            ),
+                        );
+                        self.give_up(&handler, error, "keepalive timeout").await;
+                        gave_up = true;
+                        return;
+                    }
+                    _ = sleep_until(rotate_by.unwrap_or_else(Instant::now)), if rotate_by.is_some() => {
+                        if rotated {
+                            // The peer didn't answer the close in time
+                            return;
+                        }
+                        rotated = true;
+                        closing = true;
+                        handler.handle(Event::Rotating(started.elapsed()));
+                        self.link.set_state(ReadyState::Closing);
+                        if let Err(e) = self
+                            .link
+                            .send_message(Message::Close(Some(CloseFrame {
+                                code: CloseCode::Normal,
+                                reason: "max connection age".into(),
+                            })))
+                            .await
+                        {
+                            handler.handle(Event::Error(e));
+                        }
+                        let wait = self.options.close_timeout.unwrap_or(DEFAULT_CLOSE_TIMEOUT);
+                        rotate_by = Some(Instant::now() + wait);
+                        continue;
+                    }
+                };
+                if let Ok(message) = &message_result {
+                    last_frame = Instant::now();
+                    if !received_frame {
+                        received_frame = true;
+                        self.time_first(|timings, ms| timings.first_byte_ms = Some(ms));
+                    }
+                    if !received_message && (message.is_text() || message.is_binary()) {
+                        received_message = true;
+                        self.time_first(|timings, ms| timings.first_message_ms = Some(ms));
+                    }
+                }
+                match message_result {
+                    Ok(message) => match message {
+                        Message::Text(_) | Message::Binary(_) => {
+                            if let Some(anomaly) = sequence
+                                .as_mut()
+                                .and_then(|sequence| sequence.check(&message))
+                            {
+                                handler.handle(Event::SequenceAnomaly(anomaly));
+                            }
+                            if let Some(message) = sampler.offer(message, Instant::now()) {
+                                deliver(&handler, message);
+                            }
+                        }
+                        Message::Close(frame) => {
+                            // Only found in strict mode, the code of the frame is 1002 by now
+                            if let Some(violation) = control.take_close_violation() {
+                                handler.handle(Event::Error(violation));
+                                close_frame = Some(frame);
+                                return;
+                            }
+                            // The freshest value isn't lost to the close
+                            if let Some(message) = sampler.take(Instant::now()) {
+                                deliver(&handler, message);
+                            }
+                            close_frame = Some(frame);
+                        }
+                        Message::Ping(ping_message) => {
+                            let pong_message = handler.ping(ping_message).await;
+                            if let Some(pong_message) =
+                                pong_message.filter(|_| replies.policy != PongPolicy::None)
+                            {
+                                if replies.policy == PongPolicy::Handler {
+                                    replies.allow(pong_message.clone());
+                                }
+                                if let Err(e) =
+                                    self.link.send_message(Message::Pong(pong_message)).await
+                                {
+                                    handler.handle(Event::Error(e));
+                                }
+                            }
+                        }
+                        Message::Pong(pong_message) => {
+                            let pong = Pong::new(pong_message);
+                            if let (Some((id, _)), Some(answered)) = (awaiting, pong.keepalive_id) {
+                                if answered >= id {
+                                    awaiting = None;
+                                    next_ping = keep_alive
+                                        .map(|keep_alive| Instant::now() + keep_alive.interval);
+                                }
+                            }
+                            handler.handle(Event::Pong(pong))
+                        }
+                        _ => {} // 忽略其他类型的消息
+                    },
+                    // Errors of the stream are fatal, e.g. a reset or a protocol violation,
+                    // unless the policy tolerates a few protocol errors
+                    Err(e) => {
+                        let violation = matches!(
+                            e,
+                            tungstenite::Error::Protocol(_) | tungstenite::Error::Utf8(_)
+                        );
+                        let error = match e {
+                            tungstenite::Error::Protocol(ProtocolError::ControlFrameTooBig) => {
+                                let oversized = control.take().map_or_else(
+                                    || "an oversized control frame".to_string(),
+                                    |frame| frame.to_string(),
+                                );
+                                Error::new(
+                                    WebSocketError::ProtocolError,
+                                    format!(
+                                        "protocol violation: {}, control frames carry at most 125",
+                                        oversized
+                                    ),
+                                )
+                            }
+                            tungstenite::Error::Protocol(violation)
+                                if self.options.strict_rfc6455 =>
+                            {
+                                Error::new(
+                                    WebSocketError::ProtocolError,
+                                    format!("protocol violation: {}", violation),
+                                )
+                            }
+                            tungstenite::Error::Utf8(_) if self.options.strict_rfc6455 => {
+                                Error::new(
+                                    WebSocketError::ProtocolError,
+                                    "protocol violation: invalid UTF-8 in a text message",
+                                )
+                            }
+                            e => Error::new(WebSocketError::ReceiveError, e.to_string()),
+                        };
+                        handler.handle(Event::Error(error));
+                        let policy = match self.options.protocol_errors {
+                            Some(policy) if violation && !closing => policy,
+                            _ => return,
+                        };
+                        let now = Instant::now();
+                        violations.retain(|at| now.duration_since(*at) < policy.window);
+                        violations.push_back(now);
+                        if violations.len() >= policy.max_errors as usize {
+                            let reason = format!(
+                                "{} protocol errors within {} ms",
+                                violations.len(),
+                                policy.window.as_millis()
+                            );
+                            handler.handle(Event::Error(Error::new(
+                                WebSocketError::ProtocolError,
+                                format!("closing with {}: {}", CLOSE_PROTOCOL_ERROR, reason),
+                            )));
+                            closing = true;
+                            if self
+                                .link
+                                .close_with(CLOSE_PROTOCOL_ERROR, &reason)
+                                .await
+                                .is_err()
+                            {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+            ended = true;
+        };
+
+        // Whether the close frame of the server was held back
+        let mut server_closed = false;
+        let server_closing = async {
+            let mut closing = grace.subscribe();
+            let info = match closing.wait_for(Option::is_some).await {
+                Ok(info) => info.clone(),
+                Err(_) => None,
+            };
+            if let Some(info) = info {
+                server_closed = true;
+                self.link.set_state(ReadyState::Closing);
+                handler.handle(Event::ServerClosing(info));
+                // The close of the app ends the grace period early
+                let _ = self
+                    .link
+                    .subscribe_close_requested()
+                    .wait_for(|requested| *requested)
+                    .await;
+                grace.release();
+            }
+            std::future::pending::<()>().await
+        };
+
+        let suspend_hints = async {
+            loop {
+                self.link.suspend_hinted().await;
+                handler.handle(Event::SuspendHint);
+            }
+        };
+
+        // Truncated control frames are reported, without the policy tungstenite fails on them
+        let truncated_frames = async {
+            if control.policy != ControlFramePolicy::Truncate {
+                return std::future::pending::<()>().await;
+            }
+            loop {
+                let frame = control.next().await;
+                handler.handle(Event::Error(Error::new(
+                    WebSocketError::ProtocolError,
+                    format!("protocol violation: {}, truncated to 125", frame),
+                )));
+            }
+        };
+
+        tokio::select! {
+          _ = read_loop => {},
+          _ = write_loop => {},
+          _ = suspend_hints => {},
+          _ = server_closing => {},
+          _ = truncated_frames => {},
+          _ = self.stop.notified() => {},
+          _ = self.migration.notified() => migrated = true,
+        }
+
+        self.link.detach();
+        self.link.set_state(ReadyState::Closed);
+
+        let initiator = if gave_up {
+            CloseInitiator::KeepaliveTimeout
+        } else if server_closed {
+            CloseInitiator::Server
+        } else if self.link.close_requested() {
+            CloseInitiator::Client
+        } else if migrated {
+            CloseInitiator::NetworkChange
+        } else if rotated {
+            CloseInitiator::Rotation
+        } else if close_frame.is_some() {
+            CloseInitiator::Server
+        } else {
+            CloseInitiator::TransportError
+        };
+        let info = match close_frame {
+            Some(frame) => CloseInfo {
+                was_clean: ended,
+                code: frame
+                    .as_ref()
+                    .map_or(CLOSE_NO_STATUS, |frame| u16::from(frame.code)),
+                reason: frame
+                    .map(|frame| frame.reason.to_string())
+                    .unwrap_or_default(),
+                initiator,
+                error: self.errors.take_current(),
+                closed_at_ms: now_ms(),
+            },
+            None => CloseInfo {
+                was_clean: false,
+                code: CLOSE_ABNORMAL,
+                reason: String::new(),
+                initiator,
+                error: self.errors.take_current(),
+                closed_at_ms: now_ms(),
+            },
+        };
+        self.last_close
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(info.clone());
+        handler.handle(Event::Close(info.clone()));
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redirect(from: &str, location: &str) -> Option<String> {
+        redirect_target(&from.parse::<Uri>().unwrap(), location)
+    }
+
+    #[test]
+    fn redirects_keep_or_upgrade_tls() {
+        assert_eq!(
+            redirect("ws://a.test/chat", "http://b.test/v2").as_deref(),
+            Some("ws://b.test/v2")
+        );
+        assert_eq!(
+            redirect("ws://a.test/chat", "HTTPS://b.test/v2").as_deref(),
+            Some("wss://b.test/v2")
+        );
+        assert_eq!(redirect("wss://a.test/chat", "ws://b.test/v2"), None);
+        assert_eq!(redirect("wss://a.test/chat", "http://b.test/v2"), None);
+        assert_eq!(redirect("ws://a.test/chat", "ftp://b.test/v2"), None);
+    }
+
+    #[test]
+    fn relative_redirects_resolve_against_the_url() {
+        assert_eq!(
+            redirect("wss://a.test:8443/chat", "/v2?x=1").as_deref(),
+            Some("wss://a.test:8443/v2?x=1")
+        );
+        assert_eq!(
+            redirect("wss://a.test/chat", "//b.test/v2").as_deref(),
+            Some("wss://b.test/v2")
+        );
+        assert_eq!(redirect("wss://a.test/chat", "v2"), None);
+    }
+
+    #[test]
+    fn credentials_are_not_sent_to_another_host() {
+        let headers = || {
+            [
+                ("Authorization", "Bearer abc"),
+                ("Cookie", "session=1"),
+                ("Proxy-Authorization", "Basic eDp5"),
+                ("X-Auth-Token", "abc"),
+                ("X-Client", "harmony"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .to_vec()
+        };
+        let from = "wss://a.test/chat".parse::<Uri>().unwrap();
+        assert_eq!(
+            redirect_headers(headers(), &from, "wss://evil.test/chat"),
+            [("X-Client".to_string(), "harmony".to_string())]
+        );
+        assert_eq!(
+            redirect_headers(headers(), &from, "wss://A.test:8443/v2"),
+            headers()
+        );
+    }
+
+    #[test]
+    fn reconnect_delay_grows_up_to_the_limit() {
+        let policy = ReconnectPolicy::default();
+        let delays: Vec<u64> = (1..=7)
+            .map(|attempt| policy.delay(attempt).as_secs())
+            .collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30]);
+        assert_eq!(policy.delay(0), policy.initial_delay);
+        assert_eq!(policy.delay(u32::MAX), policy.max_delay);
+    }
+
+    #[test]
+    fn reconnect_delay_never_shrinks() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_millis(500),
+            multiplier: 0.5,
+            ..ReconnectPolicy::default()
+        };
+        assert_eq!(policy.delay(5), Duration::from_millis(500));
+    }
+}
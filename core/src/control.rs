@@ -0,0 +1,446 @@
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{ready, Context, Poll},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::Notify,
+};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+
+use crate::{
+    error::{Error, WebSocketError},
+    pong::{frame_header, payload},
+};
+
+/// Largest payload of a control frame RFC 6455 allows
+const MAX_CONTROL_PAYLOAD: usize = 125;
+const OPCODE_CLOSE: u8 = 0x8;
+
+/// What happens with control frames of the server over the 125 bytes RFC 6455 allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ControlFramePolicy {
+    /// Fail the connection with a protocol error
+    #[default]
+    Fail,
+    /// Cut the payload to 125 bytes and keep reading, e.g. for known-bad servers.
+    /// The reason of a close frame is cut where it stays valid UTF-8.
+    Truncate,
+}
+
+/// A control frame of the server over the 125 bytes RFC 6455 allows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OversizedFrame {
+    pub opcode: u8,
+    /// Payload length the frame announced
+    pub len: u64,
+}
+
+impl fmt::Display for OversizedFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.opcode {
+            0x8 => write!(f, "close frame of {} bytes", self.len),
+            0x9 => write!(f, "ping frame of {} bytes", self.len),
+            0xA => write!(f, "pong frame of {} bytes", self.len),
+            opcode => write!(f, "control frame 0x{:X} of {} bytes", opcode, self.len),
+        }
+    }
+}
+
+/// The oversized control frames a [`ControlGate`] came across, for the connection task to report
+pub(crate) struct ControlFrames {
+    pub policy: ControlFramePolicy,
+    /// Check the codes of close frames, see [`crate::ConnectOptions::strict_rfc6455`]
+    pub strict: bool,
+    oversized: Mutex<VecDeque<OversizedFrame>>,
+    notify: Notify,
+    /// Code of the last close frame whose code may not be sent
+    invalid_close: Mutex<Option<u16>>,
+}
+
+impl ControlFrames {
+    pub fn new(policy: ControlFramePolicy, strict: bool) -> Self {
+        ControlFrames {
+            policy,
+            strict,
+            oversized: Mutex::default(),
+            notify: Notify::new(),
+            invalid_close: Mutex::default(),
+        }
+    }
+
+    /// Remember `code` of a close frame if it may not be sent.
+    /// tungstenite replaces such a code with 1002 before the frame is read, so it's checked here.
+    fn check_close(&self, code: u16) {
+        if !CloseCode::from(code).is_allowed() {
+            self.invalid_close
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .replace(code);
+        }
+    }
+
+    /// The violation of the close frame which was read last, if its code may not be sent
+    pub fn take_close_violation(&self) -> Option<Error> {
+        let code = self
+            .invalid_close
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()?;
+        Some(Error::new(
+            WebSocketError::ProtocolError,
+            format!("protocol violation: close code {} may not be sent", code),
+        ))
+    }
+
+    fn push(&self, frame: OversizedFrame) {
+        self.oversized
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back(frame);
+        self.notify.notify_one();
+    }
+
+    /// The oldest oversized frame which wasn't reported yet
+    pub fn take(&self) -> Option<OversizedFrame> {
+        self.oversized
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop_front()
+    }
+
+    /// Wait for the next oversized frame
+    pub async fn next(&self) -> OversizedFrame {
+        loop {
+            if let Some(frame) = self.take() {
+                return frame;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
+
+/// An oversized control frame being cut down
+struct Truncating {
+    /// Its header and the payload read so far
+    frame: Vec<u8>,
+    header_len: usize,
+    payload_len: u64,
+}
+
+/// Finds control frames of the server over 125 bytes before tungstenite fails on them,
+/// so they're reported with what they were, and cuts them down with [`ControlFramePolicy::Truncate`].
+/// Incoming bytes are parsed into frames once [`ControlGate::activate`] is called after the handshake.
+pub struct ControlGate<S> {
+    inner: S,
+    frames: Arc<ControlFrames>,
+    /// The http response until the gate is activated
+    handshake: Option<Vec<u8>>,
+    /// Header of the frame being read
+    header: Vec<u8>,
+    /// Payload bytes of the current frame which still pass straight through
+    passing: u64,
+    /// Payload bytes of a truncated frame which are dropped
+    skipping: u64,
+    truncating: Option<Truncating>,
+    /// The code bytes read so far of a close frame whose code is checked
+    close_code: Option<Vec<u8>>,
+    /// Bytes read from `inner` with [`ControlFramePolicy::Truncate`], reused between reads
+    input: Vec<u8>,
+    /// Bytes which passed the gate but weren't read yet, only with [`ControlFramePolicy::Truncate`]
+    output: Vec<u8>,
+}
+
+impl<S> ControlGate<S> {
+    pub(crate) fn new(inner: S, frames: Arc<ControlFrames>) -> Self {
+        ControlGate {
+            inner,
+            frames,
+            handshake: Some(Vec::new()),
+            header: Vec::new(),
+            passing: 0,
+            skipping: 0,
+            truncating: None,
+            close_code: None,
+            input: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    /// Start gating, the bytes read from now on are websocket frames.
+    /// The ones read along with the http response were taken by the protocol already,
+    /// they're only parsed to find where the next frame starts.
+    pub(crate) fn activate(&mut self) {
+        let Some(response) = self.handshake.take() else {
+            return;
+        };
+        let tail = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(&[][..], |end| &response[end + 4..]);
+        self.process(tail, false);
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub(crate) fn frames(&self) -> &Arc<ControlFrames> {
+        &self.frames
+    }
+
+    /// Parse the frames in `bytes`. With `truncate` they're passed to `output` with oversized control frames cut down,
+    /// otherwise nothing is changed and they stay where they were read.
+    fn process(&mut self, bytes: &[u8], truncate: bool) {
+        let mut index = 0;
+        while index < bytes.len() {
+            let rest = &bytes[index..];
+            if self.skipping > 0 {
+                let len = rest
+                    .len()
+                    .min(usize::try_from(self.skipping).unwrap_or(usize::MAX));
+                self.skipping -= len as u64;
+                index += len;
+                continue;
+            }
+            if self.passing > 0 {
+                let len = rest
+                    .len()
+                    .min(usize::try_from(self.passing).unwrap_or(usize::MAX));
+                if truncate {
+                    self.output.extend_from_slice(&rest[..len]);
+                }
+                self.read_close_code(&rest[..len]);
+                self.passing -= len as u64;
+                index += len;
+                continue;
+            }
+            if let Some(truncating) = &mut self.truncating {
+                let wanted = truncating.header_len + MAX_CONTROL_PAYLOAD - truncating.frame.len();
+                let len = rest.len().min(wanted);
+                truncating.frame.extend_from_slice(&rest[..len]);
+                index += len;
+                if len == wanted {
+                    self.truncated();
+                }
+                continue;
+            }
+            self.header.push(rest[0]);
+            index += 1;
+            let Some((header_len, payload_len)) = frame_header(&self.header) else {
+                continue;
+            };
+            let (first, second) = (self.header[0], self.header[1]);
+            if first & 0x08 != 0 && payload_len > MAX_CONTROL_PAYLOAD as u64 {
+                self.frames.push(OversizedFrame {
+                    opcode: first & 0x0F,
+                    len: payload_len,
+                });
+                if truncate {
+                    self.truncating = Some(Truncating {
+                        frame: std::mem::take(&mut self.header),
+                        header_len,
+                        payload_len,
+                    });
+                    continue;
+                }
+            }
+            // Masked frames of the server fail in tungstenite anyway
+            if self.frames.strict
+                && first & 0x0F == OPCODE_CLOSE
+                && second & 0x80 == 0
+                && payload_len >= 2
+            {
+                self.close_code = Some(Vec::with_capacity(2));
+            }
+            if truncate {
+                self.output.extend_from_slice(&self.header);
+            }
+            self.header.clear();
+            self.passing = payload_len;
+        }
+    }
+
+    /// Collect the code at the start of a close frame's payload and check it once both bytes are there
+    fn read_close_code(&mut self, bytes: &[u8]) {
+        let Some(code) = &mut self.close_code else {
+            return;
+        };
+        let len = bytes.len().min(2 - code.len());
+        code.extend_from_slice(&bytes[..len]);
+        if code.len() == 2 {
+            let code = u16::from_be_bytes([code[0], code[1]]);
+            self.close_code = None;
+            self.frames.check_close(code);
+        }
+    }
+
+    /// The first 125 payload bytes of the truncated frame are there, pass them as an unmasked frame
+    fn truncated(&mut self) {
+        let Some(truncating) = self.truncating.take() else {
+            return;
+        };
+        let mut payload = payload(&truncating.frame, truncating.header_len);
+        if truncating.frame[0] & 0x0F == OPCODE_CLOSE {
+            if let (true, Some(code)) = (self.frames.strict, payload.get(..2)) {
+                self.frames
+                    .check_close(u16::from_be_bytes([code[0], code[1]]));
+            }
+            if let Some(reason) = payload.get(2..) {
+                let valid = std::str::from_utf8(reason).map_or_else(|e| e.valid_up_to(), str::len);
+                payload.truncate(2 + valid);
+            }
+        }
+        self.output.push(truncating.frame[0]);
+        self.output.push(payload.len() as u8);
+        self.output.extend_from_slice(&payload);
+        self.skipping = truncating.payload_len - MAX_CONTROL_PAYLOAD as u64;
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ControlGate<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let gate = &mut *self;
+        loop {
+            if !gate.output.is_empty() {
+                let len = gate.output.len().min(buf.remaining());
+                buf.put_slice(&gate.output[..len]);
+                gate.output.drain(..len);
+                return Poll::Ready(Ok(()));
+            }
+            let before = buf.filled().len();
+            ready!(Pin::new(&mut gate.inner).poll_read(cx, buf))?;
+            if let Some(response) = &mut gate.handshake {
+                response.extend_from_slice(&buf.filled()[before..]);
+                return Poll::Ready(Ok(()));
+            }
+            if buf.filled().len() == before {
+                // The end of the stream
+                return Poll::Ready(Ok(()));
+            }
+            if gate.frames.policy != ControlFramePolicy::Truncate {
+                // Nothing is cut down, the frames are parsed where they were read
+                gate.process(&buf.filled()[before..], false);
+                return Poll::Ready(Ok(()));
+            }
+            let mut input = std::mem::take(&mut gate.input);
+            input.clear();
+            input.extend_from_slice(&buf.filled()[before..]);
+            buf.set_filled(before);
+            gate.process(&input, true);
+            gate.input = input;
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ControlGate<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Everything `bytes` hold read through an activated gate, one byte per read
+    async fn read_through(frames: &Arc<ControlFrames>, bytes: &[u8]) -> Vec<u8> {
+        let mut gate = ControlGate::new(bytes, frames.clone());
+        gate.activate();
+        let mut read = Vec::new();
+        loop {
+            let mut byte = [0; 1];
+            let mut buf = ReadBuf::new(&mut byte);
+            std::future::poll_fn(|cx| Pin::new(&mut gate).poll_read(cx, &mut buf))
+                .await
+                .unwrap();
+            if buf.filled().is_empty() {
+                return read;
+            }
+            read.extend_from_slice(buf.filled());
+        }
+    }
+
+    /// A ping of 130 bytes and a text frame after it
+    fn oversized_ping() -> Vec<u8> {
+        let mut bytes = vec![0x89, 126, 0x00, 130];
+        bytes.extend_from_slice(&[b'a'; 130]);
+        bytes.extend_from_slice(&[0x81, 0x01, b'x']);
+        bytes
+    }
+
+    #[tokio::test]
+    async fn failing_leaves_the_bytes_as_they_are() {
+        let frames = Arc::new(ControlFrames::new(ControlFramePolicy::Fail, false));
+        assert_eq!(
+            read_through(&frames, &oversized_ping()).await,
+            oversized_ping()
+        );
+        assert_eq!(
+            frames.take(),
+            Some(OversizedFrame {
+                opcode: 0x9,
+                len: 130
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn truncating_cuts_the_payload_to_125_bytes() {
+        let frames = Arc::new(ControlFrames::new(ControlFramePolicy::Truncate, false));
+        let mut truncated = vec![0x89, 125];
+        truncated.extend_from_slice(&[b'a'; 125]);
+        truncated.extend_from_slice(&[0x81, 0x01, b'x']);
+        assert_eq!(read_through(&frames, &oversized_ping()).await, truncated);
+        assert!(frames.take().is_some());
+    }
+
+    #[tokio::test]
+    async fn strict_mode_reports_close_codes_which_may_not_be_sent() {
+        for (code, frame) in [
+            (1005, [0x88, 0x02, 0x03, 0xED]),
+            (999, [0x88, 0x02, 0x03, 0xE7]),
+        ] {
+            let frames = Arc::new(ControlFrames::new(ControlFramePolicy::Fail, true));
+            assert_eq!(read_through(&frames, &frame).await, frame);
+            let violation = frames.take_close_violation().unwrap();
+            assert_eq!(violation.kind, WebSocketError::ProtocolError);
+            assert_eq!(
+                violation.message,
+                format!("protocol violation: close code {} may not be sent", code)
+            );
+            assert!(frames.take_close_violation().is_none());
+        }
+    }
+
+    #[tokio::test]
+    async fn close_codes_are_only_checked_in_strict_mode() {
+        let frames = Arc::new(ControlFrames::new(ControlFramePolicy::Fail, true));
+        read_through(&frames, &[0x88, 0x02, 0x03, 0xE8]).await;
+        assert!(frames.take_close_violation().is_none());
+
+        let frames = Arc::new(ControlFrames::new(ControlFramePolicy::Fail, false));
+        read_through(&frames, &[0x88, 0x02, 0x03, 0xED]).await;
+        assert!(frames.take_close_violation().is_none());
+    }
+}
@@ -0,0 +1,559 @@
+use std::{
+    fmt,
+    future::Future,
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use futures_util::{stream::FuturesUnordered, StreamExt};
+use socket2::{SockRef, TcpKeepalive};
+use tokio::net::{TcpSocket, TcpStream};
+use tokio_tungstenite::{
+    client_async_with_config,
+    tungstenite::{self, handshake::client::Request, handshake::client::Response},
+    MaybeTlsStream, WebSocketStream,
+};
+
+use crate::{
+    control::{ControlFrames, ControlGate},
+    dns::{self, DnsOptions},
+    grace::{CloseGrace, GraceGate},
+    pong::{PongGate, PongReplies},
+    proxy::{tunnel, Proxy},
+    tls::TlsConnector,
+    ConnState, ConnectOptions, Link, WebSocketError,
+};
+
+pub type WsStream = WebSocketStream<PongGate<GraceGate<ControlGate<MaybeTlsStream<TcpStream>>>>>;
+
+/// The stream under the gates of `ws_stream`
+pub(crate) fn transport(ws_stream: &WsStream) -> &MaybeTlsStream<TcpStream> {
+    ws_stream.get_ref().get_ref().get_ref().get_ref()
+}
+
+/// The shared halves of the gates of a connection
+pub(crate) struct Gates {
+    pub replies: Arc<PongReplies>,
+    pub grace: Arc<CloseGrace>,
+    pub control: Arc<ControlFrames>,
+}
+
+/// Address of the server `stream` is connected to
+pub(crate) fn peer_addr(stream: &MaybeTlsStream<TcpStream>) -> Option<SocketAddr> {
+    match stream {
+        MaybeTlsStream::Plain(tcp) => tcp.peer_addr().ok(),
+        #[cfg(feature = "native-tls")]
+        MaybeTlsStream::NativeTls(tls) => tls.get_ref().get_ref().get_ref().peer_addr().ok(),
+        #[cfg(feature = "rustls")]
+        MaybeTlsStream::Rustls(tls) => tls.get_ref().0.peer_addr().ok(),
+        _ => None,
+    }
+}
+
+/// Duration of every phase of the most recent connect, in milliseconds.
+/// A phase which has not been reached, or is not needed (e.g. TLS for `ws://`), is empty.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectTimings {
+    pub dns_ms: Option<f64>,
+    pub tcp_connect_ms: Option<f64>,
+    pub tls_handshake_ms: Option<f64>,
+    pub upgrade_ms: Option<f64>,
+    pub total_ms: Option<f64>,
+    /// From the start of the connect, including a wait for a connection slot, to the first frame of the server
+    pub first_byte_ms: Option<f64>,
+    /// From the start of the connect to the first text or binary message, when the app is ready in realtime
+    pub first_message_ms: Option<f64>,
+}
+
+/// What a dial went through, also when it failed
+#[derive(Debug, Clone, Default)]
+pub struct DialTrace {
+    pub timings: ConnectTimings,
+    /// Addresses the host resolved to, the ones of the proxy through a proxy
+    pub addresses: Vec<SocketAddr>,
+    /// The proxy which was tunneled through, `None` for a direct connection
+    pub proxy: Option<Proxy>,
+}
+
+/// Limits of the phases of a connect, `None` waits as long as the system does
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DialTimeouts {
+    /// The tcp connect, over all addresses of the host, and separately the wait for a connection slot
+    pub connect: Option<Duration>,
+    /// The proxy tunnel, the tls handshake and the http upgrade
+    pub handshake: Option<Duration>,
+}
+
+/// Where the socket is bound before it connects, e.g. to pin it to Wi-Fi or cellular on a multi-homed device.
+/// Unbound, the kernel picks the route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LocalBind {
+    /// Local ip address, only server addresses of its family are tried
+    pub address: Option<IpAddr>,
+    /// Network interface, e.g. `wlan0`, bound with `SO_BINDTODEVICE`
+    pub interface: Option<String>,
+}
+
+/// Options of the tcp socket, e.g. `TCP_NODELAY` for latency-sensitive clients.
+/// `None` keeps what the system sets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TcpOptions {
+    /// `TCP_NODELAY`, small writes go out at once instead of waiting for Nagle's algorithm
+    pub no_delay: Option<bool>,
+    /// `SO_KEEPALIVE` with this idle time before the first probe, zero turns it off
+    pub keep_alive: Option<Duration>,
+    /// `SO_SNDBUF` in bytes
+    pub send_buffer_size: Option<u32>,
+    /// `SO_RCVBUF` in bytes, set before the connect so the window scale follows it
+    pub recv_buffer_size: Option<u32>,
+}
+
+/// A resolver which doesn't answer within this time counts as a transient failure,
+/// unless the dns options have another limit
+const DNS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait before the next address is tried while an attempt is still pending, as RFC 8305 recommends
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The phase of a connect
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectStage {
+    Url,
+    Dns,
+    Tcp,
+    /// The `CONNECT` tunnel through the proxy
+    Proxy,
+    Tls,
+    /// The http upgrade
+    Upgrade,
+}
+
+pub enum DialError {
+    InvalidUrl(String),
+    /// The socket was refused by the system, usually `ohos.permission.INTERNET` is missing
+    PermissionDenied(io::Error),
+    Dns(io::Error),
+    Tcp(io::Error),
+    Proxy(io::Error),
+    Tls(String),
+    Handshake(tungstenite::Error),
+    ConnectTimeout(Duration),
+    HandshakeTimeout(Duration),
+}
+
+impl DialError {
+    pub fn stage(&self) -> ConnectStage {
+        match self {
+            DialError::InvalidUrl(_) => ConnectStage::Url,
+            DialError::Dns(_) => ConnectStage::Dns,
+            DialError::PermissionDenied(_) | DialError::Tcp(_) | DialError::ConnectTimeout(_) => {
+                ConnectStage::Tcp
+            }
+            DialError::Proxy(_) => ConnectStage::Proxy,
+            DialError::Tls(_) => ConnectStage::Tls,
+            DialError::Handshake(_) | DialError::HandshakeTimeout(_) => ConnectStage::Upgrade,
+        }
+    }
+
+    pub fn kind(&self) -> WebSocketError {
+        match self {
+            DialError::PermissionDenied(_) => WebSocketError::PermissionDenied,
+            DialError::ConnectTimeout(_) => WebSocketError::ConnectTimeout,
+            DialError::HandshakeTimeout(_) => WebSocketError::HandshakeTimeout,
+            _ => WebSocketError::ConnectError,
+        }
+    }
+}
+
+fn is_permission_denied(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::PermissionDenied
+}
+
+impl fmt::Display for DialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialError::InvalidUrl(e) => write!(f, "invalid url: {}", e),
+            DialError::PermissionDenied(e) => write!(
+                f,
+                "{}, please make sure that `ohos.permission.INTERNET` is declared in module.json5",
+                e
+            ),
+            DialError::Dns(e) => write!(f, "dns lookup failed: {}", e),
+            DialError::Tcp(e) => write!(f, "tcp connect failed: {}", e),
+            DialError::Proxy(e) => write!(f, "proxy tunnel failed: {}", e),
+            DialError::Tls(e) => write!(f, "tls handshake failed: {}", e),
+            DialError::Handshake(e) => write!(f, "{}", e),
+            DialError::ConnectTimeout(limit) => {
+                write!(f, "tcp connect timed out after {} ms", limit.as_millis())
+            }
+            DialError::HandshakeTimeout(limit) => {
+                write!(f, "handshake timed out after {} ms", limit.as_millis())
+            }
+        }
+    }
+}
+
+fn is_transient_dns(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::TimedOut {
+        return true;
+    }
+    // getaddrinfo failures only carry the text of `EAI_AGAIN`, which differs between libcs
+    let message = e.to_string();
+    message.contains("Temporary failure") || message.contains("Try again")
+}
+
+async fn lookup(host: &str, port: u16, dns: &DnsOptions) -> Result<Vec<SocketAddr>, DialError> {
+    if let Some(addrs) = dns.overridden(host) {
+        return Ok(addrs.iter().map(|ip| SocketAddr::new(*ip, port)).collect());
+    }
+    let limit = dns.timeout.unwrap_or(DNS_TIMEOUT);
+    let mut attempt = 0;
+    loop {
+        let addrs = async {
+            match dns.resolver {
+                Some(server) if host.parse::<IpAddr>().is_err() => {
+                    dns::query(server, host).await.map(|ips| {
+                        ips.into_iter()
+                            .map(|ip| SocketAddr::new(ip, port))
+                            .collect()
+                    })
+                }
+                _ => tokio::net::lookup_host((host, port))
+                    .await
+                    .map(|addrs| addrs.collect()),
+            }
+        };
+        let result = match tokio::time::timeout(limit, addrs).await {
+            Ok(result) => result,
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "dns lookup timed out",
+            )),
+        };
+        match result {
+            Ok(addrs) => return Ok(addrs),
+            Err(e) if is_permission_denied(&e) => return Err(DialError::PermissionDenied(e)),
+            Err(e) if attempt < dns.retry.retries && is_transient_dns(&e) => {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_millis(dns.retry.delay_ms * attempt as u64))
+                    .await;
+            }
+            Err(e) => return Err(DialError::Dns(e)),
+        }
+    }
+}
+
+/// Addresses alternating between IPv6 and IPv4, starting with the family of the first one (RFC 8305 section 4)
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+    let first_v6 = first.is_ipv6();
+    let (preferred, other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut other = other.into_iter();
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    for addr in preferred {
+        interleaved.push(addr);
+        interleaved.extend(other.next());
+    }
+    interleaved.extend(other);
+    interleaved
+}
+
+/// A tcp connect to `addr` from `local`, with the buffer sizes of `tcp`
+async fn connect_from(
+    addr: SocketAddr,
+    local: &LocalBind,
+    tcp: &TcpOptions,
+) -> io::Result<TcpStream> {
+    if *local == LocalBind::default()
+        && tcp.send_buffer_size.is_none()
+        && tcp.recv_buffer_size.is_none()
+    {
+        return TcpStream::connect(addr).await;
+    }
+    let socket = if addr.is_ipv6() {
+        TcpSocket::new_v6()?
+    } else {
+        TcpSocket::new_v4()?
+    };
+    if let Some(interface) = &local.interface {
+        bind_device(&socket, interface)?;
+    }
+    if let Some(address) = local.address {
+        socket.bind(SocketAddr::new(address, 0))?;
+    }
+    if let Some(size) = tcp.send_buffer_size {
+        socket.set_send_buffer_size(size)?;
+    }
+    if let Some(size) = tcp.recv_buffer_size {
+        socket.set_recv_buffer_size(size)?;
+    }
+    socket.connect(addr).await
+}
+
+/// The options of `tcp` which apply to the connected socket, before any handshake
+fn tune(stream: &TcpStream, tcp: &TcpOptions) -> io::Result<()> {
+    if let Some(no_delay) = tcp.no_delay {
+        stream.set_nodelay(no_delay)?;
+    }
+    match tcp.keep_alive {
+        Some(idle) if idle.is_zero() => SockRef::from(stream).set_keepalive(false)?,
+        Some(idle) => {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?
+        }
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_device(socket: &TcpSocket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes())).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("binding to interface {} failed: {}", interface, e),
+        )
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_device(_socket: &TcpSocket, interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to interface {} isn't supported here", interface),
+    ))
+}
+
+/// Connect to one of `addrs` the Happy Eyeballs way (RFC 8305): the next address is tried as soon as
+/// the attempts so far failed or didn't succeed within [`ATTEMPT_DELAY`], the first connection wins.
+/// A dual-stack host with a broken IPv6 route costs the delay then, not a tcp timeout per address.
+/// Bound to a local address, only the addresses of its family are tried.
+async fn connect_any(
+    host: &str,
+    mut addrs: Vec<SocketAddr>,
+    local: &LocalBind,
+    tcp: &TcpOptions,
+) -> Result<TcpStream, DialError> {
+    let mut last_error =
+        io::Error::new(io::ErrorKind::NotFound, format!("no address for {}", host));
+    if let Some(address) = local.address {
+        addrs.retain(|addr| addr.is_ipv6() == address.is_ipv6());
+        last_error = io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no address for {} reachable from {}", host, address),
+        );
+    }
+    let mut pending = interleave(addrs).into_iter();
+    let mut attempts = FuturesUnordered::new();
+    loop {
+        if attempts.is_empty() {
+            match pending.next() {
+                Some(addr) => attempts.push(connect_from(addr, local, tcp)),
+                None => return Err(DialError::Tcp(last_error)),
+            }
+        }
+        tokio::select! {
+            Some(result) = attempts.next() => match result {
+                Ok(stream) => return Ok(stream),
+                // Every address fails the same way without the permission
+                Err(e) if is_permission_denied(&e) => return Err(DialError::PermissionDenied(e)),
+                Err(e) => {
+                    last_error = e;
+                    attempts.extend(pending.next().map(|addr| connect_from(addr, local, tcp)));
+                }
+            },
+            _ = tokio::time::sleep(ATTEMPT_DELAY), if !pending.as_slice().is_empty() => {
+                attempts.extend(pending.next().map(|addr| connect_from(addr, local, tcp)));
+            }
+        }
+    }
+}
+
+/// Run `future` within `limit`, failing with `timed_out` once it's over
+async fn within<T>(
+    limit: Option<Duration>,
+    timed_out: fn(Duration) -> DialError,
+    future: impl Future<Output = Result<T, DialError>>,
+) -> Result<T, DialError> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, future)
+            .await
+            .unwrap_or_else(|_| Err(timed_out(limit))),
+        None => future.await,
+    }
+}
+
+fn elapsed_ms(start: Instant) -> f64 {
+    start.elapsed().as_secs_f64() * 1000.0
+}
+
+/// Connect to the server of `request` step by step: dns lookup, tcp connect,
+/// tls handshake for `wss://` and finally the http upgrade.
+/// `trace` is filled while going, so it is still useful when dial fails,
+/// the state of `link` follows the tls handshake and the upgrade.
+pub(crate) async fn dial(
+    request: Request,
+    tls_connector: TlsConnector,
+    options: &ConnectOptions,
+    gates: Gates,
+    link: &Link,
+    trace: &mut DialTrace,
+) -> Result<(WsStream, Response), DialError> {
+    let start = Instant::now();
+    link.set_conn_state(ConnState::Connecting);
+
+    let uri = request.uri();
+    let secure = match uri.scheme_str() {
+        Some("wss") => true,
+        Some("ws") => false,
+        _ => {
+            return Err(DialError::InvalidUrl(format!(
+                "unsupported scheme in {}",
+                uri
+            )))
+        }
+    };
+    let host = uri
+        .host()
+        .ok_or_else(|| DialError::InvalidUrl(format!("missing host in {}", uri)))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string();
+    let port = uri.port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+    let proxy = match (&options.proxy, &options.proxy_resolver) {
+        (Some(proxy), _) => Some(proxy.clone()),
+        (None, Some(resolver)) => resolver.resolve(&host),
+        (None, None) => None,
+    };
+    trace.proxy = proxy.clone();
+
+    // Through a proxy only the proxy is looked up and connected to, it resolves the host itself
+    let (peer_host, peer_port) = match &proxy {
+        Some(proxy) => (proxy.host.as_str(), proxy.port),
+        None => (host.as_str(), port),
+    };
+
+    let phase = Instant::now();
+    let addrs = lookup(peer_host, peer_port, &options.dns).await?;
+    trace.addresses = addrs.clone();
+    trace.timings.dns_ms = Some(elapsed_ms(phase));
+
+    let phase = Instant::now();
+    let mut tcp = within(
+        options.timeouts.connect,
+        DialError::ConnectTimeout,
+        connect_any(peer_host, addrs, &options.local, &options.tcp),
+    )
+    .await?;
+    tune(&tcp, &options.tcp).map_err(DialError::Tcp)?;
+    trace.timings.tcp_connect_ms = Some(elapsed_ms(phase));
+
+    within(
+        options.timeouts.handshake,
+        DialError::HandshakeTimeout,
+        async {
+            if let Some(proxy) = &proxy {
+                tunnel(&mut tcp, proxy, &host, port)
+                    .await
+                    .map_err(DialError::Proxy)?;
+            }
+            let stream = if secure {
+                link.set_conn_state(ConnState::TlsHandshake);
+                let phase = Instant::now();
+                let tls = tls_connector
+                    .connect(&host, tcp)
+                    .await
+                    .map_err(DialError::Tls)?;
+                trace.timings.tls_handshake_ms = Some(elapsed_ms(phase));
+                tls
+            } else {
+                MaybeTlsStream::Plain(tcp)
+            };
+
+            link.set_conn_state(ConnState::Upgrading);
+            let phase = Instant::now();
+            let stream = ControlGate::new(stream, gates.control);
+            let stream = PongGate::new(GraceGate::new(stream, gates.grace), gates.replies);
+            let (mut ws_stream, response) = client_async_with_config(request, stream, None)
+                .await
+                .map_err(DialError::Handshake)?;
+            ws_stream.get_mut().activate();
+            ws_stream.get_mut().get_mut().activate();
+            ws_stream.get_mut().get_mut().get_mut().activate();
+            trace.timings.upgrade_ms = Some(elapsed_ms(phase));
+            trace.timings.total_ms = Some(elapsed_ms(start));
+
+            Ok((ws_stream, response))
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(addr: &str) -> SocketAddr {
+        addr.parse().unwrap()
+    }
+
+    #[test]
+    fn interleave_alternates_families() {
+        let addrs = vec![
+            addr("[::1]:443"),
+            addr("[::2]:443"),
+            addr("[::3]:443"),
+            addr("10.0.0.1:443"),
+            addr("10.0.0.2:443"),
+        ];
+        assert_eq!(
+            interleave(addrs),
+            [
+                addr("[::1]:443"),
+                addr("10.0.0.1:443"),
+                addr("[::2]:443"),
+                addr("10.0.0.2:443"),
+                addr("[::3]:443"),
+            ]
+        );
+    }
+
+    #[test]
+    fn interleave_starts_with_the_first_family() {
+        let addrs = vec![
+            addr("10.0.0.1:443"),
+            addr("10.0.0.2:443"),
+            addr("10.0.0.3:443"),
+            addr("[::1]:443"),
+        ];
+        assert_eq!(
+            interleave(addrs),
+            [
+                addr("10.0.0.1:443"),
+                addr("[::1]:443"),
+                addr("10.0.0.2:443"),
+                addr("10.0.0.3:443"),
+            ]
+        );
+        assert!(interleave(Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn overridden_hosts_are_not_looked_up() {
+        let mut dns = DnsOptions::default();
+        dns.overrides.insert(
+            "Api.Example.Test".to_string(),
+            vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+        );
+        let Ok(addrs) = lookup("api.example.test", 8443, &dns).await else {
+            panic!("the override wasn't used");
+        };
+        assert_eq!(addrs, [addr("10.0.0.1:8443"), addr("[::1]:8443")]);
+    }
+}
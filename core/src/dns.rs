@@ -0,0 +1,239 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::net::UdpSocket;
+
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+const RCODE_NXDOMAIN: u8 = 3;
+/// Longest answer without EDNS
+const MAX_ANSWER: usize = 512;
+
+/// Retries of dns lookups which failed for a transient reason,
+/// e.g. while the radio is still waking up
+#[derive(Debug, Clone, Copy)]
+pub struct DnsRetry {
+    pub retries: u32,
+    /// Delay before the first retry, the following ones wait a multiple of it
+    pub delay_ms: u64,
+}
+
+impl Default for DnsRetry {
+    fn default() -> Self {
+        DnsRetry {
+            retries: 2,
+            delay_ms: 200,
+        }
+    }
+}
+
+/// How the host of a connection is resolved, the system resolver is used by default.
+/// The host stays the one of the url for TLS and the `Host` header, only the addresses change.
+#[derive(Debug, Clone, Default)]
+pub struct DnsOptions {
+    /// Addresses a host resolves to without any lookup, e.g. a test server. Hosts match case-insensitively.
+    pub overrides: BTreeMap<String, Vec<IpAddr>>,
+    /// Limit of one lookup, 5 seconds if `None`
+    pub timeout: Option<Duration>,
+    /// A dns server which is asked over udp instead of the system resolver, e.g. for split-horizon dns
+    pub resolver: Option<SocketAddr>,
+    /// Retries of transient failures within one connect
+    pub retry: DnsRetry,
+}
+
+impl DnsOptions {
+    /// The addresses `host` is overridden with
+    pub(crate) fn overridden(&self, host: &str) -> Option<&[IpAddr]> {
+        self.overrides
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(host))
+            .map(|(_, addrs)| addrs.as_slice())
+    }
+}
+
+/// Ask `server` for the A and AAAA records of `host`, IPv4 addresses first.
+/// CNAMEs are left to the server to follow, as recursive resolvers do.
+pub(crate) async fn query(server: SocketAddr, host: &str) -> io::Result<Vec<IpAddr>> {
+    let local: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    let id = RandomState::new().build_hasher().finish() as u16;
+    let mut pending = vec![id, id.wrapping_add(1)];
+    socket.send(&question(id, host, TYPE_A)?).await?;
+    socket
+        .send(&question(id.wrapping_add(1), host, TYPE_AAAA)?)
+        .await?;
+    let mut addrs = Vec::new();
+    let mut buf = [0; MAX_ANSWER];
+    while !pending.is_empty() {
+        let len = socket.recv(&mut buf).await?;
+        // Stray or malformed datagrams are ignored, the timeout of the lookup ends the wait
+        let Some(answer) = parse(&buf[..len]) else {
+            continue;
+        };
+        let Some(index) = pending.iter().position(|id| *id == answer.id) else {
+            continue;
+        };
+        pending.remove(index);
+        match answer.rcode {
+            0 | RCODE_NXDOMAIN => addrs.extend(answer.addrs),
+            rcode => {
+                return Err(io::Error::other(format!(
+                    "dns server {} answered with rcode {}",
+                    server, rcode
+                )))
+            }
+        }
+    }
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} has no address at dns server {}", host, server),
+        ));
+    }
+    addrs.sort_by_key(IpAddr::is_ipv6);
+    Ok(addrs)
+}
+
+/// A recursive query for the records of `kind`
+fn question(id: u16, host: &str, kind: u16) -> io::Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(18 + host.len());
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired
+    packet.extend_from_slice(&[0x01, 0x00]);
+    // One question, no other records
+    packet.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a valid host name", host),
+            ));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&kind.to_be_bytes());
+    packet.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(packet)
+}
+
+struct Answer {
+    id: u16,
+    rcode: u8,
+    addrs: Vec<IpAddr>,
+}
+
+/// `None` unless `packet` is a well-formed response
+fn parse(packet: &[u8]) -> Option<Answer> {
+    let header = packet.get(..12)?;
+    if header[2] & 0x80 == 0 {
+        return None;
+    }
+    let questions = u16::from_be_bytes([header[4], header[5]]);
+    let answers = u16::from_be_bytes([header[6], header[7]]);
+    let mut index = 12;
+    for _ in 0..questions {
+        // The name, its type and class
+        index = skip_name(packet, index)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        index = skip_name(packet, index)?;
+        let record = packet.get(index..index + 10)?;
+        let kind = u16::from_be_bytes([record[0], record[1]]);
+        let len = usize::from(u16::from_be_bytes([record[8], record[9]]));
+        let data = packet.get(index + 10..index + 10 + len)?;
+        match kind {
+            TYPE_A => addrs.push(IpAddr::from(<[u8; 4]>::try_from(data).ok()?)),
+            TYPE_AAAA => addrs.push(IpAddr::from(<[u8; 16]>::try_from(data).ok()?)),
+            _ => {}
+        }
+        index += 10 + len;
+    }
+    Some(Answer {
+        id: u16::from_be_bytes([header[0], header[1]]),
+        rcode: header[3] & 0x0F,
+        addrs,
+    })
+}
+
+/// Where the record after the name at `index` starts
+fn skip_name(packet: &[u8], mut index: usize) -> Option<usize> {
+    loop {
+        let len = *packet.get(index)?;
+        match len {
+            0 => return Some(index + 1),
+            // A pointer to an earlier name ends it
+            len if len & 0xC0 == 0xC0 => return Some(index + 2),
+            len => index += 1 + usize::from(len),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_match_case_insensitively() {
+        let mut dns = DnsOptions::default();
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        dns.overrides.insert("Api.Test".to_string(), vec![ip]);
+        assert_eq!(dns.overridden("api.test"), Some(&[ip][..]));
+        assert_eq!(dns.overridden("API.TEST"), Some(&[ip][..]));
+        assert_eq!(dns.overridden("other.test"), None);
+    }
+
+    #[test]
+    fn question_encodes_labels() {
+        let packet = question(0x1234, "a.example.", TYPE_AAAA).unwrap();
+        assert_eq!(&packet[..2], [0x12, 0x34]);
+        assert_eq!(&packet[12..], b"\x01a\x07example\x00\x00\x1c\x00\x01");
+        assert!(question(1, "a..example", TYPE_A).is_err());
+        assert!(question(1, &"a".repeat(64), TYPE_A).is_err());
+    }
+
+    #[test]
+    fn parse_reads_the_answers() {
+        let mut packet = question(7, "a.test", TYPE_A).unwrap();
+        // A response with two answers
+        packet[2] |= 0x80;
+        packet[7] = 2;
+        // A pointer to the name of the question, then an A and an AAAA record
+        packet.extend_from_slice(&[0xC0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 10, 0, 0, 1]);
+        packet.extend_from_slice(&[0xC0, 12, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+        packet.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        let answer = parse(&packet).unwrap();
+        assert_eq!(answer.id, 7);
+        assert_eq!(answer.rcode, 0);
+        assert_eq!(
+            answer.addrs,
+            [
+                IpAddr::from([10, 0, 0, 1]),
+                IpAddr::from(Ipv6Addr::LOCALHOST)
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_rejects_queries_and_truncated_packets() {
+        let packet = question(7, "a.test", TYPE_A).unwrap();
+        assert!(parse(&packet).is_none());
+        let mut response = packet.clone();
+        response[2] |= 0x80;
+        response[7] = 1;
+        assert!(parse(&response).is_none());
+        assert!(parse(&response[..8]).is_none());
+    }
+}
@@ -0,0 +1,175 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{Map, Value};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Id of the envelope created last in this process
+pub(crate) fn last_envelope_id() -> u64 {
+    NEXT_ID.load(Ordering::Relaxed) - 1
+}
+
+/// Continue the ids of new envelopes after `last_id`, e.g. the last one of an earlier process
+pub(crate) fn skip_envelope_ids(last_id: u64) {
+    NEXT_ID.fetch_max(last_id + 1, Ordering::Relaxed);
+}
+
+/// The envelope most in-house protocols reinvent, as a JSON text message:
+/// `{"type": "chat", "id": "7", "ts": 1700000000000, "payload": {..}}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub kind: String,
+    pub id: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<u64>,
+    pub payload: Value,
+}
+
+impl Envelope {
+    /// A new envelope with the next id of this process and the current time
+    pub fn new(kind: String, payload: Value) -> Self {
+        Envelope {
+            kind,
+            id: Some(NEXT_ID.fetch_add(1, Ordering::Relaxed).to_string()),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|now| now.as_millis() as u64),
+            payload,
+        }
+    }
+
+    pub fn pack(&self) -> String {
+        let mut object = Map::new();
+        object.insert("type".to_string(), Value::String(self.kind.clone()));
+        if let Some(id) = &self.id {
+            object.insert("id".to_string(), Value::String(id.clone()));
+        }
+        if let Some(timestamp) = self.timestamp {
+            object.insert("ts".to_string(), Value::from(timestamp));
+        }
+        object.insert("payload".to_string(), self.payload.clone());
+        Value::Object(object).to_string()
+    }
+
+    /// `None` unless `text` is a JSON object with a string `type`.
+    /// A numeric `id` is taken as its JSON text, a missing `payload` is `null`.
+    pub fn unpack(text: &str) -> Option<Self> {
+        let Ok(Value::Object(mut object)) = serde_json::from_str::<Value>(text) else {
+            return None;
+        };
+        let Some(Value::String(kind)) = object.remove("type") else {
+            return None;
+        };
+        let id = match object.remove("id") {
+            Some(Value::String(id)) => Some(id),
+            Some(Value::Number(id)) => Some(id.to_string()),
+            _ => None,
+        };
+        Some(Envelope {
+            kind,
+            id,
+            timestamp: object.get("ts").and_then(Value::as_u64),
+            payload: object.remove("payload").unwrap_or(Value::Null),
+        })
+    }
+}
+
+/// Handlers of received envelopes by their type
+#[derive(Clone)]
+pub struct EnvelopeRouter<H> {
+    next_id: u64,
+    handlers: Vec<(u64, String, H)>,
+}
+
+impl<H> Default for EnvelopeRouter<H> {
+    fn default() -> Self {
+        EnvelopeRouter {
+            next_id: 0,
+            handlers: Vec::new(),
+        }
+    }
+}
+
+impl<H> EnvelopeRouter<H> {
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+
+    /// Returns the id for [`EnvelopeRouter::remove`]
+    pub fn add(&mut self, kind: String, handler: H) -> u64 {
+        self.next_id += 1;
+        self.handlers.push((self.next_id, kind, handler));
+        self.next_id
+    }
+
+    /// Returns whether the handler existed
+    pub fn remove(&mut self, id: u64) -> bool {
+        let before = self.handlers.len();
+        self.handlers.retain(|(handler_id, _, _)| *handler_id != id);
+        self.handlers.len() != before
+    }
+
+    /// Handlers of envelopes of `kind`, in the order they were added
+    pub fn matching(&self, kind: &str) -> Vec<&H> {
+        self.handlers
+            .iter()
+            .filter(|(_, handler_kind, _)| handler_kind == kind)
+            .map(|(_, _, handler)| handler)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_and_unpack() {
+        let envelope = Envelope {
+            kind: "chat".to_string(),
+            id: Some("7".to_string()),
+            timestamp: Some(1_700_000_000_000),
+            payload: serde_json::json!({"text": "hi"}),
+        };
+        assert_eq!(Envelope::unpack(&envelope.pack()), Some(envelope));
+    }
+
+    #[test]
+    fn unpack_is_lenient_about_id_and_payload() {
+        let envelope = Envelope::unpack(r#"{"type":"ping","id":42}"#).unwrap();
+        assert_eq!(envelope.id.as_deref(), Some("42"));
+        assert_eq!(envelope.payload, Value::Null);
+        assert_eq!(envelope.timestamp, None);
+        for text in [r#"{"type":1}"#, r#"{"payload":{}}"#, r#"["chat"]"#, "chat"] {
+            assert!(Envelope::unpack(text).is_none(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn new_envelopes_get_increasing_ids() {
+        skip_envelope_ids(1000);
+        let first = Envelope::new("a".to_string(), Value::Null);
+        let second = Envelope::new("a".to_string(), Value::Null);
+        let id = |envelope: &Envelope| envelope.id.as_deref().unwrap().parse::<u64>().unwrap();
+        assert!(id(&first) > 1000);
+        assert!(id(&second) > id(&first));
+        assert!(last_envelope_id() >= id(&second));
+    }
+
+    #[test]
+    fn router_matches_kinds() {
+        let mut router = EnvelopeRouter::default();
+        let chat = router.add("chat".to_string(), 1);
+        router.add("chat".to_string(), 2);
+        router.add("presence".to_string(), 3);
+        assert_eq!(router.matching("chat"), [&1, &2]);
+        assert!(router.matching("Chat").is_empty());
+        assert!(router.remove(chat));
+        assert!(!router.remove(chat));
+        assert_eq!(router.matching("chat"), [&2]);
+    }
+}
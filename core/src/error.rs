@@ -0,0 +1,77 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebSocketError {
+    TlsError,
+    HeaderError,
+    ConnectError,
+    SendError,
+    ReceiveError,
+    CloseError,
+    PermissionDenied,
+    TooManyConnections,
+    /// The server violated RFC 6455, only reported in strict mode
+    ProtocolError,
+    /// The tcp connect took longer than the connect timeout
+    ConnectTimeout,
+    /// The tls handshake or http upgrade took longer than the handshake timeout
+    HandshakeTimeout,
+    /// The connect was cancelled with [`crate::Connection::abort`]
+    Aborted,
+    /// Nothing was received within the idle timeout, the connection was given up
+    IdleTimeout,
+    /// A keepalive ping wasn't answered in time, the connection was given up
+    KeepaliveTimeout,
+    /// A connect while the connection is still connecting, open or closing
+    InvalidState,
+    /// A message sent without a connection, see [`crate::SendBeforeConnect::Reject`]
+    NotConnected,
+}
+
+impl AsRef<str> for WebSocketError {
+    fn as_ref(&self) -> &str {
+        match self {
+            WebSocketError::TlsError => "TlsError",
+            WebSocketError::HeaderError => "HeaderError",
+            WebSocketError::ConnectError => "ConnectError",
+            WebSocketError::SendError => "SendError",
+            WebSocketError::ReceiveError => "ReceiveError",
+            WebSocketError::CloseError => "CloseError",
+            WebSocketError::PermissionDenied => "PermissionDenied",
+            WebSocketError::TooManyConnections => "TooManyConnections",
+            WebSocketError::ProtocolError => "ProtocolError",
+            WebSocketError::ConnectTimeout => "ConnectTimeout",
+            WebSocketError::HandshakeTimeout => "HandshakeTimeout",
+            WebSocketError::Aborted => "Aborted",
+            WebSocketError::IdleTimeout => "IdleTimeout",
+            WebSocketError::KeepaliveTimeout => "KeepaliveTimeout",
+            WebSocketError::InvalidState => "InvalidState",
+            WebSocketError::NotConnected => "NotConnected",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Error {
+    pub kind: WebSocketError,
+    pub message: String,
+}
+
+impl Error {
+    pub fn new(kind: WebSocketError, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub type Result<T> = std::result::Result<T, Error>;
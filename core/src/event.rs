@@ -0,0 +1,58 @@
+use std::{collections::HashMap, future::Future, pin::Pin, time::Duration};
+
+use tokio_tungstenite::tungstenite::{Bytes, Utf8Bytes};
+
+use crate::{
+    CloseInfo, ConnState, DroppedMessage, Error, OpenInfo, Pong, ReconnectAttempt, RedirectInfo,
+    SequenceAnomaly, ServerClosing,
+};
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// Every change of the [`ConnState`], also the ones in between the other events
+    StateChange(ConnState),
+    Open(OpenInfo),
+    HeaderReceived(HashMap<String, String>),
+    Text(Utf8Bytes),
+    Binary(Bytes),
+    /// The connection ended, cleanly or not
+    Close(CloseInfo),
+    /// The server is closing, messages can still be sent until the grace period is over
+    ServerClosing(ServerClosing),
+    Pong(Pong),
+    /// Outgoing messages started to be held back by power save or data saver
+    Deferred,
+    /// The handshake was redirected to another url, which is tried next
+    Redirect(RedirectInfo),
+    /// The connection ended abnormally and is made again after the delay
+    Reconnecting(ReconnectAttempt),
+    /// The connection is open again, after this many attempts
+    Reconnected(u32),
+    /// The connection reached its [`crate::MaxAge`] at this age and is closed to be made again right away
+    Rotating(Duration),
+    /// Reconnecting gave up after this many attempts
+    ReconnectFailed(u32),
+    /// The extension offer was dropped and the connection made again without it
+    ExtensionFallback(String),
+    /// The app is about to be frozen, see [`crate::Link::prepare_for_suspend`]
+    SuspendHint,
+    /// A queued message was given up before it was written
+    Dropped(DroppedMessage),
+    /// The counter of a received message didn't follow the previous one, see [`crate::SequenceSource`]
+    SequenceAnomaly(SequenceAnomaly),
+    Error(Error),
+}
+
+/// Receiver of everything happening on a [`crate::Connection`].
+/// It's called from the connection task, so it should hand events off quickly.
+pub trait EventHandler: Send + Sync {
+    fn handle(&self, event: Event);
+
+    /// Called for every received ping. A returned payload is sent back as a pong:
+    /// an extra one with [`crate::PongPolicy::Auto`], the only one with [`crate::PongPolicy::Handler`].
+    fn ping(&self, _payload: Bytes) -> BoxFuture<Option<Bytes>> {
+        Box::pin(async { None })
+    }
+}
@@ -0,0 +1,305 @@
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Bytes;
+
+use crate::JsonPath;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Path(JsonPath),
+    Literal(Value),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    /// The operand is there and neither `null` nor `false`
+    Truthy(Operand),
+    Compare(Operand, Comparison, Operand),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Operand(Operand),
+    Compare(Comparison),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        let (token, len) = match c {
+            '(' => (Token::Open, 1),
+            ')' => (Token::Close, 1),
+            '&' if rest.starts_with("&&") => (Token::And, 2),
+            '|' if rest.starts_with("||") => (Token::Or, 2),
+            '=' if rest.starts_with("==") => (Token::Compare(Comparison::Eq), 2),
+            '!' if rest.starts_with("!=") => (Token::Compare(Comparison::Ne), 2),
+            '!' => (Token::Not, 1),
+            '<' if rest.starts_with("<=") => (Token::Compare(Comparison::Le), 2),
+            '<' => (Token::Compare(Comparison::Lt), 1),
+            '>' if rest.starts_with(">=") => (Token::Compare(Comparison::Ge), 2),
+            '>' => (Token::Compare(Comparison::Gt), 1),
+            '$' => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || "=!<>&|()".contains(c))
+                    .unwrap_or(rest.len());
+                let path = JsonPath::parse(&rest[..len])?;
+                (Token::Operand(Operand::Path(path)), len)
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                let mut chars = rest.char_indices().skip(1);
+                let len = loop {
+                    match chars.next() {
+                        Some((index, quote)) if quote == c => break index + 1,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => value.push(escaped),
+                            None => return Err(format!("unterminated string in: {}", source)),
+                        },
+                        Some((_, other)) => value.push(other),
+                        None => return Err(format!("unterminated string in: {}", source)),
+                    }
+                };
+                (Token::Operand(Operand::Literal(Value::String(value))), len)
+            }
+            _ => {
+                let len = rest
+                    .find(|c: char| c.is_whitespace() || "=!<>&|()".contains(c))
+                    .unwrap_or(rest.len());
+                let word = &rest[..len];
+                let value = match word {
+                    "true" => Value::Bool(true),
+                    "false" => Value::Bool(false),
+                    "null" => Value::Null,
+                    _ => word
+                        .parse::<f64>()
+                        .ok()
+                        .and_then(serde_json::Number::from_f64)
+                        .map(Value::Number)
+                        .ok_or_else(|| format!("unexpected `{}` in: {}", word, source))?,
+                };
+                (Token::Operand(Operand::Literal(value)), len.max(1))
+            }
+        };
+        tokens.push(token);
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: std::iter::Peekable<std::vec::IntoIter<Token>>,
+}
+
+impl Parser<'_> {
+    fn error(&self) -> String {
+        format!("invalid filter expression: {}", self.source)
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.tokens.next_if_eq(&Token::Or).is_some() {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.tokens.next_if_eq(&Token::And).is_some() {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.tokens.next() {
+            Some(Token::Not) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.tokens.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(self.error()),
+                }
+            }
+            Some(Token::Operand(left)) => match self.tokens.peek() {
+                Some(Token::Compare(comparison)) => {
+                    let comparison = *comparison;
+                    self.tokens.next();
+                    match self.tokens.next() {
+                        Some(Token::Operand(right)) => Ok(Expr::Compare(left, comparison, right)),
+                        _ => Err(self.error()),
+                    }
+                }
+                _ => Ok(Expr::Truthy(left)),
+            },
+            _ => Err(self.error()),
+        }
+    }
+}
+
+fn resolve<'a>(operand: &'a Operand, document: &'a Value) -> Option<&'a Value> {
+    match operand {
+        Operand::Path(path) => path.lookup(document),
+        Operand::Literal(value) => Some(value),
+    }
+}
+
+fn compare(left: Option<&Value>, comparison: Comparison, right: Option<&Value>) -> bool {
+    let left = left.unwrap_or(&Value::Null);
+    let right = right.unwrap_or(&Value::Null);
+    let ordering = match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64().partial_cmp(&right.as_f64()),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        (left, right) if left == right => Some(std::cmp::Ordering::Equal),
+        _ => None,
+    };
+    match comparison {
+        Comparison::Eq => ordering.is_some_and(|ordering| ordering.is_eq()),
+        Comparison::Ne => !ordering.is_some_and(|ordering| ordering.is_eq()),
+        Comparison::Lt => ordering.is_some_and(|ordering| ordering.is_lt()),
+        Comparison::Le => ordering.is_some_and(|ordering| ordering.is_le()),
+        Comparison::Gt => ordering.is_some_and(|ordering| ordering.is_gt()),
+        Comparison::Ge => ordering.is_some_and(|ordering| ordering.is_ge()),
+    }
+}
+
+impl Expr {
+    fn eval(&self, document: &Value) -> bool {
+        match self {
+            Expr::Truthy(operand) => !matches!(
+                resolve(operand, document),
+                None | Some(Value::Null) | Some(Value::Bool(false))
+            ),
+            Expr::Compare(left, comparison, right) => compare(
+                resolve(left, document),
+                *comparison,
+                resolve(right, document),
+            ),
+            Expr::Not(expr) => !expr.eval(document),
+            Expr::And(left, right) => left.eval(document) && right.eval(document),
+            Expr::Or(left, right) => left.eval(document) || right.eval(document),
+        }
+    }
+}
+
+/// Which received messages are delivered, checked before they are handed to JS.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MessageFilter {
+    /// Messages starting with these bytes, text or binary
+    Prefix(Bytes),
+    /// Text messages which are JSON and satisfy the expression, see [`MessageFilter::expression`]
+    Expression(Box<Expression>),
+}
+
+/// A compiled filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression(Expr);
+
+impl MessageFilter {
+    /// Compile an expression like `$.type == "trade" && ($.price >= 100 || !$.delayed)`.
+    /// Paths are compared with strings, numbers, `true`, `false` and `null`,
+    /// a path on its own is true when the field is there and neither `null` nor `false`.
+    pub fn expression(source: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            source,
+            tokens: tokenize(source)?.into_iter().peekable(),
+        };
+        let expr = parser.or()?;
+        if parser.tokens.next().is_some() {
+            return Err(parser.error());
+        }
+        Ok(MessageFilter::Expression(Box::new(Expression(expr))))
+    }
+
+    pub fn matches_text(&self, text: &str) -> bool {
+        match self {
+            MessageFilter::Prefix(prefix) => text.as_bytes().starts_with(prefix),
+            MessageFilter::Expression(expression) => serde_json::from_str::<Value>(text)
+                .is_ok_and(|document| expression.0.eval(&document)),
+        }
+    }
+
+    pub fn matches_binary(&self, data: &[u8]) -> bool {
+        match self {
+            MessageFilter::Prefix(prefix) => data.starts_with(prefix),
+            MessageFilter::Expression(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expression_matches_json_text() {
+        let filter =
+            MessageFilter::expression(r#"$.type == "trade" && ($.price >= 100 || !$.delayed)"#)
+                .unwrap();
+        assert!(filter.matches_text(r#"{"type":"trade","price":150}"#));
+        assert!(filter.matches_text(r#"{"type":"trade","price":50,"delayed":false}"#));
+        assert!(!filter.matches_text(r#"{"type":"trade","price":50,"delayed":true}"#));
+        assert!(!filter.matches_text(r#"{"type":"quote","price":150}"#));
+        assert!(!filter.matches_text("not json"));
+    }
+
+    #[test]
+    fn missing_fields_compare_as_null() {
+        let filter = MessageFilter::expression("$.error == null").unwrap();
+        assert!(filter.matches_text("{}"));
+        assert!(!filter.matches_text(r#"{"error":"gone"}"#));
+        let filter = MessageFilter::expression("$.count != 0").unwrap();
+        assert!(filter.matches_text("{}"));
+        assert!(!filter.matches_text(r#"{"count":0}"#));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for source in [
+            "",
+            "$.a ==",
+            "($.a",
+            "$.a $.b",
+            "$.a == 'open",
+            "$.a == maybe",
+            "$..a",
+        ] {
+            assert!(MessageFilter::expression(source).is_err(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn prefix_matches_text_and_binary() {
+        let filter = MessageFilter::Prefix(Bytes::from_static(b"ev:"));
+        assert!(filter.matches_text("ev:login"));
+        assert!(!filter.matches_text("log:ev:"));
+        assert!(filter.matches_binary(b"ev:\x00\x01"));
+        assert!(!filter.matches_binary(b"e"));
+    }
+
+    #[test]
+    fn expression_never_matches_binary() {
+        let filter = MessageFilter::expression("$.type").unwrap();
+        assert!(filter.matches_text(r#"{"type":1}"#));
+        assert!(!filter.matches_binary(br#"{"type":1}"#));
+    }
+}
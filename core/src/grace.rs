@@ -0,0 +1,275 @@
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+
+use futures_util::task::AtomicWaker;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::watch,
+    time::{sleep, Sleep},
+};
+
+use crate::pong::{frame_header, payload};
+
+const OPCODE_CLOSE: u8 = 0x8;
+/// Status code reported for a close frame without one
+const CLOSE_NO_STATUS: u16 = 1005;
+
+/// The server started the close handshake, its close frame is held back for the grace period
+#[derive(Debug, Clone)]
+pub struct ServerClosing {
+    pub code: u16,
+    pub reason: String,
+    /// How long messages can still be sent
+    pub grace: Duration,
+}
+
+/// The grace period of a connection, shared between its [`GraceGate`] and the connection task
+pub(crate) struct CloseGrace {
+    pub period: Option<Duration>,
+    closing: watch::Sender<Option<ServerClosing>>,
+    released: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl CloseGrace {
+    pub fn new(period: Option<Duration>) -> Self {
+        CloseGrace {
+            period,
+            closing: watch::channel(None).0,
+            released: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Changes once the close frame of the server is held back
+    pub fn subscribe(&self) -> watch::Receiver<Option<ServerClosing>> {
+        self.closing.subscribe()
+    }
+
+    /// End the grace period early, e.g. because the app closed as well
+    pub fn release(&self) {
+        self.released.store(true, Ordering::Release);
+        self.waker.wake();
+    }
+}
+
+/// Where the incoming bytes are
+enum GateState {
+    /// Recording the http response, frames start after it
+    Handshake(Vec<u8>),
+    /// Passing frames, until a close frame starts
+    Scanning,
+    /// Collecting the close frame
+    Holding,
+    /// The close frame is complete, waiting for the grace period to end
+    Waiting(Pin<Box<Sleep>>),
+    /// Handing out what was held back
+    Releasing,
+    /// Passing everything untouched
+    Passing,
+}
+
+/// Holds back the close frame of the server for the grace period, so messages can still be sent.
+/// tungstenite answers a close frame right away and refuses to send anything afterwards otherwise.
+/// Incoming bytes are parsed into frames once [`GraceGate::activate`] is called after the handshake,
+/// without a grace period everything passes untouched.
+pub struct GraceGate<S> {
+    inner: S,
+    grace: Arc<CloseGrace>,
+    state: GateState,
+    /// Header of the frame being read
+    header: Vec<u8>,
+    /// Payload bytes of the current frame which still pass straight through
+    passing: u64,
+    /// The close frame and everything read after it
+    held: Vec<u8>,
+}
+
+impl<S> GraceGate<S> {
+    pub(crate) fn new(inner: S, grace: Arc<CloseGrace>) -> Self {
+        let state = match grace.period {
+            Some(_) => GateState::Handshake(Vec::new()),
+            None => GateState::Passing,
+        };
+        GraceGate {
+            inner,
+            grace,
+            state,
+            header: Vec::new(),
+            passing: 0,
+            held: Vec::new(),
+        }
+    }
+
+    /// Start gating, the bytes read from now on are websocket frames.
+    /// The ones read along with the http response were taken by the protocol already,
+    /// they're only parsed to find where the next frame starts.
+    pub(crate) fn activate(&mut self) {
+        let GateState::Handshake(response) =
+            std::mem::replace(&mut self.state, GateState::Scanning)
+        else {
+            return;
+        };
+        let tail = response
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+            .map_or(&[][..], |end| &response[end + 4..]);
+        if self.scan(tail).is_some() {
+            // Too late to hold it back
+            self.state = GateState::Passing;
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub(crate) fn grace(&self) -> &Arc<CloseGrace> {
+        &self.grace
+    }
+
+    /// Follow the frames in `bytes`, returns where a close frame starts
+    fn scan(&mut self, bytes: &[u8]) -> Option<usize> {
+        let mut index = 0;
+        while index < bytes.len() {
+            if self.passing > 0 {
+                let len =
+                    (bytes.len() - index).min(usize::try_from(self.passing).unwrap_or(usize::MAX));
+                self.passing -= len as u64;
+                index += len;
+                continue;
+            }
+            if self.header.is_empty() && bytes[index] & 0x0F == OPCODE_CLOSE {
+                return Some(index);
+            }
+            self.header.push(bytes[index]);
+            index += 1;
+            if let Some((_, payload_len)) = frame_header(&self.header) {
+                self.header.clear();
+                self.passing = payload_len;
+            }
+        }
+        None
+    }
+
+    /// The code and reason of the complete close frame at the start of `held`
+    fn server_closing(&self, header_len: usize, payload_len: usize) -> ServerClosing {
+        let payload = payload(&self.held[..header_len + payload_len], header_len);
+        let (code, reason) = match payload.get(..2) {
+            Some(code) => (
+                u16::from_be_bytes([code[0], code[1]]),
+                String::from_utf8_lossy(&payload[2..]).into_owned(),
+            ),
+            None => (CLOSE_NO_STATUS, String::new()),
+        };
+        ServerClosing {
+            code,
+            reason,
+            grace: self.grace.period.unwrap_or_default(),
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for GraceGate<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let gate = &mut *self;
+        loop {
+            match &mut gate.state {
+                GateState::Handshake(response) => {
+                    let before = buf.filled().len();
+                    ready!(Pin::new(&mut gate.inner).poll_read(cx, buf))?;
+                    response.extend_from_slice(&buf.filled()[before..]);
+                    return Poll::Ready(Ok(()));
+                }
+                GateState::Scanning => {
+                    let before = buf.filled().len();
+                    ready!(Pin::new(&mut gate.inner).poll_read(cx, buf))?;
+                    let Some(at) = gate.scan(&buf.filled()[before..]) else {
+                        return Poll::Ready(Ok(()));
+                    };
+                    gate.held = buf.filled()[before + at..].to_vec();
+                    buf.set_filled(before + at);
+                    gate.state = GateState::Holding;
+                    // Nothing read yet would look like the end of the stream
+                    if at > 0 {
+                        return Poll::Ready(Ok(()));
+                    }
+                }
+                GateState::Holding => {
+                    if let Some((header_len, payload_len)) = frame_header(&gate.held) {
+                        let payload_len = usize::try_from(payload_len).unwrap_or(usize::MAX);
+                        if gate.held.len() >= header_len.saturating_add(payload_len) {
+                            let closing = gate.server_closing(header_len, payload_len);
+                            gate.grace.closing.send_replace(Some(closing));
+                            let period = gate.grace.period.unwrap_or_default();
+                            gate.state = GateState::Waiting(Box::pin(sleep(period)));
+                            continue;
+                        }
+                    }
+                    let mut chunk = [0; 256];
+                    let mut chunk = ReadBuf::new(&mut chunk);
+                    ready!(Pin::new(&mut gate.inner).poll_read(cx, &mut chunk))?;
+                    if chunk.filled().is_empty() {
+                        // The stream ended within the frame, the protocol reports it
+                        gate.state = GateState::Releasing;
+                        continue;
+                    }
+                    gate.held.extend_from_slice(chunk.filled());
+                }
+                GateState::Waiting(timer) => {
+                    gate.grace.waker.register(cx.waker());
+                    if !gate.grace.released.load(Ordering::Acquire) {
+                        ready!(timer.as_mut().poll(cx));
+                    }
+                    gate.state = GateState::Releasing;
+                }
+                GateState::Releasing => {
+                    if gate.held.is_empty() {
+                        gate.state = GateState::Passing;
+                        continue;
+                    }
+                    let len = gate.held.len().min(buf.remaining());
+                    buf.put_slice(&gate.held[..len]);
+                    gate.held.drain(..len);
+                    return Poll::Ready(Ok(()));
+                }
+                GateState::Passing => return Pin::new(&mut gate.inner).poll_read(cx, buf),
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for GraceGate<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
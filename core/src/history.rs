@@ -0,0 +1,157 @@
+use std::{
+    collections::VecDeque,
+    net::SocketAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tokio_tungstenite::tungstenite::Bytes;
+
+use crate::{BoxFuture, ConnectStage, Error, Event, EventHandler, Proxy};
+
+/// Connect attempts kept per connection
+const CONNECT_HISTORY_SIZE: usize = 16;
+/// Errors kept per connection for diagnostics
+const RECENT_ERRORS_SIZE: usize = 16;
+
+/// One handshake of a connect, successful or not.
+/// A connect makes several after an extension fallback or redirects.
+#[derive(Debug, Clone)]
+pub struct ConnectAttempt {
+    /// Milliseconds since the unix epoch
+    pub started_at_ms: f64,
+    pub url: String,
+    /// Addresses the host resolved to
+    pub addresses: Vec<SocketAddr>,
+    /// The address which was connected to
+    pub remote_address: Option<SocketAddr>,
+    /// The proxy which was tunneled through, `None` for a direct connection
+    pub proxy: Option<Proxy>,
+    /// Where it failed, `None` when the handshake succeeded
+    pub failed_stage: Option<ConnectStage>,
+    pub error: Option<Error>,
+    pub duration_ms: f64,
+}
+
+/// The latest connect attempts, oldest first
+#[derive(Default)]
+pub(crate) struct ConnectHistory {
+    attempts: Mutex<VecDeque<ConnectAttempt>>,
+}
+
+impl ConnectHistory {
+    pub fn push(&self, attempt: ConnectAttempt) {
+        let mut attempts = self.attempts.lock().unwrap_or_else(PoisonError::into_inner);
+        if attempts.len() == CONNECT_HISTORY_SIZE {
+            attempts.pop_front();
+        }
+        attempts.push_back(attempt);
+    }
+
+    pub fn attempts(&self) -> Vec<ConnectAttempt> {
+        self.attempts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+/// Milliseconds since the unix epoch
+pub(crate) fn now_ms() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0.0, |since| since.as_secs_f64() * 1000.0)
+}
+
+/// An error reported by a connection, along with when
+#[derive(Debug, Clone)]
+pub struct ReportedError {
+    pub error: Error,
+    /// Milliseconds since the unix epoch
+    pub at_ms: f64,
+}
+
+/// The errors of a connection, kept for the getters
+#[derive(Default)]
+pub(crate) struct ErrorLog {
+    last: Mutex<Option<ReportedError>>,
+    /// The latest ones, oldest first
+    recent: Mutex<VecDeque<ReportedError>>,
+    /// The latest one since the running connection opened
+    current: Mutex<Option<Error>>,
+}
+
+impl ErrorLog {
+    pub fn record(&self, error: &Error) {
+        let reported = ReportedError {
+            error: error.clone(),
+            at_ms: now_ms(),
+        };
+        let mut recent = self.recent.lock().unwrap_or_else(PoisonError::into_inner);
+        if recent.len() == RECENT_ERRORS_SIZE {
+            recent.pop_front();
+        }
+        recent.push_back(reported.clone());
+        drop(recent);
+        self.last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(reported);
+        self.current
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .replace(error.clone());
+    }
+
+    pub fn last(&self) -> Option<ReportedError> {
+        self.last
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    #[cfg(feature = "diagnostics")]
+    pub fn recent(&self) -> Vec<ReportedError> {
+        self.recent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    /// The latest error since the previous call
+    pub fn take_current(&self) -> Option<Error> {
+        self.current
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take()
+    }
+}
+
+/// Keeps the errors passed to `inner` in the [`ErrorLog`]
+pub(crate) struct ErrorRecorder {
+    log: Arc<ErrorLog>,
+    inner: Arc<dyn EventHandler>,
+}
+
+impl ErrorRecorder {
+    pub fn new(log: Arc<ErrorLog>, inner: Arc<dyn EventHandler>) -> Self {
+        ErrorRecorder { log, inner }
+    }
+}
+
+impl EventHandler for ErrorRecorder {
+    fn handle(&self, event: Event) {
+        if let Event::Error(error) = &event {
+            self.log.record(error);
+        }
+        self.inner.handle(event);
+    }
+
+    fn ping(&self, payload: Bytes) -> BoxFuture<Option<Bytes>> {
+        self.inner.ping(payload)
+    }
+}
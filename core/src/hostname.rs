@@ -0,0 +1,147 @@
+/// How wildcard names in server certificates are matched
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WildcardPolicy {
+    /// `*.example.com` matches exactly one label, as in RFC 6125
+    #[default]
+    Standard,
+    /// Wildcard names never match, the host must be listed as it is
+    Disallow,
+}
+
+/// Which names of the server certificate are accepted for the host
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostnamePolicy {
+    pub wildcard: WildcardPolicy,
+    /// Names accepted in place of the host, e.g. the internal name of a device.
+    /// The certificate chain is still verified.
+    pub accepted_names: Vec<String>,
+}
+
+impl HostnamePolicy {
+    /// The verification of the TLS backend is enough
+    pub(crate) fn is_default(&self) -> bool {
+        *self == HostnamePolicy::default()
+    }
+}
+
+#[cfg(feature = "rustls")]
+pub(crate) mod rustls_impl {
+    use std::{net::IpAddr, sync::Arc};
+
+    use rustls::{
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            WebPkiServerVerifier,
+        },
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        CertificateError, DigitallySignedStruct, SignatureScheme,
+    };
+    use x509_parser::extensions::GeneralName;
+
+    use super::{HostnamePolicy, WildcardPolicy};
+
+    fn matches_dns(wildcard: WildcardPolicy, pattern: &str, name: &str) -> bool {
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        let name = name.trim_end_matches('.').to_ascii_lowercase();
+        if pattern == name {
+            return true;
+        }
+        match (wildcard, pattern.strip_prefix("*.")) {
+            (WildcardPolicy::Standard, Some(suffix)) => name
+                .split_once('.')
+                .is_some_and(|(label, rest)| !label.is_empty() && rest == suffix),
+            _ => false,
+        }
+    }
+
+    /// Applies a [`HostnamePolicy`] on top of the webpki verification
+    #[derive(Debug)]
+    pub struct PolicyVerifier {
+        inner: Arc<WebPkiServerVerifier>,
+        policy: HostnamePolicy,
+    }
+
+    impl PolicyVerifier {
+        pub fn new(inner: Arc<WebPkiServerVerifier>, policy: HostnamePolicy) -> Self {
+            PolicyVerifier { inner, policy }
+        }
+
+        /// The first of the host and the accepted names which the certificate is valid for
+        fn matching_name(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            server_name: &ServerName<'_>,
+        ) -> Option<ServerName<'static>> {
+            let (_, cert) = x509_parser::parse_x509_certificate(end_entity).ok()?;
+            let alt_names = cert.subject_alternative_name().ok()??;
+            let alt_names = &alt_names.value.general_names;
+
+            let candidates = std::iter::once(server_name.to_owned()).chain(
+                self.policy
+                    .accepted_names
+                    .iter()
+                    .filter_map(|name| ServerName::try_from(name.clone()).ok()),
+            );
+            for candidate in candidates {
+                let found = alt_names
+                    .iter()
+                    .any(|alt_name| match (alt_name, &candidate) {
+                        (GeneralName::DNSName(pattern), ServerName::DnsName(name)) => {
+                            matches_dns(self.policy.wildcard, pattern, name.as_ref())
+                        }
+                        (GeneralName::IPAddress(bytes), ServerName::IpAddress(ip)) => {
+                            match IpAddr::from(*ip) {
+                                IpAddr::V4(ip) => ip.octets().as_slice() == *bytes,
+                                IpAddr::V6(ip) => ip.octets().as_slice() == *bytes,
+                            }
+                        }
+                        _ => false,
+                    });
+                if found {
+                    return Some(candidate);
+                }
+            }
+            None
+        }
+    }
+
+    impl ServerCertVerifier for PolicyVerifier {
+        fn verify_server_cert(
+            &self,
+            end_entity: &CertificateDer<'_>,
+            intermediates: &[CertificateDer<'_>],
+            server_name: &ServerName<'_>,
+            ocsp_response: &[u8],
+            now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            let name = self.matching_name(end_entity, server_name).ok_or(
+                rustls::Error::InvalidCertificate(CertificateError::NotValidForName),
+            )?;
+            // The chain, and the name once more with the usual rules
+            self.inner
+                .verify_server_cert(end_entity, intermediates, &name, ocsp_response, now)
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            self.inner.verify_tls12_signature(message, cert, dss)
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            self.inner.verify_tls13_signature(message, cert, dss)
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            self.inner.supported_verify_schemes()
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Bytes;
+
+/// Start of the payload of keepalive pings, followed by the decimal id
+const KEEPALIVE_PREFIX: &[u8] = b"harmony-ws-keepalive:";
+
+/// Pings sent by the connection itself, to find out that it's dead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeepAlive {
+    /// Time between pings. With data saver a ping is only sent when nothing was received for this long.
+    pub interval: Duration,
+    /// The pong must arrive within this time, otherwise the connection is given up
+    pub timeout: Duration,
+}
+
+/// A place on a timer shared by several connections, so their pings go out in one radio wakeup.
+/// The ticks are at `epoch + offset` and every keepalive interval after it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeepaliveSlot {
+    pub epoch: Instant,
+    /// Keeps the connections of the timer from pinging all at once
+    pub offset: Duration,
+}
+
+impl KeepaliveSlot {
+    /// The latest tick at or before `at`, for ticks `interval` apart
+    pub fn align(&self, at: Instant, interval: Duration) -> Instant {
+        let start = self.epoch + self.offset;
+        if at <= start || interval.is_zero() {
+            return start;
+        }
+        let ticks = (at - start).as_nanos() / interval.as_nanos();
+        start + interval * ticks as u32
+    }
+
+    /// The first tick after `now`
+    pub fn next(&self, now: Instant, interval: Duration) -> Instant {
+        let tick = self.align(now, interval);
+        if tick > now {
+            tick
+        } else {
+            tick + interval
+        }
+    }
+}
+
+/// Payload of the keepalive ping with `id`, which the server echoes in its pong
+pub fn keepalive_ping(id: u64) -> Bytes {
+    let mut payload = KEEPALIVE_PREFIX.to_vec();
+    payload.extend_from_slice(id.to_string().as_bytes());
+    Bytes::from(payload)
+}
+
+/// A received pong
+#[derive(Debug, Clone)]
+pub struct Pong {
+    pub payload: Bytes,
+    /// The id of the keepalive ping this answers, `None` for pings of the app
+    pub keepalive_id: Option<u64>,
+}
+
+impl Pong {
+    pub fn new(payload: Bytes) -> Self {
+        let keepalive_id = payload
+            .strip_prefix(KEEPALIVE_PREFIX)
+            .and_then(|id| std::str::from_utf8(id).ok())
+            .and_then(|id| id.parse().ok());
+        Pong {
+            payload,
+            keepalive_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pong_of_keepalive_ping() {
+        assert_eq!(Pong::new(keepalive_ping(42)).keepalive_id, Some(42));
+        assert_eq!(Pong::new(Bytes::from_static(b"app")).keepalive_id, None);
+    }
+
+    #[test]
+    fn slot_aligns_to_its_ticks() {
+        let epoch = Instant::now();
+        let slot = KeepaliveSlot {
+            epoch,
+            offset: Duration::from_millis(100),
+        };
+        let interval = Duration::from_secs(30);
+        let first = epoch + Duration::from_millis(100);
+        assert_eq!(slot.align(epoch, interval), first);
+        assert_eq!(slot.align(first + Duration::from_secs(29), interval), first);
+        assert_eq!(
+            slot.align(first + Duration::from_secs(61), interval),
+            first + Duration::from_secs(60)
+        );
+        assert_eq!(slot.next(first, interval), first + interval);
+        assert_eq!(
+            slot.next(first + Duration::from_secs(1), interval),
+            first + interval
+        );
+    }
+}
@@ -0,0 +1,83 @@
+//! Connection engine of `@ohos-rs/websocket`, free of any napi binding.
+
+mod assembly;
+#[cfg(feature = "diagnostics")]
+mod benchmark;
+mod certstore;
+#[cfg(feature = "diagnostics")]
+mod conformance;
+mod connection;
+mod control;
+mod dial;
+mod dns;
+mod envelope;
+mod error;
+mod event;
+mod filter;
+mod grace;
+mod history;
+mod hostname;
+mod keepalive;
+mod limits;
+mod link;
+mod pong;
+mod power;
+mod profile;
+mod proxy;
+mod queue;
+#[cfg(feature = "diagnostics")]
+mod report;
+mod request;
+mod route;
+mod sample;
+mod sequence;
+mod shared;
+mod signer;
+mod snapshot;
+mod state;
+mod tls;
+mod writer;
+
+pub use assembly::{AssemblyError, Framing, TextAssembler, DEFAULT_MAX_DOCUMENT};
+#[cfg(feature = "diagnostics")]
+pub use benchmark::{run_benchmark, BenchmarkOptions, BenchmarkReport};
+pub use certstore::{set_certificate_store, CertificateStore, StoredCredential};
+#[cfg(feature = "diagnostics")]
+pub use conformance::{run_conformance_suite, CaseResult, ConformanceOptions};
+pub use connection::{
+    CloseInfo, CloseInitiator, ConnectOptions, Connection, MaxAge, OpenInfo, ProtocolErrorPolicy,
+    ReconnectAttempt, ReconnectPolicy, RedirectInfo, DEFAULT_CLOSE_TIMEOUT, DEFAULT_READ_BUDGET,
+};
+pub use control::{ControlFramePolicy, OversizedFrame};
+pub use dial::{ConnectStage, ConnectTimings, DialTimeouts, LocalBind, TcpOptions};
+pub use dns::{DnsOptions, DnsRetry};
+pub use envelope::{Envelope, EnvelopeRouter};
+pub use error::{Error, Result, WebSocketError};
+pub use event::{BoxFuture, Event, EventHandler};
+pub use filter::{Expression, MessageFilter};
+pub use grace::ServerClosing;
+pub use history::{ConnectAttempt, ReportedError};
+pub use hostname::{HostnamePolicy, WildcardPolicy};
+pub use keepalive::{keepalive_ping, KeepAlive, KeepaliveSlot, Pong};
+pub use limits::{connection_limits, set_connection_limits, ConnectionLimits};
+pub use link::{
+    DropReason, DroppedMessage, Link, SendBeforeConnect, SendOptions, SuspendOptions, TrySend,
+    WriteStats, CLOSE_GOING_AWAY,
+};
+pub use pong::PongPolicy;
+pub use power::{Background, PowerSave};
+pub use profile::{register_tls_profile, unregister_tls_profile, TlsProfile};
+pub use proxy::{Proxy, ResolveProxy};
+pub use request::{HandshakeRequest, HandshakeResponse};
+pub use route::{JsonPath, LatestCache, RouteValue, Router};
+pub use sample::SampleOptions;
+pub use sequence::{SequenceAnomaly, SequenceAnomalyKind, SequenceSource};
+pub use shared::{SharedConnection, SharedConsumer};
+pub use signer::{block_on_signature, ClientKey, ExternalSigner, SignScheme};
+pub use snapshot::SessionState;
+pub use state::{ConnState, ReadyState};
+pub use tls::{
+    Pkcs12Bundle, RevocationMode, TlsBackend, TlsConnector, TlsInfo, TlsOptions, TlsTrust,
+    TlsVersion,
+};
+pub use tokio_tungstenite::tungstenite::{protocol::Message, Bytes, Utf8Bytes};
@@ -0,0 +1,122 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock, PoisonError},
+};
+
+use tokio::sync::Notify;
+
+use crate::{Error, Result, WebSocketError};
+
+/// Caps on the number of connections of the whole process, `None` means unlimited
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConnectionLimits {
+    pub max_total: Option<usize>,
+    pub max_per_host: Option<usize>,
+
+    /// Wait until a slot becomes free instead of failing with `TooManyConnections`,
+    /// at most for the connect timeout of the connection
+    pub wait_for_slot: bool,
+}
+
+#[derive(Default)]
+struct State {
+    limits: ConnectionLimits,
+    total: usize,
+    per_host: HashMap<String, usize>,
+}
+
+#[derive(Default)]
+struct Registry {
+    state: Mutex<State>,
+    released: Notify,
+}
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(Registry::default)
+}
+
+/// Apply new limits, connections which are already open are kept
+pub fn set_connection_limits(limits: ConnectionLimits) {
+    let registry = registry();
+    registry
+        .state
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .limits = limits;
+    // Raised limits may let waiting connections in
+    registry.released.notify_waiters();
+}
+
+pub fn connection_limits() -> ConnectionLimits {
+    registry()
+        .state
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .limits
+}
+
+/// A counted connection, released on drop
+pub(crate) struct Slot {
+    host: String,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let registry = registry();
+        {
+            let mut state = registry
+                .state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            state.total -= 1;
+            if let Some(count) = state.per_host.get_mut(&self.host) {
+                *count -= 1;
+                if *count == 0 {
+                    state.per_host.remove(&self.host);
+                }
+            }
+        }
+        registry.released.notify_waiters();
+    }
+}
+
+/// Take a slot for a connection to `host`
+pub(crate) async fn acquire(host: &str) -> Result<Slot> {
+    let registry = registry();
+    let host = host.to_ascii_lowercase();
+    loop {
+        // Created before checking, so a release in between is not missed
+        let released = registry.released.notified();
+        {
+            let mut state = registry
+                .state
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let host_count = state.per_host.get(&host).copied().unwrap_or(0);
+            let rejected = match (state.limits.max_total, state.limits.max_per_host) {
+                (Some(max), _) if state.total >= max => Some(format!(
+                    "too many connections: {} of {} open",
+                    state.total, max
+                )),
+                (_, Some(max)) if host_count >= max => Some(format!(
+                    "too many connections to {}: {} of {} open",
+                    host, host_count, max
+                )),
+                _ => None,
+            };
+            match rejected {
+                None => {
+                    state.total += 1;
+                    *state.per_host.entry(host.clone()).or_insert(0) += 1;
+                    return Ok(Slot { host });
+                }
+                Some(message) if !state.limits.wait_for_slot => {
+                    return Err(Error::new(WebSocketError::TooManyConnections, message));
+                }
+                Some(_) => {}
+            }
+        }
+        released.await;
+    }
+}
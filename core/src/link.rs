@@ -0,0 +1,738 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, PoisonError, RwLock, Weak,
+    },
+    time::Duration,
+};
+
+use tokio::{
+    sync::{watch, Notify},
+    time::{timeout, Instant},
+};
+use tokio_tungstenite::tungstenite::{
+    protocol::{
+        frame::{coding::CloseCode, CloseFrame},
+        Message,
+    },
+    Bytes,
+};
+
+use crate::{
+    queue::{Payload, Queue},
+    Background, ConnState, Error, Event, EventHandler, KeepaliveSlot, PowerSave, ReadyState,
+    Result, WebSocketError,
+};
+
+#[derive(Debug, Clone)]
+pub struct SendOptions {
+    /// Urgent messages are written right away, others may be deferred by power save
+    pub urgent: bool,
+
+    /// How often a failed write of the message is retried, also on the next connection
+    pub retries: u32,
+
+    /// Free-form label, e.g. to clear the queued messages of one upload
+    pub tag: Option<String>,
+
+    /// Drop the message when it couldn't be written within this time
+    pub ttl: Option<Duration>,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        SendOptions {
+            urgent: true,
+            retries: 3,
+            tag: None,
+            ttl: None,
+        }
+    }
+}
+
+/// Why a message was dropped before it was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Its `ttl` passed
+    Expired,
+    /// Writing it failed for good, the error is reported as well
+    WriteFailed,
+}
+
+#[derive(Debug, Clone)]
+pub struct DroppedMessage {
+    pub id: u64,
+    pub tag: Option<String>,
+    pub reason: DropReason,
+}
+
+/// Status code of a close frame for a client which goes away
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+
+/// What [`Link::prepare_for_suspend`] does before the app is frozen
+#[derive(Debug, Clone)]
+pub struct SuspendOptions {
+    /// Sent first, so the server can switch to push notifications
+    pub going_away_message: Option<String>,
+    /// Longest wait for the queue to be written
+    pub flush_timeout: Duration,
+    /// Close with 1001 afterwards
+    pub close: bool,
+}
+
+impl Default for SuspendOptions {
+    fn default() -> Self {
+        SuspendOptions {
+            going_away_message: None,
+            flush_timeout: Duration::from_secs(3),
+            close: false,
+        }
+    }
+}
+
+/// How messages were batched by the writer, over all connections of a link
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriteStats {
+    /// Writes followed by one flush
+    pub batches: u64,
+    /// Messages written in them
+    pub messages: u64,
+    /// Most messages written in one batch
+    pub largest_batch: u64,
+}
+
+/// What a data message sent while there's no connection does, also between the attempts of a reconnect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SendBeforeConnect {
+    /// It's dropped, the send succeeds without an id
+    #[default]
+    Drop,
+    /// The send fails with `NotConnected`
+    Reject,
+    /// It's queued and written once connected, unless its ttl runs out first
+    Queue,
+}
+
+/// Outcome of queueing a message without waiting
+pub enum TrySend {
+    Queued,
+    NotConnected,
+}
+
+/// A message which is sent again after every connect, see [`Link::remember`]
+struct Remembered {
+    id: u64,
+    message: Message,
+    /// The id of its queued copy, which is dropped before it's queued again
+    queued: Option<u64>,
+}
+
+/// The sending half of a connection, it can be shared by any number of senders.
+pub struct Link {
+    queue: Queue,
+    /// A [`ConnState`]
+    state: AtomicU8,
+    /// Gets the state changes, the handler of the latest connect
+    observer: RwLock<Option<Weak<dyn EventHandler>>>,
+    power_save: watch::Sender<PowerSave>,
+    /// Set while received frames are left in the socket, see [`Link::pause_reading`]
+    reading_paused: watch::Sender<bool>,
+    suspend: Notify,
+    /// Set while the app is in the background, see [`Link::suspend`]
+    background: watch::Sender<Option<Background>>,
+    /// Set while the keepalive is on a shared timer, see [`Link::set_keepalive_slot`]
+    keepalive_slot: watch::Sender<Option<KeepaliveSlot>>,
+    /// Whether the connection was open when it was suspended
+    open_at_suspend: AtomicBool,
+    tag: RwLock<HashMap<String, String>>,
+    /// Set by closing, so a connection which was closed on purpose isn't made again
+    close_requested: watch::Sender<bool>,
+    write_stats: Mutex<WriteStats>,
+    remembered: Mutex<Vec<Remembered>>,
+    next_remembered: AtomicU64,
+    send_before_connect: RwLock<SendBeforeConnect>,
+}
+
+impl Default for Link {
+    fn default() -> Self {
+        Link {
+            queue: Queue::default(),
+            state: AtomicU8::new(ConnState::Closed as u8),
+            observer: RwLock::default(),
+            power_save: watch::channel(PowerSave::default()).0,
+            reading_paused: watch::channel(false).0,
+            suspend: Notify::new(),
+            background: watch::channel(None).0,
+            keepalive_slot: watch::channel(None).0,
+            open_at_suspend: AtomicBool::new(false),
+            tag: RwLock::default(),
+            close_requested: watch::channel(false).0,
+            write_stats: Mutex::default(),
+            remembered: Mutex::default(),
+            next_remembered: AtomicU64::new(1),
+            send_before_connect: RwLock::default(),
+        }
+    }
+}
+
+impl Link {
+    pub fn state(&self) -> ReadyState {
+        self.conn_state().into()
+    }
+
+    pub fn set_state(&self, state: ReadyState) {
+        self.set_conn_state(state.into());
+    }
+
+    pub fn conn_state(&self) -> ConnState {
+        self.state.load(Ordering::Acquire).into()
+    }
+
+    /// Change the state and tell the observer, unless it's the same one
+    pub(crate) fn set_conn_state(&self, state: ConnState) {
+        if self.state.swap(state as u8, Ordering::AcqRel) == state as u8 {
+            return;
+        }
+        let observer = self
+            .observer
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .and_then(Weak::upgrade);
+        if let Some(observer) = observer {
+            observer.handle(Event::StateChange(state));
+        }
+    }
+
+    /// Send the state changes to `handler` from now on, it's not kept alive by the link
+    pub(crate) fn observe(&self, handler: &Arc<dyn EventHandler>) {
+        *self
+            .observer
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = Some(Arc::downgrade(handler));
+    }
+
+    /// Metadata of the connection, e.g. which feature of the app it belongs to
+    pub fn tag(&self) -> HashMap<String, String> {
+        self.tag
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn set_tag(&self, tag: HashMap<String, String>) {
+        *self.tag.write().unwrap_or_else(PoisonError::into_inner) = tag;
+    }
+
+    /// The tag as `[key=value ...]` for log lines, empty without a tag
+    pub fn log_label(&self) -> String {
+        let tag = self.tag.read().unwrap_or_else(PoisonError::into_inner);
+        if tag.is_empty() {
+            return String::new();
+        }
+        let mut pairs: Vec<String> = tag
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        pairs.sort();
+        format!("[{}]", pairs.join(" "))
+    }
+
+    pub fn power_save(&self) -> PowerSave {
+        *self.power_save.borrow()
+    }
+
+    pub fn set_power_save(&self, power_save: PowerSave) {
+        self.power_save.send_replace(power_save);
+    }
+
+    pub fn subscribe_power_save(&self) -> watch::Receiver<PowerSave> {
+        self.power_save.subscribe()
+    }
+
+    /// Stop reading frames until [`Link::resume_reading`], so the tcp window closes and the server has to wait,
+    /// e.g. while the app can't keep up. The idle timeout and keepalive are suspended meanwhile.
+    pub fn pause_reading(&self) {
+        self.reading_paused.send_replace(true);
+    }
+
+    pub fn resume_reading(&self) {
+        self.reading_paused.send_replace(false);
+    }
+
+    pub fn is_reading_paused(&self) -> bool {
+        *self.reading_paused.borrow()
+    }
+
+    pub(crate) fn subscribe_reading_paused(&self) -> watch::Receiver<bool> {
+        self.reading_paused.subscribe()
+    }
+
+    /// The app went to the background: the keepalive is slowed down as `background` says
+    /// and data messages are held back until [`Link::resume`]. Suspending again replaces the options.
+    pub fn suspend(&self, background: Background) {
+        let was_suspended = self.background.send_replace(Some(background)).is_some();
+        if !was_suspended {
+            self.open_at_suspend
+                .store(self.state() == ReadyState::Open, Ordering::Release);
+        }
+    }
+
+    /// Back in the foreground: the held messages are written and the keepalive deadlines start over.
+    /// Returns whether the connection was open when it was suspended.
+    pub fn resume(&self) -> bool {
+        if self.background.send_replace(None).is_none() {
+            return false;
+        }
+        self.open_at_suspend.swap(false, Ordering::AcqRel)
+    }
+
+    /// The options of [`Link::suspend`] while the app is in the background
+    pub fn background(&self) -> Option<Background> {
+        *self.background.borrow()
+    }
+
+    pub(crate) fn subscribe_background(&self) -> watch::Receiver<Option<Background>> {
+        self.background.subscribe()
+    }
+
+    /// Send the keepalive pings on the ticks of `slot`, which several connections share, `None` goes back to their own timer.
+    /// A ping is moved to the latest tick before it's due, so the interval is kept.
+    pub fn set_keepalive_slot(&self, slot: Option<KeepaliveSlot>) {
+        self.keepalive_slot.send_replace(slot);
+    }
+
+    pub fn keepalive_slot(&self) -> Option<KeepaliveSlot> {
+        *self.keepalive_slot.borrow()
+    }
+
+    pub(crate) fn subscribe_keepalive_slot(&self) -> watch::Receiver<Option<KeepaliveSlot>> {
+        self.keepalive_slot.subscribe()
+    }
+
+    pub(crate) fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    /// Accept messages for a new connection and queue the remembered ones
+    pub(crate) fn attach(&self) {
+        let mut remembered = self
+            .remembered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.queue.attach();
+        for remembered in remembered.iter_mut() {
+            if let Some(queued) = remembered.queued {
+                self.queue.cancel(queued);
+            }
+            remembered.queued = self
+                .queue
+                .push(remembered.message.clone(), SendOptions::default());
+        }
+    }
+
+    /// Send `message` now when connected, and again after every connect in the order they were remembered,
+    /// e.g. the subscriptions of a pub/sub protocol. Returns the id for [`Link::forget`].
+    pub fn remember(&self, message: Message) -> u64 {
+        let mut remembered = self
+            .remembered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let id = self.next_remembered.fetch_add(1, Ordering::Relaxed);
+        let queued = self.queue.push(message.clone(), SendOptions::default());
+        remembered.push(Remembered {
+            id,
+            message,
+            queued,
+        });
+        id
+    }
+
+    /// Stop sending a remembered message, its copy is dropped if it wasn't written yet.
+    /// Returns whether it was remembered.
+    pub fn forget(&self, id: u64) -> bool {
+        let mut remembered = self
+            .remembered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let Some(index) = remembered.iter().position(|remembered| remembered.id == id) else {
+            return false;
+        };
+        if let Some(queued) = remembered.remove(index).queued {
+            self.queue.cancel(queued);
+        }
+        true
+    }
+
+    /// The remembered messages in the order they were remembered
+    pub fn remembered(&self) -> Vec<Message> {
+        self.remembered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|remembered| remembered.message.clone())
+            .collect()
+    }
+
+    /// Id of the message remembered last
+    pub(crate) fn last_remembered_id(&self) -> u64 {
+        self.next_remembered.load(Ordering::Relaxed) - 1
+    }
+
+    /// Continue the ids of remembered messages after `last_id`
+    pub(crate) fn skip_remembered_ids(&self, last_id: u64) {
+        self.next_remembered
+            .fetch_max(last_id + 1, Ordering::Relaxed);
+    }
+
+    /// Queued data messages which weren't written yet, see [`Queue::snapshot`].
+    /// The copies of remembered messages are left out, they're queued again on every connect anyway.
+    pub(crate) fn queued(&self) -> Vec<(Message, SendOptions)> {
+        let remembered = self
+            .remembered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        self.queue
+            .snapshot(Instant::now())
+            .into_iter()
+            .filter(|(id, _, _)| {
+                remembered
+                    .iter()
+                    .all(|remembered| remembered.queued != Some(*id))
+            })
+            .map(|(_, message, options)| (message, options))
+            .collect()
+    }
+
+    pub(crate) fn detach(&self) {
+        self.queue.detach();
+    }
+
+    /// Drop every queued, scheduled and remembered message and refuse new ones,
+    /// for a connection which is never used again
+    pub(crate) fn release(&self) {
+        self.close_requested.send_replace(true);
+        self.remembered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+        self.queue.clear(|_| true);
+        self.queue.detach();
+    }
+
+    /// Whether the connection was closed on purpose since the last connect
+    pub(crate) fn close_requested(&self) -> bool {
+        *self.close_requested.borrow()
+    }
+
+    pub(crate) fn subscribe_close_requested(&self) -> watch::Receiver<bool> {
+        self.close_requested.subscribe()
+    }
+
+    /// Forget an earlier close, for a new connect
+    pub(crate) fn reset_close_requested(&self) {
+        self.close_requested.send_replace(false);
+    }
+
+    pub fn send_before_connect(&self) -> SendBeforeConnect {
+        *self
+            .send_before_connect
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn set_send_before_connect(&self, policy: SendBeforeConnect) {
+        *self
+            .send_before_connect
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = policy;
+    }
+
+    /// Queue a message the way [`SendBeforeConnect`] says, control frames are dropped without a connection
+    fn push(&self, payload: Payload, options: SendOptions) -> Result<Option<u64>> {
+        let data = payload.is_data();
+        match self.send_before_connect() {
+            SendBeforeConnect::Queue => Ok(self.queue.push_detached(payload, options)),
+            SendBeforeConnect::Reject if data => {
+                self.queue.push(payload, options).map(Some).ok_or_else(|| {
+                    Error::new(
+                        WebSocketError::NotConnected,
+                        "ws-rs send failed: not connected",
+                    )
+                })
+            }
+            _ => Ok(self.queue.push(payload, options)),
+        }
+    }
+
+    pub async fn send_message(&self, message: Message) -> Result<()> {
+        self.send_with(message, SendOptions::default())
+            .await
+            .map(|_| ())
+    }
+
+    /// Queue a message and return its id for [`Link::cancel_send`].
+    /// Without a connection it's handled as [`Link::send_before_connect`] says, `None` is returned when it's dropped.
+    pub async fn send_with(&self, message: Message, options: SendOptions) -> Result<Option<u64>> {
+        self.push(message.into(), options)
+    }
+
+    /// Queue one binary message made of `parts`, without joining them.
+    /// Every part is written as a fragment of the message.
+    pub async fn send_parts(&self, parts: Vec<Bytes>, options: SendOptions) -> Result<Option<u64>> {
+        self.push(Payload::Parts(parts), options)
+    }
+
+    /// Queue a message after `delay`, tied to the current connection.
+    /// With `keep_across_reconnect` it survives a broken connection and is written on the next one,
+    /// [`Link::close`] drops it either way. Returns its id, `None` without a connection.
+    /// Fails with `SendError` for a delay beyond what the clock can represent.
+    pub fn send_after(
+        &self,
+        delay: Duration,
+        message: Message,
+        options: SendOptions,
+        keep_across_reconnect: bool,
+    ) -> Result<Option<u64>> {
+        let due = Instant::now().checked_add(delay).ok_or_else(|| {
+            Error::new(
+                WebSocketError::SendError,
+                format!("send delay of {} ms is too long", delay.as_millis()),
+            )
+        })?;
+        Ok(self
+            .queue
+            .schedule(message, options, due, keep_across_reconnect))
+    }
+
+    /// Remove a message which hasn't been written yet, returns whether it was still queued
+    pub fn cancel_send(&self, id: u64) -> bool {
+        self.queue.cancel(id)
+    }
+
+    /// Queue a message without waiting, for callers outside of the async runtime
+    pub fn try_send_message(&self, message: Message) -> TrySend {
+        match self.push(message.into(), SendOptions::default()) {
+            Ok(Some(_)) => TrySend::Queued,
+            Ok(None) | Err(_) => TrySend::NotConnected,
+        }
+    }
+
+    /// Number of messages which haven't been written yet
+    pub fn pending_count(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Payload bytes of the messages which haven't been written yet
+    pub fn pending_bytes(&self) -> usize {
+        self.queue.bytes()
+    }
+
+    pub fn write_stats(&self) -> WriteStats {
+        *self
+            .write_stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count a batch of `messages` which reached the socket
+    pub(crate) fn record_batch(&self, messages: usize) {
+        let mut stats = self
+            .write_stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        stats.batches += 1;
+        stats.messages += messages as u64;
+        stats.largest_batch = stats.largest_batch.max(messages as u64);
+    }
+
+    /// Distinct tags of the messages which haven't been written yet
+    pub fn pending_tags(&self) -> Vec<String> {
+        self.queue.tags()
+    }
+
+    /// Drop messages which haven't been written yet, only those with `tag` if given.
+    /// Returns how many were dropped.
+    pub fn clear_queue(&self, tag: Option<&str>) -> usize {
+        self.queue
+            .clear(|outgoing| tag.is_none_or(|tag| outgoing.options.tag.as_deref() == Some(tag)))
+    }
+
+    /// Wait until every message sent so far has been written to the socket.
+    /// Messages dropped after failed writes count as done, they are reported as errors.
+    pub async fn flush(&self, wait: Option<Duration>) -> Result<()> {
+        let last = self.queue.last_id();
+        let flushed = async {
+            loop {
+                let changed = self.queue.notified();
+                if self.queue.is_flushed(last) {
+                    return;
+                }
+                changed.await;
+            }
+        };
+        match wait {
+            Some(wait) => timeout(wait, flushed).await.map_err(|_| {
+                Error::new(
+                    WebSocketError::SendError,
+                    format!("flush timed out after {} ms", wait.as_millis()),
+                )
+            }),
+            None => {
+                flushed.await;
+                Ok(())
+            }
+        }
+    }
+
+    /// Completes when [`Link::prepare_for_suspend`] is called
+    pub(crate) async fn suspend_hinted(&self) {
+        self.suspend.notified().await
+    }
+
+    /// Get the connection ready for the app being frozen: the connection reports
+    /// [`crate::Event::SuspendHint`], then the going away message is sent and the queue flushed.
+    /// With `close` it's closed with 1001 even if the flush timed out.
+    pub async fn prepare_for_suspend(&self, options: SuspendOptions) -> Result<()> {
+        self.suspend.notify_waiters();
+        if let Some(message) = options.going_away_message {
+            self.send_message(Message::Text(message.into())).await?;
+        }
+        let flushed = self.flush(Some(options.flush_timeout)).await;
+        if options.close {
+            self.close_with(CLOSE_GOING_AWAY, "going away").await?;
+        }
+        flushed
+    }
+
+    /// Close with a status code and reason
+    pub async fn close_with(&self, code: u16, reason: &str) -> Result<()> {
+        self.close_requested.send_replace(true);
+        self.queue.unschedule();
+        if self.state() == ReadyState::Open {
+            self.set_state(ReadyState::Closing);
+        }
+        self.send_message(Message::Close(Some(CloseFrame {
+            code: CloseCode::from(code),
+            reason: reason.to_string().into(),
+        })))
+        .await
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.close_requested.send_replace(true);
+        self.queue.unschedule();
+        if self.state() == ReadyState::Open {
+            self.set_state(ReadyState::Closing);
+        }
+        self.send_message(Message::Close(None)).await
+    }
+
+    pub fn try_close(&self) -> TrySend {
+        self.close_requested.send_replace(true);
+        self.queue.unschedule();
+        if self.state() == ReadyState::Open {
+            self.set_state(ReadyState::Closing);
+        }
+        self.try_send_message(Message::Close(None))
+    }
+
+    /// Send a ping, `None` sends the default `ping` payload
+    pub async fn ping(&self, ping_message: Option<Bytes>) -> Result<()> {
+        let ping_message = ping_message.unwrap_or_else(|| Bytes::from_static(b"ping"));
+
+        // Control frames carry at most 125 bytes, RFC 6455 5.5
+        if ping_message.len() > 125 {
+            return Err(Error::new(
+                WebSocketError::SendError,
+                "ping message length exceeds 125 bytes",
+            ));
+        }
+        self.send_message(Message::Ping(ping_message)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(link: &Link, message: Message) -> Result<Option<u64>> {
+        link.push(message.into(), SendOptions::default())
+    }
+
+    #[test]
+    fn send_before_connect_drop() {
+        let link = Link::default();
+        assert!(matches!(push(&link, Message::text("a")), Ok(None)));
+        assert_eq!(link.queue().len(), 0);
+    }
+
+    #[test]
+    fn send_before_connect_reject() {
+        let link = Link::default();
+        link.set_send_before_connect(SendBeforeConnect::Reject);
+        let error = push(&link, Message::text("a")).unwrap_err();
+        assert_eq!(error.kind, WebSocketError::NotConnected);
+        // Control frames are dropped quietly
+        assert!(matches!(push(&link, Message::Ping(Bytes::new())), Ok(None)));
+        link.attach();
+        assert!(matches!(push(&link, Message::text("a")), Ok(Some(_))));
+    }
+
+    #[test]
+    fn send_before_connect_queue() {
+        let link = Link::default();
+        link.set_send_before_connect(SendBeforeConnect::Queue);
+        assert!(matches!(push(&link, Message::text("a")), Ok(Some(_))));
+        assert!(matches!(
+            push(&link, Message::Ping(Bytes::new())),
+            Ok(Some(_))
+        ));
+        link.detach();
+        assert_eq!(link.queue().len(), 1);
+        assert_eq!(link.queued().len(), 1);
+    }
+
+    #[test]
+    fn remembered_messages_are_queued_again_on_attach() {
+        let link = Link::default();
+        link.attach();
+        let subscribe = link.remember(Message::text("subscribe"));
+        link.remember(Message::text("presence"));
+        assert_eq!(link.queue().len(), 2);
+        // The copies of the last connection are replaced, not added to
+        link.attach();
+        assert_eq!(link.queue().len(), 2);
+        assert!(link.queued().is_empty());
+        assert!(link.forget(subscribe));
+        assert!(!link.forget(subscribe));
+        assert_eq!(link.remembered(), [Message::text("presence")]);
+        assert_eq!(link.queue().len(), 1);
+    }
+
+    #[test]
+    fn resume_tells_whether_the_connection_was_open() {
+        let link = Link::default();
+        assert!(!link.resume());
+        link.set_state(ReadyState::Open);
+        link.suspend(Background::default());
+        link.set_state(ReadyState::Closed);
+        link.suspend(Background::default());
+        assert!(link.background().is_some());
+        assert!(link.resume());
+        assert!(link.background().is_none());
+        assert!(!link.resume());
+    }
+
+    #[tokio::test]
+    async fn ping_payload_fits_a_control_frame() {
+        let link = Link::default();
+        assert!(link.ping(Some(Bytes::from(vec![0; 125]))).await.is_ok());
+        let error = link
+            .ping(Some(Bytes::from(vec![0; 126])))
+            .await
+            .unwrap_err();
+        assert_eq!(error.kind, WebSocketError::SendError);
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex, PoisonError},
+    task::{ready, Context, Poll},
+};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::Bytes;
+
+const OPCODE_PONG: u8 = 0xA;
+
+/// Who answers the pings of the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PongPolicy {
+    /// The protocol answers every ping, a payload returned by the handler is sent as an extra pong
+    #[default]
+    Auto,
+    /// Only the payload returned by the handler is sent back, nothing if it returns none
+    Handler,
+    /// Pings are never answered, e.g. to test how the server handles a client which went silent
+    None,
+}
+
+/// The pongs of the handler which may pass the [`PongGate`] of a connection
+pub(crate) struct PongReplies {
+    pub policy: PongPolicy,
+    allowed: Mutex<VecDeque<Bytes>>,
+}
+
+impl PongReplies {
+    pub fn new(policy: PongPolicy) -> Self {
+        PongReplies {
+            policy,
+            allowed: Mutex::default(),
+        }
+    }
+
+    /// Let the next pong with `payload` through
+    pub fn allow(&self, payload: Bytes) {
+        self.allowed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push_back(payload);
+    }
+
+    fn take(&self, payload: &[u8]) -> bool {
+        let mut allowed = self.allowed.lock().unwrap_or_else(PoisonError::into_inner);
+        match allowed.iter().position(|allowed| allowed == payload) {
+            Some(index) => {
+                allowed.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Header and payload length of the frame starting with `bytes`, `None` while the header is incomplete
+pub(crate) fn frame_header(bytes: &[u8]) -> Option<(usize, u64)> {
+    let second = *bytes.get(1)?;
+    let length_bytes = match second & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0,
+    };
+    let mask_bytes = if second & 0x80 != 0 { 4 } else { 0 };
+    let header_len = 2 + length_bytes + mask_bytes;
+    if bytes.len() < header_len {
+        return None;
+    }
+    let payload_len = match length_bytes {
+        0 => u64::from(second & 0x7F),
+        _ => bytes[2..2 + length_bytes]
+            .iter()
+            .fold(0, |len, byte| len << 8 | u64::from(*byte)),
+    };
+    Some((header_len, payload_len))
+}
+
+/// The unmasked payload of a complete frame
+pub(crate) fn payload(frame: &[u8], header_len: usize) -> Vec<u8> {
+    let mut payload = frame[header_len..].to_vec();
+    if frame[1] & 0x80 != 0 {
+        let mask = &frame[header_len - 4..header_len];
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+    }
+    payload
+}
+
+/// Drops the pongs the protocol writes on its own, every ping is answered by tungstenite otherwise.
+/// Outgoing bytes are parsed into frames once [`PongGate::activate`] is called after the handshake,
+/// with [`PongPolicy::Auto`] everything passes untouched.
+pub struct PongGate<S> {
+    inner: S,
+    replies: Arc<PongReplies>,
+    active: bool,
+    /// Header of the frame being written, or all of it for a pong
+    header: Vec<u8>,
+    /// Payload bytes of the current frame which still pass straight through
+    passing: u64,
+    /// Bytes accepted from the writer but not yet written to `inner`
+    pending: Vec<u8>,
+    written: usize,
+}
+
+impl<S> PongGate<S> {
+    pub(crate) fn new(inner: S, replies: Arc<PongReplies>) -> Self {
+        PongGate {
+            inner,
+            replies,
+            active: false,
+            header: Vec::new(),
+            passing: 0,
+            pending: Vec::new(),
+            written: 0,
+        }
+    }
+
+    /// Start gating, the bytes written from now on are websocket frames
+    pub(crate) fn activate(&mut self) {
+        self.active = self.replies.policy != PongPolicy::Auto;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    pub(crate) fn replies(&self) -> &Arc<PongReplies> {
+        &self.replies
+    }
+}
+
+impl<S: AsyncWrite + Unpin> PongGate<S> {
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    /// A frame is complete in `header`, decide what happens with it
+    fn frame_complete(&mut self, header_len: usize, payload_len: u64) {
+        let frame = std::mem::take(&mut self.header);
+        if frame[0] & 0x0F != OPCODE_PONG {
+            self.pending.extend_from_slice(&frame);
+            self.passing = payload_len;
+        } else if self.replies.take(&payload(&frame, header_len)) {
+            self.pending.extend_from_slice(&frame);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for PongGate<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for PongGate<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let gate = &mut *self;
+        if !gate.active {
+            return Pin::new(&mut gate.inner).poll_write(cx, buf);
+        }
+        ready!(gate.poll_pending(cx))?;
+        if gate.passing > 0 {
+            let len = buf
+                .len()
+                .min(usize::try_from(gate.passing).unwrap_or(usize::MAX));
+            let n = ready!(Pin::new(&mut gate.inner).poll_write(cx, &buf[..len]))?;
+            gate.passing -= n as u64;
+            return Poll::Ready(Ok(n));
+        }
+        // Header bytes, and pongs as a whole, are collected until it's clear where they go
+        let mut consumed = 0;
+        while consumed < buf.len() {
+            gate.header.push(buf[consumed]);
+            consumed += 1;
+            let Some((header_len, payload_len)) = frame_header(&gate.header) else {
+                continue;
+            };
+            let pong = gate.header[0] & 0x0F == OPCODE_PONG;
+            if !pong || gate.header.len() as u64 == header_len as u64 + payload_len {
+                gate.frame_complete(header_len, payload_len);
+                break;
+            }
+        }
+        Poll::Ready(Ok(consumed))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        ready!(self.poll_pending(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,42 @@
+/// Power save mode, to be switched by the app from its battery or power mode callbacks
+#[derive(Debug, Clone, Copy)]
+pub struct PowerSave {
+    pub enabled: bool,
+
+    /// Longest time a non-urgent message is held back while enabled, in milliseconds.
+    /// Deferred messages are also written together with the next urgent one.
+    pub max_defer_ms: u64,
+
+    /// The keepalive interval is multiplied by this while enabled, so the radio wakes up less often.
+    /// Values below 1 count as 1.
+    pub keepalive_factor: u32,
+}
+
+impl Default for PowerSave {
+    fn default() -> Self {
+        PowerSave {
+            enabled: false,
+            max_defer_ms: 30_000,
+            keepalive_factor: 2,
+        }
+    }
+}
+
+/// What a connection does while the app is in the background, see [`crate::Link::suspend`]
+#[derive(Debug, Clone, Copy)]
+pub struct Background {
+    /// The keepalive interval is multiplied by this, 0 stops the keepalive and the idle timeout
+    pub keepalive_factor: u32,
+    /// Hold data messages back until resumed, so a write isn't cut off when the app is frozen.
+    /// Pings and pongs are still written, a close writes the held messages before it.
+    pub buffer: bool,
+}
+
+impl Default for Background {
+    fn default() -> Self {
+        Background {
+            keepalive_factor: 4,
+            buffer: true,
+        }
+    }
+}
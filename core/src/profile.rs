@@ -0,0 +1,58 @@
+use std::sync::{Mutex, OnceLock, PoisonError};
+
+use crate::TlsOptions;
+
+/// TLS options shared by every connection to the hosts matching one of `hosts`.
+/// A pattern is a host name, or `*.example.com` for all of its subdomains.
+#[derive(Debug, Clone)]
+pub struct TlsProfile {
+    pub name: String,
+    pub hosts: Vec<String>,
+    pub tls: TlsOptions,
+}
+
+fn profiles() -> &'static Mutex<Vec<TlsProfile>> {
+    static PROFILES: OnceLock<Mutex<Vec<TlsProfile>>> = OnceLock::new();
+    PROFILES.get_or_init(Mutex::default)
+}
+
+/// Add `profile`, replacing the one with the same name. Open connections keep their options.
+pub fn register_tls_profile(profile: TlsProfile) {
+    let mut profiles = profiles().lock().unwrap_or_else(PoisonError::into_inner);
+    profiles.retain(|registered| registered.name != profile.name);
+    profiles.push(profile);
+}
+
+/// Remove the profile named `name`, false if there is none
+pub fn unregister_tls_profile(name: &str) -> bool {
+    let mut profiles = profiles().lock().unwrap_or_else(PoisonError::into_inner);
+    let before = profiles.len();
+    profiles.retain(|registered| registered.name != name);
+    profiles.len() != before
+}
+
+/// The options of the profile for `host`.
+/// A host name pattern wins over wildcards, among those the longest suffix wins.
+pub(crate) fn tls_profile_for(host: &str) -> Option<TlsOptions> {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let profiles = profiles().lock().unwrap_or_else(PoisonError::into_inner);
+    let mut best: Option<(usize, &TlsProfile)> = None;
+    for profile in profiles.iter() {
+        for pattern in &profile.hosts {
+            let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+            let score = match pattern.strip_prefix("*.") {
+                Some(suffix) => host
+                    .strip_suffix(suffix)
+                    .is_some_and(|name| name.len() > 1 && name.ends_with('.'))
+                    .then_some(suffix.len()),
+                None => (pattern == host).then_some(usize::MAX),
+            };
+            if let Some(score) = score {
+                if best.is_none_or(|(best, _)| score > best) {
+                    best = Some((score, profile));
+                }
+            }
+        }
+    }
+    best.map(|(_, profile)| profile.tls.clone())
+}
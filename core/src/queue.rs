@@ -0,0 +1,631 @@
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, PoisonError},
+};
+
+use tokio::{
+    sync::{futures::Notified, Notify},
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::{
+    protocol::{
+        frame::{
+            coding::{Data, OpCode},
+            Frame,
+        },
+        Message,
+    },
+    Bytes,
+};
+
+use crate::SendOptions;
+
+/// What a queued message is made of
+pub enum Payload {
+    Message(Message),
+    /// One binary message of several parts, each written as a fragment so they are never joined
+    Parts(Vec<Bytes>),
+}
+
+impl From<Message> for Payload {
+    fn from(message: Message) -> Self {
+        Payload::Message(message)
+    }
+}
+
+impl Payload {
+    pub fn is_data(&self) -> bool {
+        match self {
+            Payload::Message(message) => {
+                matches!(message, Message::Text(_) | Message::Binary(_))
+            }
+            Payload::Parts(_) => true,
+        }
+    }
+
+    /// Bytes of the data, without framing
+    pub fn len(&self) -> usize {
+        match self {
+            Payload::Message(message) => message.len(),
+            Payload::Parts(parts) => parts.iter().map(Bytes::len).sum(),
+        }
+    }
+
+    /// What has to be fed to the sink
+    pub fn messages(&self) -> Vec<Message> {
+        match self {
+            Payload::Message(message) => vec![message.clone()],
+            Payload::Parts(parts) if parts.len() < 2 => {
+                vec![Message::Binary(parts.first().cloned().unwrap_or_default())]
+            }
+            Payload::Parts(parts) => {
+                let last = parts.len() - 1;
+                parts
+                    .iter()
+                    .enumerate()
+                    .map(|(index, part)| {
+                        let opcode = match index {
+                            0 => OpCode::Data(Data::Binary),
+                            _ => OpCode::Data(Data::Continue),
+                        };
+                        Message::Frame(Frame::message(part.clone(), opcode, index == last))
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// A message on its way to the writer
+pub struct Outgoing {
+    pub id: u64,
+    pub payload: Payload,
+    pub options: SendOptions,
+    pub enqueued_at: Instant,
+    /// Failed writes so far
+    pub attempts: u32,
+}
+
+impl Outgoing {
+    fn new(inner: &mut Inner, payload: Payload, options: SendOptions) -> Self {
+        inner.next_id += 1;
+        Outgoing {
+            id: inner.next_id,
+            payload,
+            options,
+            enqueued_at: Instant::now(),
+            attempts: 0,
+        }
+    }
+
+    fn expires_at(&self) -> Option<Instant> {
+        self.options.ttl.map(|ttl| self.enqueued_at + ttl)
+    }
+
+    fn is_data(&self) -> bool {
+        self.payload.is_data()
+    }
+}
+
+/// What the writer should do next
+pub enum Ready {
+    Write(Vec<Outgoing>),
+    /// Everything queued may still be deferred, until the deadline if any
+    Wait(Option<Instant>),
+}
+
+/// A message which is queued once it's due
+struct Scheduled {
+    due: Instant,
+    /// Kept when the connection breaks before it's due
+    keep_across_reconnect: bool,
+    outgoing: Outgoing,
+}
+
+#[derive(Default)]
+struct Inner {
+    attached: bool,
+    next_id: u64,
+    messages: VecDeque<Outgoing>,
+    scheduled: Vec<Scheduled>,
+    /// Lowest id of the batch the writer is busy with
+    writing: Option<u64>,
+}
+
+/// Messages which have been sent but not written yet.
+/// It outlives a single connection, so messages whose write failed can go out on the next one.
+#[derive(Default)]
+pub struct Queue {
+    inner: Mutex<Inner>,
+    changed: Notify,
+}
+
+impl Inner {
+    /// Move scheduled messages which are due to the back of the queue, earliest first
+    fn enqueue_due(&mut self, now: Instant) {
+        let mut due: Vec<Scheduled> = Vec::new();
+        let mut index = 0;
+        while index < self.scheduled.len() {
+            if self.scheduled[index].due <= now {
+                due.push(self.scheduled.swap_remove(index));
+            } else {
+                index += 1;
+            }
+        }
+        due.sort_by_key(|scheduled| (scheduled.due, scheduled.outgoing.id));
+        for scheduled in due {
+            let mut outgoing = scheduled.outgoing;
+            outgoing.enqueued_at = now;
+            self.messages.push_back(outgoing);
+        }
+    }
+}
+
+impl Queue {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Completes on the next change. Create it before looking at the queue, so no change is missed.
+    pub fn notified(&self) -> Notified<'_> {
+        self.changed.notified()
+    }
+
+    pub fn attach(&self) {
+        self.lock().attached = true;
+        self.changed.notify_waiters();
+    }
+
+    /// Control frames belong to the connection which is gone, data messages are kept
+    pub fn detach(&self) {
+        let mut inner = self.lock();
+        inner.attached = false;
+        inner.writing = None;
+        inner.messages.retain(Outgoing::is_data);
+        inner
+            .scheduled
+            .retain(|scheduled| scheduled.keep_across_reconnect);
+        drop(inner);
+        self.changed.notify_waiters();
+    }
+
+    /// Returns the id of the message, `None` if there is no connection
+    pub fn push(&self, payload: impl Into<Payload>, options: SendOptions) -> Option<u64> {
+        self.push_as(payload.into(), options, false)
+    }
+
+    /// Queue it also without a connection, it's written once one is attached.
+    /// `None` only if it isn't kept then, see [`Queue::detach`].
+    pub fn push_detached(&self, payload: Payload, options: SendOptions) -> Option<u64> {
+        let keep = payload.is_data();
+        self.push_as(payload, options, keep)
+    }
+
+    fn push_as(&self, payload: Payload, options: SendOptions, detached: bool) -> Option<u64> {
+        let mut inner = self.lock();
+        if !inner.attached && !detached {
+            return None;
+        }
+        let outgoing = Outgoing::new(&mut inner, payload, options);
+        let id = outgoing.id;
+        inner.messages.push_back(outgoing);
+        drop(inner);
+        self.changed.notify_waiters();
+        Some(id)
+    }
+
+    /// Queue a message at `due`. Returns its id, `None` if there is no connection
+    pub fn schedule(
+        &self,
+        payload: impl Into<Payload>,
+        options: SendOptions,
+        due: Instant,
+        keep_across_reconnect: bool,
+    ) -> Option<u64> {
+        let mut inner = self.lock();
+        if !inner.attached {
+            return None;
+        }
+        let outgoing = Outgoing::new(&mut inner, payload.into(), options);
+        let id = outgoing.id;
+        inner.scheduled.push(Scheduled {
+            due,
+            keep_across_reconnect,
+            outgoing,
+        });
+        drop(inner);
+        self.changed.notify_waiters();
+        Some(id)
+    }
+
+    /// Drop every scheduled message which isn't due yet
+    pub fn unschedule(&self) {
+        self.lock().scheduled.clear();
+    }
+
+    /// When the next scheduled message is due or a queued one expires
+    pub fn next_wake(&self) -> Option<Instant> {
+        let inner = self.lock();
+        let due = inner.scheduled.iter().map(|scheduled| scheduled.due);
+        let expiry = inner.messages.iter().filter_map(Outgoing::expires_at);
+        due.chain(expiry).min()
+    }
+
+    /// Remove the messages whose ttl passed. Scheduled ones expire counting from when they are due.
+    pub fn take_expired(&self, now: Instant) -> Vec<Outgoing> {
+        let mut inner = self.lock();
+        let (expired, kept): (VecDeque<Outgoing>, VecDeque<Outgoing>) = inner
+            .messages
+            .drain(..)
+            .partition(|outgoing| outgoing.expires_at().is_some_and(|at| at <= now));
+        inner.messages = kept;
+        drop(inner);
+        if !expired.is_empty() {
+            self.changed.notify_waiters();
+        }
+        expired.into()
+    }
+
+    /// Put messages whose write failed back in front, in their order
+    pub fn requeue(&self, messages: Vec<Outgoing>) {
+        let mut inner = self.lock();
+        inner.writing = None;
+        for outgoing in messages.into_iter().rev() {
+            inner.messages.push_front(outgoing);
+        }
+        drop(inner);
+        self.changed.notify_waiters();
+    }
+
+    /// Remove data messages matching `filter`, also scheduled ones, control frames stay.
+    /// Returns how many were removed.
+    pub fn clear(&self, filter: impl Fn(&Outgoing) -> bool) -> usize {
+        let mut inner = self.lock();
+        let before = inner.messages.len() + inner.scheduled.len();
+        inner
+            .messages
+            .retain(|outgoing| !(outgoing.is_data() && filter(outgoing)));
+        inner
+            .scheduled
+            .retain(|scheduled| !(scheduled.outgoing.is_data() && filter(&scheduled.outgoing)));
+        let removed = before - inner.messages.len() - inner.scheduled.len();
+        drop(inner);
+        self.changed.notify_waiters();
+        removed
+    }
+
+    /// Remove the data message `id`, returns whether it was queued or scheduled
+    pub fn cancel(&self, id: u64) -> bool {
+        let mut inner = self.lock();
+        if let Some(index) = inner
+            .messages
+            .iter()
+            .position(|outgoing| outgoing.id == id && outgoing.is_data())
+        {
+            inner.messages.remove(index);
+        } else if let Some(index) = inner
+            .scheduled
+            .iter()
+            .position(|scheduled| scheduled.outgoing.id == id)
+        {
+            inner.scheduled.swap_remove(index);
+        } else {
+            return false;
+        }
+        drop(inner);
+        self.changed.notify_waiters();
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().messages.len()
+    }
+
+    /// Payload bytes of all queued messages
+    pub fn bytes(&self) -> usize {
+        self.lock()
+            .messages
+            .iter()
+            .map(|outgoing| outgoing.payload.len())
+            .sum()
+    }
+
+    /// Distinct tags of the queued messages, in queue order
+    pub fn tags(&self) -> Vec<String> {
+        let inner = self.lock();
+        let mut tags: Vec<String> = Vec::new();
+        for tag in inner
+            .messages
+            .iter()
+            .filter_map(|outgoing| outgoing.options.tag.as_ref())
+        {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        tags
+    }
+
+    /// Take what can be written now. `defer_until` tells until when a message may be held back.
+    /// Held back messages are written together with the first one which can't wait, keeping the order.
+    pub fn take_ready(
+        &self,
+        now: Instant,
+        defer_until: impl Fn(&Outgoing) -> Option<Instant>,
+    ) -> Ready {
+        let mut inner = self.lock();
+        inner.enqueue_due(now);
+        let mut deadline: Option<Instant> = None;
+        let mut ready = 0;
+        for (index, outgoing) in inner.messages.iter().enumerate() {
+            match defer_until(outgoing) {
+                Some(until) if until > now => {
+                    deadline = Some(deadline.map_or(until, |deadline| deadline.min(until)));
+                }
+                _ => ready = index + 1,
+            }
+        }
+        if ready == 0 {
+            return Ready::Wait(deadline);
+        }
+        let batch: Vec<Outgoing> = inner.messages.drain(..ready).collect();
+        inner.writing = batch.iter().map(|outgoing| outgoing.id).min();
+        Ready::Write(batch)
+    }
+
+    /// Take the queued pings and pongs ahead of the data messages, which are held back meanwhile,
+    /// see [`crate::Link::suspend`]. `None` once a close frame is queued, everything before it is written then.
+    pub fn take_control(&self, now: Instant) -> Option<Vec<Outgoing>> {
+        let mut inner = self.lock();
+        inner.enqueue_due(now);
+        let closing = inner
+            .messages
+            .iter()
+            .any(|outgoing| matches!(outgoing.payload, Payload::Message(Message::Close(_))));
+        if closing {
+            return None;
+        }
+        let (control, data): (VecDeque<Outgoing>, VecDeque<Outgoing>) = inner
+            .messages
+            .drain(..)
+            .partition(|outgoing| !outgoing.is_data());
+        inner.messages = data;
+        inner.writing = control.iter().map(|outgoing| outgoing.id).min();
+        Some(control.into())
+    }
+
+    /// The batch taken last reached the socket
+    pub fn written(&self) {
+        self.lock().writing = None;
+        self.changed.notify_waiters();
+    }
+
+    /// Id of the message queued last
+    pub fn last_id(&self) -> u64 {
+        self.lock().next_id
+    }
+
+    /// Continue the ids after `last_id`, so they don't repeat the ones of an earlier process
+    pub fn skip_ids(&self, last_id: u64) {
+        let mut inner = self.lock();
+        inner.next_id = inner.next_id.max(last_id);
+    }
+
+    /// Copies of the queued data messages with what's left of their ttl, expired ones are left out.
+    /// The parts of a message sent with parts are joined.
+    pub fn snapshot(&self, now: Instant) -> Vec<(u64, Message, SendOptions)> {
+        self.lock()
+            .messages
+            .iter()
+            .filter(|outgoing| outgoing.is_data())
+            .filter_map(|outgoing| {
+                let mut options = outgoing.options.clone();
+                if let Some(ttl) = options.ttl {
+                    options.ttl = Some(ttl.checked_sub(now.duration_since(outgoing.enqueued_at))?);
+                }
+                let message = match &outgoing.payload {
+                    Payload::Message(message) => message.clone(),
+                    Payload::Parts(parts) => Message::Binary(parts.concat().into()),
+                };
+                Some((outgoing.id, message, options))
+            })
+            .collect()
+    }
+
+    /// Queue messages of an earlier process behind the queued ones, also without a connection
+    pub fn restore(&self, messages: Vec<(Message, SendOptions)>) {
+        let mut inner = self.lock();
+        for (message, options) in messages {
+            let outgoing = Outgoing::new(&mut inner, message.into(), options);
+            inner.messages.push_back(outgoing);
+        }
+        drop(inner);
+        self.changed.notify_waiters();
+    }
+
+    /// Whether no queued message up to `id` waits to be written, scheduled ones don't count
+    pub fn is_flushed(&self, id: u64) -> bool {
+        let inner = self.lock();
+        inner.writing.is_none_or(|writing| writing > id)
+            && inner.messages.iter().all(|outgoing| outgoing.id > id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn text(text: &'static str) -> Payload {
+        Message::text(text).into()
+    }
+
+    fn ping() -> Payload {
+        Message::Ping(Bytes::new()).into()
+    }
+
+    fn ids(ready: Ready) -> Vec<u64> {
+        match ready {
+            Ready::Write(batch) => batch.iter().map(|outgoing| outgoing.id).collect(),
+            Ready::Wait(_) => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn push_needs_a_connection() {
+        let queue = Queue::default();
+        assert_eq!(queue.push(text("a"), SendOptions::default()), None);
+        queue.attach();
+        assert_eq!(queue.push(text("a"), SendOptions::default()), Some(1));
+    }
+
+    #[test]
+    fn push_detached_keeps_data_until_attached() {
+        let queue = Queue::default();
+        assert_eq!(
+            queue.push_detached(text("a"), SendOptions::default()),
+            Some(1)
+        );
+        assert_eq!(queue.push_detached(ping(), SendOptions::default()), Some(2));
+        assert_eq!(queue.len(), 2);
+        // Control frames of no connection are dropped at the next detach, data is kept
+        queue.detach();
+        assert_eq!(queue.len(), 1);
+        queue.attach();
+        assert_eq!(ids(queue.take_ready(Instant::now(), |_| None)), [1]);
+        assert!(queue.is_flushed(0));
+        assert!(!queue.is_flushed(1));
+        queue.written();
+        assert!(queue.is_flushed(1));
+    }
+
+    #[test]
+    fn deferred_messages_go_out_with_the_first_which_cant_wait() {
+        let queue = Queue::default();
+        queue.attach();
+        let now = Instant::now();
+        let later = now + Duration::from_secs(5);
+        let deferrable = SendOptions {
+            urgent: false,
+            ..SendOptions::default()
+        };
+        queue.push(text("a"), deferrable.clone());
+        queue.push(text("b"), deferrable.clone());
+        let defer = |outgoing: &Outgoing| (!outgoing.options.urgent).then_some(later);
+        assert!(matches!(queue.take_ready(now, defer), Ready::Wait(Some(at)) if at == later));
+        queue.push(text("c"), SendOptions::default());
+        queue.push(text("d"), deferrable);
+        assert_eq!(ids(queue.take_ready(now, defer)), [1, 2, 3]);
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn expired_messages_are_taken_out() {
+        let queue = Queue::default();
+        queue.attach();
+        let now = Instant::now();
+        let ttl = SendOptions {
+            ttl: Some(Duration::from_secs(1)),
+            ..SendOptions::default()
+        };
+        queue.push(text("a"), ttl);
+        queue.push(text("b"), SendOptions::default());
+        assert!(queue.next_wake().is_some());
+        assert!(queue.take_expired(now).is_empty());
+        let expired = queue.take_expired(now + Duration::from_secs(2));
+        assert_eq!(
+            expired
+                .iter()
+                .map(|outgoing| outgoing.id)
+                .collect::<Vec<_>>(),
+            [1]
+        );
+        assert_eq!(queue.len(), 1);
+        assert!(queue.next_wake().is_none());
+    }
+
+    #[test]
+    fn requeued_messages_go_first() {
+        let queue = Queue::default();
+        queue.attach();
+        queue.push(text("a"), SendOptions::default());
+        queue.push(text("b"), SendOptions::default());
+        let Ready::Write(batch) = queue.take_ready(Instant::now(), |_| None) else {
+            panic!("nothing to write");
+        };
+        queue.push(text("c"), SendOptions::default());
+        queue.requeue(batch);
+        assert_eq!(ids(queue.take_ready(Instant::now(), |_| None)), [1, 2, 3]);
+    }
+
+    #[test]
+    fn scheduled_messages_are_queued_when_due() {
+        let queue = Queue::default();
+        queue.attach();
+        let now = Instant::now();
+        let due = now + Duration::from_secs(1);
+        queue.schedule(text("kept"), SendOptions::default(), due, true);
+        queue.schedule(text("dropped"), SendOptions::default(), due, false);
+        assert_eq!(queue.next_wake(), Some(due));
+        assert!(matches!(queue.take_ready(now, |_| None), Ready::Wait(None)));
+        queue.detach();
+        queue.attach();
+        assert_eq!(ids(queue.take_ready(due, |_| None)), [1]);
+    }
+
+    #[test]
+    fn take_control_holds_data_back() {
+        let queue = Queue::default();
+        queue.attach();
+        queue.push(text("a"), SendOptions::default());
+        queue.push(ping(), SendOptions::default());
+        let control = queue.take_control(Instant::now()).unwrap();
+        assert_eq!(
+            control
+                .iter()
+                .map(|outgoing| outgoing.id)
+                .collect::<Vec<_>>(),
+            [2]
+        );
+        assert_eq!(queue.len(), 1);
+        queue.push(Message::Close(None), SendOptions::default());
+        assert!(queue.take_control(Instant::now()).is_none());
+    }
+
+    #[test]
+    fn clear_and_cancel_leave_control_frames() {
+        let queue = Queue::default();
+        queue.attach();
+        let tagged = SendOptions {
+            tag: Some("upload".to_string()),
+            ..SendOptions::default()
+        };
+        queue.push(text("a"), tagged.clone());
+        queue.push(ping(), tagged);
+        let b = queue.push(text("b"), SendOptions::default()).unwrap();
+        assert_eq!(queue.tags(), ["upload"]);
+        assert_eq!(
+            queue.clear(|outgoing| outgoing.options.tag.as_deref() == Some("upload")),
+            1
+        );
+        assert!(!queue.cancel(2));
+        assert!(queue.cancel(b));
+        assert!(!queue.cancel(b));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn parts_are_written_as_fragments() {
+        let payload = Payload::Parts(vec![Bytes::from_static(b"ab"), Bytes::from_static(b"c")]);
+        assert_eq!(payload.len(), 3);
+        assert!(payload.is_data());
+        let messages = payload.messages();
+        assert_eq!(messages.len(), 2);
+        assert!(messages
+            .iter()
+            .all(|message| matches!(message, Message::Frame(_))));
+        let single = Payload::Parts(vec![Bytes::from_static(b"ab")]).messages();
+        assert_eq!(single, [Message::Binary(Bytes::from_static(b"ab"))]);
+    }
+}
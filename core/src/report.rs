@@ -0,0 +1,462 @@
+use std::time::Duration;
+
+use serde_json::{Map, Value};
+
+use crate::{
+    request::is_secret_header, CloseInfo, ConnectAttempt, ConnectOptions, ConnectTimings, Error,
+    HandshakeRequest, HandshakeResponse, ReportedError, TlsInfo, WriteStats,
+};
+
+/// Version of the format of [`crate::Connection::diagnostics`]
+const VERSION: u64 = 1;
+const REDACTED: &str = "<redacted>";
+
+/// What a connection went through, for a bug report. Credentials are redacted.
+pub(crate) struct Diagnostics<'a> {
+    pub url: &'a str,
+    pub options: &'a ConnectOptions,
+    pub ready_state: String,
+    pub conn_state: String,
+    pub connects: Vec<ConnectAttempt>,
+    pub timings: Option<ConnectTimings>,
+    pub errors: Vec<ReportedError>,
+    pub close: Option<CloseInfo>,
+    pub tls: Option<TlsInfo>,
+    pub extensions: Vec<String>,
+    pub pending_count: usize,
+    pub pending_bytes: usize,
+    pub write_stats: WriteStats,
+    pub request: Option<HandshakeRequest>,
+    pub response: Option<HandshakeResponse>,
+    /// Milliseconds since the Unix epoch
+    pub generated_at_ms: f64,
+}
+
+impl Diagnostics<'_> {
+    /// The report as a JSON text
+    pub fn pack(&self) -> String {
+        let mut object = Map::new();
+        object.insert("version".to_string(), Value::from(VERSION));
+        object.insert("generatedAt".to_string(), Value::from(self.generated_at_ms));
+        object.insert("url".to_string(), Value::String(redact_url(self.url)));
+        object.insert("config".to_string(), pack_options(self.options));
+        object.insert(
+            "timeline".to_string(),
+            self.connects.iter().map(pack_attempt).collect(),
+        );
+        object.insert(
+            "recentErrors".to_string(),
+            self.errors
+                .iter()
+                .map(|reported| {
+                    let mut error = pack_error(&reported.error);
+                    error.insert("at".to_string(), Value::from(reported.at_ms));
+                    Value::Object(error)
+                })
+                .collect(),
+        );
+        let mut stats = Map::new();
+        stats.insert(
+            "readyState".to_string(),
+            Value::String(self.ready_state.clone()),
+        );
+        stats.insert(
+            "connState".to_string(),
+            Value::String(self.conn_state.clone()),
+        );
+        stats.insert("pendingCount".to_string(), Value::from(self.pending_count));
+        stats.insert("pendingBytes".to_string(), Value::from(self.pending_bytes));
+        stats.insert("batches".to_string(), Value::from(self.write_stats.batches));
+        stats.insert(
+            "messagesWritten".to_string(),
+            Value::from(self.write_stats.messages),
+        );
+        stats.insert(
+            "largestBatch".to_string(),
+            Value::from(self.write_stats.largest_batch),
+        );
+        stats.insert(
+            "extensions".to_string(),
+            self.extensions.iter().cloned().map(Value::String).collect(),
+        );
+        if let Some(timings) = &self.timings {
+            stats.insert("connectTimings".to_string(), pack_timings(timings));
+        }
+        if let Some(tls) = &self.tls {
+            stats.insert("tls".to_string(), pack_tls(tls));
+        }
+        if let Some(close) = &self.close {
+            stats.insert("lastClose".to_string(), pack_close(close));
+        }
+        object.insert("stats".to_string(), Value::Object(stats));
+        if let Some(request) = &self.request {
+            let mut handshake = Map::new();
+            handshake.insert("method".to_string(), Value::String(request.method.clone()));
+            handshake.insert("url".to_string(), Value::String(redact_url(&request.url)));
+            handshake.insert("headers".to_string(), pack_headers(request.headers.iter()));
+            object.insert("handshakeRequest".to_string(), Value::Object(handshake));
+        }
+        if let Some(response) = &self.response {
+            let mut handshake = Map::new();
+            handshake.insert("status".to_string(), Value::from(response.status));
+            handshake.insert("headers".to_string(), pack_headers(response.headers.iter()));
+            object.insert("handshakeResponse".to_string(), Value::Object(handshake));
+        }
+        Value::Object(object).to_string()
+    }
+}
+
+/// The url with its user info and the values of its query redacted, tokens are often passed there
+fn redact_url(url: &str) -> String {
+    let url = url.split('#').next().unwrap_or_default();
+    let (url, query) = match url.split_once('?') {
+        Some((url, query)) => (url, Some(query)),
+        None => (url, None),
+    };
+    let mut redacted = match url.split_once("://") {
+        Some((scheme, rest)) => {
+            let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+            match authority.rsplit_once('@') {
+                Some((_, host)) => format!("{}://{}@{}{}", scheme, REDACTED, host, path),
+                None => url.to_string(),
+            }
+        }
+        None => url.to_string(),
+    };
+    if let Some(query) = query {
+        let pairs: Vec<_> = query
+            .split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((key, _)) => format!("{}={}", key, REDACTED),
+                None => pair.to_string(),
+            })
+            .collect();
+        redacted.push('?');
+        redacted.push_str(&pairs.join("&"));
+    }
+    redacted
+}
+
+fn pack_headers<'a>(headers: impl Iterator<Item = (&'a String, &'a String)>) -> Value {
+    let mut headers: Vec<_> = headers.collect();
+    headers.sort();
+    Value::Object(
+        headers
+            .into_iter()
+            .map(|(name, value)| {
+                let value = if is_secret_header(name) {
+                    REDACTED.to_string()
+                } else {
+                    value.clone()
+                };
+                (name.clone(), Value::String(value))
+            })
+            .collect(),
+    )
+}
+
+fn millis(duration: Option<Duration>) -> Value {
+    duration.map_or(Value::Null, |duration| {
+        Value::from(duration.as_millis() as u64)
+    })
+}
+
+/// The options which change how the connection is made, certificates and keys only by their count
+fn pack_options(options: &ConnectOptions) -> Value {
+    let mut tls = Map::new();
+    tls.insert(
+        "backend".to_string(),
+        options.tls.backend.map_or(Value::Null, |backend| {
+            Value::String(format!("{:?}", backend))
+        }),
+    );
+    tls.insert(
+        "trust".to_string(),
+        options
+            .tls
+            .trust
+            .map_or(Value::Null, |trust| Value::String(format!("{:?}", trust))),
+    );
+    tls.insert(
+        "certPaths".to_string(),
+        Value::from(options.tls.cert_paths.clone()),
+    );
+    tls.insert(
+        "caCerts".to_string(),
+        Value::from(options.tls.ca_certs.len()),
+    );
+    tls.insert(
+        "caCertUris".to_string(),
+        Value::from(options.tls.ca_cert_uris.len()),
+    );
+    tls.insert(
+        "caBundles".to_string(),
+        Value::from(options.tls.ca_bundles.len()),
+    );
+    tls.insert(
+        "clientCertificate".to_string(),
+        Value::Bool(
+            options.tls.client_identity.is_some()
+                || options.tls.client_key.is_some()
+                || options.tls.client_credential.is_some(),
+        ),
+    );
+    tls.insert(
+        "crlPath".to_string(),
+        options
+            .tls
+            .crl_path
+            .clone()
+            .map_or(Value::Null, Value::String),
+    );
+    tls.insert("crls".to_string(), Value::from(options.tls.crls.len()));
+    tls.insert(
+        "allowUnknownRevocation".to_string(),
+        Value::Bool(options.tls.allow_unknown_revocation),
+    );
+    tls.insert(
+        "hostname".to_string(),
+        Value::String(format!("{:?}", options.tls.hostname)),
+    );
+    tls.insert("pins".to_string(), Value::from(options.tls.pins.len()));
+
+    let mut object = Map::new();
+    object.insert("tls".to_string(), Value::Object(tls));
+    object.insert(
+        "headers".to_string(),
+        Value::Array(
+            options
+                .headers
+                .iter()
+                .map(|(name, value)| {
+                    let value = if is_secret_header(name) {
+                        REDACTED
+                    } else {
+                        value
+                    };
+                    Value::Array(vec![
+                        Value::String(name.clone()),
+                        Value::String(value.to_string()),
+                    ])
+                })
+                .collect(),
+        ),
+    );
+    object.insert(
+        "enableExtension".to_string(),
+        Value::Bool(options.enable_extension),
+    );
+    object.insert(
+        "dnsOverrides".to_string(),
+        Value::Object(
+            options
+                .dns
+                .overrides
+                .iter()
+                .map(|(host, addrs)| {
+                    (
+                        host.clone(),
+                        addrs
+                            .iter()
+                            .map(|addr| Value::String(addr.to_string()))
+                            .collect(),
+                    )
+                })
+                .collect(),
+        ),
+    );
+    object.insert(
+        "dnsResolver".to_string(),
+        options
+            .dns
+            .resolver
+            .map_or(Value::Null, |resolver| Value::String(resolver.to_string())),
+    );
+    object.insert("dnsTimeoutMs".to_string(), millis(options.dns.timeout));
+    object.insert(
+        "dnsRetries".to_string(),
+        Value::from(options.dns.retry.retries),
+    );
+    object.insert(
+        "connectTimeoutMs".to_string(),
+        millis(options.timeouts.connect),
+    );
+    object.insert(
+        "handshakeTimeoutMs".to_string(),
+        millis(options.timeouts.handshake),
+    );
+    object.insert(
+        "localAddress".to_string(),
+        options
+            .local
+            .address
+            .map_or(Value::Null, |address| Value::String(address.to_string())),
+    );
+    object.insert(
+        "interfaceName".to_string(),
+        options
+            .local
+            .interface
+            .clone()
+            .map_or(Value::Null, Value::String),
+    );
+    object.insert(
+        "tcp".to_string(),
+        Value::String(format!("{:?}", options.tcp)),
+    );
+    object.insert(
+        "proxy".to_string(),
+        options
+            .proxy
+            .as_ref()
+            .map_or(Value::Null, |proxy| Value::String(proxy.to_string())),
+    );
+    object.insert(
+        "proxyResolver".to_string(),
+        Value::Bool(options.proxy_resolver.is_some()),
+    );
+    object.insert(
+        "sendBeforeConnect".to_string(),
+        Value::String(format!("{:?}", options.send_before_connect)),
+    );
+    object.insert("dataSaver".to_string(), Value::Bool(options.data_saver));
+    object.insert(
+        "strictRfc6455".to_string(),
+        Value::Bool(options.strict_rfc6455),
+    );
+    object.insert(
+        "maxRedirects".to_string(),
+        Value::from(options.max_redirects),
+    );
+    object.insert(
+        "reconnect".to_string(),
+        options
+            .reconnect
+            .map_or(Value::Null, |policy| Value::String(format!("{:?}", policy))),
+    );
+    object.insert("coalesceMs".to_string(), millis(options.coalesce));
+    object.insert(
+        "pongPolicy".to_string(),
+        Value::String(format!("{:?}", options.pong_policy)),
+    );
+    object.insert("idleTimeoutMs".to_string(), millis(options.idle_timeout));
+    object.insert(
+        "keepAlive".to_string(),
+        options.keep_alive.map_or(Value::Null, |keep_alive| {
+            Value::String(format!("{:?}", keep_alive))
+        }),
+    );
+    object.insert("closeTimeoutMs".to_string(), millis(options.close_timeout));
+    object.insert("closeGraceMs".to_string(), millis(options.close_grace));
+    object.insert(
+        "maxConnectionAgeMs".to_string(),
+        millis(options.max_age.map(|max_age| max_age.age)),
+    );
+    Value::Object(object)
+}
+
+fn pack_error(error: &Error) -> Map<String, Value> {
+    let mut object = Map::new();
+    object.insert(
+        "code".to_string(),
+        Value::String(error.kind.as_ref().to_string()),
+    );
+    object.insert("message".to_string(), Value::String(error.message.clone()));
+    object
+}
+
+fn pack_attempt(attempt: &ConnectAttempt) -> Value {
+    let mut object = Map::new();
+    object.insert("startedAt".to_string(), Value::from(attempt.started_at_ms));
+    object.insert("url".to_string(), Value::String(redact_url(&attempt.url)));
+    object.insert(
+        "addresses".to_string(),
+        attempt
+            .addresses
+            .iter()
+            .map(|address| Value::String(address.to_string()))
+            .collect(),
+    );
+    if let Some(address) = attempt.remote_address {
+        object.insert(
+            "remoteAddress".to_string(),
+            Value::String(address.to_string()),
+        );
+    }
+    if let Some(proxy) = &attempt.proxy {
+        object.insert("proxy".to_string(), Value::String(proxy.to_string()));
+    }
+    if let Some(stage) = attempt.failed_stage {
+        object.insert(
+            "failedStage".to_string(),
+            Value::String(format!("{:?}", stage)),
+        );
+    }
+    if let Some(error) = &attempt.error {
+        object.insert("error".to_string(), Value::Object(pack_error(error)));
+    }
+    object.insert("durationMs".to_string(), Value::from(attempt.duration_ms));
+    Value::Object(object)
+}
+
+fn pack_timings(timings: &ConnectTimings) -> Value {
+    let mut object = Map::new();
+    for (key, ms) in [
+        ("dnsMs", timings.dns_ms),
+        ("tcpConnectMs", timings.tcp_connect_ms),
+        ("tlsHandshakeMs", timings.tls_handshake_ms),
+        ("upgradeMs", timings.upgrade_ms),
+        ("totalMs", timings.total_ms),
+        ("firstByteMs", timings.first_byte_ms),
+        ("firstMessageMs", timings.first_message_ms),
+    ] {
+        if let Some(ms) = ms {
+            object.insert(key.to_string(), Value::from(ms));
+        }
+    }
+    Value::Object(object)
+}
+
+fn pack_tls(tls: &TlsInfo) -> Value {
+    let mut object = Map::new();
+    object.insert(
+        "backend".to_string(),
+        Value::String(format!("{:?}", tls.backend)),
+    );
+    if let Some(protocol) = &tls.protocol {
+        object.insert("protocol".to_string(), Value::String(protocol.clone()));
+    }
+    if let Some(cipher_suite) = &tls.cipher_suite {
+        object.insert(
+            "cipherSuite".to_string(),
+            Value::String(cipher_suite.clone()),
+        );
+    }
+    if let Some(resumed) = tls.resumed {
+        object.insert("resumed".to_string(), Value::Bool(resumed));
+    }
+    if let Some(not_after) = tls.not_after_ms {
+        object.insert("notAfter".to_string(), Value::from(not_after));
+    }
+    object.insert(
+        "revocationMode".to_string(),
+        Value::String(format!("{:?}", tls.revocation_mode)),
+    );
+    Value::Object(object)
+}
+
+fn pack_close(close: &CloseInfo) -> Value {
+    let mut object = Map::new();
+    object.insert("wasClean".to_string(), Value::Bool(close.was_clean));
+    object.insert("code".to_string(), Value::from(close.code));
+    object.insert("reason".to_string(), Value::String(close.reason.clone()));
+    object.insert(
+        "initiator".to_string(),
+        Value::String(format!("{:?}", close.initiator)),
+    );
+    if let Some(error) = &close.error {
+        object.insert("error".to_string(), Value::Object(pack_error(error)));
+    }
+    object.insert("closedAt".to_string(), Value::from(close.closed_at_ms));
+    Value::Object(object)
+}
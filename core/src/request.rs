@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest,
+    handshake::client::Request,
+    http::{
+        header::{HeaderName, HeaderValue, SEC_WEBSOCKET_EXTENSIONS},
+        HeaderMap, Response,
+    },
+};
+
+use crate::{Error, Result, WebSocketError};
+
+/// Headers whose values are credentials, matched case-insensitively.
+/// Any other name containing one of [`SECRET_WORDS`] counts as well.
+const SECRET_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+];
+const SECRET_WORDS: [&str; 5] = ["token", "secret", "key", "auth", "password"];
+
+/// Whether the value of the header `name` is a credential, which is redacted in reports
+/// and not sent to another host after a redirect
+pub(crate) fn is_secret_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    SECRET_HEADERS.contains(&name.as_str()) || SECRET_WORDS.iter().any(|word| name.contains(word))
+}
+
+/// The opening handshake request as it was sent to the server
+#[derive(Debug, Clone)]
+pub struct HandshakeRequest {
+    pub method: String,
+    pub url: String,
+    /// Final header set, including headers injected by the client itself.
+    /// The values of a name sent more than once are joined with `, `.
+    pub headers: HashMap<String, String>,
+}
+
+impl From<&Request> for HandshakeRequest {
+    fn from(request: &Request) -> Self {
+        HandshakeRequest {
+            method: request.method().to_string(),
+            url: request.uri().to_string(),
+            headers: joined(request.headers()),
+        }
+    }
+}
+
+/// The answer of the server to the opening handshake, also when it refused the upgrade
+#[derive(Debug, Clone)]
+pub struct HandshakeResponse {
+    pub status: u16,
+    /// The values of a name sent more than once are joined with `, `
+    pub headers: HashMap<String, String>,
+}
+
+impl<T> From<&Response<T>> for HandshakeResponse {
+    fn from(response: &Response<T>) -> Self {
+        HandshakeResponse {
+            status: response.status().as_u16(),
+            headers: joined(response.headers()),
+        }
+    }
+}
+
+fn joined(map: &HeaderMap) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (key, value) in map.iter() {
+        let value = value.to_str().unwrap_or_default();
+        headers
+            .entry(key.to_string())
+            .and_modify(|joined| {
+                joined.push_str(", ");
+                joined.push_str(value);
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    headers
+}
+
+/// Build the opening handshake request with custom headers, sent in their order.
+/// A name given more than once is sent once for every value.
+pub fn build_request(
+    url: &str,
+    custom_headers: &[(String, String)],
+    enable_extension: bool,
+    no_context_takeover: bool,
+) -> Result<Request> {
+    let mut request = url.into_client_request().map_err(|e| {
+        Error::new(
+            WebSocketError::HeaderError,
+            format!("Try to build request failed: {}", e),
+        )
+    })?;
+
+    let header = request.headers_mut();
+
+    for (key, value) in custom_headers {
+        // First parse the header name from the string
+        let header_name = match HeaderName::from_bytes(key.as_bytes()) {
+            Ok(name) => name,
+            Err(e) => {
+                return Err(Error::new(
+                    WebSocketError::HeaderError,
+                    format!("Invalid header name '{}': {}", key, e),
+                ));
+            }
+        };
+
+        // Then parse the header value
+        match value.parse() {
+            Ok(header_value) => {
+                header.append(header_name, header_value);
+            }
+            Err(e) => {
+                return Err(Error::new(
+                    WebSocketError::HeaderError,
+                    format!("Invalid header value for key '{}': {}", key, e),
+                ));
+            }
+        }
+    }
+
+    if enable_extension {
+        let extension = if no_context_takeover {
+            "permessage-deflate; client_max_window_bits; client_no_context_takeover"
+        } else {
+            "permessage-deflate; client_max_window_bits"
+        };
+        header.insert(
+            SEC_WEBSOCKET_EXTENSIONS,
+            HeaderValue::from_static(extension),
+        );
+    }
+
+    Ok(request)
+}
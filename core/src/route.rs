@@ -0,0 +1,255 @@
+use std::collections::HashMap;
+
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::Utf8Bytes;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// A path into a JSON document, like `$.type` or `$.data.items[0].id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct JsonPath(Vec<Segment>);
+
+impl JsonPath {
+    pub fn parse(path: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid JSON path: {}", path);
+        let mut rest = path.strip_prefix('$').ok_or_else(invalid)?;
+        let mut segments = Vec::new();
+        while !rest.is_empty() {
+            if let Some(after) = rest.strip_prefix('.') {
+                let end = after.find(['.', '[']).unwrap_or(after.len());
+                if end == 0 {
+                    return Err(invalid());
+                }
+                segments.push(Segment::Key(after[..end].to_string()));
+                rest = &after[end..];
+            } else if let Some(after) = rest.strip_prefix('[') {
+                let end = after.find(']').ok_or_else(invalid)?;
+                let inner = &after[..end];
+                let segment = match inner
+                    .strip_prefix(['"', '\''])
+                    .and_then(|key| key.strip_suffix(['"', '\'']))
+                {
+                    Some(key) => Segment::Key(key.to_string()),
+                    None => Segment::Index(inner.parse().map_err(|_| invalid())?),
+                };
+                segments.push(segment);
+                rest = &after[end + 1..];
+            } else {
+                return Err(invalid());
+            }
+        }
+        Ok(JsonPath(segments))
+    }
+
+    pub fn lookup<'a>(&self, value: &'a Value) -> Option<&'a Value> {
+        self.0
+            .iter()
+            .try_fold(value, |value, segment| match segment {
+                Segment::Key(key) => value.get(key),
+                Segment::Index(index) => value.get(index),
+            })
+    }
+}
+
+/// The value a route matches
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteValue {
+    String(String),
+    Number(f64),
+    Bool(bool),
+}
+
+impl RouteValue {
+    fn matches(&self, value: &Value) -> bool {
+        match (self, value) {
+            (RouteValue::String(expected), Value::String(actual)) => expected == actual,
+            (RouteValue::Number(expected), Value::Number(actual)) => {
+                actual.as_f64() == Some(*expected)
+            }
+            (RouteValue::Bool(expected), Value::Bool(actual)) => expected == actual,
+            _ => false,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Route<H> {
+    id: u64,
+    path: JsonPath,
+    value: RouteValue,
+    handler: H,
+}
+
+/// Handlers for text messages whose JSON field has a value.
+/// Every message is parsed once, however many routes there are.
+#[derive(Clone)]
+pub struct Router<H> {
+    next_id: u64,
+    routes: Vec<Route<H>>,
+}
+
+impl<H> Default for Router<H> {
+    fn default() -> Self {
+        Router {
+            next_id: 0,
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl<H> Router<H> {
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    /// Returns the id for [`Router::remove`]
+    pub fn add(&mut self, path: JsonPath, value: RouteValue, handler: H) -> u64 {
+        self.next_id += 1;
+        self.routes.push(Route {
+            id: self.next_id,
+            path,
+            value,
+            handler,
+        });
+        self.next_id
+    }
+
+    /// Returns whether the route existed
+    pub fn remove(&mut self, id: u64) -> bool {
+        let before = self.routes.len();
+        self.routes.retain(|route| route.id != id);
+        self.routes.len() != before
+    }
+
+    /// Handlers of every route matching `text`, in the order they were added.
+    /// Empty if `text` is not JSON.
+    pub fn matching(&self, text: &str) -> Vec<&H> {
+        if self.routes.is_empty() {
+            return Vec::new();
+        }
+        let Ok(document) = serde_json::from_str::<Value>(text) else {
+            return Vec::new();
+        };
+        self.routes
+            .iter()
+            .filter(|route| {
+                route
+                    .path
+                    .lookup(&document)
+                    .is_some_and(|value| route.value.matches(value))
+            })
+            .map(|route| &route.handler)
+            .collect()
+    }
+}
+
+/// The latest text message per value of a JSON field, e.g. per `$.symbol` of a ticker
+pub struct LatestCache {
+    path: JsonPath,
+    messages: HashMap<String, Utf8Bytes>,
+}
+
+impl LatestCache {
+    pub fn new(path: JsonPath) -> Self {
+        LatestCache {
+            path,
+            messages: HashMap::new(),
+        }
+    }
+
+    /// Keep `text` if it's JSON with a string, number or boolean at the path
+    pub fn insert(&mut self, text: &Utf8Bytes) {
+        let Ok(document) = serde_json::from_str::<Value>(text) else {
+            return;
+        };
+        let key = match self.path.lookup(&document) {
+            Some(Value::String(key)) => key.clone(),
+            Some(key @ (Value::Number(_) | Value::Bool(_))) => key.to_string(),
+            _ => return,
+        };
+        self.messages.insert(key, text.clone());
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Utf8Bytes> {
+        self.messages.get(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_lookup() {
+        let document: Value =
+            serde_json::from_str(r#"{"data":{"items":[{"id":7}],"a.b":true}}"#).unwrap();
+        let lookup = |path: &str| JsonPath::parse(path).unwrap().lookup(&document).cloned();
+        assert_eq!(lookup("$.data.items[0].id"), Some(Value::from(7)));
+        assert_eq!(lookup(r#"$.data["a.b"]"#), Some(Value::Bool(true)));
+        assert_eq!(lookup("$.data['items'][1]"), None);
+        assert_eq!(lookup("$.missing.id"), None);
+        assert_eq!(lookup("$"), Some(document.clone()));
+    }
+
+    #[test]
+    fn invalid_paths_are_rejected() {
+        for path in ["", "data", "$.", "$..a", "$[x]", "$[0", "$a"] {
+            assert!(JsonPath::parse(path).is_err(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn router_matches_values_in_order() {
+        let mut router = Router::default();
+        let path = || JsonPath::parse("$.type").unwrap();
+        router.add(path(), RouteValue::String("chat".to_string()), "first");
+        let second = router.add(path(), RouteValue::String("chat".to_string()), "second");
+        router.add(
+            JsonPath::parse("$.seq").unwrap(),
+            RouteValue::Number(3.0),
+            "seq",
+        );
+        router.add(
+            JsonPath::parse("$.ok").unwrap(),
+            RouteValue::Bool(true),
+            "ok",
+        );
+
+        assert_eq!(router.matching(r#"{"type":"chat"}"#), [&"first", &"second"]);
+        assert_eq!(router.matching(r#"{"seq":3,"ok":true}"#), [&"seq", &"ok"]);
+        // Values only match their own JSON type
+        assert!(router.matching(r#"{"seq":"3","ok":1}"#).is_empty());
+        assert!(router.matching("chat").is_empty());
+
+        assert!(router.remove(second));
+        assert!(!router.remove(second));
+        assert_eq!(router.matching(r#"{"type":"chat"}"#), [&"first"]);
+    }
+
+    #[test]
+    fn latest_cache_keeps_the_last_message_per_key() {
+        let mut cache = LatestCache::new(JsonPath::parse("$.symbol").unwrap());
+        for text in [
+            r#"{"symbol":"A","price":1}"#,
+            r#"{"symbol":"A","price":2}"#,
+            r#"{"symbol":7}"#,
+            r#"{"symbol":null}"#,
+            "not json",
+        ] {
+            cache.insert(&Utf8Bytes::from(text));
+        }
+        assert_eq!(
+            cache.get("A").map(Utf8Bytes::as_str),
+            Some(r#"{"symbol":"A","price":2}"#)
+        );
+        assert_eq!(
+            cache.get("7").map(Utf8Bytes::as_str),
+            Some(r#"{"symbol":7}"#)
+        );
+        assert!(cache.get("null").is_none());
+    }
+}
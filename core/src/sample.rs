@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+/// Deliver only some of the received data messages, e.g. of a ticker whose UI only needs the freshest value.
+/// Control frames are never sampled.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SampleOptions {
+    /// Deliver every nth data message, the ones in between are dropped
+    pub every: Option<u32>,
+    /// Deliver at most one data message per interval, the latest one.
+    /// A message held back is delivered once the interval is over.
+    pub throttle: Option<Duration>,
+}
+
+/// The latest-value slot of [`SampleOptions`]
+pub(crate) struct Sampler {
+    options: SampleOptions,
+    received: u64,
+    latest: Option<Message>,
+    delivered_at: Option<Instant>,
+}
+
+impl Sampler {
+    pub fn new(options: SampleOptions) -> Self {
+        Sampler {
+            options,
+            received: 0,
+            latest: None,
+            delivered_at: None,
+        }
+    }
+
+    /// The message to deliver now, if any
+    pub fn offer(&mut self, message: Message, now: Instant) -> Option<Message> {
+        if let Some(every) = self.options.every.filter(|every| *every > 1) {
+            self.received += 1;
+            if self.received % u64::from(every) != 0 {
+                return None;
+            }
+        }
+        let Some(throttle) = self.options.throttle else {
+            return Some(message);
+        };
+        if self
+            .delivered_at
+            .is_some_and(|delivered_at| now < delivered_at + throttle)
+        {
+            self.latest = Some(message);
+            return None;
+        }
+        self.delivered_at = Some(now);
+        self.latest = None;
+        Some(message)
+    }
+
+    /// When the held back message is due
+    pub fn due(&self) -> Option<Instant> {
+        self.latest.as_ref()?;
+        self.delivered_at
+            .zip(self.options.throttle)
+            .map(|(at, throttle)| at + throttle)
+    }
+
+    /// The held back message, it's delivered now
+    pub fn take(&mut self, now: Instant) -> Option<Message> {
+        let message = self.latest.take()?;
+        self.delivered_at = Some(now);
+        Some(message)
+    }
+}
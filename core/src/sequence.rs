@@ -0,0 +1,97 @@
+use serde_json::Value;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::JsonPath;
+
+/// Where the counter the server puts into every message is
+#[derive(Debug, Clone, PartialEq)]
+pub enum SequenceSource {
+    /// A number, or a string of digits, at this path of JSON text messages
+    Json(JsonPath),
+    /// A big-endian unsigned integer of `width` bytes, 1 to 8, at `offset` of binary messages
+    Binary { offset: usize, width: usize },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceAnomalyKind {
+    /// The counter skipped values, messages were lost on the way
+    Gap,
+    /// The counter didn't grow, a message was delivered again or out of order
+    Duplicate,
+}
+
+/// A counter which didn't follow the previous one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceAnomaly {
+    pub kind: SequenceAnomalyKind,
+    /// The counter after the previous one
+    pub expected: u64,
+    pub received: u64,
+    /// Values skipped by a gap, 0 for a duplicate
+    pub missing: u64,
+}
+
+/// Verifies the counters of the received messages of one connection.
+/// The first counter is taken as it is, messages without one are skipped.
+pub(crate) struct SequenceCheck {
+    source: SequenceSource,
+    last: Option<u64>,
+}
+
+impl SequenceCheck {
+    pub fn new(source: SequenceSource) -> Self {
+        SequenceCheck { source, last: None }
+    }
+
+    fn counter(&self, message: &Message) -> Option<u64> {
+        match (&self.source, message) {
+            (SequenceSource::Json(path), Message::Text(text)) => {
+                let value = serde_json::from_str::<Value>(text).ok()?;
+                match path.lookup(&value)? {
+                    Value::Number(number) => number.as_u64(),
+                    Value::String(digits) => digits.parse().ok(),
+                    _ => None,
+                }
+            }
+            (SequenceSource::Binary { offset, width }, Message::Binary(data)) => {
+                let bytes = data.get(*offset..offset.checked_add(*width)?)?;
+                Some(
+                    bytes
+                        .iter()
+                        .fold(0, |counter, byte| counter << 8 | u64::from(*byte)),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Take the counter of `message`, an anomaly if it doesn't follow the previous one.
+    /// After a gap the counter goes on from the received one, after a duplicate from the previous one.
+    pub fn check(&mut self, message: &Message) -> Option<SequenceAnomaly> {
+        let received = self.counter(message)?;
+        let Some(last) = self.last else {
+            self.last = Some(received);
+            return None;
+        };
+        let expected = last.wrapping_add(1);
+        if received == expected {
+            self.last = Some(received);
+            return None;
+        }
+        if received > expected {
+            self.last = Some(received);
+            return Some(SequenceAnomaly {
+                kind: SequenceAnomalyKind::Gap,
+                expected,
+                received,
+                missing: received - expected,
+            });
+        }
+        Some(SequenceAnomaly {
+            kind: SequenceAnomalyKind::Duplicate,
+            expected,
+            received,
+            missing: 0,
+        })
+    }
+}
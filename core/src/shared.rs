@@ -0,0 +1,245 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, PoisonError, RwLock, Weak,
+    },
+};
+
+use tokio_tungstenite::tungstenite::{http::Uri, Bytes};
+
+use crate::{BoxFuture, CloseInfo, ConnectOptions, Connection, Event, EventHandler, Result};
+
+/// Scheme and host in lower case, without default port and with at least `/` as path
+fn normalize_url(url: &str) -> String {
+    let Ok(uri) = url.parse::<Uri>() else {
+        return url.to_string();
+    };
+    let scheme = uri.scheme_str().unwrap_or_default().to_ascii_lowercase();
+    let host = uri.host().unwrap_or_default().to_ascii_lowercase();
+    let port = match (scheme.as_str(), uri.port_u16()) {
+        (_, None) | ("ws", Some(80)) | ("wss", Some(443)) => String::new(),
+        (_, Some(port)) => format!(":{}", port),
+    };
+    let path = match uri.path_and_query().map(|path| path.as_str()) {
+        None | Some("") => "/",
+        Some(path) => path,
+    };
+    format!("{}://{}{}{}", scheme, host, port, path)
+}
+
+/// Everything of `options` which changes the connection
+fn options_key(options: &ConnectOptions) -> String {
+    let bundles: Vec<_> = options
+        .tls
+        .ca_bundles
+        .iter()
+        .chain(&options.tls.client_identity)
+        .map(|bundle| (&bundle.path, &bundle.data, &bundle.password))
+        .collect();
+    format!(
+        "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {} {:?} {:?} {} {:?} {} {} {} {:?} {} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+        options.tls.backend,
+        options.tls.trust,
+        options.tls.cert_paths,
+        options.tls.ca_certs,
+        options.tls.ca_cert_uris,
+        options.tls.client_credential,
+        options.tls.crl_path,
+        options.tls.crls,
+        options.tls.allow_unknown_revocation,
+        options.tls.hostname,
+        options.tls.client_identity.is_some(),
+        options.tls.min_version,
+        options.tls.max_version,
+        options.tls.pins,
+        bundles,
+        options.headers,
+        options.enable_extension,
+        options.data_saver,
+        options.strict_rfc6455,
+        options.sample,
+        options.max_redirects,
+        options.reconnect,
+        options.coalesce,
+        options.pong_policy,
+        options.idle_timeout,
+        options.keep_alive,
+        options.close_timeout,
+        options.max_age,
+        options.close_grace,
+        options.oversized_control,
+        options.dns,
+        options.local,
+        options.sequence,
+        options.tcp,
+        options.proxy,
+        options.proxy_resolver,
+        options.send_before_connect
+    )
+}
+
+/// Delivers the events of a shared connection to all of its consumers
+#[derive(Default)]
+struct Fanout {
+    next_id: AtomicU64,
+    subscribers: RwLock<Vec<(u64, Arc<dyn EventHandler>)>>,
+}
+
+impl Fanout {
+    fn subscribe(&self, handler: Arc<dyn EventHandler>) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((id, handler));
+        id
+    }
+
+    /// Returns the number of consumers left
+    fn unsubscribe(&self, id: u64) -> usize {
+        let mut subscribers = self
+            .subscribers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscribers.retain(|(subscriber, _)| *subscriber != id);
+        subscribers.len()
+    }
+
+    fn snapshot(&self) -> Vec<Arc<dyn EventHandler>> {
+        self.subscribers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(_, handler)| handler.clone())
+            .collect()
+    }
+}
+
+impl EventHandler for Fanout {
+    fn handle(&self, event: Event) {
+        for handler in self.snapshot() {
+            handler.handle(event.clone());
+        }
+    }
+
+    /// The first consumer answering the ping decides the extra pong
+    fn ping(&self, payload: Bytes) -> BoxFuture<Option<Bytes>> {
+        let handlers = self.snapshot();
+        Box::pin(async move {
+            for handler in handlers {
+                if let Some(pong) = handler.ping(payload.clone()).await {
+                    return Some(pong);
+                }
+            }
+            None
+        })
+    }
+}
+
+fn registry() -> &'static Mutex<HashMap<String, Weak<SharedConnection>>> {
+    static REGISTRY: OnceLock<Mutex<HashMap<String, Weak<SharedConnection>>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// A connection used by several consumers at once
+pub struct SharedConnection {
+    key: String,
+    connection: Arc<Connection>,
+    fanout: Arc<Fanout>,
+    connecting: AtomicBool,
+}
+
+impl SharedConnection {
+    pub fn connection(&self) -> &Arc<Connection> {
+        &self.connection
+    }
+
+    /// Connect unless another consumer already did.
+    /// Consumers joining a connection which is already connecting or running return right away.
+    pub async fn connect(&self) -> Result<()> {
+        if self.connection.is_running() || self.connecting.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+        let result = self.connection.connect(self.fanout.clone()).await;
+        self.connecting.store(false, Ordering::SeqCst);
+        result
+    }
+
+    /// Close the connection and connect again, for all consumers, see [`Connection::restart`]
+    pub async fn restart(&self) -> Result<()> {
+        self.connection.restart(self.fanout.clone()).await
+    }
+
+    /// Wait until the running connection is over, see [`Connection::closed`]
+    pub async fn closed(&self) -> Option<CloseInfo> {
+        self.connection.closed().await
+    }
+}
+
+impl Drop for SharedConnection {
+    fn drop(&mut self) {
+        let mut registry = registry().lock().unwrap_or_else(PoisonError::into_inner);
+        if registry
+            .get(&self.key)
+            .is_some_and(|shared| shared.strong_count() == 0)
+        {
+            registry.remove(&self.key);
+        }
+    }
+}
+
+/// One consumer of a [`SharedConnection`]. The connection is closed when the last one is dropped.
+pub struct SharedConsumer {
+    shared: Arc<SharedConnection>,
+    id: u64,
+}
+
+impl SharedConsumer {
+    /// Join the connection to the same normalized `url` with equal `options`,
+    /// a new one is created when there is none.
+    /// A client key set on the connection is not part of the comparison.
+    pub fn join(
+        url: String,
+        options: ConnectOptions,
+        handler: Arc<dyn EventHandler>,
+    ) -> SharedConsumer {
+        let key = format!("{} {}", normalize_url(&url), options_key(&options));
+        // Dropped after the registry is unlocked, its drop needs the registry as well
+        let mut closing = None;
+        let (shared, id) = {
+            let mut registry = registry().lock().unwrap_or_else(PoisonError::into_inner);
+            let shared = match registry.get(&key).and_then(Weak::upgrade) {
+                Some(shared) if !shared.fanout.snapshot().is_empty() => shared,
+                // A connection without consumers is already closing
+                existing => {
+                    closing = existing;
+                    let shared = Arc::new(SharedConnection {
+                        key: key.clone(),
+                        connection: Arc::new(Connection::new(url, options)),
+                        fanout: Arc::new(Fanout::default()),
+                        connecting: AtomicBool::new(false),
+                    });
+                    registry.insert(key, Arc::downgrade(&shared));
+                    shared
+                }
+            };
+            let id = shared.fanout.subscribe(handler);
+            (shared, id)
+        };
+        drop(closing);
+        SharedConsumer { shared, id }
+    }
+
+    pub fn shared(&self) -> Arc<SharedConnection> {
+        self.shared.clone()
+    }
+}
+
+impl Drop for SharedConsumer {
+    fn drop(&mut self) {
+        if self.shared.fanout.unsubscribe(self.id) == 0 {
+            self.shared.connection.link().try_close();
+        }
+    }
+}
@@ -0,0 +1,191 @@
+use std::{fmt, future::Future, sync::Arc};
+
+use tokio::runtime::{Handle, RuntimeFlavor};
+
+/// Signature scheme of a client key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignScheme {
+    EcdsaP256Sha256,
+    EcdsaP384Sha384,
+    RsaPkcs1Sha256,
+    RsaPssSha256,
+    Ed25519,
+}
+
+impl SignScheme {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SignScheme::EcdsaP256Sha256 => "ecdsa-p256-sha256",
+            SignScheme::EcdsaP384Sha384 => "ecdsa-p384-sha384",
+            SignScheme::RsaPkcs1Sha256 => "rsa-pkcs1-sha256",
+            SignScheme::RsaPssSha256 => "rsa-pss-sha256",
+            SignScheme::Ed25519 => "ed25519",
+        }
+    }
+}
+
+/// Performs the TLS client-auth signature with a key which is kept elsewhere,
+/// e.g. in HUKS, so the private key never enters this process.
+/// It's called from the connection task and may block until the signature is ready,
+/// see [`block_on_signature`] for a signature which is produced asynchronously.
+pub trait ExternalSigner: Send + Sync {
+    fn sign(&self, scheme: SignScheme, message: &[u8]) -> Result<Vec<u8>, String>;
+}
+
+/// Wait in [`ExternalSigner::sign`] for a signature which is produced asynchronously, e.g. by a JS callback.
+/// The TLS handshake asks for the signature synchronously, so the runtime worker running the connection
+/// is parked until it's there, other tasks move to the remaining workers meanwhile.
+/// That needs the multi-thread tokio runtime, on any other runtime or outside of one it fails instead.
+pub fn block_on_signature<F>(signature: F) -> Result<Vec<u8>, String>
+where
+    F: Future<Output = Result<Vec<u8>, String>>,
+{
+    let handle = Handle::try_current()
+        .map_err(|_| "signing with an external key needs a tokio runtime".to_string())?;
+    if handle.runtime_flavor() != RuntimeFlavor::MultiThread {
+        return Err(
+            "signing with an external key needs the multi-thread tokio runtime".to_string(),
+        );
+    }
+    tokio::task::block_in_place(|| handle.block_on(signature))
+}
+
+/// Client certificate whose private key is only reachable through an [`ExternalSigner`]
+#[derive(Clone)]
+pub struct ClientKey {
+    /// Certificate chain, leaf first, each one PEM or DER encoded
+    pub cert_chain: Vec<Vec<u8>>,
+    pub scheme: SignScheme,
+    pub signer: Arc<dyn ExternalSigner>,
+}
+
+impl fmt::Debug for ClientKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientKey")
+            .field("cert_chain", &self.cert_chain.len())
+            .field("scheme", &self.scheme)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "rustls")]
+pub(crate) mod rustls_impl {
+    use std::{fmt, sync::Arc};
+
+    use rustls::{
+        client::ResolvesClientCert,
+        sign::{CertifiedKey, Signer, SigningKey},
+        SignatureAlgorithm, SignatureScheme,
+    };
+
+    use super::{ExternalSigner, SignScheme};
+
+    fn to_rustls(scheme: SignScheme) -> SignatureScheme {
+        match scheme {
+            SignScheme::EcdsaP256Sha256 => SignatureScheme::ECDSA_NISTP256_SHA256,
+            SignScheme::EcdsaP384Sha384 => SignatureScheme::ECDSA_NISTP384_SHA384,
+            SignScheme::RsaPkcs1Sha256 => SignatureScheme::RSA_PKCS1_SHA256,
+            SignScheme::RsaPssSha256 => SignatureScheme::RSA_PSS_SHA256,
+            SignScheme::Ed25519 => SignatureScheme::ED25519,
+        }
+    }
+
+    #[derive(Clone)]
+    struct ExternalKey {
+        signer: Arc<dyn ExternalSigner>,
+        scheme: SignScheme,
+    }
+
+    impl fmt::Debug for ExternalKey {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("ExternalKey")
+                .field("scheme", &self.scheme)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl SigningKey for ExternalKey {
+        fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+            if offered.contains(&to_rustls(self.scheme)) {
+                Some(Box::new(self.clone()))
+            } else {
+                None
+            }
+        }
+
+        fn algorithm(&self) -> SignatureAlgorithm {
+            match self.scheme {
+                SignScheme::EcdsaP256Sha256 | SignScheme::EcdsaP384Sha384 => {
+                    SignatureAlgorithm::ECDSA
+                }
+                SignScheme::RsaPkcs1Sha256 | SignScheme::RsaPssSha256 => SignatureAlgorithm::RSA,
+                SignScheme::Ed25519 => SignatureAlgorithm::ED25519,
+            }
+        }
+    }
+
+    impl Signer for ExternalKey {
+        fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+            self.signer
+                .sign(self.scheme, message)
+                .map_err(rustls::Error::General)
+        }
+
+        fn scheme(&self) -> SignatureScheme {
+            to_rustls(self.scheme)
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct ExternalClientCert(Arc<CertifiedKey>);
+
+    impl ExternalClientCert {
+        pub fn new(
+            cert_chain: Vec<rustls::pki_types::CertificateDer<'static>>,
+            signer: Arc<dyn ExternalSigner>,
+            scheme: SignScheme,
+        ) -> Self {
+            let key = Arc::new(ExternalKey { signer, scheme });
+            ExternalClientCert(Arc::new(CertifiedKey::new(cert_chain, key)))
+        }
+    }
+
+    impl ResolvesClientCert for ExternalClientCert {
+        fn resolve(
+            &self,
+            _root_hint_subjects: &[&[u8]],
+            sigschemes: &[SignatureScheme],
+        ) -> Option<Arc<CertifiedKey>> {
+            self.0.key.choose_scheme(sigschemes).map(|_| self.0.clone())
+        }
+
+        fn has_certs(&self) -> bool {
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn signing_waits_on_the_multi_thread_runtime() {
+        let signature = block_on_signature(async {
+            tokio::task::yield_now().await;
+            Ok(vec![1, 2, 3])
+        });
+        assert_eq!(signature, Ok(vec![1, 2, 3]));
+    }
+
+    #[tokio::test]
+    async fn signing_fails_on_the_current_thread_runtime() {
+        let error = block_on_signature(async { Ok(Vec::new()) }).unwrap_err();
+        assert!(error.contains("multi-thread"), "{}", error);
+    }
+
+    #[test]
+    fn signing_fails_outside_of_a_runtime() {
+        assert!(block_on_signature(async { Ok(Vec::new()) }).is_err());
+    }
+}
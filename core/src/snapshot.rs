@@ -0,0 +1,245 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde_json::{Map, Value};
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+use crate::{ReconnectPolicy, SendOptions};
+
+/// Version of the format [`SessionState::pack`] writes, newer ones aren't read
+const VERSION: u64 = 1;
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// What a connection needs to carry on after the process was killed, see [`crate::Connection::export_state`].
+/// The connection itself isn't part of it, it's made again with a connect.
+#[derive(Debug, Clone)]
+pub struct SessionState {
+    pub url: String,
+    pub tag: HashMap<String, String>,
+    pub reconnect: Option<ReconnectPolicy>,
+    /// The remembered messages, e.g. the subscriptions, in the order they were remembered
+    pub remembered: Vec<Message>,
+    /// Data messages which weren't written yet, with what was left of their ttl
+    pub queued: Vec<(Message, SendOptions)>,
+    pub last_message_id: u64,
+    pub last_remembered_id: u64,
+    pub last_envelope_id: u64,
+    /// Milliseconds since the Unix epoch
+    pub exported_at_ms: f64,
+}
+
+impl SessionState {
+    /// The state as a JSON text, binary messages are base64
+    pub fn pack(&self) -> String {
+        let mut object = Map::new();
+        object.insert("version".to_string(), Value::from(VERSION));
+        object.insert("url".to_string(), Value::String(self.url.clone()));
+        object.insert(
+            "tag".to_string(),
+            Value::Object(
+                self.tag
+                    .iter()
+                    .map(|(key, value)| (key.clone(), Value::String(value.clone())))
+                    .collect(),
+            ),
+        );
+        if let Some(policy) = &self.reconnect {
+            object.insert("reconnect".to_string(), pack_policy(policy));
+        }
+        object.insert(
+            "remembered".to_string(),
+            self.remembered.iter().map(pack_message).collect(),
+        );
+        object.insert(
+            "queued".to_string(),
+            self.queued
+                .iter()
+                .map(|(message, options)| {
+                    let mut queued = Map::new();
+                    queued.insert("message".to_string(), pack_message(message));
+                    queued.insert("urgent".to_string(), Value::Bool(options.urgent));
+                    queued.insert("retries".to_string(), Value::from(options.retries));
+                    if let Some(tag) = &options.tag {
+                        queued.insert("tag".to_string(), Value::String(tag.clone()));
+                    }
+                    if let Some(ttl) = options.ttl {
+                        queued.insert("ttlMs".to_string(), Value::from(ttl.as_millis() as u64));
+                    }
+                    Value::Object(queued)
+                })
+                .collect(),
+        );
+        object.insert(
+            "lastMessageId".to_string(),
+            Value::from(self.last_message_id),
+        );
+        object.insert(
+            "lastRememberedId".to_string(),
+            Value::from(self.last_remembered_id),
+        );
+        object.insert(
+            "lastEnvelopeId".to_string(),
+            Value::from(self.last_envelope_id),
+        );
+        object.insert("exportedAt".to_string(), Value::from(self.exported_at_ms));
+        Value::Object(object).to_string()
+    }
+
+    /// `None` unless `text` was written by [`SessionState::pack`] of this version or an older one
+    pub fn unpack(text: &str) -> Option<Self> {
+        let Ok(Value::Object(object)) = serde_json::from_str::<Value>(text) else {
+            return None;
+        };
+        if object.get("version")?.as_u64()? > VERSION {
+            return None;
+        }
+        let tag = match object.get("tag") {
+            Some(Value::Object(tag)) => tag
+                .iter()
+                .map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect::<Option<_>>()?,
+            _ => HashMap::new(),
+        };
+        let reconnect = match object.get("reconnect") {
+            Some(policy) => Some(unpack_policy(policy)?),
+            None => None,
+        };
+        let remembered = object
+            .get("remembered")?
+            .as_array()?
+            .iter()
+            .map(unpack_message)
+            .collect::<Option<_>>()?;
+        let queued = object
+            .get("queued")?
+            .as_array()?
+            .iter()
+            .map(|queued| {
+                let message = unpack_message(queued.get("message")?)?;
+                let defaults = SendOptions::default();
+                let options = SendOptions {
+                    urgent: queued
+                        .get("urgent")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(defaults.urgent),
+                    retries: queued
+                        .get("retries")
+                        .and_then(Value::as_u64)
+                        .map_or(defaults.retries, |retries| retries as u32),
+                    tag: queued
+                        .get("tag")
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    ttl: queued
+                        .get("ttlMs")
+                        .and_then(Value::as_u64)
+                        .map(Duration::from_millis),
+                };
+                Some((message, options))
+            })
+            .collect::<Option<_>>()?;
+        let counter = |key: &str| object.get(key).and_then(Value::as_u64).unwrap_or(0);
+        Some(SessionState {
+            url: object.get("url")?.as_str()?.to_string(),
+            tag,
+            reconnect,
+            remembered,
+            queued,
+            last_message_id: counter("lastMessageId"),
+            last_remembered_id: counter("lastRememberedId"),
+            last_envelope_id: counter("lastEnvelopeId"),
+            exported_at_ms: object
+                .get("exportedAt")
+                .and_then(Value::as_f64)
+                .unwrap_or(0.0),
+        })
+    }
+}
+
+fn pack_policy(policy: &ReconnectPolicy) -> Value {
+    let mut object = Map::new();
+    object.insert(
+        "initialDelayMs".to_string(),
+        Value::from(policy.initial_delay.as_millis() as u64),
+    );
+    object.insert(
+        "maxDelayMs".to_string(),
+        Value::from(policy.max_delay.as_millis() as u64),
+    );
+    object.insert("multiplier".to_string(), Value::from(policy.multiplier));
+    if let Some(max_attempts) = policy.max_attempts {
+        object.insert("maxAttempts".to_string(), Value::from(max_attempts));
+    }
+    Value::Object(object)
+}
+
+fn unpack_policy(value: &Value) -> Option<ReconnectPolicy> {
+    Some(ReconnectPolicy {
+        initial_delay: Duration::from_millis(value.get("initialDelayMs")?.as_u64()?),
+        max_delay: Duration::from_millis(value.get("maxDelayMs")?.as_u64()?),
+        multiplier: value.get("multiplier")?.as_f64()?,
+        max_attempts: match value.get("maxAttempts") {
+            Some(max_attempts) => Some(u32::try_from(max_attempts.as_u64()?).ok()?),
+            None => None,
+        },
+    })
+}
+
+/// `{"text": ".."}` or `{"binary": "<base64>"}`, only data messages are kept
+fn pack_message(message: &Message) -> Value {
+    let mut object = Map::new();
+    match message {
+        Message::Text(text) => {
+            object.insert("text".to_string(), Value::String(text.to_string()));
+        }
+        other => {
+            object.insert(
+                "binary".to_string(),
+                Value::String(encode_base64(&other.clone().into_data())),
+            );
+        }
+    }
+    Value::Object(object)
+}
+
+fn unpack_message(value: &Value) -> Option<Message> {
+    if let Some(text) = value.get("text") {
+        return Some(Message::text(text.as_str()?));
+    }
+    let data = decode_base64(value.get("binary")?.as_str()?)?;
+    Some(Message::binary(data))
+}
+
+pub(crate) fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let block = chunk.iter().enumerate().fold(0u32, |block, (i, byte)| {
+            block | (u32::from(*byte) << (16 - 8 * i))
+        });
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[((block >> (18 - 6 * i)) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+pub(crate) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text = text.trim_end_matches('=');
+    let mut decoded = Vec::with_capacity(text.len() * 3 / 4);
+    let mut block = 0u32;
+    let mut bits = 0;
+    for byte in text.bytes() {
+        let value = BASE64.iter().position(|symbol| *symbol == byte)? as u32;
+        block = (block << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((block >> bits) as u8);
+            block &= (1 << bits) - 1;
+        }
+    }
+    Some(decoded)
+}
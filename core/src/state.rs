@@ -0,0 +1,72 @@
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyState {
+    Connecting = 0,
+    Open = 1,
+    Closing = 2,
+    Closed = 3,
+}
+
+impl From<u8> for ReadyState {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ReadyState::Connecting,
+            1 => ReadyState::Open,
+            2 => ReadyState::Closing,
+            _ => ReadyState::Closed,
+        }
+    }
+}
+
+/// The detailed state of a connection, e.g. for a connection status in the UI
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    /// Resolving the host and making the tcp connection
+    Connecting = 0,
+    TlsHandshake = 1,
+    /// Waiting for the server to accept the http upgrade
+    Upgrading = 2,
+    Open = 3,
+    Closing = 4,
+    Closed = 5,
+    /// Waiting for the delay before making an abnormally ended connection again
+    Reconnecting = 6,
+}
+
+impl From<u8> for ConnState {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ConnState::Connecting,
+            1 => ConnState::TlsHandshake,
+            2 => ConnState::Upgrading,
+            3 => ConnState::Open,
+            4 => ConnState::Closing,
+            6 => ConnState::Reconnecting,
+            _ => ConnState::Closed,
+        }
+    }
+}
+
+impl From<ReadyState> for ConnState {
+    fn from(state: ReadyState) -> Self {
+        match state {
+            ReadyState::Connecting => ConnState::Connecting,
+            ReadyState::Open => ConnState::Open,
+            ReadyState::Closing => ConnState::Closing,
+            ReadyState::Closed => ConnState::Closed,
+        }
+    }
+}
+
+impl From<ConnState> for ReadyState {
+    fn from(state: ConnState) -> Self {
+        match state {
+            ConnState::Connecting
+            | ConnState::TlsHandshake
+            | ConnState::Upgrading
+            | ConnState::Reconnecting => ReadyState::Connecting,
+            ConnState::Open => ReadyState::Open,
+            ConnState::Closing => ReadyState::Closing,
+            ConnState::Closed => ReadyState::Closed,
+        }
+    }
+}
@@ -0,0 +1,1044 @@
+use std::{
+    fmt,
+    fs::File,
+    io::Read,
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+};
+
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_tungstenite::MaybeTlsStream;
+
+use crate::{
+    snapshot::decode_base64, ClientKey, Error, HostnamePolicy, Result, StoredCredential,
+    WebSocketError,
+};
+
+/// Most connectors kept by [`TlsConnector::cached`], the oldest one is dropped beyond
+const MAX_CACHED_CONNECTORS: usize = 32;
+
+/// The CA store of OpenHarmony, a PEM file for every certificate
+const SYSTEM_CA_DIR: &str = "/etc/security/certificates";
+
+#[cfg(not(any(feature = "native-tls", feature = "rustls")))]
+compile_error!("at least one of the `native-tls` and `rustls` features must be enabled");
+
+/// Implementation of the TLS layer, selected by cargo features at build time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsBackend {
+    NativeTls,
+    Rustls,
+}
+
+impl TlsBackend {
+    /// `native-tls` when compiled in, otherwise `rustls`
+    pub fn default_backend() -> TlsBackend {
+        if cfg!(feature = "native-tls") {
+            TlsBackend::NativeTls
+        } else {
+            TlsBackend::Rustls
+        }
+    }
+
+    pub fn available() -> Vec<TlsBackend> {
+        let mut backends = Vec::new();
+        if cfg!(feature = "native-tls") {
+            backends.push(TlsBackend::NativeTls);
+        }
+        if cfg!(feature = "rustls") {
+            backends.push(TlsBackend::Rustls);
+        }
+        backends
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TlsBackend::NativeTls => "native-tls",
+            TlsBackend::Rustls => "rustls",
+        }
+    }
+}
+
+/// A TLS protocol version, older ones are never negotiated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+/// Which certificates a server certificate may chain up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsTrust {
+    /// The CA store of the system
+    System,
+    /// Only `cert_paths`, `ca_certs` and `ca_bundles`
+    Custom,
+    /// The CA store of the system and the custom certificates
+    Both,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsOptions {
+    /// TLS implementation, the default backend if empty
+    pub backend: Option<TlsBackend>,
+
+    /// Where trusted certificates come from, [`TlsTrust::Both`] if empty
+    pub trust: Option<TlsTrust>,
+
+    /// Trusted CA certificate files, each one PEM or DER encoded. A PEM file may hold several certificates,
+    /// e.g. an intermediate and its root, and a directory stands for all files in it.
+    pub cert_paths: Vec<String>,
+
+    /// Additional trusted CA certificates, each one PEM or DER encoded
+    pub ca_certs: Vec<Vec<u8>>,
+
+    /// Trusted CA certificates of the [`crate::CertificateStore`], looked up by URI on every connect
+    pub ca_cert_uris: Vec<String>,
+
+    /// Client certificate for mutual TLS, signing through an external key. Needs `rustls`.
+    pub client_key: Option<ClientKey>,
+
+    /// Client certificate of the [`crate::CertificateStore`], looked up by URI on every connect.
+    /// Its key signs inside the store. Needs `rustls`.
+    pub client_credential: Option<StoredCredential>,
+
+    /// Certificate revocation list file path. Needs `rustls`.
+    pub crl_path: Option<String>,
+
+    /// Certificate revocation lists, each one PEM or DER encoded. Needs `rustls`.
+    pub crls: Vec<Vec<u8>>,
+
+    /// Accept certificates whose issuer is not covered by any of the revocation lists
+    pub allow_unknown_revocation: bool,
+
+    /// Names of the server certificate accepted for the host. Needs `rustls` unless default.
+    pub hostname: HostnamePolicy,
+
+    /// Password protected PKCS#12 bundles whose certificates are trusted as CAs. Needs `rustls`.
+    pub ca_bundles: Vec<Pkcs12Bundle>,
+
+    /// Password protected PKCS#12 bundle with the client certificate and its private key
+    pub client_identity: Option<Pkcs12Bundle>,
+
+    /// Oldest and newest version which is negotiated, the backend decides if `None`.
+    /// A minimum of TLS 1.3 needs `rustls`.
+    pub min_version: Option<TlsVersion>,
+    pub max_version: Option<TlsVersion>,
+
+    /// SHA-256 hashes of the SubjectPublicKeyInfo of the server certificate, one of them must match.
+    /// It's checked right after the TLS handshake, before anything is sent. Empty pins nothing.
+    pub pins: Vec<[u8; 32]>,
+}
+
+/// A `.p12`/`.pfx` file, read from `path` unless `data` is given
+#[derive(Clone, Default)]
+pub struct Pkcs12Bundle {
+    pub path: Option<String>,
+    pub data: Option<Vec<u8>>,
+    pub password: String,
+}
+
+impl fmt::Debug for Pkcs12Bundle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pkcs12Bundle")
+            .field("path", &self.path)
+            .field("data", &self.data.as_ref().map(Vec::len))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Pkcs12Bundle {
+    fn read(&self) -> Result<Vec<u8>> {
+        match (&self.data, &self.path) {
+            (Some(data), _) => Ok(data.clone()),
+            (None, Some(path)) => read_cert_file(path),
+            (None, None) => Err(Error::new(
+                WebSocketError::TlsError,
+                "PKCS#12 bundle needs a path or data",
+            )),
+        }
+    }
+}
+
+fn pkcs12_error(e: impl fmt::Display) -> Error {
+    Error::new(
+        WebSocketError::TlsError,
+        format!("Try to open PKCS#12 bundle failed: {}", e),
+    )
+}
+
+impl TlsOptions {
+    /// A pin as written by `openssl ... | base64`: the base64 SHA-256 of the SubjectPublicKeyInfo,
+    /// optionally prefixed with `sha256/`
+    pub fn parse_pin(pin: &str) -> Result<[u8; 32]> {
+        let encoded = pin.trim();
+        let encoded = encoded.strip_prefix("sha256/").unwrap_or(encoded);
+        decode_base64(encoded)
+            .and_then(|hash| <[u8; 32]>::try_from(hash).ok())
+            .ok_or_else(|| {
+                Error::new(
+                    WebSocketError::TlsError,
+                    format!("invalid pin, expected a base64 SHA-256 hash: {}", pin),
+                )
+            })
+    }
+
+    /// Nothing was configured, the system trust and defaults of the backend apply
+    pub(crate) fn is_default(&self) -> bool {
+        self.backend.is_none()
+            && self.trust.is_none()
+            && self.cert_paths.is_empty()
+            && self.ca_certs.is_empty()
+            && self.ca_cert_uris.is_empty()
+            && self.client_key.is_none()
+            && self.client_credential.is_none()
+            && !self.checks_revocation()
+            && !self.allow_unknown_revocation
+            && self.hostname.is_default()
+            && self.ca_bundles.is_empty()
+            && self.client_identity.is_none()
+            && self.min_version.is_none()
+            && self.max_version.is_none()
+            && self.pins.is_empty()
+    }
+
+    fn has_custom_certs(&self) -> bool {
+        !self.cert_paths.is_empty() || !self.ca_certs.is_empty() || !self.ca_bundles.is_empty()
+    }
+
+    /// [`TlsOptions::trust`], an error when it contradicts the certificates given
+    fn checked_trust(&self) -> Result<TlsTrust> {
+        match self.trust.unwrap_or(TlsTrust::Both) {
+            TlsTrust::System if self.has_custom_certs() => Err(Error::new(
+                WebSocketError::TlsError,
+                "custom certificates are given, but only the system store is trusted",
+            )),
+            TlsTrust::Custom if !self.has_custom_certs() => Err(Error::new(
+                WebSocketError::TlsError,
+                "only custom certificates are trusted, but none are given",
+            )),
+            trust => Ok(trust),
+        }
+    }
+
+    fn checks_revocation(&self) -> bool {
+        self.crl_path.is_some() || !self.crls.is_empty()
+    }
+
+    /// How the handshake checks revocation with these options
+    pub(crate) fn revocation_mode(&self) -> RevocationMode {
+        match (self.checks_revocation(), self.allow_unknown_revocation) {
+            (false, _) => RevocationMode::Off,
+            (true, false) => RevocationMode::Required,
+            (true, true) => RevocationMode::AllowUnknown,
+        }
+    }
+}
+
+/// How the server certificate chain is checked for revocation, as the options configure it.
+/// It's not a result of the check: the handshake fails for a revoked certificate either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RevocationMode {
+    /// No revocation list was configured, nothing is checked
+    Off,
+    /// Every certificate has to be covered by a revocation list
+    Required,
+    /// Certificates which no revocation list covers are accepted, see [`TlsOptions::allow_unknown_revocation`]
+    AllowUnknown,
+}
+
+/// A ready to use tls client configuration of one backend
+#[derive(Clone)]
+pub struct TlsConnector {
+    backend: BackendConnector,
+    /// See [`TlsOptions::pins`]
+    pins: Arc<[[u8; 32]]>,
+}
+
+#[derive(Clone)]
+enum BackendConnector {
+    #[cfg(feature = "native-tls")]
+    NativeTls(native_tls::TlsConnector),
+    #[cfg(feature = "rustls")]
+    Rustls(Arc<rustls::ClientConfig>),
+}
+
+/// Properties of an established TLS session. What a backend can't tell is empty.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    pub backend: TlsBackend,
+    /// e.g. `TLSv1.3`
+    pub protocol: Option<String>,
+    /// IANA name, e.g. `TLS13_AES_128_GCM_SHA256`
+    pub cipher_suite: Option<String>,
+    pub resumed: Option<bool>,
+    /// Validity of the server certificate, milliseconds since the unix epoch
+    pub not_before_ms: Option<f64>,
+    pub not_after_ms: Option<f64>,
+    /// The configured revocation check the handshake passed
+    pub revocation_mode: RevocationMode,
+}
+
+impl TlsInfo {
+    /// Inspect the stream of a connection made with `options`, `None` for plain `ws://`
+    pub(crate) fn from_stream(
+        stream: &MaybeTlsStream<TcpStream>,
+        options: &TlsOptions,
+    ) -> Option<TlsInfo> {
+        match stream {
+            #[cfg(feature = "native-tls")]
+            MaybeTlsStream::NativeTls(tls) => {
+                let mut info = TlsInfo {
+                    backend: TlsBackend::NativeTls,
+                    protocol: None,
+                    cipher_suite: None,
+                    resumed: None,
+                    not_before_ms: None,
+                    not_after_ms: None,
+                    revocation_mode: options.revocation_mode(),
+                };
+                if let Ok(Some(cert)) = tls.get_ref().peer_certificate() {
+                    if let Ok(der) = cert.to_der() {
+                        info.set_validity(&der);
+                    }
+                }
+                Some(info)
+            }
+            #[cfg(feature = "rustls")]
+            MaybeTlsStream::Rustls(tls) => {
+                let connection = tls.get_ref().1;
+                let mut info = TlsInfo {
+                    backend: TlsBackend::Rustls,
+                    protocol: connection.protocol_version().map(|version| match version {
+                        rustls::ProtocolVersion::TLSv1_2 => "TLSv1.2".to_string(),
+                        rustls::ProtocolVersion::TLSv1_3 => "TLSv1.3".to_string(),
+                        other => format!("{:?}", other),
+                    }),
+                    cipher_suite: connection
+                        .negotiated_cipher_suite()
+                        .map(|suite| format!("{:?}", suite.suite())),
+                    resumed: connection
+                        .handshake_kind()
+                        .map(|kind| matches!(kind, rustls::HandshakeKind::Resumed)),
+                    not_before_ms: None,
+                    not_after_ms: None,
+                    revocation_mode: options.revocation_mode(),
+                };
+                if let Some(cert) = connection
+                    .peer_certificates()
+                    .and_then(|certs| certs.first())
+                {
+                    info.set_validity(cert);
+                }
+                Some(info)
+            }
+            _ => None,
+        }
+    }
+
+    fn set_validity(&mut self, der: &[u8]) {
+        if let Ok((_, cert)) = x509_parser::parse_x509_certificate(der) {
+            let validity = cert.validity();
+            self.not_before_ms = Some(validity.not_before.timestamp() as f64 * 1000.0);
+            self.not_after_ms = Some(validity.not_after.timestamp() as f64 * 1000.0);
+        }
+    }
+}
+
+fn read_cert_file(cert_path: &str) -> Result<Vec<u8>> {
+    let mut cert_data = Vec::new();
+    File::open(cert_path)
+        .map_err(|e| {
+            Error::new(
+                WebSocketError::TlsError,
+                format!("Try to open cert file path failed: {}", e),
+            )
+        })?
+        .read_to_end(&mut cert_data)
+        .map_err(|e| {
+            Error::new(
+                WebSocketError::TlsError,
+                format!("Try to read cert file failed: {}", e),
+            )
+        })?;
+    Ok(cert_data)
+}
+
+/// The certificates of the system CA store, read once.
+/// Empty where there is none, e.g. when the core runs on a development machine.
+fn system_ca_certs() -> &'static [Vec<u8>] {
+    static CERTS: OnceLock<Vec<Vec<u8>>> = OnceLock::new();
+    CERTS.get_or_init(|| {
+        let Ok(entries) = std::fs::read_dir(SYSTEM_CA_DIR) else {
+            return Vec::new();
+        };
+        let mut paths: Vec<_> = entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .filter_map(|path| std::fs::read(path).ok())
+            .collect()
+    })
+}
+
+/// The contents of the files of `cert_paths`, the ones of a directory in the order of their names
+fn read_cert_paths(cert_paths: &[String]) -> Result<Vec<Vec<u8>>> {
+    let mut files = Vec::new();
+    for cert_path in cert_paths {
+        if !std::path::Path::new(cert_path).is_dir() {
+            files.push(read_cert_file(cert_path)?);
+            continue;
+        }
+        let mut paths: Vec<_> = std::fs::read_dir(cert_path)
+            .map_err(|e| {
+                Error::new(
+                    WebSocketError::TlsError,
+                    format!("Try to read cert directory {} failed: {}", cert_path, e),
+                )
+            })?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.is_file())
+            .collect();
+        if paths.is_empty() {
+            return Err(Error::new(
+                WebSocketError::TlsError,
+                format!("cert directory {} has no files", cert_path),
+            ));
+        }
+        paths.sort();
+        for path in paths {
+            files.push(read_cert_file(&path.to_string_lossy())?);
+        }
+    }
+    Ok(files)
+}
+
+fn is_pem(data: &[u8]) -> bool {
+    data.trim_ascii_start().starts_with(b"-----BEGIN")
+}
+
+fn parse_cert_error(e: impl std::fmt::Display) -> Error {
+    Error::new(
+        WebSocketError::TlsError,
+        format!("Try to parse cert file failed: {}", e),
+    )
+}
+
+impl TlsConnector {
+    /// Build a connector which also trusts the certificates given in `options`
+    pub fn build(options: &TlsOptions) -> Result<TlsConnector> {
+        let backend = options.backend.unwrap_or_else(TlsBackend::default_backend);
+        let backend = match backend {
+            #[cfg(feature = "native-tls")]
+            TlsBackend::NativeTls => build_native_tls(options)?,
+            #[cfg(feature = "rustls")]
+            TlsBackend::Rustls => build_rustls(options)?,
+            #[allow(unreachable_patterns)]
+            _ => {
+                return Err(Error::new(
+                    WebSocketError::TlsError,
+                    format!("tls backend {} is not compiled in", backend.name()),
+                ))
+            }
+        };
+        Ok(TlsConnector {
+            backend,
+            pins: options.pins.as_slice().into(),
+        })
+    }
+
+    /// The connector built before for the same options, so files aren't read and parsed again on every connect.
+    /// Files count by their path, [`TlsConnector::clear_cache`] after one was replaced. Failed builds aren't kept.
+    /// With `rustls` the connections of one connector also share their sessions for resumption.
+    pub fn cached(options: &TlsOptions) -> Result<TlsConnector> {
+        let cache = connector_cache();
+        let key = cache_key(options);
+        let found = cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|(cached, _)| *cached == key)
+            .map(|(_, connector)| connector.clone());
+        if let Some(connector) = found {
+            return Ok(connector);
+        }
+        // Built outside the lock, reading files may take a while
+        let connector = TlsConnector::build(options)?;
+        let mut cache = cache.lock().unwrap_or_else(PoisonError::into_inner);
+        if !cache.iter().any(|(cached, _)| *cached == key) {
+            if cache.len() == MAX_CACHED_CONNECTORS {
+                cache.remove(0);
+            }
+            cache.push((key, connector.clone()));
+        }
+        Ok(connector)
+    }
+
+    /// Forget the connectors of [`TlsConnector::cached`], e.g. after a certificate file was replaced.
+    /// Running connections keep theirs.
+    pub fn clear_cache() {
+        connector_cache()
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    pub async fn connect(
+        self,
+        host: &str,
+        tcp: TcpStream,
+    ) -> std::result::Result<MaybeTlsStream<TcpStream>, String> {
+        let stream = match self.backend {
+            #[cfg(feature = "native-tls")]
+            BackendConnector::NativeTls(connector) => {
+                let tls = tokio_native_tls::TlsConnector::from(connector)
+                    .connect(host, tcp)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                MaybeTlsStream::NativeTls(tls)
+            }
+            #[cfg(feature = "rustls")]
+            BackendConnector::Rustls(config) => {
+                let server_name = rustls::pki_types::ServerName::try_from(host.to_string())
+                    .map_err(|e| e.to_string())?;
+                let tls = tokio_rustls::TlsConnector::from(config)
+                    .connect(server_name, tcp)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                MaybeTlsStream::Rustls(tls)
+            }
+        };
+        if !self.pins.is_empty() {
+            let pinned = leaf_certificate(&stream)
+                .and_then(|der| spki_sha256(&der))
+                .is_some_and(|hash| self.pins.contains(&hash));
+            if !pinned {
+                return Err(format!(
+                    "the public key of the certificate of {} matches none of the pins",
+                    host
+                ));
+            }
+        }
+        Ok(stream)
+    }
+}
+
+/// The connectors of [`TlsConnector::cached`] by [`cache_key`], the oldest first
+fn connector_cache() -> &'static Mutex<Vec<([u8; 32], TlsConnector)>> {
+    static CACHE: OnceLock<Mutex<Vec<([u8; 32], TlsConnector)>>> = OnceLock::new();
+    CACHE.get_or_init(Mutex::default)
+}
+
+/// Equal for options which build the same connector. Files count by their path, signers by their identity.
+fn cache_key(options: &TlsOptions) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    // Length prefixed, so neighbouring fields can't be mistaken for each other
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_le_bytes());
+        hasher.update(bytes);
+    };
+    let bundle = |bundle: &Pkcs12Bundle| {
+        format!("{:?} {:?} {:?}", bundle.path, bundle.data, bundle.password)
+    };
+    field(
+        format!(
+            "{:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?} {:?}",
+            options.backend,
+            options.trust,
+            options.cert_paths,
+            options.ca_cert_uris,
+            options.client_credential,
+            options.crl_path,
+            options.allow_unknown_revocation,
+            options.hostname,
+            options.min_version,
+            options.max_version
+        )
+        .as_bytes(),
+    );
+    for list in [&options.ca_certs, &options.crls] {
+        field(&(list.len() as u64).to_le_bytes());
+        for data in list {
+            field(data);
+        }
+    }
+    field(&(options.ca_bundles.len() as u64).to_le_bytes());
+    for ca_bundle in &options.ca_bundles {
+        field(bundle(ca_bundle).as_bytes());
+    }
+    field(
+        options
+            .client_identity
+            .as_ref()
+            .map(bundle)
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    match &options.client_key {
+        Some(key) => {
+            field(
+                format!(
+                    "{:?} {:p}",
+                    key.scheme,
+                    Arc::as_ptr(&key.signer) as *const ()
+                )
+                .as_bytes(),
+            );
+            for cert in &key.cert_chain {
+                field(cert);
+            }
+        }
+        None => field(&[]),
+    }
+    field(options.pins.as_flattened());
+    hasher.finalize().into()
+}
+
+/// The server certificate of an established TLS session, DER encoded
+fn leaf_certificate(stream: &MaybeTlsStream<TcpStream>) -> Option<Vec<u8>> {
+    match stream {
+        #[cfg(feature = "native-tls")]
+        MaybeTlsStream::NativeTls(tls) => tls.get_ref().peer_certificate().ok()??.to_der().ok(),
+        #[cfg(feature = "rustls")]
+        MaybeTlsStream::Rustls(tls) => tls
+            .get_ref()
+            .1
+            .peer_certificates()?
+            .first()
+            .map(|cert| cert.to_vec()),
+        _ => None,
+    }
+}
+
+/// SHA-256 of the SubjectPublicKeyInfo of a DER certificate, as pinned by HPKP and OkHttp
+pub(crate) fn spki_sha256(der: &[u8]) -> Option<[u8; 32]> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    Some(Sha256::digest(cert.public_key().raw).into())
+}
+
+#[cfg(feature = "native-tls")]
+fn build_native_tls(options: &TlsOptions) -> Result<BackendConnector> {
+    if options.client_key.is_some() {
+        return Err(Error::new(
+            WebSocketError::TlsError,
+            "client key with an external signer needs the rustls tls backend",
+        ));
+    }
+    if options.checks_revocation() {
+        return Err(Error::new(
+            WebSocketError::TlsError,
+            "certificate revocation lists need the rustls tls backend",
+        ));
+    }
+    if !options.hostname.is_default() {
+        return Err(Error::new(
+            WebSocketError::TlsError,
+            "hostname verification policy needs the rustls tls backend",
+        ));
+    }
+    if !options.ca_bundles.is_empty() {
+        return Err(Error::new(
+            WebSocketError::TlsError,
+            "PKCS#12 CA bundles need the rustls tls backend",
+        ));
+    }
+
+    if options.min_version == Some(TlsVersion::Tls13) {
+        return Err(Error::new(
+            WebSocketError::TlsError,
+            "a minimum of TLS 1.3 needs the rustls tls backend",
+        ));
+    }
+
+    let trust = options.checked_trust()?;
+    let mut builder = native_tls::TlsConnector::builder();
+    if trust == TlsTrust::Custom {
+        builder.disable_built_in_roots(true);
+    } else {
+        // The default paths of the TLS library don't lead to the store on every device.
+        // A certificate which doesn't parse is left out, the others are still trusted.
+        for cert_data in system_ca_certs() {
+            let cert = if is_pem(cert_data) {
+                native_tls::Certificate::from_pem(cert_data)
+            } else {
+                native_tls::Certificate::from_der(cert_data)
+            };
+            if let Ok(cert) = cert {
+                builder.add_root_certificate(cert);
+            }
+        }
+    }
+    if options.min_version == Some(TlsVersion::Tls12) {
+        builder.min_protocol_version(Some(native_tls::Protocol::Tlsv12));
+    }
+    if options.max_version == Some(TlsVersion::Tls12) {
+        builder.max_protocol_version(Some(native_tls::Protocol::Tlsv12));
+    }
+
+    for cert_data in read_cert_paths(&options.cert_paths)?
+        .iter()
+        .chain(&options.ca_certs)
+    {
+        // Every certificate of a PEM bundle, not only the first one
+        let certs = if is_pem(cert_data) {
+            native_tls::Certificate::stack_from_pem(cert_data)
+        } else {
+            native_tls::Certificate::from_der(cert_data).map(|cert| vec![cert])
+        }
+        .map_err(parse_cert_error)?;
+        for cert in certs {
+            builder.add_root_certificate(cert);
+        }
+    }
+    if let Some(bundle) = &options.client_identity {
+        let identity = native_tls::Identity::from_pkcs12(&bundle.read()?, &bundle.password)
+            .map_err(pkcs12_error)?;
+        builder.identity(identity);
+    }
+
+    let connector = builder.build().map_err(|e| {
+        Error::new(
+            WebSocketError::TlsError,
+            format!("Try to build tls connector failed: {}", e),
+        )
+    })?;
+    Ok(BackendConnector::NativeTls(connector))
+}
+
+#[cfg(feature = "rustls")]
+use crate::{hostname::rustls_impl::PolicyVerifier, signer::rustls_impl::ExternalClientCert};
+
+#[cfg(feature = "rustls")]
+fn add_pem_roots(roots: &mut rustls::RootCertStore, cert_data: &[u8]) -> Result<()> {
+    for cert in rustls_pemfile::certs(&mut &cert_data[..]) {
+        roots
+            .add(cert.map_err(parse_cert_error)?)
+            .map_err(parse_cert_error)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "rustls")]
+fn add_pem_crls(
+    crls: &mut Vec<rustls::pki_types::CertificateRevocationListDer<'static>>,
+    crl_data: &[u8],
+) -> Result<()> {
+    for crl in rustls_pemfile::crls(&mut &crl_data[..]) {
+        crls.push(crl.map_err(parse_cert_error)?);
+    }
+    Ok(())
+}
+
+/// Certificate chain and private key of the first key entry
+#[cfg(feature = "rustls")]
+fn read_pkcs12_identity(
+    bundle: &Pkcs12Bundle,
+) -> Result<(
+    Vec<rustls::pki_types::CertificateDer<'static>>,
+    rustls::pki_types::PrivateKeyDer<'static>,
+)> {
+    let store = p12_keystore::KeyStore::from_pkcs12(&bundle.read()?, &bundle.password)
+        .map_err(pkcs12_error)?;
+    for (_, entry) in store.entries() {
+        if let p12_keystore::KeyStoreEntry::PrivateKeyChain(chain) = entry {
+            let cert_chain = chain
+                .chain()
+                .iter()
+                .map(|cert| rustls::pki_types::CertificateDer::from(cert.as_der().to_vec()))
+                .collect();
+            let key = rustls::pki_types::PrivateKeyDer::Pkcs8(
+                rustls::pki_types::PrivatePkcs8KeyDer::from(chain.key().to_vec()),
+            );
+            return Ok((cert_chain, key));
+        }
+    }
+    Err(pkcs12_error("no private key in the bundle"))
+}
+
+#[cfg(feature = "rustls")]
+fn build_rustls(options: &TlsOptions) -> Result<BackendConnector> {
+    let mut roots = rustls::RootCertStore::empty();
+    if options.checked_trust()? != TlsTrust::Custom {
+        let system = system_ca_certs();
+        if system.is_empty() {
+            // The Mozilla roots bundled at build time, where the system has no store
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        for cert_data in system {
+            if is_pem(cert_data) {
+                roots.add_parsable_certificates(
+                    rustls_pemfile::certs(&mut &cert_data[..]).filter_map(|cert| cert.ok()),
+                );
+            } else {
+                roots.add_parsable_certificates([rustls::pki_types::CertificateDer::from(
+                    cert_data.clone(),
+                )]);
+            }
+        }
+    }
+
+    for cert_data in read_cert_paths(&options.cert_paths)?
+        .iter()
+        .chain(&options.ca_certs)
+    {
+        if is_pem(cert_data) {
+            add_pem_roots(&mut roots, cert_data)?;
+        } else {
+            roots
+                .add(rustls::pki_types::CertificateDer::from(cert_data.clone()))
+                .map_err(parse_cert_error)?;
+        }
+    }
+
+    for bundle in &options.ca_bundles {
+        let store = p12_keystore::KeyStore::from_pkcs12(&bundle.read()?, &bundle.password)
+            .map_err(pkcs12_error)?;
+        for (_, entry) in store.entries() {
+            let certs = match entry {
+                p12_keystore::KeyStoreEntry::Certificate(cert) => std::slice::from_ref(cert),
+                p12_keystore::KeyStoreEntry::PrivateKeyChain(chain) => chain.chain(),
+            };
+            for cert in certs {
+                roots
+                    .add(rustls::pki_types::CertificateDer::from(
+                        cert.as_der().to_vec(),
+                    ))
+                    .map_err(parse_cert_error)?;
+            }
+        }
+    }
+
+    let mut crls = Vec::new();
+    if let Some(crl_path) = &options.crl_path {
+        add_pem_crls(&mut crls, &read_cert_file(crl_path)?)?;
+    }
+    for crl_data in &options.crls {
+        if is_pem(crl_data) {
+            add_pem_crls(&mut crls, crl_data)?;
+        } else {
+            crls.push(rustls::pki_types::CertificateRevocationListDer::from(
+                crl_data.clone(),
+            ));
+        }
+    }
+
+    let build_error = |e: &dyn std::fmt::Display| {
+        Error::new(
+            WebSocketError::TlsError,
+            format!("Try to build tls connector failed: {}", e),
+        )
+    };
+    let versions: Vec<&'static rustls::SupportedProtocolVersion> = [
+        (TlsVersion::Tls12, &rustls::version::TLS12),
+        (TlsVersion::Tls13, &rustls::version::TLS13),
+    ]
+    .into_iter()
+    .filter(|(version, _)| {
+        options.min_version.is_none_or(|min| *version >= min)
+            && options.max_version.is_none_or(|max| *version <= max)
+    })
+    .map(|(_, supported)| supported)
+    .collect();
+    if versions.is_empty() {
+        return Err(Error::new(
+            WebSocketError::TlsError,
+            "the minimum tls version is newer than the maximum",
+        ));
+    }
+    let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+    let config = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&versions)
+        .map_err(|e| build_error(&e))?;
+    let config = if crls.is_empty() && options.hostname.is_default() {
+        config.with_root_certificates(roots)
+    } else {
+        let mut verifier = rustls::client::WebPkiServerVerifier::builder_with_provider(
+            std::sync::Arc::new(roots),
+            provider,
+        )
+        .with_crls(crls);
+        if options.allow_unknown_revocation {
+            verifier = verifier.allow_unknown_revocation_status();
+        }
+        let verifier = verifier.build().map_err(|e| build_error(&e))?;
+        if options.hostname.is_default() {
+            config.with_webpki_verifier(verifier)
+        } else {
+            config
+                .dangerous()
+                .with_custom_certificate_verifier(std::sync::Arc::new(PolicyVerifier::new(
+                    verifier,
+                    options.hostname.clone(),
+                )))
+        }
+    };
+
+    let config = match (&options.client_key, &options.client_identity) {
+        (Some(_), Some(_)) => {
+            return Err(Error::new(
+                WebSocketError::TlsError,
+                "client key and client identity can't be used together",
+            ))
+        }
+        (None, Some(bundle)) => {
+            let (cert_chain, key) = read_pkcs12_identity(bundle)?;
+            config
+                .with_client_auth_cert(cert_chain, key)
+                .map_err(|e| build_error(&e))?
+        }
+        (Some(client_key), None) => {
+            let mut cert_chain = Vec::new();
+            for cert_data in &client_key.cert_chain {
+                if is_pem(cert_data) {
+                    for cert in rustls_pemfile::certs(&mut &cert_data[..]) {
+                        cert_chain.push(cert.map_err(parse_cert_error)?);
+                    }
+                } else {
+                    cert_chain.push(rustls::pki_types::CertificateDer::from(cert_data.clone()));
+                }
+            }
+            config.with_client_cert_resolver(std::sync::Arc::new(ExternalClientCert::new(
+                cert_chain,
+                client_key.signer.clone(),
+                client_key.scheme,
+            )))
+        }
+        (None, None) => config.with_no_client_auth(),
+    };
+    Ok(BackendConnector::Rustls(std::sync::Arc::new(config)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_key_is_equal_for_equal_options() {
+        let options = || TlsOptions {
+            cert_paths: vec!["/data/ca.pem".to_string()],
+            ca_certs: vec![b"ca".to_vec()],
+            pins: vec![[1; 32]],
+            ..TlsOptions::default()
+        };
+        assert_eq!(cache_key(&options()), cache_key(&options()));
+    }
+
+    #[test]
+    fn cache_key_changes_with_any_option() {
+        let base = TlsOptions::default();
+        let changed = [
+            TlsOptions {
+                trust: Some(TlsTrust::System),
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                cert_paths: vec!["/data/ca.pem".to_string()],
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                ca_certs: vec![b"ca".to_vec()],
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                ca_cert_uris: vec!["oh:t=c;o=ca;u=0;a=0".to_string()],
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                crls: vec![b"ca".to_vec()],
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                pins: vec![[1; 32]],
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                min_version: Some(TlsVersion::Tls13),
+                ..TlsOptions::default()
+            },
+            TlsOptions {
+                client_identity: Some(Pkcs12Bundle {
+                    path: Some("/data/client.p12".to_string()),
+                    ..Pkcs12Bundle::default()
+                }),
+                ..TlsOptions::default()
+            },
+        ];
+        let mut keys = vec![cache_key(&base)];
+        for options in &changed {
+            let key = cache_key(options);
+            assert!(!keys.contains(&key), "{:?}", options);
+            keys.push(key);
+        }
+    }
+
+    #[test]
+    fn cache_key_keeps_fields_apart() {
+        // The same bytes in a neighbouring list make another key
+        let ca = TlsOptions {
+            ca_certs: vec![b"a".to_vec(), b"b".to_vec()],
+            ..TlsOptions::default()
+        };
+        let split = TlsOptions {
+            ca_certs: vec![b"a".to_vec()],
+            crls: vec![b"b".to_vec()],
+            ..TlsOptions::default()
+        };
+        let joined = TlsOptions {
+            ca_certs: vec![b"ab".to_vec()],
+            ..TlsOptions::default()
+        };
+        assert_ne!(cache_key(&ca), cache_key(&split));
+        assert_ne!(cache_key(&ca), cache_key(&joined));
+    }
+
+    #[test]
+    fn pins_are_base64_sha256() {
+        let pin = "sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA=";
+        assert_eq!(TlsOptions::parse_pin(pin).unwrap(), [0; 32]);
+        assert_eq!(TlsOptions::parse_pin(&pin[7..]).unwrap(), [0; 32]);
+        assert!(TlsOptions::parse_pin("sha256/AAAA").is_err());
+        assert!(TlsOptions::parse_pin("not base64!").is_err());
+    }
+
+    #[test]
+    fn trust_must_fit_the_certificates() {
+        let custom = TlsOptions {
+            ca_certs: vec![b"ca".to_vec()],
+            ..TlsOptions::default()
+        };
+        assert_eq!(custom.checked_trust().unwrap(), TlsTrust::Both);
+        let system_only = TlsOptions {
+            trust: Some(TlsTrust::System),
+            ..custom.clone()
+        };
+        assert!(system_only.checked_trust().is_err());
+        let custom_only = TlsOptions {
+            trust: Some(TlsTrust::Custom),
+            ..TlsOptions::default()
+        };
+        assert!(custom_only.checked_trust().is_err());
+    }
+
+    #[test]
+    fn revocation_mode_follows_the_options() {
+        assert_eq!(TlsOptions::default().revocation_mode(), RevocationMode::Off);
+        let crls = TlsOptions {
+            crls: vec![b"crl".to_vec()],
+            ..TlsOptions::default()
+        };
+        assert_eq!(crls.revocation_mode(), RevocationMode::Required);
+        let lenient = TlsOptions {
+            allow_unknown_revocation: true,
+            ..crls
+        };
+        assert_eq!(lenient.revocation_mode(), RevocationMode::AllowUnknown);
+        let path = TlsOptions {
+            crl_path: Some("/data/crl.pem".to_string()),
+            ..TlsOptions::default()
+        };
+        assert_eq!(path.revocation_mode(), RevocationMode::Required);
+    }
+}
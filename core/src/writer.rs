@@ -0,0 +1,218 @@
+use std::{io, sync::Arc, time::Duration};
+
+use futures_util::{stream::SplitSink, SinkExt};
+use tokio::time::{sleep, sleep_until, Instant};
+use tokio_tungstenite::tungstenite::{self, protocol::Message};
+
+use crate::{
+    dial::WsStream,
+    queue::{Outgoing, Payload, Ready},
+    DropReason, DroppedMessage, Error, Event, EventHandler, Link, PowerSave, WebSocketError,
+};
+
+type Sink = SplitSink<WsStream, Message>;
+
+/// Messages up to this size are batched by data saver
+const DATA_SAVER_SMALL_MESSAGE: usize = 1024;
+/// How long data saver waits for more small messages to batch
+const DATA_SAVER_BATCH_WINDOW: Duration = Duration::from_millis(200);
+/// Pause before writing again after a transient error, multiplied by the attempt
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WriterOptions {
+    pub data_saver: bool,
+    /// How long non-urgent messages wait for others to be written with
+    pub coalesce: Option<Duration>,
+}
+
+/// Until when `outgoing` may be held back, `None` writes it right away
+fn defer_until(
+    outgoing: &Outgoing,
+    power_save: PowerSave,
+    options: WriterOptions,
+) -> Option<Instant> {
+    if power_save.enabled && !outgoing.options.urgent {
+        return Some(outgoing.enqueued_at + Duration::from_millis(power_save.max_defer_ms));
+    }
+    if let Some(coalesce) = options.coalesce.filter(|_| !outgoing.options.urgent) {
+        return Some(outgoing.enqueued_at + coalesce);
+    }
+    let small_data = match &outgoing.payload {
+        Payload::Message(Message::Text(text)) => text.len() <= DATA_SAVER_SMALL_MESSAGE,
+        Payload::Message(Message::Binary(data)) => data.len() <= DATA_SAVER_SMALL_MESSAGE,
+        Payload::Parts(_) => outgoing.payload.len() <= DATA_SAVER_SMALL_MESSAGE,
+        _ => false,
+    };
+    if options.data_saver && small_data {
+        return Some(outgoing.enqueued_at + DATA_SAVER_BATCH_WINDOW);
+    }
+    None
+}
+
+enum Failure {
+    /// Writing again on the same connection may work
+    Transient,
+    /// The connection is gone, the message may work on the next one
+    Broken,
+    /// The message itself can't be written
+    Fatal,
+}
+
+fn classify(e: &tungstenite::Error) -> Failure {
+    match e {
+        tungstenite::Error::Io(e) => match e.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                Failure::Transient
+            }
+            _ => Failure::Broken,
+        },
+        tungstenite::Error::ConnectionClosed | tungstenite::Error::AlreadyClosed => Failure::Broken,
+        _ => Failure::Fatal,
+    }
+}
+
+struct WriteError {
+    error: tungstenite::Error,
+    /// Index of the message which failed, `None` when the flush failed
+    at: Option<usize>,
+}
+
+/// Write `batch` and flush once
+async fn write_batch(sink: &mut Sink, batch: &[Outgoing]) -> Result<(), WriteError> {
+    for (index, outgoing) in batch.iter().enumerate() {
+        for message in outgoing.payload.messages() {
+            sink.feed(message).await.map_err(|error| WriteError {
+                error,
+                at: Some(index),
+            })?;
+        }
+    }
+    sink.flush()
+        .await
+        .map_err(|error| WriteError { error, at: None })
+}
+
+fn dropped(handler: &Arc<dyn EventHandler>, outgoing: Outgoing, reason: DropReason) {
+    handler.handle(Event::Dropped(DroppedMessage {
+        id: outgoing.id,
+        tag: outgoing.options.tag,
+        reason,
+    }));
+}
+
+fn write_failed(
+    handler: &Arc<dyn EventHandler>,
+    outgoing: Outgoing,
+    attempts: u32,
+    e: &tungstenite::Error,
+) {
+    handler.handle(Event::Error(Error::new(
+        WebSocketError::SendError,
+        format!("message dropped after {} failed writes: {}", attempts, e),
+    )));
+    dropped(handler, outgoing, DropReason::WriteFailed);
+}
+
+/// Write everything queued on the link to `sink`, until the connection breaks.
+/// While power save is enabled, non-urgent messages are held back and written
+/// together with the next urgent one, when power save ends or after `max_defer_ms`.
+/// Data saver does the same for small messages within a short window, and `coalesce` for non-urgent ones.
+/// While the app is in the background data messages may be held back until it's resumed, see [`Link::suspend`].
+/// Scheduled messages are queued once they are due, expired ones are dropped.
+/// Failed writes are retried within the retry budget of every message,
+/// when the connection broke the messages stay queued for the next one.
+pub async fn run_writer(
+    mut sink: Sink,
+    link: Arc<Link>,
+    handler: Arc<dyn EventHandler>,
+    options: WriterOptions,
+) {
+    let queue = link.queue();
+    let mut power_save = link.subscribe_power_save();
+    let mut background = link.subscribe_background();
+    let mut deferring = false;
+
+    loop {
+        let changed = queue.notified();
+        for outgoing in queue.take_expired(Instant::now()) {
+            dropped(&handler, outgoing, DropReason::Expired);
+        }
+        let current = *power_save.borrow();
+        let holding = background
+            .borrow_and_update()
+            .is_some_and(|background| background.buffer);
+        let control = holding
+            .then(|| queue.take_control(Instant::now()))
+            .flatten();
+        let ready = match control {
+            Some(control) if control.is_empty() => Ready::Wait(None),
+            Some(control) => Ready::Write(control),
+            None => queue.take_ready(Instant::now(), |outgoing| {
+                defer_until(outgoing, current, options)
+            }),
+        };
+        let batch = match ready {
+            Ready::Write(batch) => batch,
+            Ready::Wait(deadline) => {
+                if (deadline.is_some() || (holding && queue.len() > 0)) && !deferring {
+                    deferring = true;
+                    handler.handle(Event::Deferred);
+                }
+                let wake = match (deadline, queue.next_wake()) {
+                    (Some(deadline), Some(due)) => Some(deadline.min(due)),
+                    (deadline, due) => deadline.or(due),
+                };
+                tokio::select! {
+                    _ = changed => {},
+                    _ = power_save.changed() => {},
+                    _ = background.changed() => {},
+                    _ = sleep_until(wake.unwrap_or_else(Instant::now)), if wake.is_some() => {},
+                }
+                continue;
+            }
+        };
+        deferring = false;
+
+        let Err(WriteError { error, at }) = write_batch(&mut sink, &batch).await else {
+            queue.written();
+            link.record_batch(batch.len());
+            continue;
+        };
+        let failure = match (classify(&error), at) {
+            (Failure::Fatal, None) => Failure::Broken,
+            (failure, _) => failure,
+        };
+        let mut retry = Vec::new();
+        match (&failure, at) {
+            // Only the failed message is lost, the ones before it are already buffered
+            (Failure::Fatal, Some(at)) => {
+                let mut rest = batch.into_iter().skip(at);
+                if let Some(outgoing) = rest.next() {
+                    let attempts = outgoing.attempts + 1;
+                    write_failed(&handler, outgoing, attempts, &error);
+                }
+                retry.extend(rest);
+            }
+            // Not known which ones reached the socket, so all of them are written again
+            _ => {
+                for mut outgoing in batch {
+                    outgoing.attempts += 1;
+                    if outgoing.attempts > outgoing.options.retries {
+                        let attempts = outgoing.attempts;
+                        write_failed(&handler, outgoing, attempts, &error);
+                    } else {
+                        retry.push(outgoing);
+                    }
+                }
+            }
+        }
+        let attempts = retry.first().map_or(1, |outgoing| outgoing.attempts);
+        queue.requeue(retry);
+        match failure {
+            Failure::Transient => sleep(RETRY_DELAY * attempts).await,
+            Failure::Broken => break,
+            Failure::Fatal => {}
+        }
+    }
+}
@@ -0,0 +1,47 @@
+#ifndef HARMONY_WEBSOCKET_H
+#define HARMONY_WEBSOCKET_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Handle returned by `WebSocket.nativeHandle()` as a bigint */
+typedef struct HarmonyWsHandle HarmonyWsHandle;
+
+#define HARMONY_WS_OK 0
+#define HARMONY_WS_INVALID_ARGUMENT -1
+#define HARMONY_WS_NOT_CONNECTED -2
+
+#define HARMONY_WS_EVENT_TEXT 0
+#define HARMONY_WS_EVENT_BINARY 1
+#define HARMONY_WS_EVENT_CLOSE 2
+
+/* Called on the connection thread, `data` is only valid during the call */
+typedef void (*HarmonyWsCallback)(void *user_data, uint32_t event, const uint8_t *data, size_t len);
+
+/* Queue a text (`binary == false`, must be utf-8) or binary message.
+   The write happens later, a failure of it goes to `onError` of the WebSocket. */
+int32_t harmony_ws_send(const HarmonyWsHandle *handle, const uint8_t *data, size_t len, bool binary);
+
+/* Start the close handshake */
+int32_t harmony_ws_close(const HarmonyWsHandle *handle);
+
+/* Register a callback for inbound messages and close, returns its id or 0 on invalid arguments */
+uint64_t harmony_ws_register_callback(const HarmonyWsHandle *handle, HarmonyWsCallback callback, void *user_data);
+
+/* Remove a callback registered by `harmony_ws_register_callback`.
+   Waits for a running call of it on another thread, its `user_data` may be freed once this returns. */
+int32_t harmony_ws_unregister_callback(const HarmonyWsHandle *handle, uint64_t id);
+
+/* Give back a handle, the connection itself stays open and the callbacks stay registered */
+void harmony_ws_release(const HarmonyWsHandle *handle);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -13,6 +13,12 @@ export X86_64_UNKNOWN_LINUX_OHOS_OPENSSL_DIR="$SCRIPT_DIR/../ohos-openssl/prelud
 
 if [ "$MODE" = "vendor" ]; then
     ohrs build ${@:2} -- --features "tokio-tungstenite/native-tls-vendored"
+elif [ "$MODE" = "rustls" ]; then
+    ohrs build ${@:2} -- --no-default-features --features "rustls codecs diagnostics pool"
+elif [ "$MODE" = "all-tls" ]; then
+    ohrs build ${@:2} -- --features "rustls"
+elif [ "$MODE" = "minimal" ]; then
+    ohrs build ${@:2} -- --no-default-features --features "native-tls"
 else
     ohrs build ${@:1}
 fi
\ No newline at end of file
@@ -0,0 +1,38 @@
+use harmony_websocket_core::{Framing, TextAssembler};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+
+/// Join text messages which carry parts of documents, e.g. JSON streamed in chunks.
+/// Give either `delimiter` or `lengthPrefix`.
+#[napi(object)]
+pub struct AssemblyOptions {
+    /// Every document ends with this text, e.g. `"\n"` for newline-delimited JSON. It isn't delivered.
+    pub delimiter: Option<String>,
+    /// Every document starts with its length in bytes and a `:`, e.g. `7:{"a":1}`
+    pub length_prefix: Option<bool>,
+    /// Text buffered for an incomplete document is dropped beyond this many bytes. Defaults to 16 MiB.
+    pub max_document_bytes: Option<u32>,
+}
+
+pub fn to_assembler(options: AssemblyOptions) -> Result<TextAssembler> {
+    let framing = match (options.delimiter, options.length_prefix.unwrap_or(false)) {
+        (Some(delimiter), false) if !delimiter.is_empty() => Framing::Delimiter(delimiter),
+        (Some(_), false) => {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "the assembly delimiter can't be empty",
+            ))
+        }
+        (None, true) => Framing::LengthPrefix,
+        _ => {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "assembly needs either a delimiter or lengthPrefix",
+            ))
+        }
+    };
+    Ok(TextAssembler::new(
+        framing,
+        options.max_document_bytes.map(|bytes| bytes as usize),
+    ))
+}
@@ -0,0 +1,56 @@
+use std::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+use harmony_websocket_core::Link;
+
+/// Watches how late the JS thread runs the callbacks of received messages, see `jsBacklogThresholdMs`
+pub struct JsBacklog {
+    threshold: Duration,
+    /// Stop reading from the socket while the JS thread is behind
+    pause_reading: bool,
+    /// Callbacks which were queued but didn't run yet
+    in_flight: AtomicUsize,
+    congested: AtomicBool,
+}
+
+impl JsBacklog {
+    pub fn new(threshold: Duration, pause_reading: bool) -> Self {
+        JsBacklog {
+            threshold,
+            pause_reading,
+            in_flight: AtomicUsize::new(0),
+            congested: AtomicBool::new(false),
+        }
+    }
+
+    /// A callback is queued, returns when
+    pub fn queued(&self) -> Instant {
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        Instant::now()
+    }
+
+    /// A callback couldn't be queued after all
+    pub fn dropped(&self) {
+        self.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+
+    /// The callback queued at `queued_at` ran, returns its delay if the JS thread just fell behind.
+    /// It counts as caught up once a callback runs in time or none is left.
+    pub fn ran(&self, queued_at: Instant, link: Option<&Link>) -> Option<Duration> {
+        let delay = queued_at.elapsed();
+        let drained = self.in_flight.fetch_sub(1, Ordering::AcqRel) == 1;
+        let late = delay > self.threshold;
+        let behind = late && !drained;
+        let was_congested = self.congested.swap(behind, Ordering::AcqRel);
+        if let (true, Some(link)) = (self.pause_reading, link) {
+            match (behind, was_congested) {
+                (true, false) => link.pause_reading(),
+                (false, true) => link.resume_reading(),
+                _ => {}
+            }
+        }
+        (late && !was_congested).then_some(delay)
+    }
+}
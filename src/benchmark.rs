@@ -0,0 +1,91 @@
+#[cfg(feature = "diagnostics")]
+use std::time::Duration;
+
+use napi_derive_ohos::napi;
+use napi_ohos::Result;
+
+#[cfg(not(feature = "diagnostics"))]
+use crate::error::not_compiled_in;
+#[cfg(feature = "diagnostics")]
+use crate::error::to_napi_error;
+
+#[napi(object)]
+pub struct BenchmarkOptions {
+    /// An echo server, e.g. `ws://192.168.1.2:9001`
+    pub echo_url: String,
+    /// Payload bytes of every message, at least 8. Defaults to 1024.
+    pub size: Option<u32>,
+    /// Messages of each phase. Defaults to 1000.
+    pub count: Option<u32>,
+    /// How long to wait for an echo before giving up. Defaults to 10000.
+    pub timeout_ms: Option<u32>,
+}
+
+/// What the client measured against the echo server, times in milliseconds
+#[napi(object)]
+pub struct BenchmarkReport {
+    pub size: u32,
+    pub count: u32,
+    /// From `connect()` until the connection is open
+    pub connect_ms: f64,
+    /// Round trips of one message at a time
+    pub latency_min_ms: f64,
+    pub latency_mean_ms: f64,
+    pub latency_p50_ms: f64,
+    pub latency_p90_ms: f64,
+    pub latency_p99_ms: f64,
+    pub latency_max_ms: f64,
+    /// All messages sent at once, until the last echo arrived
+    pub throughput_ms: f64,
+    pub messages_per_second: f64,
+    /// Payload bytes sent and received per second
+    pub bytes_per_second: f64,
+}
+
+#[cfg(feature = "diagnostics")]
+impl From<harmony_websocket_core::BenchmarkReport> for BenchmarkReport {
+    fn from(report: harmony_websocket_core::BenchmarkReport) -> Self {
+        BenchmarkReport {
+            size: report.size as u32,
+            count: report.count,
+            connect_ms: report.connect_ms,
+            latency_min_ms: report.latency_min_ms,
+            latency_mean_ms: report.latency_mean_ms,
+            latency_p50_ms: report.latency_p50_ms,
+            latency_p90_ms: report.latency_p90_ms,
+            latency_p99_ms: report.latency_p99_ms,
+            latency_max_ms: report.latency_max_ms,
+            throughput_ms: report.throughput_ms,
+            messages_per_second: report.messages_per_second,
+            bytes_per_second: report.bytes_per_second,
+        }
+    }
+}
+
+/// Measure round-trip latency and throughput against an echo server, natively.
+/// Latency is measured with one message in flight at a time, throughput after that with all sent at once.
+#[cfg(feature = "diagnostics")]
+#[napi]
+pub async fn benchmark(options: BenchmarkOptions) -> Result<BenchmarkReport> {
+    let mut core_options = harmony_websocket_core::BenchmarkOptions::default();
+    if let Some(size) = options.size {
+        core_options.size = size as usize;
+    }
+    if let Some(count) = options.count {
+        core_options.count = count;
+    }
+    if let Some(timeout_ms) = options.timeout_ms {
+        core_options.timeout = Duration::from_millis(u64::from(timeout_ms));
+    }
+    harmony_websocket_core::run_benchmark(&options.echo_url, core_options)
+        .await
+        .map(BenchmarkReport::from)
+        .map_err(to_napi_error)
+}
+
+/// Left out of this build, rejects with "not compiled in"
+#[cfg(not(feature = "diagnostics"))]
+#[napi]
+pub async fn benchmark(_options: BenchmarkOptions) -> Result<BenchmarkReport> {
+    Err(not_compiled_in("benchmark()", "diagnostics"))
+}
@@ -0,0 +1,119 @@
+use std::{ptr, slice};
+
+use harmony_websocket_core::Bytes;
+use napi_ohos::{bindgen_prelude::*, check_status, sys};
+
+/// Binary data from JS: an `ArrayBuffer`, or any `TypedArray` or `DataView`
+pub type Binary = Either<Buffer, BinaryView>;
+
+/// The bytes a `TypedArray` or `DataView` looks at, only `byteLength` bytes from `byteOffset`.
+/// They are copied while converting, so the view may be reused right after the call.
+pub struct BinaryView(Bytes);
+
+impl BinaryView {
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+fn element_size(typed_array_type: sys::napi_typedarray_type) -> usize {
+    match typed_array_type {
+        sys::TypedarrayType::int16_array | sys::TypedarrayType::uint16_array => 2,
+        sys::TypedarrayType::int32_array
+        | sys::TypedarrayType::uint32_array
+        | sys::TypedarrayType::float32_array => 4,
+        sys::TypedarrayType::float64_array
+        | sys::TypedarrayType::bigint64_array
+        | sys::TypedarrayType::biguint64_array => 8,
+        _ => 1,
+    }
+}
+
+/// The viewed bytes of `value`, `None` if it's neither a `TypedArray` nor a `DataView`
+unsafe fn viewed_bytes<'a>(env: sys::napi_env, value: sys::napi_value) -> Result<Option<&'a [u8]>> {
+    let mut data = ptr::null_mut();
+    let mut byte_length = 0;
+    let mut array_buffer = ptr::null_mut();
+    let mut byte_offset = 0;
+
+    let mut is_typed_array = false;
+    check_status!(sys::napi_is_typedarray(env, value, &mut is_typed_array))?;
+    let mut is_data_view = false;
+    if !is_typed_array {
+        check_status!(sys::napi_is_dataview(env, value, &mut is_data_view))?;
+    }
+
+    if is_typed_array {
+        let mut typed_array_type = 0;
+        let mut length = 0;
+        check_status!(sys::napi_get_typedarray_info(
+            env,
+            value,
+            &mut typed_array_type,
+            &mut length,
+            &mut data,
+            &mut array_buffer,
+            &mut byte_offset,
+        ))?;
+        byte_length = length * element_size(typed_array_type);
+    } else if is_data_view {
+        check_status!(sys::napi_get_dataview_info(
+            env,
+            value,
+            &mut byte_length,
+            &mut data,
+            &mut array_buffer,
+            &mut byte_offset,
+        ))?;
+    } else {
+        return Ok(None);
+    }
+
+    // `data` already points at `byteOffset`
+    if data.is_null() || byte_length == 0 {
+        return Ok(Some(&[]));
+    }
+    Ok(Some(slice::from_raw_parts(data as *const u8, byte_length)))
+}
+
+impl TypeName for BinaryView {
+    fn type_name() -> &'static str {
+        "ArrayBufferView"
+    }
+
+    fn value_type() -> ValueType {
+        ValueType::Object
+    }
+}
+
+impl ValidateNapiValue for BinaryView {
+    unsafe fn validate(env: sys::napi_env, napi_val: sys::napi_value) -> Result<sys::napi_value> {
+        match viewed_bytes(env, napi_val)? {
+            Some(_) => Ok(ptr::null_mut()),
+            None => Err(Error::new(
+                Status::InvalidArg,
+                "Expected a TypedArray or DataView",
+            )),
+        }
+    }
+}
+
+impl FromNapiValue for BinaryView {
+    unsafe fn from_napi_value(env: sys::napi_env, napi_val: sys::napi_value) -> Result<Self> {
+        match viewed_bytes(env, napi_val)? {
+            Some(bytes) => Ok(BinaryView(Bytes::copy_from_slice(bytes))),
+            None => Err(Error::new(
+                Status::InvalidArg,
+                "Expected a TypedArray or DataView",
+            )),
+        }
+    }
+}
+
+/// `ArrayBuffer`s are not copied, the bytes keep the JS buffer alive
+pub fn to_bytes(data: Binary) -> Bytes {
+    match data {
+        Either::A(buf) => Bytes::from_owner(buf),
+        Either::B(view) => view.into_bytes(),
+    }
+}
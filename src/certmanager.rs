@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+use harmony_websocket_core::{
+    block_on_signature, BoxFuture, CertificateStore, ClientKey, ExternalSigner, StoredCredential,
+    WebSocketError,
+};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, threadsafe_function::ThreadsafeFunction, Result};
+
+use crate::{
+    binary::{to_bytes, Binary},
+    tls::SignScheme,
+};
+
+/// A client certificate installed in the Certificate Manager, its private key signs there
+#[napi(object)]
+pub struct CertManagerCredential {
+    /// URI of the app credential, as returned by `certificateManager.installPrivateCertificate()`
+    pub uri: String,
+    pub scheme: SignScheme,
+}
+
+impl From<CertManagerCredential> for StoredCredential {
+    fn from(credential: CertManagerCredential) -> Self {
+        StoredCredential {
+            uri: credential.uri,
+            scheme: credential.scheme.into(),
+        }
+    }
+}
+
+/// Data to sign with the key of a Certificate Manager credential
+#[napi(object)]
+pub struct CredentialSignRequest {
+    /// URI of the credential
+    pub uri: String,
+    /// Name of the signature scheme, as in `SignScheme`
+    pub scheme: String,
+    pub data: Buffer,
+}
+
+/// Weak, the registration doesn't keep the app alive
+pub type LookupCallback = ThreadsafeFunction<String, Promise<Binary>, String, false, true>;
+pub type CredentialSignCallback =
+    ThreadsafeFunction<CredentialSignRequest, Promise<Binary>, CredentialSignRequest, false, true>;
+
+async fn lookup(
+    callback: Arc<LookupCallback>,
+    uri: String,
+    what: &'static str,
+) -> harmony_websocket_core::Result<Vec<u8>> {
+    let fail = |e: Error| {
+        harmony_websocket_core::Error::new(
+            WebSocketError::TlsError,
+            format!("Certificate Manager {} {}: {}", what, uri, e),
+        )
+    };
+    let cert = callback
+        .call_async(uri.clone())
+        .await
+        .map_err(fail)?
+        .await
+        .map_err(fail)?;
+    Ok(Vec::from(to_bytes(cert)))
+}
+
+/// Signs with the key of a credential inside the Certificate Manager
+struct CredentialSigner {
+    uri: String,
+    callback: Arc<CredentialSignCallback>,
+}
+
+impl ExternalSigner for CredentialSigner {
+    fn sign(
+        &self,
+        scheme: harmony_websocket_core::SignScheme,
+        message: &[u8],
+    ) -> std::result::Result<Vec<u8>, String> {
+        let callback = self.callback.clone();
+        let request = CredentialSignRequest {
+            uri: self.uri.clone(),
+            scheme: scheme.name().to_string(),
+            data: Buffer::from(message),
+        };
+        // Same as `JsSigner`, the worker waits for the promise on the multi-thread runtime
+        block_on_signature(async move {
+            let signature = callback
+                .call_async(request)
+                .await
+                .map_err(|e| e.to_string())?
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(Vec::from(to_bytes(signature)))
+        })
+    }
+}
+
+/// The Certificate Manager, reached through the ArkTS functions given to `registerCertManager()`
+struct CertManager {
+    get_ca_certificate: Arc<LookupCallback>,
+    get_credential: Arc<LookupCallback>,
+    sign: Arc<CredentialSignCallback>,
+    /// One signer per credential, so connections with the same credential share their TLS connector
+    signers: Mutex<HashMap<String, Arc<CredentialSigner>>>,
+}
+
+impl CertificateStore for CertManager {
+    fn ca_cert(&self, uri: &str) -> BoxFuture<harmony_websocket_core::Result<Vec<u8>>> {
+        Box::pin(lookup(
+            self.get_ca_certificate.clone(),
+            uri.to_string(),
+            "CA certificate",
+        ))
+    }
+
+    fn client_key(
+        &self,
+        credential: &StoredCredential,
+    ) -> BoxFuture<harmony_websocket_core::Result<ClientKey>> {
+        let signer = self
+            .signers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(credential.uri.clone())
+            .or_insert_with(|| {
+                Arc::new(CredentialSigner {
+                    uri: credential.uri.clone(),
+                    callback: self.sign.clone(),
+                })
+            })
+            .clone();
+        let cert_chain = lookup(
+            self.get_credential.clone(),
+            credential.uri.clone(),
+            "credential",
+        );
+        let scheme = credential.scheme;
+        Box::pin(async move {
+            Ok(ClientKey {
+                cert_chain: vec![cert_chain.await?],
+                scheme,
+                signer,
+            })
+        })
+    }
+}
+
+/// Look up `caCertUris` and `clientCredential` of the config with these functions on every connect.
+/// The package registers the Certificate Manager when it's imported, there is no need to call it.
+#[napi]
+pub fn register_cert_manager(
+    get_ca_certificate: Function<String, Promise<Binary>>,
+    get_credential: Function<String, Promise<Binary>>,
+    sign: Function<CredentialSignRequest, Promise<Binary>>,
+) -> Result<()> {
+    let get_ca_certificate = get_ca_certificate
+        .build_threadsafe_function()
+        .callee_handled::<false>()
+        .weak::<true>()
+        .build()?;
+    let get_credential = get_credential
+        .build_threadsafe_function()
+        .callee_handled::<false>()
+        .weak::<true>()
+        .build()?;
+    let sign = sign
+        .build_threadsafe_function()
+        .callee_handled::<false>()
+        .weak::<true>()
+        .build()?;
+    harmony_websocket_core::set_certificate_store(Some(Arc::new(CertManager {
+        get_ca_certificate: Arc::new(get_ca_certificate),
+        get_credential: Arc::new(get_credential),
+        sign: Arc::new(sign),
+        signers: Mutex::default(),
+    })));
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, PoisonError, RwLock},
+};
+
+use harmony_websocket_core::Message;
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, threadsafe_function::ThreadsafeFunction, Error, Result};
+use serde_json::Value;
+
+#[cfg(not(feature = "codecs"))]
+use crate::error::not_compiled_in;
+use crate::handle::{to_message, SendData};
+
+/// Turns a value into the data of a message
+pub type EncodeCallback = ThreadsafeFunction<Value, SendData, Value, false>;
+/// Turns a received message back into a value
+pub type DecodeCallback =
+    ThreadsafeFunction<Either<String, Buffer>, Value, Either<String, Buffer>, false>;
+
+/// How values are turned into messages and back
+pub enum Codec {
+    /// Text messages of JSON, binary ones are decoded as well
+    Json,
+    /// Binary messages of MessagePack, maps keep their field names
+    #[cfg(feature = "codecs")]
+    MessagePack,
+    /// Binary messages of CBOR
+    #[cfg(feature = "codecs")]
+    Cbor,
+    /// Registered with `registerCodec()`
+    Js {
+        encode: EncodeCallback,
+        decode: DecodeCallback,
+    },
+}
+
+const BUILT_IN: [&str; 3] = ["json", "msgpack", "cbor"];
+
+fn registry() -> &'static RwLock<HashMap<String, Arc<Codec>>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<String, Arc<Codec>>>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+fn invalid(name: &str, e: impl std::fmt::Display) -> Error {
+    Error::new(Status::InvalidArg, format!("{} codec: {}", name, e))
+}
+
+/// The built-in or registered codec called `name`
+pub fn lookup(name: &str) -> Result<Arc<Codec>> {
+    let codec = match name {
+        "json" => Codec::Json,
+        #[cfg(feature = "codecs")]
+        "msgpack" => Codec::MessagePack,
+        #[cfg(feature = "codecs")]
+        "cbor" => Codec::Cbor,
+        #[cfg(not(feature = "codecs"))]
+        "msgpack" | "cbor" => {
+            return Err(not_compiled_in(&format!("{} codec", name), "codecs"));
+        }
+        _ => {
+            return registry()
+                .read()
+                .unwrap_or_else(PoisonError::into_inner)
+                .get(name)
+                .cloned()
+                .ok_or_else(|| Error::new(Status::InvalidArg, format!("unknown codec: {}", name)))
+        }
+    };
+    Ok(Arc::new(codec))
+}
+
+impl Codec {
+    fn name(&self) -> &'static str {
+        match self {
+            Codec::Json => "json",
+            #[cfg(feature = "codecs")]
+            Codec::MessagePack => "msgpack",
+            #[cfg(feature = "codecs")]
+            Codec::Cbor => "cbor",
+            Codec::Js { .. } => "registered",
+        }
+    }
+
+    pub async fn encode(&self, value: Value) -> Result<Message> {
+        match self {
+            Codec::Json => serde_json::to_string(&value)
+                .map(|text| Message::Text(text.into()))
+                .map_err(|e| invalid(self.name(), e)),
+            #[cfg(feature = "codecs")]
+            Codec::MessagePack => rmp_serde::to_vec_named(&value)
+                .map(|data| Message::Binary(data.into()))
+                .map_err(|e| invalid(self.name(), e)),
+            #[cfg(feature = "codecs")]
+            Codec::Cbor => {
+                let mut data = Vec::new();
+                ciborium::into_writer(&value, &mut data).map_err(|e| invalid(self.name(), e))?;
+                Ok(Message::Binary(data.into()))
+            }
+            Codec::Js { encode, .. } => encode.call_async(value).await.map(to_message),
+        }
+    }
+
+    /// Decode natively, `None` for registered codecs which decode in JS
+    pub fn decode(&self, data: &[u8]) -> Option<std::result::Result<Value, String>> {
+        let decoded = match self {
+            Codec::Json => serde_json::from_slice(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "codecs")]
+            Codec::MessagePack => rmp_serde::from_slice(data).map_err(|e| e.to_string()),
+            #[cfg(feature = "codecs")]
+            Codec::Cbor => ciborium::from_reader(data).map_err(|e| e.to_string()),
+            Codec::Js { .. } => return None,
+        };
+        Some(decoded.map_err(|e| format!("ws-rs {} decode failed: {}", self.name(), e)))
+    }
+}
+
+/// Register a codec for `sendEncoded()` and the `codec` of `WebSocketConfig`.
+/// `json`, `msgpack` and `cbor` are built in and can't be replaced, registering another name again replaces it.
+/// Values cross the bridge as JSON-like data, so `encode` gets and `decode` returns plain objects, arrays and primitives.
+#[napi]
+pub fn register_codec(
+    name: String,
+    encode: Function<Value, SendData>,
+    decode: Function<Either<String, Buffer>, Value>,
+) -> Result<()> {
+    if BUILT_IN.contains(&name.as_str()) {
+        return Err(Error::new(
+            Status::InvalidArg,
+            format!("the {} codec is built in", name),
+        ));
+    }
+    let encode = encode
+        .build_threadsafe_function()
+        .callee_handled::<false>()
+        .build()?;
+    let decode = decode
+        .build_threadsafe_function()
+        .callee_handled::<false>()
+        .build()?;
+    registry()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(name, Arc::new(Codec::Js { encode, decode }));
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use napi_derive_ohos::napi;
+use napi_ohos::Result;
+
+#[cfg(not(feature = "diagnostics"))]
+use crate::error::not_compiled_in;
+#[cfg(feature = "diagnostics")]
+use crate::error::to_napi_error;
+
+#[napi(object)]
+pub struct ConformanceOptions {
+    /// Name of the client in the reports of the server. Defaults to `harmony-websocket`.
+    pub agent: Option<String>,
+    /// Case numbers to run, starting at 1. Defaults to all cases of the server.
+    pub cases: Option<Vec<u32>>,
+    /// Defaults to true
+    pub strict_rfc6455: Option<bool>,
+}
+
+/// The verdict of the server on one case
+#[napi(object)]
+pub struct ConformanceCase {
+    pub case: u32,
+    /// e.g. `1.1.1`
+    pub id: Option<String>,
+    pub description: Option<String>,
+    /// `OK`, `NON-STRICT`, `INFORMATIONAL`, `UNIMPLEMENTED` or `FAILED`
+    pub behavior: String,
+    /// The first error of the client while running the case
+    pub error: Option<String>,
+}
+
+#[cfg(feature = "diagnostics")]
+impl From<harmony_websocket_core::CaseResult> for ConformanceCase {
+    fn from(result: harmony_websocket_core::CaseResult) -> Self {
+        ConformanceCase {
+            case: result.case,
+            id: result.id,
+            description: result.description,
+            behavior: result.behavior,
+            error: result.error,
+        }
+    }
+}
+
+/// Run the cases of an Autobahn fuzzing server, e.g. `ws://192.168.1.2:9001`, against this client.
+/// The client echoes every message as the cases expect and the server's reports are updated at the end.
+#[cfg(feature = "diagnostics")]
+#[napi]
+pub async fn run_conformance_suite(
+    server_url: String,
+    options: Option<ConformanceOptions>,
+) -> Result<Vec<ConformanceCase>> {
+    let mut core_options = harmony_websocket_core::ConformanceOptions::default();
+    if let Some(options) = options {
+        if let Some(agent) = options.agent {
+            core_options.agent = agent;
+        }
+        core_options.cases = options.cases;
+        if let Some(strict_rfc6455) = options.strict_rfc6455 {
+            core_options.connect.strict_rfc6455 = strict_rfc6455;
+        }
+    }
+    harmony_websocket_core::run_conformance_suite(&server_url, core_options)
+        .await
+        .map(|results| results.into_iter().map(Into::into).collect())
+        .map_err(to_napi_error)
+}
+
+/// Left out of this build, rejects with "not compiled in"
+#[cfg(not(feature = "diagnostics"))]
+#[napi]
+pub async fn run_conformance_suite(
+    _server_url: String,
+    _options: Option<ConformanceOptions>,
+) -> Result<Vec<ConformanceCase>> {
+    Err(not_compiled_in("runConformanceSuite()", "diagnostics"))
+}
@@ -0,0 +1,70 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use harmony_websocket_core::{DnsOptions, DnsRetry};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+
+/// Resolve hosts differently from the system, e.g. a test server or split-horizon dns.
+/// The host of the url is still used for TLS and the `Host` header.
+#[napi(object)]
+pub struct DnsConfig {
+    /// Addresses used for a host without any lookup, one or several, e.g. `{ "api.example.com": "10.0.0.5" }`
+    pub overrides: Option<HashMap<String, Either<String, Vec<String>>>>,
+    /// Longest wait for one lookup in milliseconds, it counts as a transient failure afterwards. Defaults to 5000.
+    pub timeout_ms: Option<u32>,
+    /// A dns server asked over udp instead of the system resolver, `"10.0.0.53"` or with a port, `"10.0.0.53:5353"`
+    pub resolver: Option<String>,
+}
+
+fn invalid(what: &str, value: &str) -> Error {
+    Error::new(
+        Status::InvalidArg,
+        format!("dns {} is not an ip address: {}", what, value),
+    )
+}
+
+impl DnsConfig {
+    /// The options with the retries of the other settings
+    pub fn to_options(self, retry: DnsRetry) -> Result<DnsOptions> {
+        let overrides = self
+            .overrides
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(host, addrs)| {
+                let addrs = match addrs {
+                    Either::A(addr) => vec![addr],
+                    Either::B(addrs) => addrs,
+                };
+                let addrs = addrs
+                    .iter()
+                    .map(|addr| {
+                        addr.parse::<IpAddr>()
+                            .map_err(|_| invalid(&format!("override of {}", host), addr))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok((host.to_ascii_lowercase(), addrs))
+            })
+            .collect::<Result<_>>()?;
+        let resolver = match self.resolver {
+            Some(resolver) => Some(
+                resolver
+                    .parse::<SocketAddr>()
+                    .or_else(|_| resolver.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 53)))
+                    .map_err(|_| invalid("resolver", &resolver))?,
+            ),
+            None => None,
+        };
+        Ok(DnsOptions {
+            overrides,
+            timeout: self
+                .timeout_ms
+                .map(|ms| Duration::from_millis(u64::from(ms))),
+            resolver,
+            retry,
+        })
+    }
+}
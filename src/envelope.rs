@@ -0,0 +1,47 @@
+use napi_derive_ohos::napi;
+use serde_json::Value;
+
+/// `{"type": .., "id": .., "ts": .., "payload": ..}` as a JSON text message
+#[napi(object)]
+pub struct Envelope {
+    #[napi(js_name = "type")]
+    pub kind: String,
+    pub id: Option<String>,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: Option<f64>,
+    pub payload: Value,
+}
+
+impl From<harmony_websocket_core::Envelope> for Envelope {
+    fn from(envelope: harmony_websocket_core::Envelope) -> Self {
+        Envelope {
+            kind: envelope.kind,
+            id: envelope.id,
+            timestamp: envelope.timestamp.map(|timestamp| timestamp as f64),
+            payload: envelope.payload,
+        }
+    }
+}
+
+impl From<Envelope> for harmony_websocket_core::Envelope {
+    fn from(envelope: Envelope) -> Self {
+        harmony_websocket_core::Envelope {
+            kind: envelope.kind,
+            id: envelope.id,
+            timestamp: envelope.timestamp.map(|timestamp| timestamp as u64),
+            payload: envelope.payload,
+        }
+    }
+}
+
+/// The JSON text of `envelope`, as `sendEnvelope()` sends it
+#[napi]
+pub fn pack_envelope(envelope: Envelope) -> String {
+    harmony_websocket_core::Envelope::from(envelope).pack()
+}
+
+/// The envelope in `text`, `null` unless it's a JSON object with a string `type`
+#[napi]
+pub fn unpack_envelope(text: String) -> Option<Envelope> {
+    harmony_websocket_core::Envelope::unpack(&text).map(Envelope::from)
+}
@@ -1,21 +1,24 @@
-pub enum WebSocketError {
-    TlsError,
-    HeaderError,
-    ConnectError,
-    SendError,
-    ReceiveError,
-    CloseError,
+use napi_ohos::{Error, Status};
+
+pub use harmony_websocket_core::WebSocketError;
+
+/// Errors rejecting a promise keep the `GenericFailure` status, the kind is part of `onError` only
+pub fn to_napi_error(e: harmony_websocket_core::Error) -> Error {
+    Error::new(Status::GenericFailure, e.message)
+}
+
+/// An API whose subsystem was left out of this build, e.g. in `--no-default-features` builds
+#[cfg(not(all(feature = "codecs", feature = "diagnostics", feature = "pool")))]
+pub fn not_compiled_in(api: &str, feature: &str) -> Error {
+    Error::new(
+        Status::GenericFailure,
+        format!(
+            "ws-rs {} is not compiled in, it needs the {} feature",
+            api, feature
+        ),
+    )
 }
 
-impl AsRef<str> for WebSocketError {
-    fn as_ref(&self) -> &str {
-        match self {
-            WebSocketError::TlsError => "TlsError",
-            WebSocketError::HeaderError => "HeaderError",
-            WebSocketError::ConnectError => "ConnectError",
-            WebSocketError::SendError => "SendError",
-            WebSocketError::ReceiveError => "ReceiveError",
-            WebSocketError::CloseError => "CloseError",
-        }
-    }
+pub fn to_callback_error(e: harmony_websocket_core::Error) -> Error<WebSocketError> {
+    Error::new(e.kind, e.message)
 }
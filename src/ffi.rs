@@ -0,0 +1,255 @@
+//! C surface for other native libraries of the same app.
+//! They receive a handle from `WebSocket.nativeHandle()` and can use the socket without crossing into JS.
+//! See `include/harmony_websocket.h` for the declarations.
+
+use std::{
+    cell::Cell,
+    ffi::c_void,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+};
+
+use harmony_websocket_core::{Link, Message, TrySend};
+
+pub const HARMONY_WS_OK: i32 = 0;
+pub const HARMONY_WS_INVALID_ARGUMENT: i32 = -1;
+pub const HARMONY_WS_NOT_CONNECTED: i32 = -2;
+
+pub const HARMONY_WS_EVENT_TEXT: u32 = 0;
+pub const HARMONY_WS_EVENT_BINARY: u32 = 1;
+pub const HARMONY_WS_EVENT_CLOSE: u32 = 2;
+
+pub type HarmonyWsCallback =
+    extern "C" fn(user_data: *mut c_void, event: u32, data: *const u8, len: usize);
+
+thread_local! {
+    /// The listener whose callback runs on this thread, 0 for none
+    static CALLING: Cell<u64> = const { Cell::new(0) };
+}
+
+struct Listener {
+    id: u64,
+    callback: HarmonyWsCallback,
+    user_data: *mut c_void,
+    /// Calls of the callback which are running, unregistering waits for them
+    calls: Mutex<usize>,
+    finished: Condvar,
+}
+
+// The owner of `user_data` promises that it can be used from any thread when registering.
+unsafe impl Send for Listener {}
+unsafe impl Sync for Listener {}
+
+impl Listener {
+    /// Wait until no call is running, except the one of this thread when the callback unregisters itself
+    fn wait_for_calls(&self) {
+        let own = usize::from(CALLING.get() == self.id);
+        let calls = self.calls.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(
+            self.finished
+                .wait_while(calls, |calls| *calls > own)
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+    }
+}
+
+/// Callbacks registered from native code, called on the connection thread.
+#[derive(Default)]
+pub struct NativeListeners {
+    next_id: AtomicU64,
+    listeners: Mutex<Vec<Arc<Listener>>>,
+}
+
+impl NativeListeners {
+    pub fn emit(&self, event: u32, data: &[u8]) {
+        // Copy the list out, so a callback is able to (un)register without deadlock
+        let ids: Vec<u64> = self
+            .listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|listener| listener.id)
+            .collect();
+        for id in ids {
+            // Counted while the list is locked, so an unregister either sees the call or it isn't made
+            let listener = {
+                let listeners = self
+                    .listeners
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let Some(listener) = listeners.iter().find(|listener| listener.id == id) else {
+                    continue;
+                };
+                *listener
+                    .calls
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner) += 1;
+                listener.clone()
+            };
+            let outer = CALLING.replace(id);
+            (listener.callback)(listener.user_data, event, data.as_ptr(), data.len());
+            CALLING.set(outer);
+            *listener
+                .calls
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) -= 1;
+            listener.finished.notify_all();
+        }
+    }
+
+    /// Drop every listener, their `user_data` isn't used once this returns
+    pub fn clear(&self) {
+        let removed = std::mem::take(
+            &mut *self
+                .listeners
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        for listener in removed {
+            listener.wait_for_calls();
+        }
+    }
+
+    fn register(&self, callback: HarmonyWsCallback, user_data: *mut c_void) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Arc::new(Listener {
+                id,
+                callback,
+                user_data,
+                calls: Mutex::new(0),
+                finished: Condvar::new(),
+            }));
+        id
+    }
+
+    /// Returns once a running call of the callback finished, unless it's the caller
+    fn unregister(&self, id: u64) -> bool {
+        let removed = {
+            let mut listeners = self
+                .listeners
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let Some(index) = listeners.iter().position(|listener| listener.id == id) else {
+                return false;
+            };
+            listeners.remove(index)
+        };
+        removed.wait_for_calls();
+        true
+    }
+}
+
+/// The object behind a native handle
+pub struct HarmonyWsHandle {
+    link: Arc<Link>,
+    listeners: Arc<NativeListeners>,
+}
+
+impl HarmonyWsHandle {
+    /// Leak a new handle, it must be given back with `harmony_ws_release`
+    pub fn into_raw(link: Arc<Link>, listeners: Arc<NativeListeners>) -> *const HarmonyWsHandle {
+        Arc::into_raw(Arc::new(HarmonyWsHandle { link, listeners }))
+    }
+}
+
+fn try_send_code(result: TrySend) -> i32 {
+    match result {
+        TrySend::Queued => HARMONY_WS_OK,
+        TrySend::NotConnected => HARMONY_WS_NOT_CONNECTED,
+    }
+}
+
+/// Queue a text (`binary == false`, must be utf-8) or binary message.
+/// The write happens later, a failure of it goes to `onError` of the WebSocket.
+///
+/// # Safety
+/// `handle` must come from `nativeHandle()` and not be released, `data` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn harmony_ws_send(
+    handle: *const HarmonyWsHandle,
+    data: *const u8,
+    len: usize,
+    binary: bool,
+) -> i32 {
+    let Some(handle) = handle.as_ref() else {
+        return HARMONY_WS_INVALID_ARGUMENT;
+    };
+    if data.is_null() && len > 0 {
+        return HARMONY_WS_INVALID_ARGUMENT;
+    }
+    let bytes = if len == 0 {
+        Vec::new()
+    } else {
+        std::slice::from_raw_parts(data, len).to_vec()
+    };
+    let message = if binary {
+        Message::Binary(bytes.into())
+    } else {
+        match String::from_utf8(bytes) {
+            Ok(text) => Message::Text(text.into()),
+            Err(_) => return HARMONY_WS_INVALID_ARGUMENT,
+        }
+    };
+    try_send_code(handle.link.try_send_message(message))
+}
+
+/// Start the close handshake.
+///
+/// # Safety
+/// `handle` must come from `nativeHandle()` and not be released.
+#[no_mangle]
+pub unsafe extern "C" fn harmony_ws_close(handle: *const HarmonyWsHandle) -> i32 {
+    let Some(handle) = handle.as_ref() else {
+        return HARMONY_WS_INVALID_ARGUMENT;
+    };
+    try_send_code(handle.link.try_close())
+}
+
+/// Register a callback for inbound messages and close, returns its id or 0 on invalid arguments.
+///
+/// # Safety
+/// `handle` must come from `nativeHandle()` and not be released.
+/// `user_data` must be usable from any thread until the callback is unregistered.
+#[no_mangle]
+pub unsafe extern "C" fn harmony_ws_register_callback(
+    handle: *const HarmonyWsHandle,
+    callback: Option<HarmonyWsCallback>,
+    user_data: *mut c_void,
+) -> u64 {
+    match (handle.as_ref(), callback) {
+        (Some(handle), Some(callback)) => handle.listeners.register(callback, user_data),
+        _ => 0,
+    }
+}
+
+/// Remove a callback registered by `harmony_ws_register_callback`.
+/// Waits for a running call of it on another thread, its `user_data` may be freed once this returns.
+///
+/// # Safety
+/// `handle` must come from `nativeHandle()` and not be released.
+#[no_mangle]
+pub unsafe extern "C" fn harmony_ws_unregister_callback(
+    handle: *const HarmonyWsHandle,
+    id: u64,
+) -> i32 {
+    match handle.as_ref() {
+        Some(handle) if handle.listeners.unregister(id) => HARMONY_WS_OK,
+        _ => HARMONY_WS_INVALID_ARGUMENT,
+    }
+}
+
+/// Give back a handle. The connection itself stays open and the callbacks stay registered.
+///
+/// # Safety
+/// `handle` must come from `nativeHandle()` and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn harmony_ws_release(handle: *const HarmonyWsHandle) {
+    if !handle.is_null() {
+        drop(Arc::from_raw(handle));
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::Arc;
+
+use harmony_websocket_core::Bytes;
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+
+/// Which received messages are delivered to JS. Give either `expression` or `prefix`.
+#[napi(object)]
+pub struct MessageFilter {
+    /// e.g. `$.type == "trade" && ($.price >= 100 || !$.delayed)`.
+    /// Only text messages which are JSON and satisfy it are delivered.
+    pub expression: Option<String>,
+    /// Only messages starting with these bytes are delivered, text or binary
+    pub prefix: Option<Either<String, Buffer>>,
+}
+
+/// Compile `filter`, `None` if it filters nothing
+pub fn to_filter(
+    filter: MessageFilter,
+) -> Result<Option<Arc<harmony_websocket_core::MessageFilter>>> {
+    let filter = match (filter.expression, filter.prefix) {
+        (Some(_), Some(_)) => {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "a filter has either an expression or a prefix",
+            ))
+        }
+        (Some(expression), None) => harmony_websocket_core::MessageFilter::expression(&expression)
+            .map_err(|e| Error::new(Status::InvalidArg, e))?,
+        (None, Some(prefix)) => harmony_websocket_core::MessageFilter::Prefix(match prefix {
+            Either::A(prefix) => Bytes::from(prefix),
+            Either::B(prefix) => Bytes::copy_from_slice(&prefix),
+        }),
+        (None, None) => return Ok(None),
+    };
+    Ok(Some(Arc::new(filter)))
+}
@@ -0,0 +1,542 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use harmony_websocket_core::{Background, Bytes, Link, Message, PowerSave};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Result};
+
+use crate::{
+    binary::{to_bytes, Binary, BinaryView},
+    codec,
+    error::to_napi_error,
+    info::WriteStats,
+};
+
+#[napi]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReadyState {
+    Connecting = 0,
+    Open = 1,
+    Closing = 2,
+    Closed = 3,
+}
+
+impl From<harmony_websocket_core::ReadyState> for ReadyState {
+    fn from(state: harmony_websocket_core::ReadyState) -> Self {
+        match state {
+            harmony_websocket_core::ReadyState::Connecting => ReadyState::Connecting,
+            harmony_websocket_core::ReadyState::Open => ReadyState::Open,
+            harmony_websocket_core::ReadyState::Closing => ReadyState::Closing,
+            harmony_websocket_core::ReadyState::Closed => ReadyState::Closed,
+        }
+    }
+}
+
+/// The detailed state of a connection, e.g. for a connection status in the UI
+#[napi(string_enum)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ConnState {
+    /// Resolving the host and making the tcp connection
+    #[napi(value = "connecting")]
+    Connecting,
+    #[napi(value = "tls-handshake")]
+    TlsHandshake,
+    /// Waiting for the server to accept the http upgrade
+    #[napi(value = "upgrading")]
+    Upgrading,
+    #[napi(value = "open")]
+    Open,
+    #[napi(value = "closing")]
+    Closing,
+    #[napi(value = "closed")]
+    Closed,
+    /// Waiting for the delay before `reconnect` makes the connection again
+    #[napi(value = "reconnecting")]
+    Reconnecting,
+}
+
+impl From<harmony_websocket_core::ConnState> for ConnState {
+    fn from(state: harmony_websocket_core::ConnState) -> Self {
+        match state {
+            harmony_websocket_core::ConnState::Connecting => ConnState::Connecting,
+            harmony_websocket_core::ConnState::TlsHandshake => ConnState::TlsHandshake,
+            harmony_websocket_core::ConnState::Upgrading => ConnState::Upgrading,
+            harmony_websocket_core::ConnState::Open => ConnState::Open,
+            harmony_websocket_core::ConnState::Closing => ConnState::Closing,
+            harmony_websocket_core::ConnState::Closed => ConnState::Closed,
+            harmony_websocket_core::ConnState::Reconnecting => ConnState::Reconnecting,
+        }
+    }
+}
+
+#[napi(object)]
+pub struct SendOptions {
+    /// Urgent messages are written right away, others may be deferred by power save.
+    /// Defaults to true.
+    pub urgent: Option<bool>,
+    /// How often a failed write is retried, also on the next connection. Defaults to 3.
+    pub retries: Option<u32>,
+    /// Free-form label, e.g. to clear the queued messages of one upload
+    pub tag: Option<String>,
+    /// Drop the message when it couldn't be written within this time, e.g. during an outage.
+    /// `onDropped` is called then.
+    pub ttl_ms: Option<u32>,
+    /// For `sendAfter()` and `sendAt()` only: when the connection breaks before the message is due,
+    /// write it on the next connection instead of dropping it. Defaults to false.
+    pub keep_across_reconnect: Option<bool>,
+}
+
+impl From<SendOptions> for harmony_websocket_core::SendOptions {
+    fn from(options: SendOptions) -> Self {
+        let defaults = harmony_websocket_core::SendOptions::default();
+        harmony_websocket_core::SendOptions {
+            urgent: options.urgent.unwrap_or(defaults.urgent),
+            retries: options.retries.unwrap_or(defaults.retries),
+            tag: options.tag,
+            ttl: options
+                .ttl_ms
+                .map(|ttl_ms| Duration::from_millis(u64::from(ttl_ms))),
+        }
+    }
+}
+
+/// What sending does while there's no connection, also between the attempts of a reconnect
+#[napi(string_enum)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SendBeforeConnect {
+    /// The message is dropped and the send resolves with `null`
+    #[napi(value = "drop")]
+    Drop,
+    /// The send rejects with `NotConnected`
+    #[napi(value = "reject")]
+    Reject,
+    /// The message is queued and written once connected, unless its `ttlMs` runs out first
+    #[napi(value = "queue")]
+    Queue,
+    /// Like `queue`, and `connect()` is called unless a connection is running or reconnecting.
+    /// The send rejects when that connect fails.
+    #[napi(value = "connect")]
+    Connect,
+}
+
+impl From<SendBeforeConnect> for harmony_websocket_core::SendBeforeConnect {
+    fn from(policy: SendBeforeConnect) -> Self {
+        match policy {
+            SendBeforeConnect::Drop => harmony_websocket_core::SendBeforeConnect::Drop,
+            SendBeforeConnect::Reject => harmony_websocket_core::SendBeforeConnect::Reject,
+            SendBeforeConnect::Queue | SendBeforeConnect::Connect => {
+                harmony_websocket_core::SendBeforeConnect::Queue
+            }
+        }
+    }
+}
+
+#[napi(object)]
+pub struct ClearQueueFilter {
+    /// Only drop messages sent with this tag
+    pub tag: Option<String>,
+}
+
+pub fn clear_queue(link: &Link, filter: Option<ClearQueueFilter>) -> u32 {
+    let tag = filter.and_then(|filter| filter.tag);
+    link.clear_queue(tag.as_deref()) as u32
+}
+
+#[napi(object)]
+pub struct SuspendOptions {
+    /// Text message sent first, e.g. to let the server switch to push notifications
+    pub going_away_message: Option<String>,
+    /// Longest wait for the queue to be written, in milliseconds. Defaults to 3000.
+    pub timeout_ms: Option<u32>,
+    /// Close with 1001 (going away) afterwards. Defaults to false.
+    pub close: Option<bool>,
+}
+
+pub async fn prepare_for_suspend(link: &Link, options: Option<SuspendOptions>) -> Result<()> {
+    let defaults = harmony_websocket_core::SuspendOptions::default();
+    let options = match options {
+        Some(options) => harmony_websocket_core::SuspendOptions {
+            going_away_message: options.going_away_message,
+            flush_timeout: options
+                .timeout_ms
+                .map(|ms| Duration::from_millis(u64::from(ms)))
+                .unwrap_or(defaults.flush_timeout),
+            close: options.close.unwrap_or(defaults.close),
+        },
+        None => defaults,
+    };
+    link.prepare_for_suspend(options)
+        .await
+        .map_err(to_napi_error)
+}
+
+#[napi(object)]
+pub struct PowerSaveOptions {
+    /// Longest time a non-urgent message is held back, in milliseconds. Defaults to 30000.
+    pub max_defer_ms: Option<u32>,
+    /// The keepalive interval is multiplied by this while enabled, at least 1. Defaults to 2.
+    pub keepalive_factor: Option<u32>,
+}
+
+pub fn set_power_save(link: &Link, enabled: bool, options: Option<PowerSaveOptions>) {
+    let defaults = PowerSave::default();
+    link.set_power_save(PowerSave {
+        enabled,
+        max_defer_ms: options
+            .as_ref()
+            .and_then(|o| o.max_defer_ms)
+            .map(u64::from)
+            .unwrap_or(defaults.max_defer_ms),
+        keepalive_factor: options
+            .and_then(|o| o.keepalive_factor)
+            .unwrap_or(defaults.keepalive_factor),
+    });
+}
+
+#[napi(object)]
+pub struct BackgroundOptions {
+    /// The keepalive interval is multiplied by this while in the background, 0 stops the keepalive
+    /// and the idle timeout. Defaults to 4.
+    pub keepalive_factor: Option<u32>,
+    /// Hold sent messages back until `resume()`, so no write is cut off when the app is frozen.
+    /// Pings and pongs are still written. Defaults to true.
+    pub buffer: Option<bool>,
+}
+
+pub fn suspend(link: &Link, options: Option<BackgroundOptions>) {
+    let defaults = Background::default();
+    link.suspend(match options {
+        Some(options) => Background {
+            keepalive_factor: options
+                .keepalive_factor
+                .unwrap_or(defaults.keepalive_factor),
+            buffer: options.buffer.unwrap_or(defaults.buffer),
+        },
+        None => defaults,
+    });
+}
+
+/// Text, an `ArrayBuffer` or any `TypedArray` or `DataView`
+pub type SendData = Either3<String, Buffer, BinaryView>;
+
+/// Binary data of an `ArrayBuffer` is not copied, the message keeps the JS buffer until it's written
+pub fn to_message(data: SendData) -> Message {
+    match data {
+        Either3::A(text) => Message::Text(text.into()),
+        Either3::B(buf) => Message::Binary(to_bytes(Either::A(buf))),
+        Either3::C(view) => Message::Binary(to_bytes(Either::B(view))),
+    }
+}
+
+pub async fn send(
+    link: &Link,
+    data: SendData,
+    options: Option<SendOptions>,
+) -> Result<Option<i64>> {
+    let options = options.map(Into::into).unwrap_or_default();
+    link.send_with(to_message(data), options)
+        .await
+        .map(|id| id.map(|id| id as i64))
+        .map_err(to_napi_error)
+}
+
+/// Encode `value` with the codec called `codec` and send the result
+pub async fn send_encoded(
+    link: &Link,
+    codec: String,
+    value: serde_json::Value,
+    options: Option<SendOptions>,
+) -> Result<Option<i64>> {
+    let message = codec::lookup(&codec)?.encode(value).await?;
+    let options = options.map(Into::into).unwrap_or_default();
+    link.send_with(message, options)
+        .await
+        .map(|id| id.map(|id| id as i64))
+        .map_err(to_napi_error)
+}
+
+/// Send `payload` in an envelope of `kind` with a new id, returns the id of the envelope
+pub async fn send_envelope(
+    link: &Link,
+    kind: String,
+    payload: serde_json::Value,
+    options: Option<SendOptions>,
+) -> Result<String> {
+    let envelope = harmony_websocket_core::Envelope::new(kind, payload);
+    let options = options.map(Into::into).unwrap_or_default();
+    link.send_with(Message::Text(envelope.pack().into()), options)
+        .await
+        .map_err(to_napi_error)?;
+    Ok(envelope.id.unwrap_or_default())
+}
+
+pub async fn send_parts(
+    link: &Link,
+    parts: Vec<Binary>,
+    options: Option<SendOptions>,
+) -> Result<Option<i64>> {
+    let options = options.map(Into::into).unwrap_or_default();
+    link.send_parts(parts.into_iter().map(to_bytes).collect(), options)
+        .await
+        .map(|id| id.map(|id| id as i64))
+        .map_err(to_napi_error)
+}
+
+pub fn send_after(
+    link: &Link,
+    delay: Duration,
+    data: SendData,
+    options: Option<SendOptions>,
+) -> Result<Option<i64>> {
+    let keep_across_reconnect = options
+        .as_ref()
+        .and_then(|options| options.keep_across_reconnect)
+        .unwrap_or(false);
+    let options = options.map(Into::into).unwrap_or_default();
+    link.send_after(delay, to_message(data), options, keep_across_reconnect)
+        .map(|id| id.map(|id| id as i64))
+        .map_err(to_napi_error)
+}
+
+/// The delay until `timestamp`, in milliseconds since the epoch like `Date.now()`.
+/// A timestamp in the past is due right away, one which isn't finite or is out of range is rejected.
+pub fn delay_until(timestamp: f64) -> Result<Duration> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let at = Some(timestamp)
+        .filter(|timestamp| timestamp.is_finite())
+        .and_then(|timestamp| Duration::try_from_secs_f64((timestamp / 1000.0).max(0.0)).ok())
+        .ok_or_else(|| {
+            Error::new(
+                Status::InvalidArg,
+                format!(
+                    "send timestamp must be a finite time in range, got {}",
+                    timestamp
+                ),
+            )
+        })?;
+    Ok(at.saturating_sub(now))
+}
+
+pub fn cancel_send(link: &Link, id: i64) -> bool {
+    u64::try_from(id).is_ok_and(|id| link.cancel_send(id))
+}
+
+pub fn remember_send(link: &Link, data: SendData) -> i64 {
+    link.remember(to_message(data)) as i64
+}
+
+pub fn forget_send(link: &Link, id: i64) -> bool {
+    u64::try_from(id).is_ok_and(|id| link.forget(id))
+}
+
+pub async fn flush(link: &Link, timeout_ms: Option<u32>) -> Result<()> {
+    link.flush(timeout_ms.map(|ms| Duration::from_millis(u64::from(ms))))
+        .await
+        .map_err(to_napi_error)
+}
+
+/// Close with `code` and `reason` when given. As in browsers only 1000 and 3000 to 4999
+/// are allowed, and the reason is at most 123 bytes.
+pub async fn close(link: &Link, code: Option<u32>, reason: Option<String>) -> Result<()> {
+    let code = match (code, &reason) {
+        (Some(code), _) => code,
+        (None, Some(_)) => 1000,
+        (None, None) => return link.close().await.map_err(to_napi_error),
+    };
+    if code != 1000 && !(3000..=4999).contains(&code) {
+        return Err(Error::new(
+            Status::InvalidArg,
+            format!(
+                "close code must be 1000 or between 3000 and 4999, got {}",
+                code
+            ),
+        ));
+    }
+    let reason = reason.unwrap_or_default();
+    if reason.len() > 123 {
+        return Err(Error::new(
+            Status::InvalidArg,
+            format!(
+                "close reason must be at most 123 bytes, got {}",
+                reason.len()
+            ),
+        ));
+    }
+    link.close_with(code as u16, &reason)
+        .await
+        .map_err(to_napi_error)
+}
+
+pub async fn ping(link: &Link, ping_message: Option<Buffer>) -> Result<()> {
+    link.ping(ping_message.map(Bytes::from_owner))
+        .await
+        .map_err(to_napi_error)
+}
+
+/// A cheap send-only handle of a [`crate::WebSocket`].
+/// It shares the connection but none of the callbacks, so it can be passed around freely.
+#[napi]
+pub struct WebSocketHandle {
+    link: Arc<Link>,
+}
+
+impl WebSocketHandle {
+    pub fn new(link: Arc<Link>) -> Self {
+        WebSocketHandle { link }
+    }
+}
+
+#[napi]
+impl WebSocketHandle {
+    #[napi]
+    pub async fn send(&self, data: SendData, options: Option<SendOptions>) -> Result<Option<i64>> {
+        send(&self.link, data, options).await
+    }
+
+    #[napi]
+    pub async fn send_parts(
+        &self,
+        parts: Vec<Binary>,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        send_parts(&self.link, parts, options).await
+    }
+
+    #[napi]
+    pub async fn send_encoded(
+        &self,
+        codec: String,
+        value: serde_json::Value,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        send_encoded(&self.link, codec, value, options).await
+    }
+
+    #[napi]
+    pub async fn send_envelope(
+        &self,
+        kind: String,
+        payload: serde_json::Value,
+        options: Option<SendOptions>,
+    ) -> Result<String> {
+        send_envelope(&self.link, kind, payload, options).await
+    }
+
+    #[napi]
+    pub fn send_after(
+        &self,
+        delay_ms: u32,
+        data: SendData,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        send_after(
+            &self.link,
+            Duration::from_millis(u64::from(delay_ms)),
+            data,
+            options,
+        )
+    }
+
+    #[napi]
+    pub fn send_at(
+        &self,
+        timestamp: f64,
+        data: SendData,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        send_after(&self.link, delay_until(timestamp)?, data, options)
+    }
+
+    #[napi]
+    pub fn cancel_send(&self, id: i64) -> bool {
+        cancel_send(&self.link, id)
+    }
+
+    #[napi]
+    pub fn remember_send(&self, data: SendData) -> i64 {
+        remember_send(&self.link, data)
+    }
+
+    #[napi]
+    pub fn forget_send(&self, id: i64) -> bool {
+        forget_send(&self.link, id)
+    }
+
+    #[napi]
+    pub async fn flush(&self, timeout_ms: Option<u32>) -> Result<()> {
+        flush(&self.link, timeout_ms).await
+    }
+
+    #[napi]
+    pub fn clear_queue(&self, filter: Option<ClearQueueFilter>) -> u32 {
+        clear_queue(&self.link, filter)
+    }
+
+    /// Resolves once the close is queued, `WebSocket.close()` waits for the server's answer as well
+    #[napi]
+    pub async fn close(&self, code: Option<u32>, reason: Option<String>) -> Result<()> {
+        close(&self.link, code, reason).await
+    }
+
+    /// Send a ping with `pingMessage` or `ping`, it's rejected when the payload is over 125 bytes
+    #[napi]
+    pub async fn ping(&self, ping_message: Option<Buffer>) -> Result<()> {
+        ping(&self.link, ping_message).await
+    }
+
+    #[napi]
+    pub fn set_power_save(&self, enabled: bool, options: Option<PowerSaveOptions>) {
+        set_power_save(&self.link, enabled, options);
+    }
+
+    #[napi]
+    pub async fn prepare_for_suspend(&self, options: Option<SuspendOptions>) -> Result<()> {
+        prepare_for_suspend(&self.link, options).await
+    }
+
+    #[napi(getter)]
+    pub fn state(&self) -> ReadyState {
+        self.link.state().into()
+    }
+
+    #[napi(getter)]
+    pub fn conn_state(&self) -> ConnState {
+        self.link.conn_state().into()
+    }
+
+    #[napi(getter)]
+    pub fn tag(&self) -> HashMap<String, String> {
+        self.link.tag()
+    }
+
+    #[napi(getter)]
+    pub fn pending_count(&self) -> u32 {
+        self.link.pending_count() as u32
+    }
+
+    #[napi(getter)]
+    pub fn pending_bytes(&self) -> f64 {
+        self.link.pending_bytes() as f64
+    }
+
+    #[napi]
+    pub fn pending_tags(&self) -> Vec<String> {
+        self.link.pending_tags()
+    }
+
+    #[napi]
+    pub fn get_write_stats(&self) -> WriteStats {
+        self.link.write_stats().into()
+    }
+
+    /// Create another handle of the same connection
+    #[napi(js_name = "clone")]
+    pub fn duplicate(&self) -> WebSocketHandle {
+        WebSocketHandle::new(self.link.clone())
+    }
+}
@@ -0,0 +1,549 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock, PoisonError, RwLock},
+};
+
+use harmony_websocket_core::{
+    BoxFuture, Bytes, EnvelopeRouter, Event, EventHandler, Link, MessageFilter, PongPolicy, Router,
+    TextAssembler, Utf8Bytes,
+};
+use napi_ohos::{
+    bindgen_prelude::*,
+    threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode},
+    Error,
+};
+use ohos_hilog_binding::hilog_error;
+
+use crate::{
+    backlog::JsBacklog,
+    binary::{to_bytes, Binary},
+    codec::Codec,
+    envelope::Envelope,
+    error::{to_callback_error, WebSocketError},
+    ffi::{
+        NativeListeners, HARMONY_WS_EVENT_BINARY, HARMONY_WS_EVENT_CLOSE, HARMONY_WS_EVENT_TEXT,
+    },
+    handle::ConnState,
+    info::{
+        CloseInfo, DroppedMessage, OpenInfo, PongInfo, ReconnectAttempt, RedirectInfo,
+        SequenceAnomaly, ServerClosing,
+    },
+    large::LargeMessage,
+    network::NetworkChange,
+    worker,
+};
+
+pub type ErrorCallback =
+    ThreadsafeFunction<Error<WebSocketError>, (), Error<WebSocketError>, false>;
+pub type MessageCallback =
+    ThreadsafeFunction<Either<String, Buffer>, (), Either<String, Buffer>, false>;
+/// Weak, it doesn't keep the env of the page which added it alive
+pub type MessageListener =
+    ThreadsafeFunction<Either<String, Buffer>, (), Either<String, Buffer>, false, true>;
+pub type OpenCallback = ThreadsafeFunction<OpenInfo, (), OpenInfo, false>;
+pub type CloseCallback = ThreadsafeFunction<CloseInfo, (), CloseInfo, false>;
+pub type ServerClosingCallback = ThreadsafeFunction<ServerClosing, (), ServerClosing, false>;
+pub type StateChangeCallback = ThreadsafeFunction<ConnState, (), ConnState, false>;
+pub type PingCallback = ThreadsafeFunction<Buffer, Option<Binary>, Buffer, false>;
+pub type PongCallback =
+    ThreadsafeFunction<FnArgs<(Buffer, PongInfo)>, (), FnArgs<(Buffer, PongInfo)>, false>;
+pub type HeaderReceivedCallback =
+    ThreadsafeFunction<HashMap<String, String>, (), HashMap<String, String>, false>;
+pub type DeferredCallback = ThreadsafeFunction<(), (), (), false>;
+pub type RedirectCallback = ThreadsafeFunction<RedirectInfo, (), RedirectInfo, false>;
+pub type ReconnectingCallback = ThreadsafeFunction<ReconnectAttempt, (), ReconnectAttempt, false>;
+pub type ReconnectedCallback = ThreadsafeFunction<u32, (), u32, false>;
+pub type RotatingCallback = ThreadsafeFunction<f64, (), f64, false>;
+pub type ExtensionFallbackCallback = ThreadsafeFunction<String, (), String, false>;
+pub type SuspendHintCallback = ThreadsafeFunction<(), (), (), false>;
+pub type DroppedCallback = ThreadsafeFunction<DroppedMessage, (), DroppedMessage, false>;
+pub type LargeMessageCallback = ThreadsafeFunction<LargeMessage, (), LargeMessage, false>;
+pub type RouteCallback = ThreadsafeFunction<String, (), String, false>;
+pub type EnvelopeCallback = ThreadsafeFunction<Envelope, (), Envelope, false>;
+pub type JsBacklogCallback = ThreadsafeFunction<f64, (), f64, false>;
+pub type SequenceAnomalyCallback = ThreadsafeFunction<SequenceAnomaly, (), SequenceAnomaly, false>;
+pub type NetworkChangeCallback = ThreadsafeFunction<NetworkChange, (), NetworkChange, false>;
+pub type DecodedCallback = ThreadsafeFunction<serde_json::Value, (), serde_json::Value, false>;
+
+/// All registered callbacks. A set is never mutated once published,
+/// registering or removing a callback publishes a new set instead.
+#[derive(Clone, Default)]
+pub struct Handlers {
+    pub on_error: Option<Arc<ErrorCallback>>,
+    pub on_message: Option<Arc<MessageCallback>>,
+    /// Get the messages of onMessage as well, see `addMessageListener()`
+    pub message_listeners: Listeners<Arc<MessageListener>>,
+    pub on_open: Option<Arc<OpenCallback>>,
+    pub on_close: Option<Arc<CloseCallback>>,
+    pub on_server_closing: Option<Arc<ServerClosingCallback>>,
+    pub on_state_change: Option<Arc<StateChangeCallback>>,
+    pub on_ping: Option<Arc<PingCallback>>,
+    pub on_pong: Option<Arc<PongCallback>>,
+    pub on_header_received: Option<Arc<HeaderReceivedCallback>>,
+    pub on_deferred: Option<Arc<DeferredCallback>>,
+    pub on_redirect: Option<Arc<RedirectCallback>>,
+    pub on_reconnecting: Option<Arc<ReconnectingCallback>>,
+    pub on_reconnected: Option<Arc<ReconnectedCallback>>,
+    pub on_reconnect_failed: Option<Arc<ReconnectedCallback>>,
+    pub on_rotating: Option<Arc<RotatingCallback>>,
+    pub on_extension_fallback: Option<Arc<ExtensionFallbackCallback>>,
+    pub on_suspend_hint: Option<Arc<SuspendHintCallback>>,
+    pub on_dropped: Option<Arc<DroppedCallback>>,
+    pub on_large_message: Option<Arc<LargeMessageCallback>>,
+    pub on_js_backlog: Option<Arc<JsBacklogCallback>>,
+    pub on_sequence_anomaly: Option<Arc<SequenceAnomalyCallback>>,
+    pub on_network_change: Option<Arc<NetworkChangeCallback>>,
+    /// Handlers of text messages by the value of a JSON field, see `route()`
+    pub router: Router<Arc<RouteCallback>>,
+    /// Handlers of envelopes by their type, see `onEnvelope()`
+    pub envelopes: EnvelopeRouter<Arc<EnvelopeCallback>>,
+    /// Messages which don't match aren't delivered to JS
+    pub filter: Option<Arc<MessageFilter>>,
+    /// Decodes received messages for onDecoded, see the `codec` of `WebSocketConfig`
+    pub codec: Option<Arc<Codec>>,
+    pub on_decoded: Option<Arc<DecodedCallback>>,
+}
+
+/// Callbacks of one event by id, in the order they were added
+#[derive(Clone)]
+pub struct Listeners<H> {
+    next_id: u64,
+    listeners: Vec<(u64, H)>,
+}
+
+impl<H> Default for Listeners<H> {
+    fn default() -> Self {
+        Listeners {
+            next_id: 0,
+            listeners: Vec::new(),
+        }
+    }
+}
+
+impl<H> Listeners<H> {
+    /// Returns the id for [`Listeners::remove`]
+    pub fn add(&mut self, listener: H) -> u64 {
+        self.next_id += 1;
+        self.listeners.push((self.next_id, listener));
+        self.next_id
+    }
+
+    /// Returns whether the listener existed
+    pub fn remove(&mut self, id: u64) -> bool {
+        let before = self.listeners.len();
+        self.listeners.retain(|(listener_id, _)| *listener_id != id);
+        self.listeners.len() != before
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(u64, H)> {
+        self.listeners.iter()
+    }
+}
+
+/// Holder of the current [`Handlers`], shared between the JS thread which
+/// registers callbacks and the connection loops which deliver events.
+#[derive(Default)]
+pub struct HandlerSet {
+    current: RwLock<Arc<Handlers>>,
+}
+
+impl HandlerSet {
+    /// Take the current set. Every event is delivered through exactly one snapshot,
+    /// so a callback swapped mid-event never sees half of it.
+    pub fn snapshot(&self) -> Arc<Handlers> {
+        self.current
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub fn update(&self, f: impl FnOnce(&mut Handlers)) {
+        let mut current = self.current.write().unwrap_or_else(PoisonError::into_inner);
+        let mut next = Handlers::clone(&current);
+        f(&mut next);
+        *current = Arc::new(next);
+    }
+}
+
+/// Delivers connection events to the JS callbacks and the native listeners
+#[derive(Default)]
+pub struct Dispatcher {
+    pub handlers: HandlerSet,
+    pub native_listeners: Arc<NativeListeners>,
+    /// The link of the connection, for its tag in log lines
+    pub link: OnceLock<Arc<Link>>,
+    /// The latest text message per key, when `latestKey` is configured
+    pub latest: OnceLock<Mutex<LatestCache>>,
+    /// Messages over this many bytes go to onLargeMessage, when it's registered
+    pub large_message_threshold: OnceLock<usize>,
+    /// The channel of `onWorkerMessage()` which gets the messages over the threshold, see `workerRoute`
+    pub worker_route: OnceLock<(String, usize)>,
+    /// With `handler`, a ping onPing returns nothing for isn't answered
+    pub pong_policy: OnceLock<PongPolicy>,
+    /// Times the onMessage callbacks, when `jsBacklogThresholdMs` is configured
+    pub js_backlog: OnceLock<Arc<JsBacklog>>,
+    /// Joins received text messages into documents, see `setAssembly()`
+    pub assembler: Mutex<Option<TextAssembler>>,
+}
+
+impl Dispatcher {
+    /// Drop every callback and native listener, see `WebSocket.dispose()`
+    pub fn release(&self) {
+        self.handlers.update(|h| *h = Handlers::default());
+        self.native_listeners.clear();
+    }
+
+    /// The callback for a received message of `size` bytes, if it's a large one
+    fn large_message(&self, handlers: &Handlers, size: usize) -> Option<Arc<LargeMessageCallback>> {
+        let threshold = *self.large_message_threshold.get()?;
+        handlers
+            .on_large_message
+            .clone()
+            .filter(|_| size > threshold)
+    }
+
+    /// Hand a message of `size` bytes to the worker of `workerRoute` if it's over the threshold.
+    /// Returns false when it's delivered as usual, also when no worker listens on the channel.
+    fn to_worker(&self, size: usize, message: impl Fn() -> Either<String, Buffer>) -> bool {
+        let Some((channel, threshold)) = self.worker_route.get() else {
+            return false;
+        };
+        size > *threshold && worker::deliver(channel, message)
+    }
+
+    /// Decode `data` with `codec` for onDecoded. Registered codecs decode on the JS thread,
+    /// `message` is what their `decode` gets.
+    fn decoded(
+        &self,
+        handlers: &Handlers,
+        codec: &Codec,
+        on_decoded: &Arc<DecodedCallback>,
+        data: &[u8],
+        message: impl FnOnce() -> Either<String, Buffer>,
+    ) {
+        let on_error = handlers.on_error.clone();
+        let failed = move |message: String| {
+            if let Some(on_error) = on_error {
+                on_error.call(
+                    Error::new(WebSocketError::ReceiveError, message),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                );
+            }
+        };
+        match codec.decode(data) {
+            Some(Ok(value)) => {
+                on_decoded.call(value, ThreadsafeFunctionCallMode::NonBlocking);
+            }
+            Some(Err(e)) => failed(e),
+            None => {
+                let Codec::Js { decode, .. } = codec else {
+                    return;
+                };
+                let on_decoded = on_decoded.clone();
+                decode.call_with_return_value(
+                    message(),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                    move |value, _| {
+                        match value {
+                            Ok(value) => {
+                                on_decoded.call(value, ThreadsafeFunctionCallMode::NonBlocking);
+                            }
+                            Err(e) => failed(format!("ws-rs decode failed: {}", e)),
+                        }
+                        Ok(())
+                    },
+                );
+            }
+        }
+    }
+
+    /// Deliver a message to onMessage and every message listener.
+    /// Listeners whose env was released, e.g. of a destroyed worker, are dropped on the way.
+    fn message(&self, handlers: &Handlers, message: impl Fn() -> Either<String, Buffer>) {
+        match (&handlers.on_message, self.js_backlog.get()) {
+            (Some(on_message), Some(backlog)) => {
+                let ran = backlog.clone();
+                let link = self.link.get().cloned();
+                let on_js_backlog = handlers.on_js_backlog.clone();
+                let queued_at = backlog.queued();
+                let status = on_message.call_with_return_value(
+                    message(),
+                    ThreadsafeFunctionCallMode::NonBlocking,
+                    move |_, _| {
+                        if let (Some(delay), Some(on_js_backlog)) =
+                            (ran.ran(queued_at, link.as_deref()), on_js_backlog)
+                        {
+                            on_js_backlog.call(
+                                delay.as_secs_f64() * 1000.0,
+                                ThreadsafeFunctionCallMode::NonBlocking,
+                            );
+                        }
+                        Ok(())
+                    },
+                );
+                if status != Status::Ok {
+                    backlog.dropped();
+                }
+            }
+            (Some(on_message), None) => {
+                on_message.call(message(), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+            (None, _) => {}
+        }
+        let released: Vec<u64> = handlers
+            .message_listeners
+            .iter()
+            .filter(|(_, listener)| {
+                listener.call(message(), ThreadsafeFunctionCallMode::NonBlocking) == Status::Closing
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        if !released.is_empty() {
+            self.handlers.update(|h| {
+                for id in released {
+                    h.message_listeners.remove(id);
+                }
+            });
+        }
+    }
+
+    /// Deliver a received text message, or a document assembled from several of them
+    fn text(&self, handlers: &Handlers, text: Utf8Bytes) {
+        if let Some(filter) = &handlers.filter {
+            if !filter.matches_text(&text) {
+                return;
+            }
+        }
+        if let Some(latest) = self.latest.get() {
+            latest
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(&text);
+        }
+        if self.to_worker(text.len(), || Either::A(text.to_string())) {
+            return;
+        }
+        if let Some(on_large_message) = self.large_message(handlers, text.len()) {
+            let message = LargeMessage::new(Bytes::from(text), true);
+            on_large_message.call(message, ThreadsafeFunctionCallMode::NonBlocking);
+            return;
+        }
+        // Envelopes with a handler are unpacked natively and go to it only
+        if !handlers.envelopes.is_empty() {
+            if let Some(envelope) = harmony_websocket_core::Envelope::unpack(&text) {
+                let matching = handlers.envelopes.matching(&envelope.kind);
+                if !matching.is_empty() {
+                    for handler in matching {
+                        handler.call(
+                            Envelope::from(envelope.clone()),
+                            ThreadsafeFunctionCallMode::NonBlocking,
+                        );
+                    }
+                    return;
+                }
+            }
+        }
+        // Routed messages only cross the bridge to their routes
+        let routes = handlers.router.matching(&text);
+        if !routes.is_empty() {
+            for route in routes {
+                route.call(text.to_string(), ThreadsafeFunctionCallMode::NonBlocking);
+            }
+        } else if let (Some(codec), Some(on_decoded)) = (&handlers.codec, &handlers.on_decoded) {
+            self.decoded(handlers, codec, on_decoded, text.as_bytes(), || {
+                Either::A(text.to_string())
+            });
+        } else {
+            self.message(handlers, || Either::A(text.to_string()));
+        }
+    }
+
+    fn log_label(&self) -> String {
+        self.link
+            .get()
+            .map(|link| link.log_label())
+            .unwrap_or_default()
+    }
+}
+
+impl EventHandler for Dispatcher {
+    fn handle(&self, event: Event) {
+        let handlers = self.handlers.snapshot();
+        match event {
+            Event::Open(info) => {
+                // A document left incomplete by the previous connection never completes
+                if let Some(assembler) = self
+                    .assembler
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .as_mut()
+                {
+                    assembler.reset();
+                }
+                if let Some(on_open) = &handlers.on_open {
+                    on_open.call(info.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::HeaderReceived(headers) => {
+                if let Some(on_header_received) = &handlers.on_header_received {
+                    on_header_received.call(headers, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Text(text) => {
+                self.native_listeners
+                    .emit(HARMONY_WS_EVENT_TEXT, text.as_bytes());
+                let assembled = {
+                    let mut assembler = self
+                        .assembler
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner);
+                    assembler.as_mut().map(|assembler| assembler.push(&text))
+                };
+                let Some((documents, error)) = assembled else {
+                    self.text(&handlers, text);
+                    return;
+                };
+                for document in documents {
+                    self.text(&handlers, Utf8Bytes::from(document));
+                }
+                if let (Some(e), Some(on_error)) = (error, &handlers.on_error) {
+                    on_error.call(
+                        Error::new(WebSocketError::ReceiveError, e.to_string()),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            }
+            Event::Binary(data) => {
+                self.native_listeners.emit(HARMONY_WS_EVENT_BINARY, &data);
+                if let Some(filter) = &handlers.filter {
+                    if !filter.matches_binary(&data) {
+                        return;
+                    }
+                }
+                if self.to_worker(data.len(), || Either::B(Buffer::from(data.as_ref()))) {
+                    return;
+                }
+                if let Some(on_large_message) = self.large_message(&handlers, data.len()) {
+                    let message = LargeMessage::new(data, false);
+                    on_large_message.call(message, ThreadsafeFunctionCallMode::NonBlocking);
+                    return;
+                }
+                if let (Some(codec), Some(on_decoded)) = (&handlers.codec, &handlers.on_decoded) {
+                    self.decoded(&handlers, codec, on_decoded, &data, || {
+                        Either::B(Buffer::from(data.as_ref()))
+                    });
+                } else {
+                    self.message(&handlers, || Either::B(Buffer::from(data.as_ref())));
+                }
+            }
+            Event::Close(info) => {
+                self.native_listeners.emit(HARMONY_WS_EVENT_CLOSE, &[]);
+                if let Some(on_close) = &handlers.on_close {
+                    on_close.call(info.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::StateChange(state) => {
+                if let Some(on_state_change) = &handlers.on_state_change {
+                    on_state_change.call(state.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::ServerClosing(closing) => {
+                if let Some(on_server_closing) = &handlers.on_server_closing {
+                    on_server_closing.call(closing.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Pong(pong) => {
+                if let Some(on_pong) = &handlers.on_pong {
+                    let info = PongInfo::from(&pong);
+                    // Takes over the received bytes unless they're still shared
+                    let payload = Buffer::from(Vec::from(pong.payload));
+                    on_pong.call(
+                        (payload, info).into(),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            }
+            Event::Deferred => {
+                if let Some(on_deferred) = &handlers.on_deferred {
+                    on_deferred.call((), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Redirect(redirect) => {
+                if let Some(on_redirect) = &handlers.on_redirect {
+                    on_redirect.call(redirect.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Reconnecting(attempt) => {
+                if let Some(on_reconnecting) = &handlers.on_reconnecting {
+                    on_reconnecting.call(attempt.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Reconnected(attempts) => {
+                if let Some(on_reconnected) = &handlers.on_reconnected {
+                    on_reconnected.call(attempts, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Rotating(age) => {
+                if let Some(on_rotating) = &handlers.on_rotating {
+                    on_rotating.call(
+                        age.as_secs_f64() * 1000.0,
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            }
+            Event::ReconnectFailed(attempts) => {
+                if let Some(on_reconnect_failed) = &handlers.on_reconnect_failed {
+                    on_reconnect_failed.call(attempts, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::ExtensionFallback(reason) => {
+                if let Some(on_extension_fallback) = &handlers.on_extension_fallback {
+                    on_extension_fallback.call(reason, ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::SuspendHint => {
+                if let Some(on_suspend_hint) = &handlers.on_suspend_hint {
+                    on_suspend_hint.call((), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Dropped(dropped) => {
+                if let Some(on_dropped) = &handlers.on_dropped {
+                    on_dropped.call(dropped.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::SequenceAnomaly(anomaly) => {
+                if let Some(on_sequence_anomaly) = &handlers.on_sequence_anomaly {
+                    on_sequence_anomaly
+                        .call(anomaly.into(), ThreadsafeFunctionCallMode::NonBlocking);
+                }
+            }
+            Event::Error(e) => {
+                if let Some(on_error) = &handlers.on_error {
+                    on_error.call(
+                        to_callback_error(e),
+                        ThreadsafeFunctionCallMode::NonBlocking,
+                    );
+                }
+            }
+        }
+    }
+
+    fn ping(&self, payload: Bytes) -> BoxFuture<Option<Bytes>> {
+        let Some(on_ping) = self.handlers.snapshot().on_ping.clone() else {
+            return Box::pin(async { None });
+        };
+        let log_label = self.log_label();
+        let default_pong = (self.pong_policy.get() != Some(&PongPolicy::Handler))
+            .then(|| Bytes::from_static(b"pong"));
+        Box::pin(async move {
+            match on_ping.call_async(Buffer::from(Vec::from(payload))).await {
+                Ok(Some(pong_message)) => Some(to_bytes(pong_message)),
+                Ok(None) => default_pong,
+                Err(e) => {
+                    hilog_error!(format!("ws-rs{}: onPing error: {}", log_label, e));
+                    default_pong
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,521 @@
+use std::collections::HashMap;
+
+use napi_derive_ohos::napi;
+
+use crate::tls::{RevocationMode, TlsBackend};
+
+/// The opening handshake request as it was sent to the server
+#[napi(object)]
+pub struct HandshakeRequest {
+    pub method: String,
+    pub url: String,
+    /// Final header set, including headers injected by the client itself
+    pub headers: HashMap<String, String>,
+}
+
+impl From<harmony_websocket_core::HandshakeRequest> for HandshakeRequest {
+    fn from(request: harmony_websocket_core::HandshakeRequest) -> Self {
+        HandshakeRequest {
+            method: request.method,
+            url: request.url,
+            headers: request.headers,
+        }
+    }
+}
+
+/// Duration of every phase of the most recent connect, in milliseconds.
+/// A phase which has not been reached, or is not needed (e.g. TLS for `ws://`), is empty.
+#[napi(object)]
+pub struct ConnectTimings {
+    pub dns_ms: Option<f64>,
+    pub tcp_connect_ms: Option<f64>,
+    pub tls_handshake_ms: Option<f64>,
+    pub upgrade_ms: Option<f64>,
+    pub total_ms: Option<f64>,
+    /// From the start of the connect, including a wait for a connection slot, to the first frame of the server
+    pub first_byte_ms: Option<f64>,
+    /// From the start of the connect to the first text or binary message, when the app is ready in realtime
+    pub first_message_ms: Option<f64>,
+}
+
+impl From<harmony_websocket_core::ConnectTimings> for ConnectTimings {
+    fn from(timings: harmony_websocket_core::ConnectTimings) -> Self {
+        ConnectTimings {
+            dns_ms: timings.dns_ms,
+            tcp_connect_ms: timings.tcp_connect_ms,
+            tls_handshake_ms: timings.tls_handshake_ms,
+            upgrade_ms: timings.upgrade_ms,
+            total_ms: timings.total_ms,
+            first_byte_ms: timings.first_byte_ms,
+            first_message_ms: timings.first_message_ms,
+        }
+    }
+}
+
+/// The phase of a connect
+#[napi(string_enum)]
+pub enum ConnectStage {
+    #[napi(value = "url")]
+    Url,
+    #[napi(value = "dns")]
+    Dns,
+    #[napi(value = "tcp")]
+    Tcp,
+    /// The `CONNECT` tunnel through the proxy
+    #[napi(value = "proxy")]
+    Proxy,
+    #[napi(value = "tls")]
+    Tls,
+    /// The http upgrade
+    #[napi(value = "upgrade")]
+    Upgrade,
+}
+
+impl From<harmony_websocket_core::ConnectStage> for ConnectStage {
+    fn from(stage: harmony_websocket_core::ConnectStage) -> Self {
+        match stage {
+            harmony_websocket_core::ConnectStage::Url => ConnectStage::Url,
+            harmony_websocket_core::ConnectStage::Dns => ConnectStage::Dns,
+            harmony_websocket_core::ConnectStage::Tcp => ConnectStage::Tcp,
+            harmony_websocket_core::ConnectStage::Proxy => ConnectStage::Proxy,
+            harmony_websocket_core::ConnectStage::Tls => ConnectStage::Tls,
+            harmony_websocket_core::ConnectStage::Upgrade => ConnectStage::Upgrade,
+        }
+    }
+}
+
+/// One handshake of a connect, successful or not.
+/// A connect makes several after an extension fallback or redirects.
+#[napi(object)]
+pub struct ConnectAttempt {
+    /// Milliseconds since the unix epoch
+    pub started_at: f64,
+    pub url: String,
+    /// Addresses the host resolved to, e.g. `93.184.215.14:443`
+    pub addresses: Vec<String>,
+    /// The address which was connected to
+    pub remote_address: Option<String>,
+    /// `host:port` of the proxy which was tunneled through, empty for a direct connection
+    pub proxy: Option<String>,
+    /// Where it failed, empty when the handshake succeeded
+    pub failed_stage: Option<ConnectStage>,
+    /// The `code` `onError` got, e.g. `ConnectTimeout`
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    pub duration_ms: f64,
+}
+
+impl From<harmony_websocket_core::ConnectAttempt> for ConnectAttempt {
+    fn from(attempt: harmony_websocket_core::ConnectAttempt) -> Self {
+        ConnectAttempt {
+            started_at: attempt.started_at_ms,
+            url: attempt.url,
+            addresses: attempt
+                .addresses
+                .iter()
+                .map(|address| address.to_string())
+                .collect(),
+            remote_address: attempt.remote_address.map(|address| address.to_string()),
+            proxy: attempt.proxy.map(|proxy| proxy.to_string()),
+            failed_stage: attempt.failed_stage.map(Into::into),
+            error_code: attempt.error.as_ref().map(|e| e.kind.as_ref().to_string()),
+            error_message: attempt.error.map(|e| e.message),
+            duration_ms: attempt.duration_ms,
+        }
+    }
+}
+
+/// Summary of an established connection
+#[napi(object)]
+pub struct OpenInfo {
+    /// `Sec-WebSocket-Protocol` chosen by the server
+    pub protocol: Option<String>,
+    /// Extensions accepted by the server
+    pub extensions: Vec<String>,
+    /// e.g. `93.184.215.14:443`
+    pub remote_address: Option<String>,
+    /// An earlier connect of this `WebSocket` was open
+    pub reconnect: bool,
+    /// Handshakes it took, 2 after an extension fallback
+    pub attempts: u32,
+    /// The url of the accepted handshake, another one than the configured url after redirects
+    pub url: String,
+    /// Redirects followed on the way
+    pub redirects: u32,
+    /// `host:port` of the proxy the accepted handshake was tunneled through, empty for a direct connection
+    pub proxy: Option<String>,
+}
+
+impl From<harmony_websocket_core::OpenInfo> for OpenInfo {
+    fn from(info: harmony_websocket_core::OpenInfo) -> Self {
+        OpenInfo {
+            protocol: info.protocol,
+            extensions: info.extensions,
+            remote_address: info.remote_address.map(|address| address.to_string()),
+            reconnect: info.reconnect,
+            attempts: info.attempts,
+            url: info.url,
+            redirects: info.redirects,
+            proxy: info.proxy.map(|proxy| proxy.to_string()),
+        }
+    }
+}
+
+/// A redirect of the handshake which was followed
+#[napi(object)]
+pub struct RedirectInfo {
+    pub from: String,
+    pub to: String,
+    /// e.g. 301 or 307
+    pub status: u32,
+    /// Redirects followed so far, including this one
+    pub hops: u32,
+}
+
+impl From<harmony_websocket_core::RedirectInfo> for RedirectInfo {
+    fn from(info: harmony_websocket_core::RedirectInfo) -> Self {
+        RedirectInfo {
+            from: info.from,
+            to: info.to,
+            status: u32::from(info.status),
+            hops: info.hops,
+        }
+    }
+}
+
+/// An attempt to make the connection again, before its delay
+#[napi(object)]
+pub struct ReconnectAttempt {
+    /// Starting at 1 after every drop
+    pub attempt: u32,
+    pub delay_ms: f64,
+}
+
+impl From<harmony_websocket_core::ReconnectAttempt> for ReconnectAttempt {
+    fn from(attempt: harmony_websocket_core::ReconnectAttempt) -> Self {
+        ReconnectAttempt {
+            attempt: attempt.attempt,
+            delay_ms: attempt.delay.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Who sent the ping a pong answers
+#[napi(string_enum)]
+pub enum PingSource {
+    /// `ping()`
+    #[napi(value = "app")]
+    App,
+    #[napi(value = "keepalive")]
+    Keepalive,
+}
+
+/// What a received pong answers
+#[napi(object)]
+pub struct PongInfo {
+    pub source: PingSource,
+    /// Correlation id of the keepalive ping
+    pub keepalive_id: Option<f64>,
+}
+
+impl From<&harmony_websocket_core::Pong> for PongInfo {
+    fn from(pong: &harmony_websocket_core::Pong) -> Self {
+        PongInfo {
+            source: match pong.keepalive_id {
+                Some(_) => PingSource::Keepalive,
+                None => PingSource::App,
+            },
+            keepalive_id: pong.keepalive_id.map(|id| id as f64),
+        }
+    }
+}
+
+/// Who answers the pings of the server
+#[napi(string_enum)]
+#[derive(Clone, Copy)]
+pub enum PongPolicy {
+    /// Every ping is answered natively, a payload returned by `onPing` is sent as an extra pong
+    #[napi(value = "auto")]
+    Auto,
+    /// Only what `onPing` returns is sent back, nothing without `onPing` or when it returns null
+    #[napi(value = "handler")]
+    Handler,
+    /// Pings are never answered, e.g. to test how the server handles a client which went silent
+    #[napi(value = "none")]
+    None,
+}
+
+impl From<PongPolicy> for harmony_websocket_core::PongPolicy {
+    fn from(policy: PongPolicy) -> Self {
+        match policy {
+            PongPolicy::Auto => harmony_websocket_core::PongPolicy::Auto,
+            PongPolicy::Handler => harmony_websocket_core::PongPolicy::Handler,
+            PongPolicy::None => harmony_websocket_core::PongPolicy::None,
+        }
+    }
+}
+
+/// What happens with control frames of the server over the 125 bytes RFC 6455 allows
+#[napi(string_enum)]
+pub enum ControlFramePolicy {
+    /// Fail the connection, `onError` gets a `ProtocolError` telling the frame and its size
+    #[napi(value = "fail")]
+    Fail,
+    /// Cut the payload to 125 bytes and keep reading, e.g. for known-bad servers.
+    /// Every truncated frame is reported to `onError` as `ProtocolError`.
+    #[napi(value = "truncate")]
+    Truncate,
+}
+
+impl From<ControlFramePolicy> for harmony_websocket_core::ControlFramePolicy {
+    fn from(policy: ControlFramePolicy) -> Self {
+        match policy {
+            ControlFramePolicy::Fail => harmony_websocket_core::ControlFramePolicy::Fail,
+            ControlFramePolicy::Truncate => harmony_websocket_core::ControlFramePolicy::Truncate,
+        }
+    }
+}
+
+/// Who ended a connection
+#[napi(string_enum)]
+pub enum CloseInitiator {
+    /// `close()`, `reconnect()` or `prepareForSuspend()` with `close`
+    #[napi(value = "client")]
+    Client,
+    /// The server sent a close frame first
+    #[napi(value = "server")]
+    Server,
+    /// The stream broke or ended without a close frame
+    #[napi(value = "transport-error")]
+    TransportError,
+    /// `idleTimeoutMs` or `keepAlive` ran out and the connection was given up
+    #[napi(value = "keepalive-timeout")]
+    KeepaliveTimeout,
+    /// `maxConnectionAgeMs` was reached and the connection is made again
+    #[napi(value = "rotation")]
+    Rotation,
+    /// The device switched networks and the connection is made again, see `migrateOnNetworkChange`
+    #[napi(value = "network-change")]
+    NetworkChange,
+}
+
+impl From<harmony_websocket_core::CloseInitiator> for CloseInitiator {
+    fn from(initiator: harmony_websocket_core::CloseInitiator) -> Self {
+        match initiator {
+            harmony_websocket_core::CloseInitiator::Client => CloseInitiator::Client,
+            harmony_websocket_core::CloseInitiator::Server => CloseInitiator::Server,
+            harmony_websocket_core::CloseInitiator::TransportError => {
+                CloseInitiator::TransportError
+            }
+            harmony_websocket_core::CloseInitiator::KeepaliveTimeout => {
+                CloseInitiator::KeepaliveTimeout
+            }
+            harmony_websocket_core::CloseInitiator::Rotation => CloseInitiator::Rotation,
+            harmony_websocket_core::CloseInitiator::NetworkChange => CloseInitiator::NetworkChange,
+        }
+    }
+}
+
+/// How the most recent connection ended
+#[napi(object)]
+pub struct CloseInfo {
+    /// The close handshake completed and the stream ended afterwards
+    pub was_clean: bool,
+    /// Status code of the server's close frame, 1005 if it had none and 1006 if there was no close frame
+    pub code: u32,
+    pub reason: String,
+    pub initiator: CloseInitiator,
+    /// The `code` `onError` got for the latest error while it was open, e.g. `ReceiveError`
+    pub error_code: Option<String>,
+    pub error_message: Option<String>,
+    /// Milliseconds since the unix epoch
+    pub closed_at: f64,
+}
+
+impl From<harmony_websocket_core::CloseInfo> for CloseInfo {
+    fn from(info: harmony_websocket_core::CloseInfo) -> Self {
+        CloseInfo {
+            was_clean: info.was_clean,
+            code: info.code.into(),
+            reason: info.reason,
+            initiator: info.initiator.into(),
+            error_code: info.error.as_ref().map(|e| e.kind.as_ref().to_string()),
+            error_message: info.error.map(|e| e.message),
+            closed_at: info.closed_at_ms,
+        }
+    }
+}
+
+/// The latest error, see `lastError()`
+#[napi(object)]
+pub struct ReportedError {
+    /// The `code` `onError` got, e.g. `ConnectTimeout`
+    pub code: String,
+    pub message: String,
+    /// Milliseconds since the unix epoch
+    pub at: f64,
+}
+
+impl From<harmony_websocket_core::ReportedError> for ReportedError {
+    fn from(reported: harmony_websocket_core::ReportedError) -> Self {
+        ReportedError {
+            code: reported.error.kind.as_ref().to_string(),
+            message: reported.error.message,
+            at: reported.at_ms,
+        }
+    }
+}
+
+/// The server started to close, see `closeGraceMs`
+#[napi(object)]
+pub struct ServerClosing {
+    /// Status code of the server's close frame, 1005 if it had none
+    pub code: u32,
+    pub reason: String,
+    /// How long messages can still be sent, in milliseconds
+    pub grace_ms: f64,
+}
+
+impl From<harmony_websocket_core::ServerClosing> for ServerClosing {
+    fn from(closing: harmony_websocket_core::ServerClosing) -> Self {
+        ServerClosing {
+            code: u32::from(closing.code),
+            reason: closing.reason,
+            grace_ms: closing.grace.as_secs_f64() * 1000.0,
+        }
+    }
+}
+
+/// Why a message was dropped before it was written
+#[napi(string_enum)]
+pub enum DropReason {
+    /// Its `ttlMs` passed
+    #[napi(value = "expired")]
+    Expired,
+    /// Writing it failed for good, see `onError`
+    #[napi(value = "write-failed")]
+    WriteFailed,
+}
+
+impl From<harmony_websocket_core::DropReason> for DropReason {
+    fn from(reason: harmony_websocket_core::DropReason) -> Self {
+        match reason {
+            harmony_websocket_core::DropReason::Expired => DropReason::Expired,
+            harmony_websocket_core::DropReason::WriteFailed => DropReason::WriteFailed,
+        }
+    }
+}
+
+/// A queued message which was given up before it was written
+#[napi(object)]
+pub struct DroppedMessage {
+    /// The id `send()` resolved with
+    pub id: i64,
+    pub tag: Option<String>,
+    pub reason: DropReason,
+}
+
+impl From<harmony_websocket_core::DroppedMessage> for DroppedMessage {
+    fn from(dropped: harmony_websocket_core::DroppedMessage) -> Self {
+        DroppedMessage {
+            id: dropped.id as i64,
+            tag: dropped.tag,
+            reason: dropped.reason.into(),
+        }
+    }
+}
+
+#[napi(string_enum)]
+pub enum SequenceAnomalyKind {
+    /// The counter skipped values, messages were lost on the way
+    #[napi(value = "gap")]
+    Gap,
+    /// The counter didn't grow, a message was delivered again or out of order
+    #[napi(value = "duplicate")]
+    Duplicate,
+}
+
+/// A received counter which didn't follow the previous one, see `sequence`
+#[napi(object)]
+pub struct SequenceAnomaly {
+    pub kind: SequenceAnomalyKind,
+    /// The counter after the previous one
+    pub expected: f64,
+    pub received: f64,
+    /// Values skipped by a gap, 0 for a duplicate
+    pub missing: f64,
+}
+
+impl From<harmony_websocket_core::SequenceAnomaly> for SequenceAnomaly {
+    fn from(anomaly: harmony_websocket_core::SequenceAnomaly) -> Self {
+        SequenceAnomaly {
+            kind: match anomaly.kind {
+                harmony_websocket_core::SequenceAnomalyKind::Gap => SequenceAnomalyKind::Gap,
+                harmony_websocket_core::SequenceAnomalyKind::Duplicate => {
+                    SequenceAnomalyKind::Duplicate
+                }
+            },
+            expected: anomaly.expected as f64,
+            received: anomaly.received as f64,
+            missing: anomaly.missing as f64,
+        }
+    }
+}
+
+/// How the written messages were batched, over all connections
+#[napi(object)]
+pub struct WriteStats {
+    /// Writes followed by one flush, a TCP burst each
+    pub batches: f64,
+    pub messages: f64,
+    /// Most messages written in one batch
+    pub largest_batch: f64,
+    /// Messages per batch, 0 before the first write
+    pub average_batch: f64,
+}
+
+impl From<harmony_websocket_core::WriteStats> for WriteStats {
+    fn from(stats: harmony_websocket_core::WriteStats) -> Self {
+        WriteStats {
+            batches: stats.batches as f64,
+            messages: stats.messages as f64,
+            largest_batch: stats.largest_batch as f64,
+            average_batch: if stats.batches == 0 {
+                0.0
+            } else {
+                stats.messages as f64 / stats.batches as f64
+            },
+        }
+    }
+}
+
+/// Properties of the TLS session. What the TLS backend can't tell is empty,
+/// `native-tls` only knows the certificate validity.
+#[napi(object)]
+pub struct TlsInfo {
+    pub backend: TlsBackend,
+    /// e.g. `TLSv1.3`
+    pub protocol: Option<String>,
+    /// IANA name, e.g. `TLS13_AES_128_GCM_SHA256`
+    pub cipher_suite: Option<String>,
+    pub resumed: Option<bool>,
+    /// Validity of the server certificate, milliseconds since the unix epoch as for `new Date()`
+    pub not_before: Option<f64>,
+    pub not_after: Option<f64>,
+    /// The configured revocation check the handshake passed
+    pub revocation_mode: RevocationMode,
+}
+
+impl From<harmony_websocket_core::TlsInfo> for TlsInfo {
+    fn from(info: harmony_websocket_core::TlsInfo) -> Self {
+        TlsInfo {
+            backend: info.backend.into(),
+            protocol: info.protocol,
+            cipher_suite: info.cipher_suite,
+            resumed: info.resumed,
+            not_before: info.not_before_ms,
+            not_after: info.not_after_ms,
+            revocation_mode: info.revocation_mode.into(),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::time::Duration;
+
+use harmony_websocket_core::KeepAlive;
+use napi_derive_ohos::napi;
+
+/// Ping the server natively, so a dead connection is noticed also while the app is throttled
+#[napi(object)]
+pub struct KeepAliveOptions {
+    /// Time between pings in milliseconds. With `dataSaver` a ping is only sent when nothing was received for this long.
+    pub interval_ms: u32,
+    /// The pong must arrive within this many milliseconds, otherwise `onError` gets `KeepaliveTimeout`
+    /// and the connection is given up. Defaults to 10000.
+    pub timeout_ms: Option<u32>,
+}
+
+impl From<KeepAliveOptions> for KeepAlive {
+    fn from(options: KeepAliveOptions) -> Self {
+        KeepAlive {
+            interval: Duration::from_millis(u64::from(options.interval_ms)),
+            timeout: Duration::from_millis(u64::from(options.timeout_ms.unwrap_or(10_000))),
+        }
+    }
+}
@@ -0,0 +1,60 @@
+use harmony_websocket_core::Bytes;
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, tokio, Error, Result};
+
+/// A received message over `largeMessageThreshold` bytes. It stays in native memory,
+/// JS only gets the slices it asks for.
+#[napi]
+pub struct LargeMessage {
+    data: Bytes,
+    is_text: bool,
+}
+
+impl LargeMessage {
+    pub fn new(data: Bytes, is_text: bool) -> Self {
+        LargeMessage { data, is_text }
+    }
+}
+
+/// Like the offsets of `Blob.slice()`: negative ones count from the end, out of range ones are clamped
+fn offset(offset: Option<f64>, default: usize, size: usize) -> usize {
+    match offset {
+        None => default,
+        Some(offset) if offset < 0.0 => size.saturating_sub((-offset) as usize),
+        Some(offset) => (offset as usize).min(size),
+    }
+}
+
+#[napi]
+impl LargeMessage {
+    /// Byte length of the message
+    #[napi(getter)]
+    pub fn size(&self) -> f64 {
+        self.data.len() as f64
+    }
+
+    /// Whether it was a text message, its bytes are UTF-8 then
+    #[napi(getter)]
+    pub fn is_text(&self) -> bool {
+        self.is_text
+    }
+
+    /// Copy of the bytes from `start` up to `end`, like `Blob.slice()`
+    #[napi]
+    pub fn slice(&self, start: Option<f64>, end: Option<f64>) -> Buffer {
+        let size = self.data.len();
+        let start = offset(start, 0, size);
+        let end = offset(end, size, size).max(start);
+        Buffer::from(&self.data[start..end])
+    }
+
+    /// Write the whole message to the file at `path`, replacing it
+    #[napi]
+    pub async fn save_to(&self, path: String) -> Result<()> {
+        let data = self.data.clone();
+        tokio::task::spawn_blocking(move || std::fs::write(&path, &data))
+            .await
+            .map_err(|e| Error::from_reason(e.to_string()))?
+            .map_err(|e| Error::from_reason(e.to_string()))
+    }
+}
@@ -1,434 +1,1719 @@
-use std::{collections::HashMap, fs::File, io::Read, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
 
-use error::WebSocketError;
-use futures_util::{SinkExt, StreamExt};
-use napi_derive_ohos::napi;
-use napi_ohos::{
-    bindgen_prelude::*,
-    threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode},
-    Error, Result,
+use assembly::{to_assembler, AssemblyOptions};
+use backlog::JsBacklog;
+use binary::Binary;
+use certmanager::CertManagerCredential;
+use dns::DnsConfig;
+use envelope::Envelope;
+use error::{to_napi_error, WebSocketError};
+use ffi::HarmonyWsHandle;
+use filter::{to_filter, MessageFilter};
+use handle::{
+    BackgroundOptions, ClearQueueFilter, ConnState, PowerSaveOptions, ReadyState,
+    SendBeforeConnect, SendData, SendOptions, SuspendOptions, WebSocketHandle,
+};
+use handlers::Dispatcher;
+use harmony_websocket_core::{
+    ClientKey, ConnectOptions, Connection, DialTimeouts, DnsOptions, DnsRetry, Event, EventHandler,
+    HostnamePolicy, JsonPath, LatestCache, LocalBind, MaxAge, ProtocolErrorPolicy, ResolveProxy,
+    RouteValue, SampleOptions, SharedConnection, SharedConsumer, TlsOptions,
+};
+use info::{
+    CloseInfo, ConnectAttempt, ConnectTimings, ControlFramePolicy, DroppedMessage,
+    HandshakeRequest, OpenInfo, PongInfo, PongPolicy, ReconnectAttempt, RedirectInfo,
+    ReportedError, SequenceAnomaly, ServerClosing, TlsInfo, WriteStats,
 };
-use ohos_hilog_binding::hilog_error;
-use tokio::sync::{mpsc, RwLock};
-use tokio_tungstenite::{
-    connect_async_tls_with_config,
-    tungstenite::{client::IntoClientRequest, protocol::Message},
-    Connector,
+use keepalive::KeepAliveOptions;
+use large::LargeMessage;
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+use network::NetworkChange;
+use proxy::{ProxyConfig, SystemProxy};
+use reconnect::ReconnectOptions;
+use sequence::SequenceOptions;
+use tcp::TcpConfig;
+use tls::{
+    to_cert_paths, JsSigner, Pkcs12Bundle, SignRequest, SignScheme, TlsBackend, TlsTrust,
+    WildcardPolicy,
 };
+use worker::WorkerRoute;
 
+mod assembly;
+mod backlog;
+mod benchmark;
+mod binary;
+mod certmanager;
+mod codec;
+mod conformance;
+mod dns;
+mod envelope;
 mod error;
+mod ffi;
+mod filter;
+mod handle;
+mod handlers;
+mod info;
+mod keepalive;
+mod large;
+mod limits;
+mod network;
+mod pool;
+mod profile;
+mod proxy;
+mod reconnect;
+mod sequence;
+mod tcp;
+mod tls;
+mod worker;
 
 #[napi(object)]
 pub struct WebSocketConfig {
-    /// Custom cert file path
-    pub cert_path: Option<String>,
+    /// Trusted CA certificate files, PEM or DER encoded. A PEM file may hold several certificates,
+    /// e.g. an intermediate and its root, and a directory stands for all files in it.
+    pub cert_path: Option<Either<String, Vec<String>>>,
+
+    /// Additional trusted CA certificates, each one PEM or DER encoded.
+    /// Use `getTrustedCertificates()` to read them from the Certificate Manager.
+    pub ca_certs: Option<Vec<Buffer>>,
 
-    /// Custom headers
-    pub headers: Option<HashMap<String, String>>,
+    /// URIs of user trusted CA certificates in the Certificate Manager. They're looked up on every connect,
+    /// so certificates provisioned or renewed in between are used.
+    pub ca_cert_uris: Option<Vec<String>>,
+
+    /// Custom headers, a name with an array of values is sent once for every value.
+    /// Use the array form, `[name, ...values]` entries, when the order of the headers matters.
+    pub headers: Option<Either<Vec<Vec<String>>, HashMap<String, Either<String, Vec<String>>>>>,
 
     /// Enable websocket extensions.
     /// If enabled, the client will add `Sec-WebSocket-Extensions` header with `permessage-deflate; client_max_window_bits`
+    /// It's not offered again once the server accepted it or refused the handshake because of it.
     pub enable_extension: Option<bool>,
+
+    /// TLS implementation for `wss://`, it must be one of `availableTlsBackends()`.
+    /// Defaults to `tlsBackend()`.
+    pub tls_backend: Option<TlsBackend>,
+
+    /// Which certificates the server certificate may chain up to: the CA store of the system,
+    /// only `certPath`, `caCerts` and `caBundles`, or both. Defaults to `both`.
+    pub tls_trust: Option<TlsTrust>,
+
+    /// Certificate revocation list file path, revoked server certificates are rejected.
+    /// Needs the `rustls` TLS backend.
+    pub crl_path: Option<String>,
+
+    /// Certificate revocation lists, each one PEM or DER encoded. Needs the `rustls` TLS backend.
+    pub crls: Option<Vec<Buffer>>,
+
+    /// Accept certificates whose issuer is not covered by any of the revocation lists.
+    /// Defaults to false, such certificates are rejected.
+    pub allow_unknown_revocation: Option<bool>,
+
+    /// How wildcard names in the server certificate are matched, defaults to `standard`.
+    /// Anything but the default needs the `rustls` TLS backend.
+    pub wildcard_policy: Option<WildcardPolicy>,
+
+    /// Names accepted in the server certificate in place of the host, e.g. the internal name of a device.
+    /// The certificate chain is still verified. Needs the `rustls` TLS backend.
+    pub accepted_names: Option<Vec<String>>,
+
+    /// Password protected PKCS#12 bundles whose certificates are trusted as CAs.
+    /// Needs the `rustls` TLS backend.
+    pub ca_bundles: Option<Vec<Pkcs12Bundle>>,
+
+    /// Password protected PKCS#12 bundle with the client certificate and its private key, for mutual TLS
+    pub client_identity: Option<Pkcs12Bundle>,
+
+    /// Client certificate of the Certificate Manager for mutual TLS, its key signs inside the Certificate Manager.
+    /// It's looked up on every connect, and needs the `rustls` TLS backend. `setClientKey()` takes precedence.
+    pub client_credential: Option<CertManagerCredential>,
+
+    /// How often a dns lookup which failed for a transient reason is retried within one connect,
+    /// e.g. while the radio is still waking up. Defaults to 2.
+    pub dns_retries: Option<u32>,
+
+    /// Delay before the first dns retry in milliseconds, the following ones wait a multiple of it.
+    /// Defaults to 200.
+    pub dns_retry_delay_ms: Option<u32>,
+
+    /// Host overrides, the lookup timeout and a custom dns server
+    pub dns: Option<DnsConfig>,
+
+    /// Longest wait for the tcp connect in milliseconds, over all addresses of the host.
+    /// Waiting for a free slot of the connection limits with `waitForSlot` is limited by it as well.
+    /// `connect()` is rejected and `onError` gets `ConnectTimeout` when it's over.
+    pub connect_timeout_ms: Option<u32>,
+
+    /// Longest wait for the proxy tunnel, the tls handshake and the http upgrade in milliseconds.
+    /// `connect()` is rejected and `onError` gets `HandshakeTimeout` when it's over.
+    pub handshake_timeout_ms: Option<u32>,
+
+    /// Local ip address the socket is bound to, e.g. the one of the Wi-Fi interface on a multi-homed device.
+    /// Only server addresses of its family are tried.
+    pub local_address: Option<String>,
+
+    /// Network interface the socket is bound to, e.g. `wlan0` to stay on Wi-Fi while cellular is up as well
+    pub interface_name: Option<String>,
+
+    /// Nodelay, keepalive and buffer sizes of the tcp socket
+    pub tcp: Option<TcpConfig>,
+
+    /// Tunnel the connection through this http proxy with `CONNECT`, before the TLS handshake
+    pub proxy: Option<ProxyConfig>,
+
+    /// Use the http proxy of the system settings, looked up on every connect and reconnect
+    /// and skipped for the hosts of its exclusion list. `proxy` takes precedence.
+    /// Needs `ohos.permission.GET_NETWORK_INFO`, without it the connection is made directly.
+    pub use_system_proxy: Option<bool>,
+
+    /// Give the connection up when nothing was received for this many milliseconds, e.g. a half-dead mobile connection.
+    /// A close frame is sent, `onError` gets `IdleTimeout` and with `reconnect` the connection is made again.
+    /// Pings and pongs count as received, so it pairs well with a server which pings.
+    pub idle_timeout_ms: Option<u32>,
+
+    /// Ping the server and give the connection up when a pong doesn't arrive in time.
+    /// With `reconnect` it's made again, just like after `idleTimeoutMs`.
+    pub keep_alive: Option<KeepAliveOptions>,
+
+    /// How long `close()` waits for the server to answer the close in milliseconds,
+    /// the connection is dropped afterwards. Defaults to 5000.
+    pub close_timeout_ms: Option<u32>,
+
+    /// Close the connection once it's this many milliseconds old and make it again right away,
+    /// e.g. for load balancers which only balance new connections. `onRotating` is called first.
+    pub max_connection_age_ms: Option<u32>,
+
+    /// Up to this many milliseconds are added to `maxConnectionAgeMs` at random,
+    /// so connections made together don't rotate together. Defaults to 0.
+    pub max_connection_age_jitter_ms: Option<u32>,
+
+    /// When the server closes, hold its close frame back for this many milliseconds before answering it,
+    /// so the app can still send, e.g. a final summary. Messages received before it are delivered as usual.
+    /// `onServerClosing` is called when it starts.
+    pub close_grace_ms: Option<u32>,
+
+    /// How many redirects of the handshake are followed, e.g. to a regional host. Defaults to 0.
+    /// A redirect from `wss://` to `ws://` is never followed, `headers` are sent to the new url again.
+    pub max_redirects: Option<u32>,
+
+    /// Keep reading after protocol errors of the server, until this many happened within `protocolErrorWindowMs`.
+    /// The connection is closed with 1002 then and `onError` gets the details as `ProtocolError`.
+    /// Without it the first protocol error fails the connection.
+    pub max_protocol_errors: Option<u32>,
+
+    /// Window of `maxProtocolErrors` in milliseconds. Defaults to 10000.
+    pub protocol_error_window_ms: Option<u32>,
+
+    /// What happens with ping, pong and close frames of the server over the 125 bytes RFC 6455 allows,
+    /// `fail` by default. `truncate` cuts them down and keeps the connection, e.g. for known-bad servers.
+    pub oversized_control_frames: Option<ControlFramePolicy>,
+
+    /// Messages read in a row before the reader lets sends and other work run. Defaults to 32.
+    /// Lower it if sends lag while the server floods messages.
+    pub read_budget: Option<u32>,
+
+    /// Make the connection again with the same config when it ended abnormally.
+    /// The `WebSocket` stays usable and `onOpen` is called again once it's open.
+    pub reconnect: Option<ReconnectOptions>,
+
+    /// Save data on metered networks.
+    /// Text and binary messages up to 1 KiB are collected for 200 ms and written in one batch,
+    /// and keepalive pings are skipped while frames of the server keep arriving.
+    /// `onDeferred` is called when messages start to be held back. Messages are not compressed.
+    pub data_saver: Option<bool>,
+
+    /// What `send()` and the other send methods do while there's no connection, defaults to `drop`.
+    /// `sendAfter()`, `sendAt()` and `rememberSend()` aren't affected.
+    pub send_before_connect: Option<SendBeforeConnect>,
+
+    /// Drop the open connection and make it again when the default network of the device switches,
+    /// e.g. from Wi-Fi to cellular, instead of waiting for the old socket to time out.
+    /// Needs the `ohos.permission.GET_NETWORK_INFO` permission, see `onNetworkChange()`.
+    pub migrate_on_network_change: Option<bool>,
+
+    /// Hold back messages sent with `urgent: false` for this long, so the ones sent meanwhile
+    /// go out in one TCP burst and the modem wakes up less often. Urgent messages are written right away.
+    /// See `getWriteStats()` for the batching achieved.
+    pub coalesce_ms: Option<u32>,
+
+    /// Who answers the pings of the server, `auto` by default.
+    /// `handler` replies only with what `onPing` returns, `none` never replies, e.g. to test server timeouts.
+    pub pong_policy: Option<PongPolicy>,
+
+    /// Report violations of RFC 6455 to `onError` as `ProtocolError` naming the violation,
+    /// and fail the connection on close codes which may not be sent, e.g. for running the Autobahn test suite
+    pub strict_rfc6455: Option<bool>,
+
+    /// Deliver only every nth received message, e.g. 10 for a tenth of a sensor stream
+    pub sample_every: Option<u32>,
+
+    /// Deliver at most one received message per interval in milliseconds, the latest one.
+    /// It's kept natively and delivered once the interval is over, so the freshest value always arrives.
+    pub throttle_delivery_ms: Option<u32>,
+
+    /// Metadata of the connection for diagnostics, e.g. `{ feature: "chat" }`.
+    /// It's part of the log lines of the connection, see also `tag` of `WebSocket`.
+    pub tag: Option<HashMap<String, String>>,
+
+    /// Deliver only the received messages matching this filter, see also `setFilter()`.
+    /// Messages are filtered natively, before they cross the bridge.
+    pub filter: Option<MessageFilter>,
+
+    /// Join received text messages into complete documents before they're delivered,
+    /// for servers which split documents across messages, see also `setAssembly()`
+    pub assembly: Option<AssemblyOptions>,
+
+    /// Keep the latest text message per value of this JSON field, e.g. `$.symbol`, see `getLatest()`
+    pub latest_key: Option<String>,
+
+    /// Received messages over this many bytes are passed to `onLargeMessage` as a `LargeMessage`,
+    /// which keeps them in native memory instead of the JS heap
+    pub large_message_threshold: Option<u32>,
+
+    /// Received messages over a size go to the `onWorkerMessage()` callback of a channel instead,
+    /// e.g. registered in a worker, so parsing them doesn't stall the UI thread.
+    /// Small ones keep going to `onMessage`, as do large ones while no callback is registered.
+    pub worker_route: Option<WorkerRoute>,
+
+    /// Call `onJsBacklog` when an `onMessage` callback runs this many milliseconds after the message arrived,
+    /// e.g. because the JS thread is busy rendering. Measuring is off by default.
+    pub js_backlog_threshold_ms: Option<u32>,
+
+    /// Stop reading from the socket while the JS thread is over `jsBacklogThresholdMs` behind,
+    /// so the server has to wait instead of messages piling up. Defaults to false.
+    pub pause_reading_on_backlog: Option<bool>,
+
+    /// Verify the counter the server puts into every message natively, gaps and duplicates go to `onSequenceAnomaly`
+    pub sequence: Option<SequenceOptions>,
+
+    /// Decode received messages with this codec and pass them to `onDecoded`, e.g. `json`,
+    /// see `registerCodec()` and `setCodec()`
+    pub codec: Option<String>,
+}
+
+/// The headers of the config as `(name, value)` pairs in the order they're sent
+fn to_headers(
+    headers: Either<Vec<Vec<String>>, HashMap<String, Either<String, Vec<String>>>>,
+) -> Vec<(String, String)> {
+    match headers {
+        Either::A(entries) => entries
+            .into_iter()
+            .filter_map(|entry| {
+                let mut entry = entry.into_iter();
+                let name = entry.next()?;
+                Some(entry.map(move |value| (name.clone(), value)))
+            })
+            .flatten()
+            .collect(),
+        Either::B(headers) => headers
+            .into_iter()
+            .flat_map(|(name, values)| {
+                let values = match values {
+                    Either::A(value) => vec![value],
+                    Either::B(values) => values,
+                };
+                values.into_iter().map(move |value| (name.clone(), value))
+            })
+            .collect(),
+    }
+}
+
+fn to_local_address(address: &str) -> Result<IpAddr> {
+    address.parse().map_err(|_| {
+        Error::new(
+            Status::InvalidArg,
+            format!("localAddress is not an ip address: {}", address),
+        )
+    })
+}
+
+impl From<WebSocketConfig> for ConnectOptions {
+    fn from(config: WebSocketConfig) -> Self {
+        ConnectOptions {
+            tls: TlsOptions {
+                backend: config.tls_backend.map(Into::into),
+                trust: config.tls_trust.map(Into::into),
+                cert_paths: to_cert_paths(config.cert_path),
+                ca_certs: config
+                    .ca_certs
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Vec::<u8>::from)
+                    .collect(),
+                ca_cert_uris: config.ca_cert_uris.unwrap_or_default(),
+                client_key: None,
+                client_credential: config.client_credential.map(Into::into),
+                crl_path: config.crl_path,
+                crls: config
+                    .crls
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Vec::<u8>::from)
+                    .collect(),
+                allow_unknown_revocation: config.allow_unknown_revocation.unwrap_or(false),
+                hostname: HostnamePolicy {
+                    wildcard: config.wildcard_policy.map(Into::into).unwrap_or_default(),
+                    accepted_names: config.accepted_names.unwrap_or_default(),
+                },
+                ca_bundles: config
+                    .ca_bundles
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(Into::into)
+                    .collect(),
+                client_identity: config.client_identity.map(Into::into),
+                // Only in registered tls profiles, see `registerTlsProfile()`
+                min_version: None,
+                max_version: None,
+                pins: Vec::new(),
+            },
+            headers: config.headers.map(to_headers).unwrap_or_default(),
+            enable_extension: config.enable_extension.unwrap_or(false),
+            dns: DnsOptions {
+                retry: {
+                    let default = DnsRetry::default();
+                    DnsRetry {
+                        retries: config.dns_retries.unwrap_or(default.retries),
+                        delay_ms: config
+                            .dns_retry_delay_ms
+                            .map_or(default.delay_ms, u64::from),
+                    }
+                },
+                ..DnsOptions::default()
+            },
+            timeouts: DialTimeouts {
+                connect: config
+                    .connect_timeout_ms
+                    .map(|ms| Duration::from_millis(u64::from(ms))),
+                handshake: config
+                    .handshake_timeout_ms
+                    .map(|ms| Duration::from_millis(u64::from(ms))),
+            },
+            local: LocalBind {
+                address: None,
+                interface: config.interface_name,
+            },
+            tcp: config.tcp.map(Into::into).unwrap_or_default(),
+            data_saver: config.data_saver.unwrap_or(false),
+            send_before_connect: config
+                .send_before_connect
+                .map(Into::into)
+                .unwrap_or_default(),
+            coalesce: config
+                .coalesce_ms
+                .map(|ms| Duration::from_millis(u64::from(ms))),
+            pong_policy: config.pong_policy.map(Into::into).unwrap_or_default(),
+            idle_timeout: config
+                .idle_timeout_ms
+                .map(|ms| Duration::from_millis(u64::from(ms))),
+            keep_alive: config.keep_alive.map(Into::into),
+            close_timeout: config
+                .close_timeout_ms
+                .map(|ms| Duration::from_millis(u64::from(ms))),
+            close_grace: config
+                .close_grace_ms
+                .map(|ms| Duration::from_millis(u64::from(ms))),
+            max_age: config.max_connection_age_ms.map(|ms| MaxAge {
+                age: Duration::from_millis(u64::from(ms)),
+                jitter: Duration::from_millis(u64::from(
+                    config.max_connection_age_jitter_ms.unwrap_or(0),
+                )),
+            }),
+            strict_rfc6455: config.strict_rfc6455.unwrap_or(false),
+            max_redirects: config.max_redirects.unwrap_or(0),
+            protocol_errors: config
+                .max_protocol_errors
+                .map(|max_errors| ProtocolErrorPolicy {
+                    max_errors,
+                    window: Duration::from_millis(u64::from(
+                        config.protocol_error_window_ms.unwrap_or(10_000),
+                    )),
+                }),
+            oversized_control: config
+                .oversized_control_frames
+                .map(Into::into)
+                .unwrap_or_default(),
+            // Checked by the constructors, like `dns` and `localAddress`
+            sequence: None,
+            proxy: None,
+            proxy_resolver: config
+                .use_system_proxy
+                .unwrap_or(false)
+                .then(|| Arc::new(SystemProxy) as Arc<dyn ResolveProxy>),
+            read_budget: config.read_budget,
+            reconnect: config
+                .reconnect
+                .as_ref()
+                .and_then(ReconnectOptions::to_policy),
+            sample: SampleOptions {
+                every: config.sample_every,
+                throttle: config
+                    .throttle_delivery_ms
+                    .map(|ms| Duration::from_millis(u64::from(ms))),
+            },
+        }
+    }
+}
+
+#[cfg(feature = "diagnostics")]
+fn write_diagnostics(connection: &Connection, path: &str) -> Result<()> {
+    std::fs::write(path, connection.diagnostics()).map_err(|e| {
+        Error::new(
+            Status::GenericFailure,
+            format!("writing diagnostics to {} failed: {}", path, e),
+        )
+    })
+}
+
+/// Left out of this build, throws "not compiled in"
+#[cfg(not(feature = "diagnostics"))]
+fn write_diagnostics(_connection: &Connection, _path: &str) -> Result<()> {
+    Err(error::not_compiled_in("exportDiagnostics()", "diagnostics"))
+}
+
+/// What `new()` and `shared()` make of the config, besides the options, before there is a connection
+struct Built {
+    dispatcher: Arc<Dispatcher>,
+    tag: Option<HashMap<String, String>>,
+    migrate_on_network_change: bool,
+    connect_on_send: bool,
+}
+
+impl Built {
+    fn new(mut config: Option<WebSocketConfig>) -> Result<(ConnectOptions, Self)> {
+        let tag = config.as_mut().and_then(|config| config.tag.take());
+        let filter = config.as_mut().and_then(|config| config.filter.take());
+        let assembly = config.as_mut().and_then(|config| config.assembly.take());
+        let latest_key = config.as_mut().and_then(|config| config.latest_key.take());
+        let codec = config.as_mut().and_then(|config| config.codec.take());
+        let dns = config.as_mut().and_then(|config| config.dns.take());
+        let connect_on_send = config
+            .as_ref()
+            .and_then(|config| config.send_before_connect)
+            == Some(SendBeforeConnect::Connect);
+        let migrate_on_network_change = config
+            .as_ref()
+            .and_then(|config| config.migrate_on_network_change)
+            .unwrap_or(false);
+        let local_address = config
+            .as_mut()
+            .and_then(|config| config.local_address.take())
+            .map(|address| to_local_address(&address))
+            .transpose()?;
+        let sequence = config
+            .as_ref()
+            .and_then(|config| config.sequence.as_ref())
+            .map(SequenceOptions::to_source)
+            .transpose()?;
+        let proxy = config
+            .as_ref()
+            .and_then(|config| config.proxy.as_ref())
+            .map(ProxyConfig::to_proxy)
+            .transpose()?;
+        let dispatcher = Arc::new(Dispatcher::default());
+        if let Some(threshold) = config
+            .as_ref()
+            .and_then(|config| config.large_message_threshold)
+        {
+            let _ = dispatcher.large_message_threshold.set(threshold as usize);
+        }
+        if let Some(route) = config
+            .as_mut()
+            .and_then(|config| config.worker_route.take())
+        {
+            let _ = dispatcher
+                .worker_route
+                .set((route.channel, route.threshold as usize));
+        }
+        if let Some(threshold) = config
+            .as_ref()
+            .and_then(|config| config.js_backlog_threshold_ms)
+        {
+            let pause_reading = config
+                .as_ref()
+                .and_then(|config| config.pause_reading_on_backlog)
+                .unwrap_or(false);
+            let _ = dispatcher.js_backlog.set(Arc::new(JsBacklog::new(
+                Duration::from_millis(u64::from(threshold)),
+                pause_reading,
+            )));
+        }
+        if let Some(pong_policy) = config.as_ref().and_then(|config| config.pong_policy) {
+            let _ = dispatcher.pong_policy.set(pong_policy.into());
+        }
+        if let Some(filter) = filter {
+            let filter = to_filter(filter)?;
+            dispatcher.handlers.update(|h| h.filter = filter);
+        }
+        if let Some(assembly) = assembly {
+            *dispatcher
+                .assembler
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(to_assembler(assembly)?);
+        }
+        if let Some(latest_key) = latest_key {
+            let path =
+                JsonPath::parse(&latest_key).map_err(|e| Error::new(Status::InvalidArg, e))?;
+            let _ = dispatcher.latest.set(Mutex::new(LatestCache::new(path)));
+        }
+        if let Some(codec) = codec {
+            let codec = codec::lookup(&codec)?;
+            dispatcher.handlers.update(|h| h.codec = Some(codec));
+        }
+        let mut options = config.map(ConnectOptions::from).unwrap_or_default();
+        if let Some(dns) = dns {
+            options.dns = dns.to_options(options.dns.retry)?;
+        }
+        options.local.address = local_address;
+        options.sequence = sequence;
+        options.proxy = proxy;
+        let built = Built {
+            dispatcher,
+            tag,
+            migrate_on_network_change,
+            connect_on_send,
+        };
+        Ok((options, built))
+    }
+
+    /// The `WebSocket` on `connection`, which was made with the options of [`Built::new`]
+    fn into_web_socket(
+        self,
+        connection: Arc<Connection>,
+        shared: Option<SharedConsumer>,
+    ) -> WebSocket {
+        if let Some(tag) = self.tag {
+            connection.link().set_tag(tag);
+        }
+        let _ = self.dispatcher.link.set(connection.link().clone());
+        network::watch(
+            &self.dispatcher,
+            &connection,
+            self.migrate_on_network_change,
+        );
+        WebSocket {
+            connection,
+            dispatcher: self.dispatcher,
+            shared: shared.map(|consumer| Mutex::new(Some(consumer))),
+            connect_on_send: self.connect_on_send,
+        }
+    }
 }
 
 #[napi]
 pub struct WebSocket {
-    url: String,
-    config: Option<WebSocketConfig>,
-    on_error:
-        Option<Arc<ThreadsafeFunction<Error<WebSocketError>, (), Error<WebSocketError>, false>>>,
-    on_message:
-        Option<Arc<ThreadsafeFunction<Either<String, Buffer>, (), Either<String, Buffer>, false>>>,
-    on_open: Option<Arc<ThreadsafeFunction<(), (), (), false>>>,
-    on_close: Option<Arc<ThreadsafeFunction<bool, (), bool, false>>>,
-    on_ping: Option<Arc<ThreadsafeFunction<Buffer, Option<Buffer>, Buffer, false>>>,
-    on_pong: Option<Arc<ThreadsafeFunction<Buffer, (), Buffer, false>>>,
-    on_header_received: Option<
-        Arc<ThreadsafeFunction<HashMap<String, String>, (), HashMap<String, String>, false>>,
-    >,
-    writer: RwLock<Option<mpsc::Sender<Message>>>,
+    connection: Arc<Connection>,
+    dispatcher: Arc<Dispatcher>,
+    /// The consumer of a connection created by `WebSocket.shared()`, empty once released
+    shared: Option<Mutex<Option<SharedConsumer>>>,
+    /// `sendBeforeConnect: 'connect'`
+    connect_on_send: bool,
 }
 
 #[napi]
 impl WebSocket {
     #[napi(constructor)]
-    pub fn new(url: String, config: Option<WebSocketConfig>) -> Self {
-        WebSocket {
-            url,
-            on_error: None,
-            on_message: None,
-            on_open: None,
-            on_close: None,
-            on_ping: None,
-            on_pong: None,
-            config: config,
-            on_header_received: None,
-            writer: RwLock::new(None),
-        }
+    pub fn new(url: String, config: Option<WebSocketConfig>) -> Result<Self> {
+        let (options, built) = Built::new(config)?;
+        let connection = Arc::new(Connection::new(url, options));
+        Ok(built.into_web_socket(connection, None))
+    }
+
+    /// Use the connection to the same url with the same config if there is one, instead of opening another.
+    /// Every consumer has its own callbacks. `close()` only releases this consumer,
+    /// the connection is closed when the last one is released.
+    #[napi(factory)]
+    pub fn shared(url: String, config: Option<WebSocketConfig>) -> Result<Self> {
+        let (options, built) = Built::new(config)?;
+        let consumer = SharedConsumer::join(url, options, built.dispatcher.clone());
+        let connection = consumer.shared().connection().clone();
+        Ok(built.into_web_socket(connection, Some(consumer)))
+    }
+
+    /// The shared connection, an error once this consumer is released
+    fn shared_connection(
+        consumer: &Mutex<Option<SharedConsumer>>,
+    ) -> harmony_websocket_core::Result<Arc<SharedConnection>> {
+        consumer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map(SharedConsumer::shared)
+            .ok_or_else(|| {
+                harmony_websocket_core::Error::new(
+                    WebSocketError::ConnectError,
+                    "the shared WebSocket is closed, create a new one with WebSocket.shared()",
+                )
+            })
     }
 
+    /// Resolves once the connection is open, it keeps running in the background until it's closed, see `closed()`.
+    /// Connect failures reject the promise and are also delivered to `onError` with their code.
+    /// Once a connection is over it can be connected again any number of times.
+    /// A call while a connect is pending joins it, resolving or rejecting along with it,
+    /// while the connection is open or closing it's rejected with `InvalidState`, see `reconnect()`
     #[napi]
     pub async fn connect(&self) -> Result<()> {
-        let mut connector: Option<Connector> = None;
-
-        if let Some(config) = &self.config {
-            if let Some(cert_path) = &config.cert_path {
-                let mut cert_data = Vec::new();
-                File::open(cert_path)
-                    .map_err(|e| {
-                        Error::new(
-                            Status::GenericFailure,
-                            format!("Try to open cert file path failed: {}", e.to_string()),
-                        )
-                    })?
-                    .read_to_end(&mut cert_data)
-                    .map_err(|e| {
-                        Error::new(
-                            Status::GenericFailure,
-                            format!("Try to read cert file failed: {}", e.to_string()),
-                        )
-                    })?;
-                let cert = native_tls::Certificate::from_pem(&cert_data).map_err(|e| {
-                    Error::new(
-                        Status::GenericFailure,
-                        format!("Try to parse cert file failed: {}", e.to_string()),
-                    )
-                })?;
-
-                let mut builder = native_tls::TlsConnector::builder();
-                builder.add_root_certificate(cert);
-
-                let tls_connector = builder.build().map_err(|e| {
-                    Error::new(
-                        Status::GenericFailure,
-                        format!("Try to build tls connector failed: {}", e.to_string()),
-                    )
-                })?;
-
-                connector = Some(Connector::NativeTls(tls_connector));
-            }
-        }
-        let mut request = (&self.url).into_client_request().map_err(|e| {
-            Error::new(
-                Status::GenericFailure,
-                format!("Try to build request failed: {}", e.to_string()),
-            )
-        })?;
+        let result = match &self.shared {
+            Some(consumer) => match Self::shared_connection(consumer) {
+                Ok(shared) => shared.connect().await,
+                Err(e) => Err(e),
+            },
+            None => self.connection.connect(self.dispatcher.clone()).await,
+        };
+        result.map_err(|e| {
+            self.dispatcher.handle(Event::Error(e.clone()));
+            to_napi_error(e)
+        })
+    }
 
-        let header = request.headers_mut();
+    /// Close the connection, if there is one, and connect again with the same url and config.
+    /// Callbacks stay registered. The stream is dropped when the server doesn't finish the close handshake in time.
+    /// A shared WebSocket reconnects the connection of all its consumers.
+    #[napi]
+    pub async fn reconnect(&self) -> Result<()> {
+        let result = match &self.shared {
+            Some(consumer) => match Self::shared_connection(consumer) {
+                Ok(shared) => shared.restart().await,
+                Err(e) => Err(e),
+            },
+            None => self.connection.restart(self.dispatcher.clone()).await,
+        };
+        result.map_err(|e| {
+            self.dispatcher.handle(Event::Error(e.clone()));
+            to_napi_error(e)
+        })
+    }
 
-        let custom_header = self.config.as_ref().and_then(|d| d.headers.clone());
-        let enable_extension = self
-            .config
-            .as_ref()
-            .and_then(|d| d.enable_extension.clone())
-            .unwrap_or(false);
+    /// Cancel a pending `connect()`, whichever stage it is in, and reject it with `Aborted`.
+    /// Automatic reconnects stop as well. An open connection is not affected, use `close()` for it.
+    #[napi]
+    pub fn abort(&self) {
+        self.connection.abort();
+    }
 
-        if let Some(mut h) = custom_header {
-            if enable_extension {
-                h.insert(
-                    "Sec-WebSocket-Extensions".to_string(),
-                    "permessage-deflate; client_max_window_bits".to_string(),
-                );
-            }
-            for (key, value) in h {
-                // First parse the header name from the string
-                let header_name =
-                    match tokio_tungstenite::tungstenite::http::header::HeaderName::from_bytes(
-                        key.as_bytes(),
-                    ) {
-                        Ok(name) => name,
-                        Err(e) => {
-                            return Err(Error::new(
-                                Status::GenericFailure,
-                                format!("Invalid header name '{}': {}", key, e),
-                            ));
-                        }
-                    };
-
-                // Then parse the header value
-                match value.parse() {
-                    Ok(header_value) => {
-                        header.insert(header_name, header_value);
-                    }
-                    Err(e) => {
-                        return Err(Error::new(
-                            Status::GenericFailure,
-                            format!("Invalid header value for key '{}': {}", key, e),
-                        ));
-                    }
-                }
-            }
+    /// Connect first for `sendBeforeConnect: 'connect'`, unless a connection is running or reconnecting
+    async fn connect_for_send(&self) -> Result<()> {
+        if self.connect_on_send && !self.connection.is_running() {
+            self.connect().await?;
         }
+        Ok(())
+    }
 
-        let (ws_stream, response) =
-            match connect_async_tls_with_config(request, None, false, connector).await {
-                Ok((ws_stream, response)) => {
-                    if let Some(on_open) = &self.on_open {
-                        on_open.call((), ThreadsafeFunctionCallMode::NonBlocking);
-                    }
-                    (ws_stream, response)
-                }
-                Err(e) => {
-                    return Err(Error::new(
-                        Status::GenericFailure,
-                        format!("ws-rs connection failed: {}", e),
-                    ));
-                }
-            };
-
-        let headers = response.headers();
-        if let Some(on_header_received) = &self.on_header_received {
-            let mut headers_map = HashMap::new();
-            for (key, value) in headers.iter() {
-                headers_map.insert(
-                    key.to_string(),
-                    value.to_str().unwrap_or_default().to_string(),
-                );
-            }
-            on_header_received.call(headers_map, ThreadsafeFunctionCallMode::NonBlocking);
+    /// Resolves once the running connection is over and its close handshake finished, with how it ended.
+    /// A pending `connect()` is waited for first. Resolves right away when none is running,
+    /// with how the most recent one ended, `null` if there was none.
+    #[napi]
+    pub async fn closed(&self) -> Option<CloseInfo> {
+        self.connection.closed().await.map(CloseInfo::from)
+    }
+
+    #[napi]
+    pub async fn send(&self, data: SendData, options: Option<SendOptions>) -> Result<Option<i64>> {
+        self.connect_for_send().await?;
+        handle::send(self.connection.link(), data, options).await
+    }
+
+    /// Send one binary message made of `parts`, e.g. a header and a body, without joining them.
+    /// Every part is written as a fragment of the message.
+    #[napi]
+    pub async fn send_parts(
+        &self,
+        parts: Vec<Binary>,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        self.connect_for_send().await?;
+        handle::send_parts(self.connection.link(), parts, options).await
+    }
+
+    /// Encode `value` with a built-in or registered codec, see `registerCodec()`, and send it
+    #[napi]
+    pub async fn send_encoded(
+        &self,
+        codec: String,
+        value: serde_json::Value,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        self.connect_for_send().await?;
+        handle::send_encoded(self.connection.link(), codec, value, options).await
+    }
+
+    /// Send `payload` in an envelope of type `kind` with a new id and the current time.
+    /// Resolves with the id of the envelope, e.g. to match the answer.
+    #[napi]
+    pub async fn send_envelope(
+        &self,
+        kind: String,
+        payload: serde_json::Value,
+        options: Option<SendOptions>,
+    ) -> Result<String> {
+        self.connect_for_send().await?;
+        handle::send_envelope(self.connection.link(), kind, payload, options).await
+    }
+
+    /// Send after `delayMs`, returns the id of the message or `null` if there is no connection.
+    /// It's dropped when the connection ends before, unless `keepAcrossReconnect` is set,
+    /// and always by `close()`.
+    #[napi]
+    pub fn send_after(
+        &self,
+        delay_ms: u32,
+        data: SendData,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        handle::send_after(
+            self.connection.link(),
+            Duration::from_millis(u64::from(delay_ms)),
+            data,
+            options,
+        )
+    }
+
+    /// Send at `timestamp`, in milliseconds since the epoch like `Date.now()`, see `sendAfter()`.
+    /// Throws for a timestamp which isn't finite or is out of range.
+    #[napi]
+    pub fn send_at(
+        &self,
+        timestamp: f64,
+        data: SendData,
+        options: Option<SendOptions>,
+    ) -> Result<Option<i64>> {
+        handle::send_after(
+            self.connection.link(),
+            handle::delay_until(timestamp)?,
+            data,
+            options,
+        )
+    }
+
+    /// Remove a message which hasn't been written yet, returns whether it was still queued
+    #[napi]
+    pub fn cancel_send(&self, id: i64) -> bool {
+        handle::cancel_send(self.connection.link(), id)
+    }
+
+    /// Send now when connected, and again after every connect and reconnect, in the order of the calls.
+    /// Meant for subscriptions, so they survive reconnects. Returns the id for `forgetSend()`.
+    #[napi]
+    pub fn remember_send(&self, data: SendData) -> i64 {
+        handle::remember_send(self.connection.link(), data)
+    }
+
+    /// Stop sending a message of `rememberSend()` again, a copy which wasn't written yet is dropped.
+    /// Returns whether it was remembered.
+    #[napi]
+    pub fn forget_send(&self, id: i64) -> bool {
+        handle::forget_send(self.connection.link(), id)
+    }
+
+    /// Resolve once every message sent so far has been written to the socket.
+    /// Rejects after `timeoutMs` if given.
+    #[napi]
+    pub async fn flush(&self, timeout_ms: Option<u32>) -> Result<()> {
+        handle::flush(self.connection.link(), timeout_ms).await
+    }
+
+    /// Drop messages which haven't been written yet, e.g. when the user cancels an upload.
+    /// Returns how many were dropped.
+    #[napi]
+    pub fn clear_queue(&self, filter: Option<ClearQueueFilter>) -> u32 {
+        handle::clear_queue(self.connection.link(), filter)
+    }
+
+    /// Number of messages which haven't been written yet
+    #[napi(getter)]
+    pub fn pending_count(&self) -> u32 {
+        self.connection.link().pending_count() as u32
+    }
+
+    /// Payload bytes of the messages which haven't been written yet
+    #[napi(getter)]
+    pub fn pending_bytes(&self) -> f64 {
+        self.connection.link().pending_bytes() as f64
+    }
+
+    /// Distinct tags of the messages which haven't been written yet, in queue order
+    #[napi]
+    pub fn pending_tags(&self) -> Vec<String> {
+        self.connection.link().pending_tags()
+    }
+
+    /// How the written messages were batched
+    #[napi]
+    pub fn get_write_stats(&self) -> WriteStats {
+        self.connection.link().write_stats().into()
+    }
+
+    /// Close with an optional `code`, 1000 or 3000 to 4999, and `reason`.
+    /// Resolves once the server answered the close and the stream ended, so everything sent before was written.
+    /// Without an answer within `closeTimeoutMs` the connection is dropped and the promise rejected.
+    /// A shared WebSocket only lets go of the connection right away, the last one to do so closes it without a code.
+    #[napi]
+    pub async fn close(&self, code: Option<u32>, reason: Option<String>) -> Result<()> {
+        if let Some(consumer) = &self.shared {
+            // Closes the connection if this was the last consumer
+            drop(
+                consumer
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take(),
+            );
+            return Ok(());
         }
+        handle::close(self.connection.link(), code, reason).await?;
+        self.connection
+            .finish_close()
+            .await
+            .map(|_| ())
+            .map_err(to_napi_error)
+    }
 
-        let (mut write, read) = ws_stream.split();
+    /// Release everything native right away, for a WebSocket which is discarded without closing.
+    /// Pending connects are aborted and reconnecting stops, an open connection is dropped without a close handshake,
+    /// queued, scheduled and remembered messages are discarded and every callback is released.
+    /// A shared WebSocket only lets go of the connection, like `close()`. It can't connect again afterwards.
+    #[napi]
+    pub fn dispose(&self) {
+        match &self.shared {
+            Some(consumer) => drop(
+                consumer
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take(),
+            ),
+            None => self.connection.dispose(),
+        }
+        self.dispatcher.release();
+    }
 
-        let (tx, mut rx) = mpsc::channel::<Message>(32);
+    /// Send a ping with `pingMessage` or `ping`, it's rejected when the payload is over 125 bytes
+    #[napi]
+    pub async fn ping(&self, ping_message: Option<Buffer>) -> Result<()> {
+        handle::ping(self.connection.link(), ping_message).await
+    }
 
-        self.writer.write().await.replace(tx);
+    /// Enable or disable power save, e.g. from battery or power mode callbacks.
+    /// While enabled, messages sent with `urgent: false` are held back and written in batches.
+    #[napi]
+    pub fn set_power_save(&self, enabled: bool, options: Option<PowerSaveOptions>) {
+        handle::set_power_save(self.connection.link(), enabled, options);
+    }
 
-        let write_from_js = async move {
-            while let Some(message) = rx.recv().await {
-                if let Err(e) = write.send(message).await {
-                    if let Some(on_error) = &self.on_error {
-                        on_error.call(
-                            Error::new(WebSocketError::SendError, e.to_string()),
-                            ThreadsafeFunctionCallMode::NonBlocking,
-                        );
-                    }
-                }
-            }
-        };
+    /// Get ready for the app being frozen, e.g. from `onBackground` of the ability.
+    /// `onSuspendHint` is called on every consumer, then the going away message is sent,
+    /// the queue flushed and with `close` the connection closed with 1001.
+    #[napi]
+    pub async fn prepare_for_suspend(&self, options: Option<SuspendOptions>) -> Result<()> {
+        handle::prepare_for_suspend(self.connection.link(), options).await
+    }
 
-        let read_from_ws = read.for_each(|message_result| {
-            async move {
-                match message_result {
-                    Ok(message) => match message {
-                        Message::Text(text) => {
-                            if let Some(on_message) = &self.on_message {
-                                on_message.call(
-                                    Either::A(text.to_string()),
-                                    ThreadsafeFunctionCallMode::NonBlocking,
-                                );
-                            }
-                        }
-                        Message::Binary(data) => {
-                            if let Some(on_message) = &self.on_message {
-                                let buf = data.iter().as_slice();
-                                on_message.call(
-                                    Either::B(Buffer::from(buf)),
-                                    ThreadsafeFunctionCallMode::NonBlocking,
-                                );
-                            }
-                        }
-                        Message::Close(frame) => {
-                            if let Some(_frame) = frame {
-                                if let Some(on_close) = &self.on_close {
-                                    on_close.call(true, ThreadsafeFunctionCallMode::NonBlocking);
-                                }
-                            } else {
-                                if let Some(on_close) = &self.on_close {
-                                    on_close.call(false, ThreadsafeFunctionCallMode::NonBlocking);
-                                }
-                            }
-                        }
-                        Message::Ping(ping_message) => {
-                            if let Some(on_ping) = &self.on_ping {
-                                let buf = ping_message.iter().as_slice();
-                                let pong_message = match on_ping.call_async(Buffer::from(buf)).await
-                                {
-                                    Ok(return_value) => {
-                                        if let Some(pong_message) = return_value {
-                                            let pong_buf = Vec::<u8>::from(pong_message);
-                                            Message::Pong(pong_buf.into())
-                                        } else {
-                                            Message::Pong("pong".into())
-                                        }
-                                    }
-                                    Err(e) => {
-                                        hilog_error!(format!("ws-rs: onPing error: {}", e));
-                                        Message::Pong("pong".into())
-                                    }
-                                };
-                                let writer = self.writer.read().await;
-                                if let Some(writer) = writer.as_ref() {
-                                    writer.send(pong_message).await.unwrap();
-                                }
-                            }
-                        }
-                        Message::Pong(pong_message) => {
-                            if let Some(on_pong) = &self.on_pong {
-                                let buf = pong_message.iter().as_slice();
-                                on_pong.call(
-                                    Buffer::from(buf),
-                                    ThreadsafeFunctionCallMode::NonBlocking,
-                                );
-                            }
-                        }
-                        _ => {} // 忽略其他类型的消息
-                    },
-                    Err(e) => {
-                        if let Some(on_error) = &self.on_error {
-                            on_error.call(
-                                Error::new(WebSocketError::ReceiveError, e.to_string()),
-                                ThreadsafeFunctionCallMode::NonBlocking,
-                            );
-                        }
-                    }
-                }
-            }
-        });
+    /// The app went to the background, e.g. from `onBackground` of the ability.
+    /// The keepalive is slowed down and sent messages are held back, so the app isn't frozen in the middle of a write.
+    #[napi]
+    pub fn suspend(&self, options: Option<BackgroundOptions>) {
+        handle::suspend(self.connection.link(), options);
+    }
 
-        napi_ohos::tokio::select! {
-          _ = read_from_ws => {},
-          _ = write_from_js => {},
+    /// Back in the foreground, e.g. from `onForeground` of the ability. The held messages are written
+    /// and a keepalive ping checks right away whether the connection survived.
+    /// A connection which was open when suspended and was lost meanwhile is made again.
+    #[napi]
+    pub async fn resume(&self) -> Result<()> {
+        if self.connection.resume() {
+            self.connect().await?;
         }
+        Ok(())
+    }
+
+    /// State of the connection like `readyState` of the browser WebSocket, e.g. to check it before sending
+    #[napi(getter)]
+    pub fn ready_state(&self) -> ReadyState {
+        self.connection.link().state().into()
+    }
+
+    /// The detailed state of the connection, see `onStateChange`
+    #[napi(getter)]
+    pub fn conn_state(&self) -> ConnState {
+        self.connection.link().conn_state().into()
+    }
+
+    #[napi(getter, js_name = "CONNECTING")]
+    pub fn connecting_state(&self) -> ReadyState {
+        ReadyState::Connecting
+    }
+
+    #[napi(getter, js_name = "OPEN")]
+    pub fn open_state(&self) -> ReadyState {
+        ReadyState::Open
+    }
+
+    #[napi(getter, js_name = "CLOSING")]
+    pub fn closing_state(&self) -> ReadyState {
+        ReadyState::Closing
+    }
+
+    #[napi(getter, js_name = "CLOSED")]
+    pub fn closed_state(&self) -> ReadyState {
+        ReadyState::Closed
+    }
 
+    /// Metadata of the connection, e.g. which feature of the app it belongs to.
+    /// It's part of the log lines of the connection.
+    #[napi(getter)]
+    pub fn tag(&self) -> HashMap<String, String> {
+        self.connection.link().tag()
+    }
+
+    #[napi(setter)]
+    pub fn set_tag(&self, tag: HashMap<String, String>) {
+        self.connection.link().set_tag(tag);
+    }
+
+    /// Create a send-only handle of this connection.
+    /// The handle can send, ping and close, but does not carry any callback.
+    #[napi]
+    pub fn handle(&self) -> WebSocketHandle {
+        WebSocketHandle::new(self.connection.link().clone())
+    }
+
+    /// Create a handle for other native libraries, see `include/harmony_websocket.h`.
+    /// The handle has to be given back with `harmony_ws_release`.
+    #[napi]
+    pub fn native_handle(&self) -> BigInt {
+        let handle = HarmonyWsHandle::into_raw(
+            self.connection.link().clone(),
+            self.dispatcher.native_listeners.clone(),
+        );
+        BigInt::from(handle as usize as u64)
+    }
+
+    /// Authenticate with a client certificate whose private key stays in secure storage, e.g. HUKS.
+    /// `sign` receives the data of the TLS handshake and resolves with its signature.
+    /// It takes effect on the next connect, and needs the `rustls` TLS backend.
+    #[napi]
+    pub fn set_client_key(
+        &self,
+        cert_chain: Vec<Buffer>,
+        scheme: SignScheme,
+        sign: Function<SignRequest, Promise<Binary>>,
+    ) -> Result<()> {
+        let callback = sign
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.connection.set_client_key(Some(ClientKey {
+            cert_chain: cert_chain.into_iter().map(Vec::<u8>::from).collect(),
+            scheme: scheme.into(),
+            signer: Arc::new(JsSigner {
+                callback: Arc::new(callback),
+            }),
+        }));
         Ok(())
     }
 
     #[napi]
-    pub async fn send(&self, data: Either<String, Buffer>) -> Result<()> {
-        let writer = self.writer.read().await;
-        if let Some(writer) = writer.as_ref() {
-            let message = match data {
-                Either::A(text) => Message::Text(text.into()),
-                Either::B(buf) => {
-                    let bytes = Vec::<u8>::from(buf);
-                    Message::Binary(bytes.into())
-                }
-            };
-            writer
-                .send(message)
-                .await
-                .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
-        }
+    pub fn clear_client_key(&self) {
+        self.connection.set_client_key(None);
+    }
+
+    /// Send another header in the opening handshake, after the ones of `headers`.
+    /// A name which is there already is sent again with this value, it applies from the next connect.
+    #[napi]
+    pub fn append_header(&self, name: String, value: String) {
+        self.connection.append_header(name, value);
+    }
+
+    /// Get the opening handshake request of the most recent connect.
+    /// Headers which are generated during the handshake, such as `Sec-WebSocket-Key`, are included.
+    #[napi]
+    pub fn get_last_handshake_request(&self) -> Option<HandshakeRequest> {
+        self.connection
+            .last_handshake_request()
+            .map(HandshakeRequest::from)
+    }
+
+    /// Get the duration of every phase of the most recent connect
+    #[napi]
+    pub fn get_connect_timings(&self) -> Option<ConnectTimings> {
+        self.connection
+            .last_connect_timings()
+            .map(ConnectTimings::from)
+    }
+
+    /// Get the TLS session of the current or most recent connection, empty for `ws://`
+    #[napi]
+    pub fn get_tls_info(&self) -> Option<TlsInfo> {
+        self.connection.tls_info().map(TlsInfo::from)
+    }
+
+    /// Get the latest connect attempts, oldest first, with where and why they failed,
+    /// e.g. to attach to a support request instead of a packet capture
+    #[napi]
+    pub fn get_connect_history(&self) -> Vec<ConnectAttempt> {
+        self.connection
+            .connect_history()
+            .into_iter()
+            .map(ConnectAttempt::from)
+            .collect()
+    }
+
+    /// Get how the most recent connection ended.
+    /// A reset or a dropped network is reported with `wasClean: false` and code 1006.
+    #[napi]
+    pub fn get_close_info(&self) -> Option<CloseInfo> {
+        self.connection.last_close().map(CloseInfo::from)
+    }
+
+    /// Get the latest error, the one `onError` got last, also when no callback was registered.
+    /// Unlike the error of `getCloseInfo()` it includes failed connects.
+    #[napi]
+    pub fn last_error(&self) -> Option<ReportedError> {
+        self.connection.last_error().map(ReportedError::from)
+    }
+
+    /// Get what's needed to carry on after the app was killed, as a JSON string for app storage:
+    /// the reconnect settings, the tag, the messages of `rememberSend()`, the messages which weren't sent yet
+    /// and the id counters. The open connection itself isn't part of it.
+    #[napi]
+    pub fn export_state(&self) -> String {
+        self.connection.export_state().pack()
+    }
+
+    /// Carry on with a state of `exportState()` of the same url, before `connect()`.
+    /// Remembered messages are remembered again, returns their new ids for `forgetSend()` in their order.
+    /// Queued messages are queued again, with the time since the export taken off their `ttlMs`.
+    #[napi]
+    pub fn import_state(&self, state: String) -> Result<Vec<i64>> {
+        let state = harmony_websocket_core::SessionState::unpack(&state).ok_or_else(|| {
+            Error::new(
+                Status::InvalidArg,
+                "not a state of exportState() of this version",
+            )
+        })?;
+        let ids = self.connection.import_state(state).map_err(to_napi_error)?;
+        Ok(ids.into_iter().map(|id| id as i64).collect())
+    }
+
+    /// Write a JSON report to `path` for a bug report, e.g. in the files dir of the app: the config,
+    /// the latest connect attempts and errors, the stats and the most recent handshake request and response.
+    /// Credentials are redacted: secret headers, the query values of urls and certificates and keys.
+    /// Throws "not compiled in" without the `diagnostics` feature.
+    #[napi]
+    pub fn export_diagnostics(&self, path: String) -> Result<()> {
+        write_diagnostics(&self.connection, &path)
+    }
+
+    /// Get the extensions accepted by the server on the most recent connect.
+    /// Empty means the connection is uncompressed.
+    #[napi]
+    pub fn extensions(&self) -> Vec<String> {
+        self.connection.extensions()
+    }
+
+    #[napi]
+    pub fn on_error(&self, callback: Function<Error<WebSocketError>, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_error = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub async fn close(&self) -> Result<()> {
-        let writer = self.writer.read().await;
-        if let Some(writer) = writer.as_ref() {
-            writer
-                .send(Message::Close(None))
-                .await
-                .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
-        }
+    pub fn off_error(&self) {
+        self.dispatcher.handlers.update(|h| h.on_error = None);
+    }
+
+    #[napi]
+    pub fn on_message(&self, callback: Function<Either<String, Buffer>, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_message = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub async fn ping(&self, ping_message: Option<Buffer>) -> Result<()> {
-        let ping_message = match ping_message {
-            Some(buf) => {
-                let bytes = Vec::<u8>::from(buf);
-                bytes
-            }
-            None => "ping".into(),
+    pub fn off_message(&self) {
+        self.dispatcher.handlers.update(|h| h.on_message = None);
+    }
+
+    /// Get the messages of `onMessage` in another callback as well, e.g. one per page showing the feed.
+    /// The listener doesn't keep its page or worker alive, it's dropped once their env is released.
+    /// Returns the id for `removeMessageListener()`.
+    #[napi]
+    pub fn add_message_listener(
+        &self,
+        listener: Function<Either<String, Buffer>, ()>,
+    ) -> Result<i64> {
+        let listener = listener
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .weak::<true>()
+            .build()?;
+        let mut id = 0;
+        self.dispatcher
+            .handlers
+            .update(|h| id = h.message_listeners.add(Arc::new(listener)));
+        Ok(id as i64)
+    }
+
+    /// Remove a listener of `addMessageListener()`, e.g. in `aboutToDisappear`. Returns whether it existed.
+    #[napi]
+    pub fn remove_message_listener(&self, id: i64) -> bool {
+        let Ok(id) = u64::try_from(id) else {
+            return false;
         };
+        let mut removed = false;
+        self.dispatcher
+            .handlers
+            .update(|h| removed = h.message_listeners.remove(id));
+        removed
+    }
 
-        if ping_message.len() > 128 {
-            return Err(Error::new(
-                Status::GenericFailure,
-                "ping message length exceeds 128 bytes".to_string(),
-            ));
-        }
-        let ping_message = Message::Ping(ping_message.into());
-
-        let writer = self.writer.read().await;
-        if let Some(writer) = writer.as_ref() {
-            writer
-                .send(ping_message)
-                .await
-                .map_err(|e| Error::new(Status::GenericFailure, e.to_string()))?;
-        }
+    /// The latest text message whose `latestKey` field had the value `key`, e.g. to render on mount
+    /// without waiting for the next update. Numbers and booleans are looked up by their JSON text.
+    #[napi]
+    pub fn get_latest(&self, key: String) -> Option<String> {
+        let latest = self.dispatcher.latest.get()?;
+        let latest = latest.lock().unwrap_or_else(PoisonError::into_inner);
+        latest.get(&key).map(|text| text.to_string())
+    }
+
+    /// Replace the filter of received messages, `null` delivers all of them again
+    #[napi]
+    pub fn set_filter(&self, filter: Option<MessageFilter>) -> Result<()> {
+        let filter = match filter {
+            Some(filter) => to_filter(filter)?,
+            None => None,
+        };
+        self.dispatcher.handlers.update(|h| h.filter = filter);
+        Ok(())
+    }
+
+    /// Join text messages into documents from now on, or deliver every message as it arrives again with `null`.
+    /// Text buffered for an incomplete document is dropped.
+    #[napi]
+    pub fn set_assembly(&self, assembly: Option<AssemblyOptions>) -> Result<()> {
+        let assembler = assembly.map(to_assembler).transpose()?;
+        *self
+            .dispatcher
+            .assembler
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = assembler;
+        Ok(())
+    }
+
+    /// Replace the codec which decodes received messages for `onDecoded`, `null` stops decoding
+    #[napi]
+    pub fn set_codec(&self, codec: Option<String>) -> Result<()> {
+        let codec = codec.as_deref().map(codec::lookup).transpose()?;
+        self.dispatcher.handlers.update(|h| h.codec = codec);
         Ok(())
     }
 
+    /// Call `handler` with text messages whose JSON field at `path`, like `$.type` or `$.data[0].kind`,
+    /// equals `value`. Messages are matched natively, the ones matching a route are not passed to onMessage.
+    /// Returns the id for `unroute()`.
     #[napi]
-    pub unsafe fn on_error(&mut self, callback: Function<Error<WebSocketError>, ()>) -> Result<()> {
+    pub fn route(
+        &self,
+        path: String,
+        value: Either3<String, f64, bool>,
+        handler: Function<String, ()>,
+    ) -> Result<i64> {
+        let path = JsonPath::parse(&path).map_err(|e| Error::new(Status::InvalidArg, e))?;
+        let value = match value {
+            Either3::A(value) => RouteValue::String(value),
+            Either3::B(value) => RouteValue::Number(value),
+            Either3::C(value) => RouteValue::Bool(value),
+        };
+        let handler = handler
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        let mut id = 0;
+        self.dispatcher
+            .handlers
+            .update(|h| id = h.router.add(path, value, Arc::new(handler)));
+        Ok(id as i64)
+    }
+
+    /// Remove a route, returns whether it existed
+    #[napi]
+    pub fn unroute(&self, id: i64) -> bool {
+        let Ok(id) = u64::try_from(id) else {
+            return false;
+        };
+        let mut removed = false;
+        self.dispatcher
+            .handlers
+            .update(|h| removed = h.router.remove(id));
+        removed
+    }
+
+    /// Call `handler` with received envelopes of type `kind`, unpacked natively.
+    /// They are not passed to routes or onMessage. Returns the id for `offEnvelope()`.
+    #[napi]
+    pub fn on_envelope(&self, kind: String, handler: Function<Envelope, ()>) -> Result<i64> {
+        let handler = handler
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        let mut id = 0;
+        self.dispatcher
+            .handlers
+            .update(|h| id = h.envelopes.add(kind, Arc::new(handler)));
+        Ok(id as i64)
+    }
+
+    /// Remove an envelope handler, returns whether it existed
+    #[napi]
+    pub fn off_envelope(&self, id: i64) -> bool {
+        let Ok(id) = u64::try_from(id) else {
+            return false;
+        };
+        let mut removed = false;
+        self.dispatcher
+            .handlers
+            .update(|h| removed = h.envelopes.remove(id));
+        removed
+    }
+
+    /// onOpen event
+    /// called with a summary of the connection, so no getter is needed right after opening
+    #[napi]
+    pub fn on_open(&self, callback: Function<OpenInfo, ()>) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_error = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_open = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub unsafe fn on_message(
-        &mut self,
-        callback: Function<Either<String, Buffer>, ()>,
-    ) -> Result<()> {
+    pub fn off_open(&self) {
+        self.dispatcher.handlers.update(|h| h.on_open = None);
+    }
+
+    /// onClose event
+    /// called with the code, the reason, whether the connection was closed normally and who closed it,
+    /// e.g. a 1000 close by the server or a 1006 drop of the network.
+    #[napi]
+    pub fn on_close(&self, callback: Function<CloseInfo, ()>) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_message = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_close = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub unsafe fn on_open(&mut self, callback: Function<(), ()>) -> Result<()> {
+    pub fn off_close(&self) {
+        self.dispatcher.handlers.update(|h| h.on_close = None);
+    }
+
+    /// onStateChange event
+    /// called with every change of `connState`, from `connecting` through `tls-handshake` and `upgrading` to `open`,
+    /// then `closing`, `closed` and `reconnecting` while `reconnect` waits to make it again
+    #[napi]
+    pub fn on_state_change(&self, callback: Function<ConnState, ()>) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_open = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_state_change = Some(Arc::new(callback)));
         Ok(())
     }
 
-    /// onClose event
-    /// if the connection is closed normally, the parameter is true, otherwise false
     #[napi]
-    pub unsafe fn on_close(&mut self, callback: Function<bool, ()>) -> Result<()> {
+    pub fn off_state_change(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_state_change = None);
+    }
+
+    /// onServerClosing event
+    /// called when the server sent its close frame and `closeGraceMs` holds it back,
+    /// messages sent now are still written. `close()` ends the grace period early.
+    #[napi]
+    pub fn on_server_closing(&self, callback: Function<ServerClosing, ()>) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_close = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_server_closing = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub unsafe fn on_ping(&mut self, callback: Function<Buffer, Option<Buffer>>) -> Result<()> {
+    pub fn off_server_closing(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_server_closing = None);
+    }
+
+    #[napi]
+    pub fn on_ping(&self, callback: Function<Buffer, Option<Binary>>) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_ping = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_ping = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub unsafe fn on_pong(&mut self, callback: Function<Buffer, ()>) -> Result<()> {
+    pub fn off_ping(&self) {
+        self.dispatcher.handlers.update(|h| h.on_ping = None);
+    }
+
+    /// onPong event
+    /// called with the payload and whether it answers `ping()` or a keepalive ping
+    #[napi]
+    pub fn on_pong(&self, callback: Function<FnArgs<(Buffer, PongInfo)>, ()>) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_pong = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_pong = Some(Arc::new(callback)));
         Ok(())
     }
 
     #[napi]
-    pub unsafe fn on_header_received(
-        &mut self,
+    pub fn off_pong(&self) {
+        self.dispatcher.handlers.update(|h| h.on_pong = None);
+    }
+
+    #[napi]
+    pub fn on_header_received(
+        &self,
         callback: Function<HashMap<String, String>, ()>,
     ) -> Result<()> {
         let callback = callback
             .build_threadsafe_function()
             .callee_handled::<false>()
             .build()?;
-        self.on_header_received = Some(Arc::new(callback));
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_header_received = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_header_received(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_header_received = None);
+    }
+
+    /// onDeferred event
+    /// called when outgoing messages start to be held back by power save or data saver
+    #[napi]
+    pub fn on_deferred(&self, callback: Function<(), ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_deferred = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_deferred(&self) {
+        self.dispatcher.handlers.update(|h| h.on_deferred = None);
+    }
+
+    /// onRedirect event
+    /// called for every redirect of the handshake which is followed, before the new url is tried
+    #[napi]
+    pub fn on_redirect(&self, callback: Function<RedirectInfo, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_redirect = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_redirect(&self) {
+        self.dispatcher.handlers.update(|h| h.on_redirect = None);
+    }
+
+    /// onReconnecting event
+    /// called with the attempt and its delay before every attempt to make the connection again
+    #[napi]
+    pub fn on_reconnecting(&self, callback: Function<ReconnectAttempt, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_reconnecting = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_reconnecting(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_reconnecting = None);
+    }
+
+    /// onReconnected event
+    /// called with the number of attempts it took once the connection is open again, after `onOpen`
+    #[napi]
+    pub fn on_reconnected(&self, callback: Function<u32, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_reconnected = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_reconnected(&self) {
+        self.dispatcher.handlers.update(|h| h.on_reconnected = None);
+    }
+
+    /// onRotating event
+    /// called with the age of the connection in milliseconds when it reached `maxConnectionAgeMs`
+    /// and is closed to be made again right away
+    #[napi]
+    pub fn on_rotating(&self, callback: Function<f64, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_rotating = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_rotating(&self) {
+        self.dispatcher.handlers.update(|h| h.on_rotating = None);
+    }
+
+    /// onReconnectFailed event
+    /// called with the number of attempts when `maxAttempts` are used up and reconnecting gives up
+    #[napi]
+    pub fn on_reconnect_failed(&self, callback: Function<u32, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_reconnect_failed = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_reconnect_failed(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_reconnect_failed = None);
+    }
+
+    /// onExtensionFallback event
+    /// called with the reason when the server did not handle the extension offer
+    /// and the connection is made again without it
+    #[napi]
+    pub fn on_extension_fallback(&self, callback: Function<String, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_extension_fallback = Some(Arc::new(callback)));
         Ok(())
     }
+
+    #[napi]
+    pub fn off_extension_fallback(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_extension_fallback = None);
+    }
+
+    /// onSuspendHint event
+    /// called when `prepareForSuspend()` was called on the connection, by any of its consumers or handles
+    #[napi]
+    pub fn on_suspend_hint(&self, callback: Function<(), ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_suspend_hint = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_suspend_hint(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_suspend_hint = None);
+    }
+
+    /// onDropped event
+    /// called when a queued message is given up before it was written, because its `ttlMs` passed or its writes failed
+    #[napi]
+    pub fn on_dropped(&self, callback: Function<DroppedMessage, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_dropped = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_dropped(&self) {
+        self.dispatcher.handlers.update(|h| h.on_dropped = None);
+    }
+
+    /// onLargeMessage event
+    /// called instead of onMessage with received messages over `largeMessageThreshold` bytes
+    #[napi]
+    pub fn on_large_message(&self, callback: Function<LargeMessage, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_large_message = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_large_message(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_large_message = None);
+    }
+
+    /// onJsBacklog event
+    /// called with the delay in milliseconds when an `onMessage` callback ran over `jsBacklogThresholdMs` late,
+    /// once until the JS thread caught up again
+    #[napi]
+    pub fn on_js_backlog(&self, callback: Function<f64, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_js_backlog = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_js_backlog(&self) {
+        self.dispatcher.handlers.update(|h| h.on_js_backlog = None);
+    }
+
+    /// onSequenceAnomaly event
+    /// called when the counter of a received message, see `sequence`, skipped values or didn't grow
+    #[napi]
+    pub fn on_sequence_anomaly(&self, callback: Function<SequenceAnomaly, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_sequence_anomaly = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_sequence_anomaly(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_sequence_anomaly = None);
+    }
+
+    /// onNetworkChange event
+    /// called when the default network of the device became available, switched or was lost.
+    /// Needs the `ohos.permission.GET_NETWORK_INFO` permission.
+    #[napi]
+    pub fn on_network_change(&self, callback: Function<NetworkChange, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_network_change = Some(Arc::new(callback)));
+        network::subscribe();
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_network_change(&self) {
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_network_change = None);
+    }
+
+    /// onDecoded event
+    /// called instead of onMessage with received messages decoded by the `codec` of the connection
+    #[napi]
+    pub fn on_decoded(&self, callback: Function<serde_json::Value, ()>) -> Result<()> {
+        let callback = callback
+            .build_threadsafe_function()
+            .callee_handled::<false>()
+            .build()?;
+        self.dispatcher
+            .handlers
+            .update(|h| h.on_decoded = Some(Arc::new(callback)));
+        Ok(())
+    }
+
+    #[napi]
+    pub fn off_decoded(&self) {
+        self.dispatcher.handlers.update(|h| h.on_decoded = None);
+    }
 }
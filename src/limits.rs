@@ -0,0 +1,30 @@
+use napi_derive_ohos::napi;
+
+#[napi(object)]
+pub struct ConnectionLimits {
+    /// Maximum number of connections of the app, unlimited if empty
+    pub max_connections_total: Option<u32>,
+
+    /// Maximum number of connections to the same host, unlimited if empty
+    pub max_connections_per_host: Option<u32>,
+
+    /// Let `connect()` wait until a connection is closed instead of failing with `TooManyConnections`.
+    /// The wait is limited by `connectTimeoutMs`, then it fails with `ConnectTimeout`. Defaults to false.
+    pub wait_for_slot: Option<bool>,
+}
+
+impl From<ConnectionLimits> for harmony_websocket_core::ConnectionLimits {
+    fn from(limits: ConnectionLimits) -> Self {
+        harmony_websocket_core::ConnectionLimits {
+            max_total: limits.max_connections_total.map(|max| max as usize),
+            max_per_host: limits.max_connections_per_host.map(|max| max as usize),
+            wait_for_slot: limits.wait_for_slot.unwrap_or(false),
+        }
+    }
+}
+
+/// Limit the number of open connections for all `WebSocket`s, already open connections are kept
+#[napi]
+pub fn set_connection_limits(limits: ConnectionLimits) {
+    harmony_websocket_core::set_connection_limits(limits.into());
+}
@@ -0,0 +1,281 @@
+//! Watches the default network of the device through NetManager, see `onNetworkChange` and `migrateOnNetworkChange`
+
+use std::{
+    ffi::c_void,
+    sync::{Arc, Mutex, OnceLock, PoisonError, Weak},
+};
+
+use harmony_websocket_core::Connection;
+use napi_derive_ohos::napi;
+use napi_ohos::threadsafe_function::ThreadsafeFunctionCallMode;
+use ohos_hilog_binding::hilog_error;
+
+use crate::handlers::Dispatcher;
+
+const NETCONN_MAX_CAP_SIZE: usize = 32;
+const NETCONN_MAX_BEARER_TYPE_SIZE: usize = 32;
+
+const NETCONN_BEARER_CELLULAR: i32 = 0;
+const NETCONN_BEARER_WIFI: i32 = 1;
+const NETCONN_BEARER_ETHERNET: i32 = 3;
+const NETCONN_BEARER_VPN: i32 = 4;
+
+/// `NetConn_NetHandle` of `net_connection_type.h`
+#[repr(C)]
+struct NetConnNetHandle {
+    net_id: i32,
+}
+
+/// `NetConn_NetCapabilities` of `net_connection_type.h`
+#[repr(C)]
+struct NetConnNetCapabilities {
+    link_up_bandwidth_kbps: u32,
+    link_down_bandwidth_kbps: u32,
+    net_caps: [i32; NETCONN_MAX_CAP_SIZE],
+    net_caps_size: i32,
+    bearer_types: [i32; NETCONN_MAX_BEARER_TYPE_SIZE],
+    bearer_types_size: i32,
+}
+
+/// `NetConn_NetConnCallback` of `net_connection_type.h`
+#[repr(C)]
+struct NetConnNetConnCallback {
+    on_network_available: Option<extern "C" fn(*mut NetConnNetHandle)>,
+    on_net_capabilities_change:
+        Option<extern "C" fn(*mut NetConnNetHandle, *mut NetConnNetCapabilities)>,
+    on_connection_properties_change: Option<extern "C" fn(*mut NetConnNetHandle, *mut c_void)>,
+    on_net_lost: Option<extern "C" fn(*mut NetConnNetHandle)>,
+    on_net_unavailable: Option<extern "C" fn()>,
+    on_net_block_status_change: Option<extern "C" fn(*mut NetConnNetHandle, bool)>,
+}
+
+#[link(name = "net_connection")]
+extern "C" {
+    /// Needs `ohos.permission.GET_NETWORK_INFO`
+    fn OH_NetConn_RegisterDefaultNetConnCallback(
+        callback: *mut NetConnNetConnCallback,
+        callback_id: *mut u32,
+    ) -> i32;
+    fn OH_NetConn_GetNetCapabilities(
+        net_handle: *mut NetConnNetHandle,
+        capabilities: *mut NetConnNetCapabilities,
+    ) -> i32;
+}
+
+#[napi(string_enum)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NetworkType {
+    #[napi(value = "wifi")]
+    Wifi,
+    #[napi(value = "cellular")]
+    Cellular,
+    #[napi(value = "ethernet")]
+    Ethernet,
+    #[napi(value = "vpn")]
+    Vpn,
+    #[napi(value = "other")]
+    Other,
+}
+
+/// The default network of the device changed
+#[napi(object)]
+pub struct NetworkChange {
+    /// False when the device lost its network and has none
+    pub available: bool,
+    pub network_type: Option<NetworkType>,
+    /// The network before, empty if there was none
+    pub previous_type: Option<NetworkType>,
+    /// The default network is another one than the one before, e.g. Wi-Fi to cellular
+    pub switched: bool,
+    /// The open connection of this `WebSocket` is dropped and made again on the new network
+    pub migrating: bool,
+}
+
+/// A `WebSocket` which gets the changes
+struct Watcher {
+    dispatcher: Weak<Dispatcher>,
+    connection: Weak<Connection>,
+    migrate: bool,
+}
+
+#[derive(Default)]
+struct State {
+    watchers: Vec<Watcher>,
+    /// The current default network, empty while there is none
+    current: Option<(i32, NetworkType)>,
+    /// The default network before the current one, or the last one while there is none
+    last: Option<(i32, NetworkType)>,
+}
+
+fn state() -> &'static Mutex<State> {
+    static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+    STATE.get_or_init(Mutex::default)
+}
+
+/// Deliver the changes to the callbacks of `dispatcher`, and migrate `connection` if `migrate`.
+/// The `WebSocket` is forgotten once it's dropped.
+pub fn watch(dispatcher: &Arc<Dispatcher>, connection: &Arc<Connection>, migrate: bool) {
+    let mut state = state().lock().unwrap_or_else(PoisonError::into_inner);
+    state
+        .watchers
+        .retain(|watcher| watcher.dispatcher.strong_count() > 0);
+    state.watchers.push(Watcher {
+        dispatcher: Arc::downgrade(dispatcher),
+        connection: Arc::downgrade(connection),
+        migrate,
+    });
+    drop(state);
+    if migrate {
+        subscribe();
+    }
+}
+
+/// Register with NetManager, once for the module
+pub fn subscribe() {
+    static SUBSCRIBED: OnceLock<()> = OnceLock::new();
+    SUBSCRIBED.get_or_init(|| {
+        // NetManager keeps the pointer until it's unregistered, which never happens
+        let callback = Box::leak(Box::new(NetConnNetConnCallback {
+            on_network_available: Some(on_network_available),
+            on_net_capabilities_change: None,
+            on_connection_properties_change: None,
+            on_net_lost: Some(on_net_lost),
+            on_net_unavailable: Some(on_net_unavailable),
+            on_net_block_status_change: None,
+        }));
+        let mut callback_id = 0;
+        let code = unsafe { OH_NetConn_RegisterDefaultNetConnCallback(callback, &mut callback_id) };
+        if code != 0 {
+            hilog_error!(format!(
+                "ws-rs network changes are not watched, NetManager returned {}",
+                code
+            ));
+        }
+    });
+}
+
+fn network_type(net_handle: *mut NetConnNetHandle) -> NetworkType {
+    let mut capabilities = NetConnNetCapabilities {
+        link_up_bandwidth_kbps: 0,
+        link_down_bandwidth_kbps: 0,
+        net_caps: [0; NETCONN_MAX_CAP_SIZE],
+        net_caps_size: 0,
+        bearer_types: [0; NETCONN_MAX_BEARER_TYPE_SIZE],
+        bearer_types_size: 0,
+    };
+    if unsafe { OH_NetConn_GetNetCapabilities(net_handle, &mut capabilities) } != 0 {
+        return NetworkType::Other;
+    }
+    let bearers = capabilities
+        .bearer_types_size
+        .clamp(0, NETCONN_MAX_BEARER_TYPE_SIZE as i32) as usize;
+    let bearers = &capabilities.bearer_types[..bearers];
+    // A VPN also lists the bearer it runs over
+    if bearers.contains(&NETCONN_BEARER_VPN) {
+        return NetworkType::Vpn;
+    }
+    match bearers.first() {
+        Some(&NETCONN_BEARER_WIFI) => NetworkType::Wifi,
+        Some(&NETCONN_BEARER_CELLULAR) => NetworkType::Cellular,
+        Some(&NETCONN_BEARER_ETHERNET) => NetworkType::Ethernet,
+        _ => NetworkType::Other,
+    }
+}
+
+extern "C" fn on_network_available(net_handle: *mut NetConnNetHandle) {
+    if net_handle.is_null() {
+        return;
+    }
+    let net_id = unsafe { (*net_handle).net_id };
+    let network_type = network_type(net_handle);
+    let mut state = state().lock().unwrap_or_else(PoisonError::into_inner);
+    if state.current.is_some_and(|(current, _)| current == net_id) {
+        return;
+    }
+    let previous = state.current.or(state.last);
+    let switched = previous.is_some_and(|(previous, _)| previous != net_id);
+    if let Some(current) = state.current {
+        state.last = Some(current);
+    }
+    state.current = Some((net_id, network_type));
+    notify(
+        state,
+        true,
+        Some(network_type),
+        previous.map(|(_, network_type)| network_type),
+        switched,
+    );
+}
+
+extern "C" fn on_net_lost(net_handle: *mut NetConnNetHandle) {
+    if net_handle.is_null() {
+        return;
+    }
+    let net_id = unsafe { (*net_handle).net_id };
+    let state = state().lock().unwrap_or_else(PoisonError::into_inner);
+    if state.current.is_some_and(|(current, _)| current == net_id) {
+        lost(state);
+    }
+}
+
+extern "C" fn on_net_unavailable() {
+    lost(state().lock().unwrap_or_else(PoisonError::into_inner));
+}
+
+fn lost(mut state: std::sync::MutexGuard<'_, State>) {
+    let Some(current) = state.current.take() else {
+        return;
+    };
+    state.last = Some(current);
+    notify(state, false, None, Some(current.1), false);
+}
+
+/// Tell every watching `WebSocket`, and migrate the open connections which asked for it
+fn notify(
+    mut state: std::sync::MutexGuard<'_, State>,
+    available: bool,
+    network_type: Option<NetworkType>,
+    previous_type: Option<NetworkType>,
+    switched: bool,
+) {
+    state
+        .watchers
+        .retain(|watcher| watcher.dispatcher.strong_count() > 0);
+    let watchers: Vec<_> = state
+        .watchers
+        .iter()
+        .filter_map(|watcher| {
+            Some((
+                watcher.dispatcher.upgrade()?,
+                watcher.connection.upgrade()?,
+                watcher.migrate,
+            ))
+        })
+        .collect();
+    // The callbacks don't run under the lock
+    drop(state);
+    // Consumers of a shared connection watch it each, it's migrated once
+    let mut migrated: Vec<Arc<Connection>> = Vec::new();
+    for (dispatcher, connection, migrate) in watchers {
+        let migrating = if migrated.iter().any(|other| Arc::ptr_eq(other, &connection)) {
+            true
+        } else if migrate && available && switched && connection.migrate() {
+            migrated.push(connection);
+            true
+        } else {
+            false
+        };
+        if let Some(on_network_change) = &dispatcher.handlers.snapshot().on_network_change {
+            on_network_change.call(
+                NetworkChange {
+                    available,
+                    network_type,
+                    previous_type,
+                    switched,
+                    migrating,
+                },
+                ThreadsafeFunctionCallMode::NonBlocking,
+            );
+        }
+    }
+}
@@ -0,0 +1,431 @@
+use std::{collections::HashMap, sync::Mutex};
+#[cfg(feature = "pool")]
+use std::{sync::PoisonError, time::Duration};
+
+#[cfg(feature = "pool")]
+use futures_util::future::join_all;
+#[cfg(feature = "pool")]
+use harmony_websocket_core::{Event, EventHandler, KeepaliveSlot};
+use napi_derive_ohos::napi;
+use napi_ohos::Result;
+#[cfg(feature = "pool")]
+use napi_ohos::{bindgen_prelude::*, tokio::time::Instant, Error};
+#[cfg(feature = "pool")]
+use tokio_tungstenite::tungstenite::http::Uri;
+
+#[cfg(not(feature = "pool"))]
+use crate::error::not_compiled_in;
+#[cfg(feature = "pool")]
+use crate::{
+    handle::{to_message, SendData, SendOptions},
+    WebSocketConfig,
+};
+use crate::{
+    handle::{ConnState, ReadyState},
+    info::WriteStats,
+    WebSocket,
+};
+
+/// One WebSocket of a pool, see `list()`
+#[napi(object)]
+pub struct PoolEntry {
+    pub id: String,
+    pub ready_state: ReadyState,
+    pub conn_state: ConnState,
+    pub tag: HashMap<String, String>,
+    /// Messages which haven't been written yet
+    pub pending_count: u32,
+}
+
+/// Totals over all WebSockets of a pool
+#[napi(object)]
+pub struct PoolStats {
+    pub sockets: u32,
+    pub connecting: u32,
+    pub open: u32,
+    pub closing: u32,
+    pub closed: u32,
+    /// Messages which haven't been written yet
+    pub pending_count: u32,
+    /// Payload bytes of the messages which haven't been written yet
+    pub pending_bytes: f64,
+    pub write_stats: WriteStats,
+    /// When the WebSockets with a keepalive ping, empty unless `coordinateKeepalive()` was called
+    pub keepalive: Vec<KeepaliveSchedule>,
+}
+
+/// The place of a WebSocket on the keepalive timer of its host
+#[napi(object)]
+pub struct KeepaliveSchedule {
+    pub id: String,
+    /// Host and port, the WebSockets with the same host and keepalive interval share a timer
+    pub host: String,
+    pub interval_ms: u32,
+    /// Delay of its pings after the tick of the timer
+    pub offset_ms: u32,
+    /// Milliseconds until the next tick of its place, its next ping goes out on this or a later one
+    pub next_tick_ms: u32,
+}
+
+/// See `coordinateKeepalive()`
+#[cfg(feature = "pool")]
+struct Coordination {
+    stagger: Duration,
+    /// The start of the timer of each host and interval, kept while the pool changes
+    epochs: HashMap<String, Instant>,
+}
+
+/// Host and port of `url`, with the default port of the scheme
+#[cfg(feature = "pool")]
+fn host_of(url: &str) -> String {
+    let Ok(uri) = url.parse::<Uri>() else {
+        return url.to_string();
+    };
+    let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("ws") {
+        80
+    } else {
+        443
+    });
+    format!(
+        "{}:{}",
+        uri.host().unwrap_or_default().to_ascii_lowercase(),
+        port
+    )
+}
+
+/// Put the WebSockets with a keepalive on the timer of their host, one `stagger` after another
+#[cfg(feature = "pool")]
+fn schedule(sockets: &[(String, WebSocket)], coordination: &mut Option<Coordination>) {
+    let Some(coordination) = coordination else {
+        for (_, socket) in sockets {
+            socket.connection.link().set_keepalive_slot(None);
+        }
+        return;
+    };
+    let mut members: HashMap<String, u32> = HashMap::new();
+    for (_, socket) in sockets {
+        let Some(keep_alive) = socket.connection.keep_alive() else {
+            continue;
+        };
+        let key = format!(
+            "{} {}",
+            host_of(socket.connection.url()),
+            keep_alive.interval.as_millis()
+        );
+        let epoch = *coordination
+            .epochs
+            .entry(key.clone())
+            .or_insert_with(Instant::now);
+        let index = members.entry(key).or_default();
+        let offset = coordination.stagger * *index;
+        *index += 1;
+        socket
+            .connection
+            .link()
+            .set_keepalive_slot(Some(KeepaliveSlot { epoch, offset }));
+    }
+}
+
+/// Another `WebSocket` object of the same connection and callbacks
+#[cfg(feature = "pool")]
+fn share(socket: &WebSocket) -> WebSocket {
+    WebSocket {
+        connection: socket.connection.clone(),
+        dispatcher: socket.dispatcher.clone(),
+        shared: None,
+        connect_on_send: socket.connect_on_send,
+    }
+}
+
+/// Many WebSockets by id, e.g. for a dashboard of dozens of devices.
+/// They all run on the one native runtime of the module, the pool only saves managing them one by one.
+#[napi]
+#[derive(Default)]
+#[cfg_attr(not(feature = "pool"), allow(dead_code))]
+pub struct WebSocketPool {
+    /// In the order they were added
+    sockets: Mutex<Vec<(String, WebSocket)>>,
+    #[cfg(feature = "pool")]
+    keepalive: Mutex<Option<Coordination>>,
+}
+
+/// Left out of this build, the constructor throws "not compiled in"
+#[cfg(not(feature = "pool"))]
+#[napi]
+impl WebSocketPool {
+    #[napi(constructor)]
+    pub fn new() -> Result<Self> {
+        Err(not_compiled_in("WebSocketPool", "pool"))
+    }
+}
+
+#[cfg(feature = "pool")]
+#[napi]
+impl WebSocketPool {
+    #[napi(constructor)]
+    pub fn new() -> Self {
+        WebSocketPool::default()
+    }
+
+    /// The sockets of the pool, outside of the lock
+    fn sockets(&self) -> Vec<(String, WebSocket)> {
+        self.sockets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(id, socket)| (id.clone(), share(socket)))
+            .collect()
+    }
+
+    /// Create a WebSocket with `url` and `config` under `id`, it's not connected yet.
+    /// The returned WebSocket is the one `get(id)` returns, callbacks registered on either apply to both.
+    #[napi]
+    pub fn add(
+        &self,
+        id: String,
+        url: String,
+        config: Option<WebSocketConfig>,
+    ) -> Result<WebSocket> {
+        let mut sockets = self.sockets.lock().unwrap_or_else(PoisonError::into_inner);
+        if sockets.iter().any(|(existing, _)| *existing == id) {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!("the pool has a WebSocket {} already", id),
+            ));
+        }
+        let socket = WebSocket::new(url, config)?;
+        let added = share(&socket);
+        sockets.push((id, socket));
+        schedule(
+            &sockets,
+            &mut self
+                .keepalive
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        Ok(added)
+    }
+
+    #[napi]
+    pub fn get(&self, id: String) -> Option<WebSocket> {
+        self.sockets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|(existing, _)| *existing == id)
+            .map(|(_, socket)| share(socket))
+    }
+
+    /// Take the WebSocket out of the pool, it keeps running until it's closed
+    #[napi]
+    pub fn remove(&self, id: String) -> Option<WebSocket> {
+        let mut sockets = self.sockets.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = sockets.iter().position(|(existing, _)| *existing == id)?;
+        let (_, socket) = sockets.remove(index);
+        socket.connection.link().set_keepalive_slot(None);
+        schedule(
+            &sockets,
+            &mut self
+                .keepalive
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner),
+        );
+        Some(socket)
+    }
+
+    /// Send the keepalive pings of the WebSockets with the same host and interval on one shared timer,
+    /// `staggerMs` apart (100 by default), so they go out in one radio wakeup instead of one each.
+    /// A ping is moved to the latest tick before it's due, the interval is never longer.
+    /// Applies to the WebSockets added later as well, `getStats()` has the schedule.
+    #[napi]
+    pub fn coordinate_keepalive(&self, stagger_ms: Option<u32>) {
+        let sockets = self.sockets.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut keepalive = self
+            .keepalive
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let stagger = Duration::from_millis(u64::from(stagger_ms.unwrap_or(100)));
+        match keepalive.as_mut() {
+            Some(coordination) => coordination.stagger = stagger,
+            None => {
+                *keepalive = Some(Coordination {
+                    stagger,
+                    epochs: HashMap::new(),
+                })
+            }
+        }
+        schedule(&sockets, &mut keepalive);
+    }
+
+    /// Every WebSocket pings on its own timer again
+    #[napi]
+    pub fn uncoordinate_keepalive(&self) {
+        let sockets = self.sockets.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut keepalive = self
+            .keepalive
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        *keepalive = None;
+        schedule(&sockets, &mut keepalive);
+    }
+
+    /// Ids in the order they were added
+    #[napi]
+    pub fn ids(&self) -> Vec<String> {
+        self.sockets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    #[napi(getter)]
+    pub fn size(&self) -> u32 {
+        self.sockets
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len() as u32
+    }
+
+    /// Every WebSocket with its state and tag, in the order they were added
+    #[napi]
+    pub fn list(&self) -> Vec<PoolEntry> {
+        self.sockets()
+            .into_iter()
+            .map(|(id, socket)| PoolEntry {
+                id,
+                ready_state: socket.ready_state(),
+                conn_state: socket.conn_state(),
+                tag: socket.tag(),
+                pending_count: socket.pending_count(),
+            })
+            .collect()
+    }
+
+    #[napi]
+    pub fn get_stats(&self) -> PoolStats {
+        let sockets = self.sockets();
+        let now = Instant::now();
+        let count = |state: ReadyState| {
+            sockets
+                .iter()
+                .filter(|(_, socket)| socket.ready_state() == state)
+                .count() as u32
+        };
+        let write_stats = sockets.iter().fold(
+            harmony_websocket_core::WriteStats::default(),
+            |mut total, (_, socket)| {
+                let stats = socket.connection.link().write_stats();
+                total.batches += stats.batches;
+                total.messages += stats.messages;
+                total.largest_batch = total.largest_batch.max(stats.largest_batch);
+                total
+            },
+        );
+        PoolStats {
+            sockets: sockets.len() as u32,
+            connecting: count(ReadyState::Connecting),
+            open: count(ReadyState::Open),
+            closing: count(ReadyState::Closing),
+            closed: count(ReadyState::Closed),
+            pending_count: sockets
+                .iter()
+                .map(|(_, socket)| socket.pending_count())
+                .sum(),
+            pending_bytes: sockets
+                .iter()
+                .map(|(_, socket)| socket.pending_bytes())
+                .sum(),
+            write_stats: write_stats.into(),
+            keepalive: sockets
+                .iter()
+                .filter_map(|(id, socket)| {
+                    let keep_alive = socket.connection.keep_alive()?;
+                    let slot = socket.connection.link().keepalive_slot()?;
+                    let next = slot.next(now, keep_alive.interval);
+                    Some(KeepaliveSchedule {
+                        id: id.clone(),
+                        host: host_of(socket.connection.url()),
+                        interval_ms: keep_alive.interval.as_millis() as u32,
+                        offset_ms: slot.offset.as_millis() as u32,
+                        next_tick_ms: (next - now).as_millis() as u32,
+                    })
+                })
+                .collect(),
+        }
+    }
+
+    /// Send `data` on every open WebSocket of the pool, or those of `ids`.
+    /// Resolves with the number of WebSockets it was queued on, failures are reported to their `onError`.
+    #[napi]
+    pub async fn broadcast_send(
+        &self,
+        data: SendData,
+        options: Option<SendOptions>,
+        ids: Option<Vec<String>>,
+    ) -> Result<u32> {
+        let message = to_message(data);
+        let options: harmony_websocket_core::SendOptions =
+            options.map(Into::into).unwrap_or_default();
+        let sockets: Vec<_> = self
+            .sockets()
+            .into_iter()
+            .filter(|(id, _)| ids.as_ref().is_none_or(|ids| ids.contains(id)))
+            .map(|(_, socket)| socket)
+            .filter(|socket| socket.ready_state() == ReadyState::Open)
+            .collect();
+        let sent = join_all(sockets.iter().map(|socket| async {
+            match socket
+                .connection
+                .link()
+                .send_with(message.clone(), options.clone())
+                .await
+            {
+                Ok(_) => true,
+                Err(e) => {
+                    socket.dispatcher.handle(Event::Error(e));
+                    false
+                }
+            }
+        }))
+        .await;
+        Ok(sent.into_iter().filter(|sent| *sent).count() as u32)
+    }
+
+    /// Connect every WebSocket which isn't connected yet, resolves once all of them are open.
+    /// A failed connect doesn't stop the others, the first failure rejects once they're all done.
+    #[napi]
+    pub async fn connect_all(&self) -> Result<()> {
+        let sockets: Vec<_> = self
+            .sockets()
+            .into_iter()
+            .map(|(_, socket)| socket)
+            .filter(|socket| !socket.connection.is_running())
+            .collect();
+        join_all(sockets.iter().map(WebSocket::connect))
+            .await
+            .into_iter()
+            .collect()
+    }
+
+    /// Close every running WebSocket of the pool with an optional `code` and `reason`, they stay in the pool.
+    /// Resolves once all close handshakes finished, the first failure rejects once they're all done.
+    #[napi]
+    pub async fn close_all(&self, code: Option<u32>, reason: Option<String>) -> Result<()> {
+        let sockets: Vec<_> = self
+            .sockets()
+            .into_iter()
+            .map(|(_, socket)| socket)
+            .filter(|socket| socket.connection.is_running())
+            .collect();
+        join_all(
+            sockets
+                .iter()
+                .map(|socket| socket.close(code, reason.clone())),
+        )
+        .await
+        .into_iter()
+        .collect()
+    }
+}
@@ -0,0 +1,106 @@
+use harmony_websocket_core::{HostnamePolicy, TlsOptions};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+
+use crate::{
+    certmanager::CertManagerCredential,
+    tls::{to_cert_paths, Pkcs12Bundle, TlsBackend, TlsTrust, TlsVersion, WildcardPolicy},
+};
+
+/// TLS setup shared by every `WebSocket` connecting to the hosts of a profile.
+/// The fields are the ones of `WebSocketConfig`, plus version bounds and pinning.
+#[napi(object)]
+pub struct TlsProfile {
+    pub cert_path: Option<Either<String, Vec<String>>>,
+    pub ca_certs: Option<Vec<Buffer>>,
+    pub ca_cert_uris: Option<Vec<String>>,
+    pub tls_backend: Option<TlsBackend>,
+    pub tls_trust: Option<TlsTrust>,
+    pub crl_path: Option<String>,
+    pub crls: Option<Vec<Buffer>>,
+    pub allow_unknown_revocation: Option<bool>,
+    pub wildcard_policy: Option<WildcardPolicy>,
+    pub accepted_names: Option<Vec<String>>,
+    pub ca_bundles: Option<Vec<Pkcs12Bundle>>,
+    pub client_identity: Option<Pkcs12Bundle>,
+    pub client_credential: Option<CertManagerCredential>,
+
+    /// Oldest TLS version which is negotiated. `1.3` needs the `rustls` TLS backend.
+    pub min_tls_version: Option<TlsVersion>,
+    pub max_tls_version: Option<TlsVersion>,
+
+    /// Base64 SHA-256 hashes of the public key (SubjectPublicKeyInfo) of the server certificate,
+    /// optionally prefixed with `sha256/`. The connection fails unless one of them matches.
+    pub pins: Option<Vec<String>>,
+}
+
+fn to_tls_options(profile: TlsProfile) -> Result<TlsOptions> {
+    let pins = profile
+        .pins
+        .unwrap_or_default()
+        .iter()
+        .map(|pin| TlsOptions::parse_pin(pin))
+        .collect::<harmony_websocket_core::Result<_>>()
+        .map_err(|e| Error::new(Status::InvalidArg, e.message))?;
+    Ok(TlsOptions {
+        backend: profile.tls_backend.map(Into::into),
+        trust: profile.tls_trust.map(Into::into),
+        cert_paths: to_cert_paths(profile.cert_path),
+        ca_certs: profile
+            .ca_certs
+            .unwrap_or_default()
+            .into_iter()
+            .map(Vec::<u8>::from)
+            .collect(),
+        ca_cert_uris: profile.ca_cert_uris.unwrap_or_default(),
+        client_key: None,
+        client_credential: profile.client_credential.map(Into::into),
+        crl_path: profile.crl_path,
+        crls: profile
+            .crls
+            .unwrap_or_default()
+            .into_iter()
+            .map(Vec::<u8>::from)
+            .collect(),
+        allow_unknown_revocation: profile.allow_unknown_revocation.unwrap_or(false),
+        hostname: HostnamePolicy {
+            wildcard: profile.wildcard_policy.map(Into::into).unwrap_or_default(),
+            accepted_names: profile.accepted_names.unwrap_or_default(),
+        },
+        ca_bundles: profile
+            .ca_bundles
+            .unwrap_or_default()
+            .into_iter()
+            .map(Into::into)
+            .collect(),
+        client_identity: profile.client_identity.map(Into::into),
+        min_version: profile.min_tls_version.map(Into::into),
+        max_version: profile.max_tls_version.map(Into::into),
+        pins,
+    })
+}
+
+/// Use `profile` for the connections to `hosts`, host names or `*.example.com` for all subdomains.
+/// It applies to every `WebSocket` whose config has no TLS options of its own, also after a redirect
+/// to one of the hosts. An exact host wins over wildcards, and registering a name again replaces it.
+#[napi]
+pub fn register_tls_profile(name: String, hosts: Vec<String>, profile: TlsProfile) -> Result<()> {
+    if hosts.is_empty() {
+        return Err(Error::new(
+            Status::InvalidArg,
+            format!("tls profile {} has no hosts", name),
+        ));
+    }
+    harmony_websocket_core::register_tls_profile(harmony_websocket_core::TlsProfile {
+        name,
+        hosts,
+        tls: to_tls_options(profile)?,
+    });
+    Ok(())
+}
+
+/// Remove the profile registered as `name`, false if there is none. Open connections keep it.
+#[napi]
+pub fn unregister_tls_profile(name: String) -> bool {
+    harmony_websocket_core::unregister_tls_profile(&name)
+}
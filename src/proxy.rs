@@ -0,0 +1,108 @@
+use std::ffi::c_char;
+
+use harmony_websocket_core::{Proxy, ResolveProxy};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+
+/// An http proxy the connection is tunneled through with `CONNECT`, e.g. in a corporate network
+#[napi(object)]
+pub struct ProxyConfig {
+    /// `http://host:port`, the port defaults to 80
+    pub url: String,
+    /// Sent with basic authentication along with `password`
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl ProxyConfig {
+    pub fn to_proxy(&self) -> Result<Proxy> {
+        let credentials = self.username.clone().map(|username| {
+            let password = self.password.clone().unwrap_or_default();
+            (username, password)
+        });
+        Proxy::parse(&self.url, credentials).map_err(|e| Error::new(Status::InvalidArg, e.message))
+    }
+}
+
+const NETCONN_MAX_STR_LEN: usize = 256;
+const NETCONN_MAX_EXCLUSION_SIZE: usize = 256;
+
+/// `NetConn_HttpProxy` of `net_connection_type.h`
+#[repr(C)]
+struct NetConnHttpProxy {
+    host: [c_char; NETCONN_MAX_STR_LEN],
+    exclusion_list: [[c_char; NETCONN_MAX_STR_LEN]; NETCONN_MAX_EXCLUSION_SIZE],
+    exclusion_list_size: i32,
+    port: u16,
+}
+
+#[link(name = "net_connection")]
+extern "C" {
+    /// The global proxy, or the one of the network the app is bound to. Needs `ohos.permission.GET_NETWORK_INFO`.
+    fn OH_NetConn_GetDefaultHttpProxy(http_proxy: *mut NetConnHttpProxy) -> i32;
+}
+
+fn to_string(text: &[c_char]) -> String {
+    let bytes: Vec<u8> = text
+        .iter()
+        .take_while(|c| **c != 0)
+        .map(|c| *c as u8)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// A host of the exclusion list, `*` for all, `*.example.com` or `.example.com` for the subdomains
+fn excluded(host: &str, exclusion: &str) -> bool {
+    let exclusion = exclusion.trim();
+    if exclusion == "*" {
+        return true;
+    }
+    let suffix = exclusion.trim_start_matches('*');
+    if suffix.starts_with('.') {
+        host.len() > suffix.len()
+            && host
+                .get(host.len() - suffix.len()..)
+                .is_some_and(|end| end.eq_ignore_ascii_case(suffix))
+    } else {
+        host.eq_ignore_ascii_case(exclusion)
+    }
+}
+
+/// The http proxy of the system settings, read from NetManager on every dial, see `useSystemProxy`
+#[derive(Debug)]
+pub struct SystemProxy;
+
+impl ResolveProxy for SystemProxy {
+    fn resolve(&self, host: &str) -> Option<Proxy> {
+        let mut settings = NetConnHttpProxy {
+            host: [0; NETCONN_MAX_STR_LEN],
+            exclusion_list: [[0; NETCONN_MAX_STR_LEN]; NETCONN_MAX_EXCLUSION_SIZE],
+            exclusion_list_size: 0,
+            port: 0,
+        };
+        // Without the permission the connection is made directly, as without a proxy
+        if unsafe { OH_NetConn_GetDefaultHttpProxy(&mut settings) } != 0 {
+            return None;
+        }
+        let proxy_host = to_string(&settings.host);
+        if proxy_host.is_empty() || settings.port == 0 {
+            return None;
+        }
+        let exclusions = settings
+            .exclusion_list_size
+            .clamp(0, NETCONN_MAX_EXCLUSION_SIZE as i32);
+        if settings
+            .exclusion_list
+            .iter()
+            .take(exclusions as usize)
+            .any(|exclusion| excluded(host, &to_string(exclusion)))
+        {
+            return None;
+        }
+        Some(Proxy {
+            host: proxy_host,
+            port: settings.port,
+            credentials: None,
+        })
+    }
+}
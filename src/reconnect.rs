@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use harmony_websocket_core::ReconnectPolicy;
+use napi_derive_ohos::napi;
+
+/// Make the connection again when it ended abnormally, e.g. a dropped network.
+/// It's not made again after `close()`.
+#[napi(object)]
+pub struct ReconnectOptions {
+    /// Defaults to true
+    pub enabled: Option<bool>,
+    /// Wait before the first attempt in milliseconds. Defaults to 1000.
+    pub initial_delay_ms: Option<u32>,
+    /// Longest wait between two attempts in milliseconds. Defaults to 30000.
+    pub max_delay_ms: Option<u32>,
+    /// Factor of the wait after every failed attempt. Defaults to 2.
+    pub multiplier: Option<f64>,
+    /// Attempts after every drop. Defaults to unlimited.
+    pub max_attempts: Option<u32>,
+}
+
+impl ReconnectOptions {
+    /// The policy, `None` if it isn't enabled
+    pub fn to_policy(&self) -> Option<ReconnectPolicy> {
+        if !self.enabled.unwrap_or(true) {
+            return None;
+        }
+        let default = ReconnectPolicy::default();
+        Some(ReconnectPolicy {
+            initial_delay: self.initial_delay_ms.map_or(default.initial_delay, |ms| {
+                Duration::from_millis(u64::from(ms))
+            }),
+            max_delay: self
+                .max_delay_ms
+                .map_or(default.max_delay, |ms| Duration::from_millis(u64::from(ms))),
+            multiplier: self.multiplier.unwrap_or(default.multiplier),
+            max_attempts: self.max_attempts,
+        })
+    }
+}
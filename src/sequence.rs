@@ -0,0 +1,42 @@
+use harmony_websocket_core::{JsonPath, SequenceSource};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, Error, Result};
+
+/// Where the counter the server puts into every message is, for QA builds hunting lost messages.
+/// Give `path` for JSON text messages or `offset` for binary ones.
+#[napi(object)]
+pub struct SequenceOptions {
+    /// Path of the counter in JSON text messages, e.g. `$.seq`. A number or a string of digits.
+    pub path: Option<String>,
+    /// Offset of the counter in binary messages, a big-endian unsigned integer
+    pub offset: Option<u32>,
+    /// Bytes of the binary counter, 1 to 8. Defaults to 4.
+    pub width: Option<u32>,
+}
+
+impl SequenceOptions {
+    pub fn to_source(&self) -> Result<SequenceSource> {
+        match (&self.path, self.offset) {
+            (Some(path), None) => JsonPath::parse(path)
+                .map(SequenceSource::Json)
+                .map_err(|e| Error::new(Status::InvalidArg, e)),
+            (None, Some(offset)) => {
+                let width = self.width.unwrap_or(4);
+                if !(1..=8).contains(&width) {
+                    return Err(Error::new(
+                        Status::InvalidArg,
+                        format!("sequence width must be 1 to 8 bytes, not {}", width),
+                    ));
+                }
+                Ok(SequenceSource::Binary {
+                    offset: offset as usize,
+                    width: width as usize,
+                })
+            }
+            _ => Err(Error::new(
+                Status::InvalidArg,
+                "sequence needs either a path or an offset",
+            )),
+        }
+    }
+}
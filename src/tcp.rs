@@ -0,0 +1,30 @@
+use std::time::Duration;
+
+use harmony_websocket_core::TcpOptions;
+use napi_derive_ohos::napi;
+
+/// Options of the tcp socket, applied before the TLS and websocket handshakes. Unset ones keep the system default.
+#[napi(object)]
+pub struct TcpConfig {
+    /// Send small writes at once instead of coalescing them (`TCP_NODELAY`), e.g. for trading or game clients
+    pub no_delay: Option<bool>,
+    /// Turn on tcp keepalive probes after the connection was idle this many seconds, 0 turns them off
+    pub keep_alive_secs: Option<u32>,
+    /// Size of the kernel send buffer in bytes (`SO_SNDBUF`)
+    pub send_buffer_size: Option<u32>,
+    /// Size of the kernel receive buffer in bytes (`SO_RCVBUF`), it limits the tcp window
+    pub recv_buffer_size: Option<u32>,
+}
+
+impl From<TcpConfig> for TcpOptions {
+    fn from(config: TcpConfig) -> Self {
+        TcpOptions {
+            no_delay: config.no_delay,
+            keep_alive: config
+                .keep_alive_secs
+                .map(|secs| Duration::from_secs(u64::from(secs))),
+            send_buffer_size: config.send_buffer_size,
+            recv_buffer_size: config.recv_buffer_size,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use std::sync::Arc;
+
+use harmony_websocket_core::{block_on_signature, ExternalSigner};
+use napi_derive_ohos::napi;
+use napi_ohos::{bindgen_prelude::*, threadsafe_function::ThreadsafeFunction};
+
+use crate::binary::{to_bytes, Binary};
+
+#[napi(string_enum)]
+pub enum TlsBackend {
+    #[napi(value = "native-tls")]
+    NativeTls,
+    #[napi(value = "rustls")]
+    Rustls,
+}
+
+impl From<harmony_websocket_core::TlsBackend> for TlsBackend {
+    fn from(backend: harmony_websocket_core::TlsBackend) -> Self {
+        match backend {
+            harmony_websocket_core::TlsBackend::NativeTls => TlsBackend::NativeTls,
+            harmony_websocket_core::TlsBackend::Rustls => TlsBackend::Rustls,
+        }
+    }
+}
+
+impl From<TlsBackend> for harmony_websocket_core::TlsBackend {
+    fn from(backend: TlsBackend) -> Self {
+        match backend {
+            TlsBackend::NativeTls => harmony_websocket_core::TlsBackend::NativeTls,
+            TlsBackend::Rustls => harmony_websocket_core::TlsBackend::Rustls,
+        }
+    }
+}
+
+#[napi(string_enum)]
+pub enum TlsVersion {
+    #[napi(value = "1.2")]
+    Tls12,
+    #[napi(value = "1.3")]
+    Tls13,
+}
+
+impl From<TlsVersion> for harmony_websocket_core::TlsVersion {
+    fn from(version: TlsVersion) -> Self {
+        match version {
+            TlsVersion::Tls12 => harmony_websocket_core::TlsVersion::Tls12,
+            TlsVersion::Tls13 => harmony_websocket_core::TlsVersion::Tls13,
+        }
+    }
+}
+
+#[napi(string_enum)]
+pub enum TlsTrust {
+    #[napi(value = "system")]
+    System,
+    #[napi(value = "custom")]
+    Custom,
+    #[napi(value = "both")]
+    Both,
+}
+
+impl From<TlsTrust> for harmony_websocket_core::TlsTrust {
+    fn from(trust: TlsTrust) -> Self {
+        match trust {
+            TlsTrust::System => harmony_websocket_core::TlsTrust::System,
+            TlsTrust::Custom => harmony_websocket_core::TlsTrust::Custom,
+            TlsTrust::Both => harmony_websocket_core::TlsTrust::Both,
+        }
+    }
+}
+
+/// `certPath` of the config or a profile, one path or several
+pub fn to_cert_paths(cert_path: Option<Either<String, Vec<String>>>) -> Vec<String> {
+    match cert_path {
+        Some(Either::A(cert_path)) => vec![cert_path],
+        Some(Either::B(cert_paths)) => cert_paths,
+        None => Vec::new(),
+    }
+}
+
+/// The TLS backend used when `tlsBackend` is not set in the config
+#[napi]
+pub fn tls_backend() -> TlsBackend {
+    harmony_websocket_core::TlsBackend::default_backend().into()
+}
+
+/// All TLS backends compiled into this build
+#[napi]
+pub fn available_tls_backends() -> Vec<TlsBackend> {
+    harmony_websocket_core::TlsBackend::available()
+        .into_iter()
+        .map(TlsBackend::from)
+        .collect()
+}
+
+/// Forget the TLS setups built before, e.g. after a file of `certPath` was replaced.
+/// Connections with the same TLS config share one, so their files are read once. Open connections keep theirs.
+#[napi]
+pub fn clear_tls_cache() {
+    harmony_websocket_core::TlsConnector::clear_cache();
+}
+
+/// A password protected `.p12`/`.pfx` bundle, read from `path` unless `data` is given
+#[napi(object)]
+pub struct Pkcs12Bundle {
+    pub path: Option<String>,
+    pub data: Option<Buffer>,
+    pub password: Option<String>,
+}
+
+impl From<Pkcs12Bundle> for harmony_websocket_core::Pkcs12Bundle {
+    fn from(bundle: Pkcs12Bundle) -> Self {
+        harmony_websocket_core::Pkcs12Bundle {
+            path: bundle.path,
+            data: bundle.data.map(Vec::<u8>::from),
+            password: bundle.password.unwrap_or_default(),
+        }
+    }
+}
+
+/// How wildcard names in server certificates are matched
+#[napi(string_enum)]
+pub enum WildcardPolicy {
+    /// `*.example.com` matches exactly one label, as in RFC 6125
+    #[napi(value = "standard")]
+    Standard,
+    /// Wildcard names never match, the host must be listed as it is
+    #[napi(value = "disallow")]
+    Disallow,
+}
+
+impl From<WildcardPolicy> for harmony_websocket_core::WildcardPolicy {
+    fn from(policy: WildcardPolicy) -> Self {
+        match policy {
+            WildcardPolicy::Standard => harmony_websocket_core::WildcardPolicy::Standard,
+            WildcardPolicy::Disallow => harmony_websocket_core::WildcardPolicy::Disallow,
+        }
+    }
+}
+
+/// How the server certificate chain is checked for revocation, as the config sets it.
+/// `off`: no revocation list is given. `required`: every certificate has to be covered by a list.
+/// `allow-unknown`: certificates no list covers are accepted, see `allowUnknownRevocation`.
+/// It's not a result of the check, the handshake fails for a revoked certificate either way.
+#[napi(string_enum)]
+pub enum RevocationMode {
+    #[napi(value = "off")]
+    Off,
+    #[napi(value = "required")]
+    Required,
+    #[napi(value = "allow-unknown")]
+    AllowUnknown,
+}
+
+impl From<harmony_websocket_core::RevocationMode> for RevocationMode {
+    fn from(mode: harmony_websocket_core::RevocationMode) -> Self {
+        match mode {
+            harmony_websocket_core::RevocationMode::Off => RevocationMode::Off,
+            harmony_websocket_core::RevocationMode::Required => RevocationMode::Required,
+            harmony_websocket_core::RevocationMode::AllowUnknown => RevocationMode::AllowUnknown,
+        }
+    }
+}
+
+#[napi(string_enum)]
+pub enum SignScheme {
+    #[napi(value = "ecdsa-p256-sha256")]
+    EcdsaP256Sha256,
+    #[napi(value = "ecdsa-p384-sha384")]
+    EcdsaP384Sha384,
+    #[napi(value = "rsa-pkcs1-sha256")]
+    RsaPkcs1Sha256,
+    #[napi(value = "rsa-pss-sha256")]
+    RsaPssSha256,
+    #[napi(value = "ed25519")]
+    Ed25519,
+}
+
+impl From<SignScheme> for harmony_websocket_core::SignScheme {
+    fn from(scheme: SignScheme) -> Self {
+        match scheme {
+            SignScheme::EcdsaP256Sha256 => harmony_websocket_core::SignScheme::EcdsaP256Sha256,
+            SignScheme::EcdsaP384Sha384 => harmony_websocket_core::SignScheme::EcdsaP384Sha384,
+            SignScheme::RsaPkcs1Sha256 => harmony_websocket_core::SignScheme::RsaPkcs1Sha256,
+            SignScheme::RsaPssSha256 => harmony_websocket_core::SignScheme::RsaPssSha256,
+            SignScheme::Ed25519 => harmony_websocket_core::SignScheme::Ed25519,
+        }
+    }
+}
+
+/// Data to sign for the TLS client authentication
+#[napi(object)]
+pub struct SignRequest {
+    /// Name of the signature scheme, as in `SignScheme`
+    pub scheme: String,
+    pub data: Buffer,
+}
+
+pub type SignCallback = ThreadsafeFunction<SignRequest, Promise<Binary>, SignRequest, false>;
+
+/// Signs through a JS callback, which usually forwards to HUKS
+pub struct JsSigner {
+    pub callback: Arc<SignCallback>,
+}
+
+impl ExternalSigner for JsSigner {
+    fn sign(
+        &self,
+        scheme: harmony_websocket_core::SignScheme,
+        message: &[u8],
+    ) -> std::result::Result<Vec<u8>, String> {
+        let callback = self.callback.clone();
+        let request = SignRequest {
+            scheme: scheme.name().to_string(),
+            data: Buffer::from(message),
+        };
+        // The TLS layer signs synchronously, the worker waits for the promise.
+        // Connections run on the multi-thread runtime of napi, so the other workers carry on meanwhile.
+        block_on_signature(async move {
+            let signature = callback
+                .call_async(request)
+                .await
+                .map_err(|e| e.to_string())?
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(Vec::from(to_bytes(signature)))
+        })
+    }
+}
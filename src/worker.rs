@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock, PoisonError},
+};
+
+use napi_derive_ohos::napi;
+use napi_ohos::{
+    bindgen_prelude::*,
+    threadsafe_function::{ThreadsafeFunction, ThreadsafeFunctionCallMode},
+    Result,
+};
+
+/// Weak, it doesn't keep the worker which registered it alive
+pub type WorkerCallback =
+    ThreadsafeFunction<Either<String, Buffer>, (), Either<String, Buffer>, false, true>;
+
+/// Where the received messages over a size go instead of `onMessage`, see `workerRoute`
+#[napi(object)]
+pub struct WorkerRoute {
+    /// The channel of `onWorkerMessage()`
+    pub channel: String,
+    /// Messages over this many bytes are routed
+    pub threshold: u32,
+}
+
+/// The callbacks of `onWorkerMessage()` by channel, from whichever env registered them
+fn channels() -> &'static Mutex<HashMap<String, Arc<WorkerCallback>>> {
+    static CHANNELS: OnceLock<Mutex<HashMap<String, Arc<WorkerCallback>>>> = OnceLock::new();
+    CHANNELS.get_or_init(Mutex::default)
+}
+
+/// Pass `message` to the callback of `channel`. Returns false when none is registered
+/// or its worker is gone, the message is delivered on the main thread then.
+pub fn deliver(channel: &str, message: impl Fn() -> Either<String, Buffer>) -> bool {
+    let callback = channels()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(channel)
+        .cloned();
+    let Some(callback) = callback else {
+        return false;
+    };
+    if callback.call(message(), ThreadsafeFunctionCallMode::NonBlocking) != Status::Closing {
+        return true;
+    }
+    let mut channels = channels().lock().unwrap_or_else(PoisonError::into_inner);
+    if channels
+        .get(channel)
+        .is_some_and(|current| Arc::ptr_eq(current, &callback))
+    {
+        channels.remove(channel);
+    }
+    false
+}
+
+/// Get the messages of every `WebSocket` whose `workerRoute` names `channel`, called on the thread
+/// which registers it, e.g. in a worker which imported the module, so large payloads are handled off the UI thread.
+/// Replaces the callback registered for `channel` before. It's dropped when its worker is terminated.
+#[napi]
+pub fn on_worker_message(
+    channel: String,
+    callback: Function<Either<String, Buffer>, ()>,
+) -> Result<()> {
+    let callback = callback
+        .build_threadsafe_function()
+        .callee_handled::<false>()
+        .weak::<true>()
+        .build()?;
+    channels()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(channel, Arc::new(callback));
+    Ok(())
+}
+
+/// Stop routing to `channel`, its messages go to `onMessage` again. Returns whether a callback was registered.
+#[napi]
+pub fn off_worker_message(channel: String) -> bool {
+    channels()
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&channel)
+        .is_some()
+}